@@ -0,0 +1,150 @@
+//! Lookup throughput, to weigh the `unsafe-str-decode` feature:
+//!
+//! ```text
+//! cargo bench --bench lookup
+//! cargo bench --bench lookup --features unsafe-str-decode
+//! ```
+//!
+//! Country lookups decode a single short string, City lookups a handful, so
+//! the feature matters more for the latter.
+//!
+//! Loading is measured too, for cold starts: reading the whole file, and
+//! with `--features mmap`, mapping it. There's no io_uring loader to
+//! compare with yet: it needs `tokio-uring` or `io-uring`, which this
+//! build can't fetch, so it waits until one can be vendored.
+
+use std::{hint::black_box, net::IpAddr};
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use locat::{Locat, LocatBuilder, Resolver};
+use maxminddb_writer::{metadata::IpVersion, paths::IpAddrWithMask, Database};
+use serde::Serialize;
+
+const COUNTRIES: [&str; 8] = ["US", "DE", "FR", "GB", "JP", "BR", "IN", "SE"];
+
+#[derive(Serialize)]
+struct Record {
+    country: Country,
+    city: City,
+    location: Location,
+}
+
+#[derive(Serialize)]
+struct Country {
+    iso_code: &'static str,
+}
+
+#[derive(Serialize)]
+struct City {
+    names: Names,
+}
+
+#[derive(Serialize)]
+struct Names {
+    en: &'static str,
+}
+
+#[derive(Serialize)]
+struct Location {
+    latitude: f64,
+    longitude: f64,
+    time_zone: &'static str,
+}
+
+/// A City database with a /24 for every `10.x.y.0`, spread over a few
+/// countries
+fn write_database(path: &str) {
+    let mut db = Database::default();
+    db.metadata.ip_version = IpVersion::V4;
+    db.metadata.database_type = "GeoIP2-City".to_string();
+    db.metadata.languages = vec!["en".to_string()];
+    db.metadata.binary_format_major_version = 2;
+
+    for (i, iso_code) in COUNTRIES.iter().enumerate() {
+        let data = db
+            .insert_value(Record {
+                country: Country { iso_code },
+                city: City {
+                    names: Names { en: "Somewhere" },
+                },
+                location: Location {
+                    latitude: i as f64,
+                    longitude: i as f64,
+                    time_zone: "Etc/UTC",
+                },
+            })
+            .unwrap();
+        for x in 0..=255u32 {
+            for y in (i as u32..256).step_by(COUNTRIES.len()) {
+                let network = format!("10.{x}.{y}.0/24");
+                db.insert_node(network.parse::<IpAddrWithMask>().unwrap(), data);
+            }
+        }
+    }
+
+    db.write_to(std::fs::File::create(path).unwrap()).unwrap();
+}
+
+fn lookup(c: &mut Criterion) {
+    let dir = std::env::temp_dir();
+    let geoip_path = dir.join("locat-bench.mmdb");
+    let analytics_path = dir.join("locat-bench.db");
+    let geoip_path = geoip_path.to_str().unwrap();
+    write_database(geoip_path);
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let locat = runtime
+        .block_on(Locat::new(geoip_path, analytics_path.to_str().unwrap()))
+        .unwrap();
+    let geoip = locat.geoip();
+    let addrs: Vec<IpAddr> = (0..1024u32)
+        .map(|i| IpAddr::from([10, (i * 7 % 256) as u8, (i % 256) as u8, 1]))
+        .collect();
+
+    c.bench_function("country", |b| {
+        b.iter(|| {
+            for addr in &addrs {
+                black_box(geoip.resolve(black_box(*addr)));
+            }
+        })
+    });
+    c.bench_function("location", |b| {
+        b.iter(|| {
+            for addr in &addrs {
+                black_box(locat.ip_to_location(black_box(*addr)));
+            }
+        })
+    });
+
+    _ = std::fs::remove_file(geoip_path);
+    _ = std::fs::remove_file(analytics_path);
+}
+
+fn load(c: &mut Criterion) {
+    let geoip_path = std::env::temp_dir().join("locat-bench-load.mmdb");
+    let geoip_path = geoip_path.to_str().unwrap();
+    write_database(geoip_path);
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let build = |builder: LocatBuilder| runtime.block_on(builder.build()).unwrap();
+    c.bench_function("load_read", |b| {
+        b.iter(|| black_box(build(Locat::builder().with_geoip_path(geoip_path))))
+    });
+    #[cfg(feature = "mmap")]
+    c.bench_function("load_mmap", |b| {
+        b.iter(|| {
+            black_box(build(
+                Locat::builder().with_geoip_path(geoip_path).with_mmap(true),
+            ))
+        })
+    });
+
+    _ = std::fs::remove_file(geoip_path);
+}
+
+criterion_group!(benches, lookup, load);
+criterion_main!(benches);
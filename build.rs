@@ -0,0 +1,39 @@
+fn main() {
+    #[cfg(feature = "embedded")]
+    embed::geoip();
+}
+
+/// Compresses the database named by `LOCAT_EMBED_GEOIP` into `OUT_DIR`, for
+/// `Locat::embedded` to include. Without it, there is nothing to include and
+/// `Locat::embedded` fails at runtime, so that `--all-features` builds work.
+#[cfg(feature = "embedded")]
+mod embed {
+    use std::{env, fs, io::Write, path::PathBuf};
+
+    use flate2::{write::GzEncoder, Compression};
+
+    pub(crate) fn geoip() {
+        println!("cargo:rerun-if-env-changed=LOCAT_EMBED_GEOIP");
+        let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+
+        let database = match env::var("LOCAT_EMBED_GEOIP") {
+            Ok(path) => {
+                println!("cargo:rerun-if-changed={path}");
+                fs::read(&path).unwrap_or_else(|e| panic!("can't read {path}: {e}"))
+            }
+            Err(_) => Vec::new(),
+        };
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(&database).unwrap();
+        fs::write(out.join("geoip.mmdb.gz"), encoder.finish().unwrap()).unwrap();
+        fs::write(
+            out.join("embedded.rs"),
+            format!(
+                "const GEOIP: &[u8] = include_bytes!(concat!(env!(\"OUT_DIR\"), \"/geoip.mmdb.gz\"));\n\
+                 const GEOIP_LEN: usize = {};\n",
+                database.len()
+            ),
+        )
+        .unwrap();
+    }
+}
@@ -0,0 +1,143 @@
+use std::{collections::BTreeMap, net::IpAddr};
+
+use crate::{CountryCode, Locat};
+
+/// How one country fares in an [`AccuracyReport`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CountryScore {
+    /// Addresses of this country located in it
+    pub true_positives: usize,
+    /// Addresses of other countries located in this one
+    pub false_positives: usize,
+    /// Addresses of this country located elsewhere, or not at all
+    pub false_negatives: usize,
+}
+
+impl CountryScore {
+    /// The fraction of addresses located in this country that are in it,
+    /// `None` if none were
+    pub fn precision(&self) -> Option<f64> {
+        ratio(
+            self.true_positives,
+            self.true_positives + self.false_positives,
+        )
+    }
+
+    /// The fraction of addresses in this country that were located in it,
+    /// `None` if none are labeled with it
+    pub fn recall(&self) -> Option<f64> {
+        ratio(
+            self.true_positives,
+            self.true_positives + self.false_negatives,
+        )
+    }
+
+    /// The harmonic mean of precision and recall
+    pub fn f1(&self) -> Option<f64> {
+        ratio(
+            2 * self.true_positives,
+            2 * self.true_positives + self.false_positives + self.false_negatives,
+        )
+    }
+}
+
+/// See [`Locat::score_accuracy`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccuracyReport {
+    pub total: usize,
+    pub correct: usize,
+    /// Addresses the database has no country for
+    pub unlocated: usize,
+    /// Every country that was either a label or an answer
+    pub countries: BTreeMap<String, CountryScore>,
+}
+
+impl AccuracyReport {
+    /// The fraction of addresses located in the right country, unlocated
+    /// ones included, 0 for an empty set
+    pub fn accuracy(&self) -> f64 {
+        ratio(self.correct, self.total).unwrap_or(0.0)
+    }
+
+    fn score(&mut self, iso_code: &str) -> &mut CountryScore {
+        self.countries.entry(iso_code.to_owned()).or_default()
+    }
+}
+
+impl Locat {
+    /// Scores the GeoIP database against addresses whose country is known,
+    /// e.g. from users who told us, to compare vendors on our own traffic
+    /// before switching. Lookups made here aren't counted.
+    pub fn score_accuracy(
+        &self,
+        labeled: impl IntoIterator<Item = (IpAddr, CountryCode)>,
+    ) -> AccuracyReport {
+        let geoip = self.geoip();
+        let mut report = AccuracyReport::default();
+        for (addr, label) in labeled {
+            report.total += 1;
+            let label = label.as_str();
+            let found = geoip.lookup(addr);
+            if found == Some(label) {
+                report.correct += 1;
+                report.score(label).true_positives += 1;
+                continue;
+            }
+            match found {
+                Some(found) => report.score(found).false_positives += 1,
+                None => report.unlocated += 1,
+            }
+            report.score(label).false_negatives += 1;
+        }
+        report
+    }
+}
+
+fn ratio(part: usize, whole: usize) -> Option<f64> {
+    (whole > 0).then(|| part as f64 / whole as f64)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{testing::TestDb, CountryCode, CountryScore, Locat};
+
+    #[tokio::test]
+    async fn test_score_accuracy() {
+        let locat = Locat::builder()
+            .with_geoip_bytes(
+                TestDb::new()
+                    .country("1.2.3.0/24", "US", "NA")
+                    .country("5.6.7.0/24", "FR", "EU")
+                    .build(),
+            )
+            .build()
+            .await
+            .unwrap();
+        let labeled = [
+            ("1.2.3.4", "US"),
+            ("1.2.3.5", "US"),
+            ("5.6.7.8", "FR"),
+            // a French database would have it in France
+            ("5.6.7.9", "BE"),
+            ("8.8.8.8", "US"),
+        ]
+        .map(|(addr, label)| (addr.parse().unwrap(), label.parse::<CountryCode>().unwrap()));
+
+        let report = locat.score_accuracy(labeled);
+        assert_eq!((report.total, report.correct, report.unlocated), (5, 3, 1));
+        assert_eq!(report.accuracy(), 0.6);
+        assert_eq!(
+            report.countries["US"],
+            CountryScore {
+                true_positives: 2,
+                false_positives: 0,
+                false_negatives: 1
+            }
+        );
+        assert_eq!(report.countries["US"].precision(), Some(1.0));
+        assert_eq!(report.countries["FR"].precision(), Some(0.5));
+        assert_eq!(report.countries["FR"].recall(), Some(1.0));
+        assert_eq!(report.countries["BE"].precision(), None);
+        assert_eq!(report.countries["BE"].recall(), Some(0.0));
+    }
+}
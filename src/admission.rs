@@ -0,0 +1,325 @@
+use std::{
+    fmt::Write,
+    num::NonZeroU32,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::Locat;
+
+/// What happens to lookups while analytics can't keep up, see
+/// [`Admission`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Overload {
+    /// Lookups are answered but not counted
+    SkipRecording,
+    /// One lookup in this many is counted, the others are answered only
+    Sample(NonZeroU32),
+    /// Counted lookups are refused with [`crate::LookupError::Overloaded`]
+    /// and [`crate::Outcome::Overloaded`]. Untracked ones go through.
+    Shed,
+}
+
+/// When analytics count as saturated, and what to do then, see
+/// [`Locat::with_admission`]
+#[derive(Debug, Clone)]
+pub struct Admission {
+    policy: Overload,
+    max_backlog: usize,
+    failures: u32,
+    cooldown: Duration,
+}
+
+impl Admission {
+    /// Saturated once 100,000 counts wait to be written (see
+    /// [`Locat::with_write_behind`]) or after 5 failed writes in a row,
+    /// for 30 seconds
+    pub fn new(policy: Overload) -> Self {
+        Self {
+            policy,
+            max_backlog: 100_000,
+            failures: 5,
+            cooldown: Duration::from_secs(30),
+        }
+    }
+
+    /// How many counts may wait for the write-behind flusher
+    pub fn with_max_backlog(mut self, max_backlog: usize) -> Self {
+        self.max_backlog = max_backlog;
+        self
+    }
+
+    /// Writes are taken to be failing after `failures` errors in a row, and
+    /// tried again once `cooldown` has passed. A success puts things back
+    /// to normal.
+    pub fn with_breaker(mut self, failures: u32, cooldown: Duration) -> Self {
+        self.failures = failures.max(1);
+        self.cooldown = cooldown;
+        self
+    }
+}
+
+/// An [`Admission`] and what it has done so far
+pub(crate) struct AdmissionControl {
+    config: Admission,
+    breaker: Mutex<Breaker>,
+    // lookups seen while saturated and sampling
+    sampled: AtomicU64,
+    skipped: AtomicU64,
+    sampled_out: AtomicU64,
+    shed: AtomicU64,
+}
+
+#[derive(Default)]
+struct Breaker {
+    failures: u32,
+    opened_at: Option<Instant>,
+}
+
+impl Locat {
+    /// Degrades lookups according to `admission` while the analytics
+    /// store can't keep up, rather than letting counts pile up or errors
+    /// flood the logs. What was done shows up in
+    /// [`Locat::analytics_to_prometheus`].
+    pub fn with_admission(mut self, admission: Admission) -> Self {
+        self.inner_mut().admission = Some(AdmissionControl {
+            config: admission,
+            breaker: Default::default(),
+            sampled: AtomicU64::new(0),
+            skipped: AtomicU64::new(0),
+            sampled_out: AtomicU64::new(0),
+            shed: AtomicU64::new(0),
+        });
+        self
+    }
+
+    /// Whether the analytics store is failing, or writes are too far
+    /// behind. Always `false` without [`Locat::with_admission`].
+    pub fn is_saturated(&self) -> bool {
+        let Some(admission) = &self.inner.admission else {
+            return false;
+        };
+        let breaker = admission.breaker.lock().unwrap();
+        if breaker
+            .opened_at
+            .is_some_and(|at| at.elapsed() < admission.config.cooldown)
+        {
+            return true;
+        }
+        self.inner
+            .write_behind
+            .as_ref()
+            .is_some_and(|write_behind| write_behind.backlog() >= admission.config.max_backlog)
+    }
+
+    /// Whether a counted lookup should be refused outright
+    pub(crate) fn shed(&self) -> bool {
+        let Some(admission) = &self.inner.admission else {
+            return false;
+        };
+        if admission.config.policy != Overload::Shed || !self.is_saturated() {
+            return false;
+        }
+        admission.shed.fetch_add(1, Ordering::Relaxed);
+        true
+    }
+
+    /// Whether a lookup that would be counted should be
+    pub(crate) fn admit(&self) -> bool {
+        let Some(admission) = &self.inner.admission else {
+            return true;
+        };
+        if !self.is_saturated() {
+            return true;
+        }
+        match admission.config.policy {
+            Overload::SkipRecording => {
+                admission.skipped.fetch_add(1, Ordering::Relaxed);
+                false
+            }
+            Overload::Sample(n) => {
+                let seen = admission.sampled.fetch_add(1, Ordering::Relaxed);
+                let admitted = seen % u64::from(n.get()) == 0;
+                if !admitted {
+                    admission.sampled_out.fetch_add(1, Ordering::Relaxed);
+                }
+                admitted
+            }
+            // already refused if it came to that
+            Overload::Shed => true,
+        }
+    }
+
+    /// Feeds the breaker with the outcome of a write
+    pub(crate) fn wrote(&self, ok: bool) {
+        let Some(admission) = &self.inner.admission else {
+            return;
+        };
+        let mut breaker = admission.breaker.lock().unwrap();
+        if ok {
+            *breaker = Breaker::default();
+            return;
+        }
+        breaker.failures += 1;
+        if breaker.failures >= admission.config.failures {
+            breaker.opened_at = Some(Instant::now());
+        }
+    }
+
+    /// The OpenMetrics lines for [`Locat::analytics_to_prometheus`], if
+    /// there's admission control
+    pub(crate) fn admission_metrics(&self, out: &mut String) {
+        let Some(admission) = &self.inner.admission else {
+            return;
+        };
+        out.push_str("# TYPE locat_analytics_saturated gauge\n");
+        out.push_str("# HELP locat_analytics_saturated Whether analytics can't keep up.\n");
+        _ = writeln!(
+            out,
+            "locat_analytics_saturated {}",
+            u8::from(self.is_saturated())
+        );
+        out.push_str("# TYPE locat_admission_actions counter\n");
+        out.push_str(
+            "# HELP locat_admission_actions Lookups degraded while analytics were saturated.\n",
+        );
+        for (action, count) in [
+            ("skip_recording", &admission.skipped),
+            ("sample_out", &admission.sampled_out),
+            ("shed", &admission.shed),
+        ] {
+            _ = writeln!(
+                out,
+                "locat_admission_actions_total{{action=\"{action}\"}} {}",
+                count.load(Ordering::Relaxed)
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        num::NonZeroU32,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::Duration,
+    };
+
+    use async_trait::async_trait;
+
+    use crate::{
+        testing::{RemoveOnDrop, TestDb},
+        Admission, AnalyticsStore, Error, Locat, LookupError, Measures, MemoryStore, Outcome,
+        Overload,
+    };
+
+    struct Flaky {
+        failing: Arc<AtomicBool>,
+        store: MemoryStore,
+    }
+
+    #[async_trait]
+    impl AnalyticsStore for Flaky {
+        async fn add(
+            &self,
+            analytics: &[(String, Measures)],
+            counters: &[(String, u64)],
+        ) -> Result<(), Error> {
+            if self.failing.load(Ordering::Relaxed) {
+                return Err(Error::Store("down".into()));
+            }
+            self.store.add(analytics, counters).await
+        }
+
+        async fn measures(&self) -> Result<Vec<(String, Measures)>, Error> {
+            self.store.measures().await
+        }
+
+        async fn counters(&self) -> Result<Vec<(String, u64)>, Error> {
+            self.store.counters().await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_admission() {
+        let geoip_path = "/tmp/loca-test-admission.mmdb";
+        TestDb::new()
+            .country("1.2.3.0/24", "US", "NA")
+            .write(geoip_path);
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+        let addr = "1.2.3.4".parse().unwrap();
+
+        let failing = Arc::new(AtomicBool::new(false));
+        let flaky = || Flaky {
+            failing: failing.clone(),
+            store: MemoryStore::new(),
+        };
+        let locat = Locat::from_store(geoip_path, flaky())
+            .await
+            .unwrap()
+            .with_admission(
+                Admission::new(Overload::Sample(NonZeroU32::new(2).unwrap()))
+                    .with_breaker(2, Duration::from_secs(3600)),
+            );
+        failing.store(true, Ordering::Relaxed);
+        for _ in 0..2 {
+            locat.ip_to_iso_code(addr).await;
+        }
+        assert!(locat.is_saturated());
+        failing.store(false, Ordering::Relaxed);
+        // one in two gets through, and closes the breaker
+        locat.ip_to_iso_code(addr).await;
+        assert!(!locat.is_saturated());
+        assert_eq!(
+            locat.get_analytics().await.unwrap(),
+            vec![("US".to_string(), 1)]
+        );
+
+        let locat = Locat::from_store(geoip_path, flaky())
+            .await
+            .unwrap()
+            .with_admission(
+                Admission::new(Overload::Shed).with_breaker(1, Duration::from_secs(3600)),
+            );
+        failing.store(true, Ordering::Relaxed);
+        assert_eq!(locat.ip_to_iso_code(addr).await, Some("US"));
+        assert!(matches!(
+            locat.try_ip_to_iso_code(addr).await,
+            Err(LookupError::Overloaded)
+        ));
+        assert_eq!(locat.lookup(addr).await, Outcome::Overloaded);
+        assert_eq!(locat.ip_to_iso_code_untracked(addr), Some("US"));
+
+        let text = locat.analytics_to_prometheus().await.unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert!(lines.contains(&"locat_analytics_saturated 1"));
+        assert!(lines.contains(&"locat_admission_actions_total{action=\"shed\"} 2"));
+
+        let locat = Locat::from_store(geoip_path, flaky())
+            .await
+            .unwrap()
+            .with_admission(
+                Admission::new(Overload::SkipRecording).with_breaker(1, Duration::from_secs(3600)),
+            );
+        failing.store(true, Ordering::Relaxed);
+        locat.ip_to_iso_code(addr).await;
+        failing.store(false, Ordering::Relaxed);
+        // bulk lookups' counters are skipped too
+        locat
+            .ip_to_iso_codes(&["10.0.0.1".parse().unwrap(), addr])
+            .await;
+        assert_eq!(locat.get_bogon_count().await.unwrap(), 0);
+        assert!(locat.get_analytics().await.unwrap().is_empty());
+        let text = locat.analytics_to_prometheus().await.unwrap();
+        assert!(text
+            .lines()
+            .any(|line| line == "locat_admission_actions_total{action=\"skip_recording\"} 2"));
+    }
+}
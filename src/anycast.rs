@@ -0,0 +1,71 @@
+use std::{net::IpAddr, sync::OnceLock};
+
+use ipnetwork::IpNetwork;
+
+// well-known anycast networks: the same addresses are announced from
+// dozens of locations, so whatever country the database says is at best
+// one of many and at worst meaningless.
+const RANGES: &[(&str, &str)] = &[
+    // public DNS resolvers
+    ("8.8.8.0/24", "Google Public DNS"),
+    ("8.8.4.0/24", "Google Public DNS"),
+    ("2001:4860:4860::/48", "Google Public DNS"),
+    ("1.1.1.0/24", "Cloudflare DNS"),
+    ("1.0.0.0/24", "Cloudflare DNS"),
+    ("2606:4700:4700::/48", "Cloudflare DNS"),
+    ("9.9.9.0/24", "Quad9"),
+    ("149.112.112.0/24", "Quad9"),
+    ("2620:fe::/48", "Quad9"),
+    ("208.67.216.0/21", "OpenDNS"),
+    ("2620:119::/32", "OpenDNS"),
+    // CDNs
+    ("104.16.0.0/13", "Cloudflare"),
+    ("172.64.0.0/13", "Cloudflare"),
+    ("162.158.0.0/15", "Cloudflare"),
+    ("2606:4700::/32", "Cloudflare"),
+    ("151.101.0.0/16", "Fastly"),
+    ("2a04:4e42::/32", "Fastly"),
+];
+
+fn networks() -> &'static [(IpNetwork, &'static str)] {
+    static NETWORKS: OnceLock<Vec<(IpNetwork, &'static str)>> = OnceLock::new();
+    NETWORKS.get_or_init(|| {
+        RANGES
+            .iter()
+            .map(|(network, operator)| (network.parse().unwrap(), *operator))
+            .collect()
+    })
+}
+
+/// Returns who announces `addr`, if it belongs to a well-known anycast
+/// network
+pub fn anycast_operator(addr: IpAddr) -> Option<&'static str> {
+    networks()
+        .iter()
+        .filter(|(network, _)| network.contains(addr))
+        .max_by_key(|(network, _)| network.prefix())
+        .map(|(_, operator)| *operator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::anycast_operator;
+
+    #[test]
+    fn test_anycast_operator() {
+        assert_eq!(
+            anycast_operator("8.8.8.8".parse().unwrap()),
+            Some("Google Public DNS")
+        );
+        // the resolver range is more specific than the CDN one
+        assert_eq!(
+            anycast_operator("2606:4700:4700::1111".parse().unwrap()),
+            Some("Cloudflare DNS")
+        );
+        assert_eq!(
+            anycast_operator("2606:4700:10::1".parse().unwrap()),
+            Some("Cloudflare")
+        );
+        assert_eq!(anycast_operator("81.2.69.160".parse().unwrap()), None);
+    }
+}
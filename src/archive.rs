@@ -0,0 +1,112 @@
+use std::{
+    fs::OpenOptions,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use flate2::{write::GzEncoder, Compression};
+
+use crate::{rollover::PathTemplate, Locat};
+
+/// Appends `rows` as JSON lines to the day's file in `dir`, one gzip member
+/// per call: concatenated members read back as one stream. Gzip rather
+/// than zstd, since `flate2` is already a dependency and `zstd` can't be
+/// fetched for this build; zstd frames concatenate the same way, so it can
+/// be swapped in later with a new file extension.
+pub(crate) fn append(dir: &Path, rows: &[serde_json::Value]) -> io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let name = PathTemplate::new("pruned-%Y-%m-%d.jsonl.gz")
+        .render(SystemTime::now())
+        .into_owned();
+    let mut out = GzEncoder::new(Vec::new(), Compression::default());
+    for row in rows {
+        serde_json::to_writer(&mut out, row)?;
+        out.write_all(b"\n")?;
+    }
+    let bytes = out.finish()?;
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(dir.join(name))?;
+    file.write_all(&bytes)?;
+    file.sync_all()
+}
+
+impl Locat {
+    /// Keeps what pruning deletes (see [`Locat::with_retention`]) in
+    /// `dir` rather than losing it: gzipped JSON lines, a file a day, with
+    /// a `table` field telling time buckets and subnets apart. Rows are only
+    /// deleted once written.
+    pub fn with_archive(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.inner_mut().archive = Some(dir.into());
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        time::Duration,
+    };
+
+    use flate2::read::MultiGzDecoder;
+
+    use crate::{
+        testing::{RemoveOnDrop, TestDb},
+        Locat,
+    };
+
+    #[tokio::test]
+    async fn test_archive() {
+        let geoip_path = "/tmp/loca-test-archive.mmdb";
+        let analytics_path = "/tmp/loca-test-archive.db";
+        let dir = "/tmp/loca-test-archive";
+        TestDb::new()
+            .country("1.2.3.0/24", "US", "NA")
+            .write(geoip_path);
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
+        _ = std::fs::remove_dir_all(dir);
+
+        let locat = Locat::new(geoip_path, analytics_path)
+            .await
+            .unwrap()
+            .with_time_buckets(Duration::from_secs(1))
+            .with_archive(dir);
+        locat.ip_to_iso_code("1.2.3.4".parse().unwrap()).await;
+        locat.ip_to_iso_code("1.2.3.5".parse().unwrap()).await;
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        assert_eq!(
+            locat
+                .prune_analytics_older_than(Duration::ZERO)
+                .await
+                .unwrap(),
+            1
+        );
+        // nothing left, nothing written
+        assert_eq!(
+            locat
+                .prune_analytics_older_than(Duration::ZERO)
+                .await
+                .unwrap(),
+            0
+        );
+
+        let files: Vec<_> = std::fs::read_dir(dir).unwrap().collect();
+        assert_eq!(files.len(), 1);
+        let file = std::fs::File::open(files[0].as_ref().unwrap().path()).unwrap();
+        let rows: Vec<serde_json::Value> = BufReader::new(MultiGzDecoder::new(file))
+            .lines()
+            .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+            .collect();
+        assert_eq!(rows.len(), 1);
+        assert_eq!(rows[0]["table"], "buckets");
+        assert_eq!(rows[0]["iso_code"], "US");
+        assert_eq!(rows[0]["count"], 2);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,138 @@
+use std::time::SystemTime;
+
+use crate::{Error, Field, Locat};
+
+/// An administrative action, see [`Locat::audit_log`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AuditEntry {
+    /// When it happened, to the second
+    pub at: SystemTime,
+    /// What happened, e.g. `clear_analytics`
+    pub action: String,
+    /// Who did it, see [`Locat::with_audit_actor`]
+    pub actor: Option<String>,
+    pub detail: Option<String>,
+}
+
+impl Locat {
+    /// Labels the administrative actions taken through this `Locat` in the
+    /// audit log, e.g. with a service or operator name
+    pub fn with_audit_actor(mut self, actor: impl Into<String>) -> Self {
+        self.inner_mut().actor = Some(actor.into());
+        self
+    }
+
+    /// Every administrative action recorded in the analytics file, oldest
+    /// first: clearing analytics, imports, GeoIP reloads and whatever else
+    /// was passed to [`Locat::audit`]. Entries can't be changed or removed.
+    ///
+    /// Files rolled over by date each have their own log.
+    pub async fn audit_log(&self) -> Result<Vec<AuditEntry>, Error> {
+        Ok(self.sqlite("audit_log")?.list_audit().await?)
+    }
+
+    /// Records an action taken outside of `Locat`, e.g. deploying a new
+    /// override bundle. Does nothing with stores other than SQLite.
+    pub async fn audit(&self, action: &str, detail: Option<String>) -> Result<(), Error> {
+        let Some(db) = &self.inner.sqlite else {
+            return Ok(());
+        };
+        Ok(db.append_audit(action, self.actor(), detail).await?)
+    }
+
+    /// Who actions are attributed to, scrubbed
+    pub(crate) fn actor(&self) -> Option<String> {
+        let mut actor = self.inner.actor.clone();
+        self.scrub(Field::Actor(&mut actor));
+        actor
+    }
+
+    /// Removes every count and weight from the analytics, SQLite only.
+    /// Counts still waiting to be written are dropped too. See
+    /// [`Locat::reset_analytics`] for other stores.
+    pub async fn clear_analytics(&self) -> Result<(), Error> {
+        self.sqlite("clear_analytics")?;
+        self.reset_as("clear_analytics").await
+    }
+
+    /// Corrects a country's count, e.g. to take out a bot flood once it has
+    /// been identified. The count doesn't go below zero; weights and time
+    /// buckets are left alone. SQLite only.
+    ///
+    /// Returns the new count.
+    pub async fn adjust(&self, iso_code: &str, delta: i64) -> Result<u64, Error> {
+        let (before, after) = self.sqlite("adjust")?.adjust(iso_code, delta).await?;
+        self.audit(
+            "adjust",
+            Some(format!("{iso_code} {delta:+}: {before} -> {after}")),
+        )
+        .await?;
+        Ok(after)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        testing::{RemoveOnDrop, TestDb},
+        Locat,
+    };
+
+    #[tokio::test]
+    async fn test_audit_log() {
+        let geoip_path = "/tmp/loca-test-audit.mmdb";
+        let analytics_path = "/tmp/loca-test-audit.db";
+        TestDb::new()
+            .country("1.2.3.0/24", "US", "NA")
+            .write(geoip_path);
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
+
+        let locat = Locat::new(geoip_path, analytics_path)
+            .await
+            .unwrap()
+            .with_audit_actor("ops");
+        locat.ip_to_iso_code("1.2.3.4".parse().unwrap()).await;
+        let export = locat.export_portable().await.unwrap();
+        locat.clear_analytics().await.unwrap();
+        assert!(locat.get_analytics().await.unwrap().is_empty());
+        locat.import_portable(&export).await.unwrap();
+        locat.reload_geoip().await.unwrap();
+        locat
+            .audit("deploy_overrides", Some("v2".to_owned()))
+            .await
+            .unwrap();
+        assert_eq!(locat.adjust("US", 4).await.unwrap(), 5);
+        // bounded at zero
+        assert_eq!(locat.adjust("US", -10).await.unwrap(), 0);
+
+        let log = locat.audit_log().await.unwrap();
+        let actions: Vec<_> = log.iter().map(|entry| entry.action.as_str()).collect();
+        assert_eq!(
+            actions,
+            [
+                "clear_analytics",
+                "import_portable",
+                "reload_geoip",
+                "deploy_overrides",
+                "adjust",
+                "adjust"
+            ]
+        );
+        assert!(log
+            .iter()
+            .all(|entry| entry.actor.as_deref() == Some("ops")));
+        assert_eq!(log[3].detail.as_deref(), Some("v2"));
+        assert_eq!(log[5].detail.as_deref(), Some("US -10: 5 -> 0"));
+
+        // the log can only grow
+        let conn = rusqlite::Connection::open(analytics_path).unwrap();
+        assert!(conn.execute("DELETE FROM audit", []).is_err());
+        assert!(conn
+            .execute("UPDATE audit SET actor = 'someone else'", [])
+            .is_err());
+    }
+}
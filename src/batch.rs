@@ -0,0 +1,621 @@
+use std::{
+    collections::{BTreeMap, HashSet},
+    mem,
+    net::IpAddr,
+    sync::{Arc, Mutex, OnceLock},
+    time::Duration,
+};
+
+use tokio::sync::Notify;
+
+use crate::{
+    db::{self, Db},
+    exemplars::NewExemplar,
+    scrub,
+    sketch::Sketch,
+    trace::{self, Instrument},
+    unwrap_tunneled, AnalyticsStore, Error, Locat, Measures, Scrubber,
+};
+
+/// What [`Locat::ip_to_iso_codes`] would count, see
+/// [`Locat::ip_to_iso_codes_dry_run`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DryRun {
+    /// Lookups that would be counted, by country
+    pub counts: Vec<(String, u64)>,
+    /// `bogon` and `not_found`, see [`crate::LookupStats`]
+    pub counters: Vec<(String, u64)>,
+    /// Lookups that wouldn't be counted at all: deduplicated, or not
+    /// recorded
+    pub skipped: u64,
+}
+
+/// Counts waiting to be written, see [`Locat::with_write_behind`]
+pub(crate) struct WriteBehind {
+    interval: Duration,
+    max_pending: usize,
+    pending: Mutex<Pending>,
+    // wakes the flusher up early when `max_pending` is reached
+    full: Arc<Notify>,
+    flusher: OnceLock<()>,
+    analytics: Arc<dyn AnalyticsStore>,
+    sqlite: Option<Arc<Db>>,
+    // for the final flush, which happens without a `Locat`
+    pub(crate) scrubber: Option<Arc<dyn Scrubber>>,
+}
+
+/// Counts added up in memory, to be written at once
+#[derive(Default, Clone)]
+pub(crate) struct Pending {
+    pub(crate) entries: usize,
+    pub(crate) analytics: BTreeMap<String, u64>,
+    pub(crate) buckets: BTreeMap<(String, u64), u64>,
+    // by day the lookups were made on, see `Db::write_pending`
+    pub(crate) days: BTreeMap<(String, u64), u64>,
+    pub(crate) subnets: BTreeMap<(String, String), u64>,
+    pub(crate) counters: BTreeMap<String, u64>,
+    // in microseconds, SQLite only
+    pub(crate) latencies: BTreeMap<String, Sketch>,
+    // by status class, SQLite only
+    pub(crate) statuses: BTreeMap<(String, &'static str), u64>,
+    // SQLite only, see `Locat::with_exemplars`
+    pub(crate) exemplars: Vec<NewExemplar>,
+}
+
+impl Pending {
+    /// Counts a lookup, and a time bucket and subnet if given
+    pub(crate) fn add_lookup(
+        &mut self,
+        iso_code: &str,
+        bucket_start: Option<u64>,
+        subnet: Option<String>,
+        exemplar: Option<NewExemplar>,
+    ) {
+        self.entries += 1;
+        *self.analytics.entry(iso_code.to_owned()).or_default() += 1;
+        *self
+            .days
+            .entry((iso_code.to_owned(), db::today()))
+            .or_default() += 1;
+        if let Some(bucket_start) = bucket_start {
+            *self
+                .buckets
+                .entry((iso_code.to_owned(), bucket_start))
+                .or_default() += 1;
+        }
+        if let Some(subnet) = subnet {
+            *self
+                .subnets
+                .entry((subnet, iso_code.to_owned()))
+                .or_default() += 1;
+        }
+        self.exemplars.extend(exemplar);
+    }
+
+    pub(crate) fn add_counter(&mut self, name: &str) {
+        self.entries += 1;
+        *self.counters.entry(name.to_owned()).or_default() += 1;
+    }
+
+    /// Keeps a response time, see [`Locat::record_response`]
+    #[cfg(feature = "middleware")]
+    pub(crate) fn add_latency(&mut self, iso_code: &str, latency: Duration) {
+        self.entries += 1;
+        let micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        self.latencies
+            .entry(iso_code.to_owned())
+            .or_default()
+            .insert(micros);
+    }
+
+    /// Counts a response by status class, see [`Locat::record_response`]
+    #[cfg(feature = "middleware")]
+    pub(crate) fn add_status(&mut self, iso_code: &str, class: &'static str) {
+        self.entries += 1;
+        *self
+            .statuses
+            .entry((iso_code.to_owned(), class))
+            .or_default() += 1;
+    }
+
+    fn merge(&mut self, other: Pending) {
+        self.entries += other.entries;
+        for (iso_code, count) in other.analytics {
+            *self.analytics.entry(iso_code).or_default() += count;
+        }
+        for (bucket, count) in other.buckets {
+            *self.buckets.entry(bucket).or_default() += count;
+        }
+        for (day, count) in other.days {
+            *self.days.entry(day).or_default() += count;
+        }
+        for (subnet, count) in other.subnets {
+            *self.subnets.entry(subnet).or_default() += count;
+        }
+        for (name, count) in other.counters {
+            *self.counters.entry(name).or_default() += count;
+        }
+        for (iso_code, sketch) in other.latencies {
+            self.latencies.entry(iso_code).or_default().merge(&sketch);
+        }
+        for (status, count) in other.statuses {
+            *self.statuses.entry(status).or_default() += count;
+        }
+        self.exemplars.extend(other.exemplars);
+    }
+}
+
+impl WriteBehind {
+    pub(crate) fn new(
+        interval: Duration,
+        max_pending: usize,
+        analytics: Arc<dyn AnalyticsStore>,
+        sqlite: Option<Arc<Db>>,
+    ) -> Self {
+        Self {
+            interval,
+            max_pending,
+            pending: Default::default(),
+            full: Default::default(),
+            flusher: OnceLock::new(),
+            analytics,
+            sqlite,
+            scrubber: None,
+        }
+    }
+
+    pub(crate) fn add_lookup(
+        &self,
+        iso_code: &str,
+        bucket_start: Option<u64>,
+        subnet: Option<String>,
+        exemplar: Option<NewExemplar>,
+    ) {
+        let mut pending = self.pending.lock().unwrap();
+        pending.add_lookup(iso_code, bucket_start, subnet, exemplar);
+        self.added(&pending);
+    }
+
+    pub(crate) fn add_counter(&self, name: &str) {
+        let mut pending = self.pending.lock().unwrap();
+        pending.add_counter(name);
+        self.added(&pending);
+    }
+
+    /// Adds counts made elsewhere, see [`Locat::ip_to_iso_codes`]
+    pub(crate) fn add_pending(&self, other: Pending) {
+        let mut pending = self.pending.lock().unwrap();
+        pending.merge(other);
+        self.added(&pending);
+    }
+
+    fn added(&self, pending: &Pending) {
+        if pending.entries >= self.max_pending {
+            self.full.notify_one();
+        }
+    }
+
+    /// How many counts are waiting, see [`crate::Admission::with_max_backlog`]
+    pub(crate) fn backlog(&self) -> usize {
+        self.pending.lock().unwrap().entries
+    }
+
+    /// Forgets everything pending, see [`Locat::clear_analytics`]
+    pub(crate) fn discard(&self) {
+        *self.pending.lock().unwrap() = Pending::default();
+    }
+
+    /// Writes everything pending. What couldn't be written stays pending.
+    pub(crate) async fn flush(&self) -> Result<(), Error> {
+        let pending = mem::take(&mut *self.pending.lock().unwrap());
+        if pending.entries == 0 {
+            return Ok(());
+        }
+        if let Err((e, unwritten)) = write(&*self.analytics, self.sqlite.as_deref(), pending).await
+        {
+            self.pending.lock().unwrap().merge(unwritten);
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+// best effort: there is no waiting for the write to finish in `drop`, so
+// it only happens if the runtime is still around, see `Locat::flush`
+impl Drop for WriteBehind {
+    fn drop(&mut self) {
+        let pending = mem::take(self.pending.get_mut().unwrap());
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            return;
+        };
+        if pending.entries == 0 {
+            return;
+        }
+        let (analytics, sqlite) = (self.analytics.clone(), self.sqlite.clone());
+        let scrubber = self.scrubber.clone();
+        runtime.spawn(async move {
+            if let Err((e, _)) = write(&*analytics, sqlite.as_deref(), pending).await {
+                scrub::log(
+                    scrubber.as_deref(),
+                    format!("Could not flush analytics: {e}"),
+                );
+            }
+        });
+    }
+}
+
+async fn write(
+    analytics: &dyn AnalyticsStore,
+    sqlite: Option<&Db>,
+    pending: Pending,
+) -> Result<(), (Error, Pending)> {
+    // everything only exists there, written in a single transaction
+    if let Some(db) = sqlite {
+        return db
+            .write_pending(pending.clone())
+            .await
+            .map_err(|e| (e.into(), pending));
+    }
+    let measures: Vec<_> = pending
+        .analytics
+        .iter()
+        .map(|(iso_code, count)| {
+            (
+                iso_code.clone(),
+                Measures {
+                    count: *count,
+                    sum: 0,
+                },
+            )
+        })
+        .collect();
+    let counters: Vec<_> = pending
+        .counters
+        .iter()
+        .map(|(name, count)| (name.clone(), *count))
+        .collect();
+    analytics
+        .add(&measures, &counters)
+        .await
+        .map_err(|e| (e, pending))
+}
+
+impl Locat {
+    /// Counts lookups in memory and writes them in batches, every
+    /// `interval` or as soon as `max_pending` lookups are waiting, whichever
+    /// comes first. Lookups then never wait on the analytics store, at the
+    /// cost of analytics lagging behind by up to `interval`.
+    ///
+    /// Call [`Locat::flush`] before shutting down: pending counts are only
+    /// written on drop if the Tokio runtime is still running.
+    pub fn with_write_behind(mut self, interval: Duration, max_pending: usize) -> Self {
+        let inner = self.inner_mut();
+        let mut write_behind = WriteBehind::new(
+            interval,
+            max_pending,
+            inner.analytics.clone(),
+            inner.sqlite.clone(),
+        );
+        write_behind.scrubber = inner.scrubber.clone();
+        inner.write_behind = Some(write_behind);
+        self
+    }
+
+    /// Writes the counts waiting with [`Locat::with_write_behind`], if any
+    pub async fn flush(&self) -> Result<(), Error> {
+        self.check_epoch().await?;
+        self.prune_subnets_every_minute().await;
+        self.prune_when_due().await;
+        let Some(write_behind) = &self.inner.write_behind else {
+            return Ok(());
+        };
+        // nothing written says nothing about the store
+        let writing = write_behind.backlog() > 0;
+        let result = write_behind
+            .flush()
+            .instrument(trace::span!(
+                DEBUG,
+                "locat.analytics.flush",
+                pending = write_behind.backlog()
+            ))
+            .await;
+        if writing {
+            self.wrote(result.is_ok());
+        }
+        result
+    }
+
+    /// What [`Locat::ip_to_iso_codes`] would count for `addrs`, without
+    /// counting anything or marking visitors as seen: for trying a dedup
+    /// window or [`crate::LocatBuilder::with_miss_recording`] out on a
+    /// sample of production traffic. Admission control isn't applied, as
+    /// it depends on the load at the time.
+    pub fn ip_to_iso_codes_dry_run(&self, addrs: &[IpAddr]) -> DryRun {
+        let geoip = self.inner.geoip.load_full();
+        let mut seen = HashSet::new();
+        let mut counts: BTreeMap<String, u64> = BTreeMap::new();
+        let mut counters: BTreeMap<String, u64> = BTreeMap::new();
+        let mut skipped = 0;
+        for &addr in addrs {
+            let addr = unwrap_tunneled(addr).map_or(addr, |(v4, _)| v4.into());
+            let counter = match self.lookup_cached(&geoip, addr) {
+                Ok(iso_code) => {
+                    let counted = self.inner.recording
+                        && match &self.inner.dedup {
+                            Some(dedup) => dedup.would_count(addr, iso_code, &mut seen),
+                            None => true,
+                        };
+                    if counted {
+                        *counts.entry(iso_code.to_owned()).or_default() += 1;
+                    } else {
+                        skipped += 1;
+                    }
+                    continue;
+                }
+                Err(_) if !self.inner.recording => None,
+                Err(_) if self.is_bogon(addr) => Some("bogon"),
+                Err(_) if self.inner.record_misses => Some("not_found"),
+                Err(_) => None,
+            };
+            match counter {
+                Some(counter) => *counters.entry(counter.to_owned()).or_default() += 1,
+                None => skipped += 1,
+            }
+        }
+        DryRun {
+            counts: counts.into_iter().collect(),
+            counters: counters.into_iter().collect(),
+            skipped,
+        }
+    }
+
+    /// Counts a lookup made by [`Locat::ip_to_iso_codes`] into `pending`
+    pub(crate) fn tally(
+        &self,
+        pending: &mut Pending,
+        addr: IpAddr,
+        iso_code: Option<&str>,
+        bogon: bool,
+    ) {
+        let counter = match iso_code {
+            Some(iso_code) => {
+                if self.should_count(addr, iso_code) && self.admit() {
+                    #[cfg(feature = "otlp")]
+                    self.emit("located", Some(iso_code));
+                    #[cfg(feature = "webhook")]
+                    self.start_reports();
+                    pending.add_lookup(
+                        iso_code,
+                        self.bucket_start(),
+                        self.subnet_key(addr),
+                        self.exemplar(addr, iso_code),
+                    );
+                }
+                return;
+            }
+            None if !self.inner.recording => return,
+            None if bogon => "bogon",
+            None if self.inner.record_misses => "not_found",
+            None => return,
+        };
+        // held back under load just like single lookups' counters
+        if !self.admit() {
+            return;
+        }
+        #[cfg(feature = "otlp")]
+        self.emit(counter, None);
+        pending.add_counter(counter);
+    }
+
+    /// Writes counts made with [`Locat::tally`], or hands them over to the
+    /// write-behind buffer if there is one
+    pub(crate) async fn record_pending(&self, pending: Pending) {
+        if pending.entries == 0 {
+            return;
+        }
+        if let Some(write_behind) = &self.inner.write_behind {
+            write_behind.add_pending(pending);
+            self.start_flusher(write_behind);
+            return;
+        }
+        let sqlite = self.inner.sqlite.as_deref();
+        let result = write(&*self.inner.analytics, sqlite, pending)
+            .instrument(trace::span!(DEBUG, "locat.analytics.write"))
+            .await;
+        self.wrote(result.is_ok());
+        if let Err((e, _)) = result {
+            self.write_failed(None, &e);
+        }
+    }
+
+    /// Keeps what [`crate::middleware::GeoLayer`] saw of a response to a
+    /// client located in a country, its time and HTTP status, the way
+    /// lookups are counted: not while recording is off, held back under
+    /// load, and batched with [`Locat::with_write_behind`]. Never waits for
+    /// the write: without write-behind, it happens in a task of its own.
+    /// SQLite only, ignored with other stores.
+    #[cfg(feature = "middleware")]
+    pub(crate) fn record_response(
+        &self,
+        iso_code: &str,
+        latency: Option<Duration>,
+        status: Option<u16>,
+    ) {
+        if !self.inner.recording || self.inner.sqlite.is_none() {
+            return;
+        }
+        let mut pending = Pending::default();
+        if let Some(latency) = latency {
+            pending.add_latency(iso_code, latency);
+        }
+        if let Some(class) = status.and_then(crate::status::class) {
+            pending.add_status(iso_code, class);
+        }
+        if pending.entries == 0 || !self.admit() {
+            return;
+        }
+        if let Some(write_behind) = &self.inner.write_behind {
+            write_behind.add_pending(pending);
+            self.start_flusher(write_behind);
+            return;
+        }
+        let locat = self.clone();
+        tokio::spawn(async move { locat.record_pending(pending).await });
+    }
+
+    /// Starts the task writing pending counts in the background, on first
+    /// use so that it runs within the caller's runtime
+    pub(crate) fn start_flusher(&self, write_behind: &WriteBehind) {
+        write_behind.flusher.get_or_init(|| {
+            let inner = Arc::downgrade(&self.inner);
+            let (interval, full) = (write_behind.interval, write_behind.full.clone());
+            self.spawn("flusher", |mut stop| async move {
+                let mut ticks = tokio::time::interval(interval);
+                loop {
+                    tokio::select! {
+                        _ = ticks.tick() => {}
+                        _ = full.notified() => {}
+                        _ = stop.requested() => return,
+                    }
+                    // once every handle is gone, drop takes care of the rest
+                    let Some(inner) = inner.upgrade() else {
+                        return;
+                    };
+                    let locat = Locat { inner };
+                    if let Err(e) = locat.flush().await {
+                        locat.log(format!("Could not flush analytics: {e}"));
+                    }
+                }
+            });
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::DryRun;
+    use crate::{
+        testing::{RemoveOnDrop, TestDb},
+        Locat,
+    };
+
+    #[tokio::test]
+    async fn test_write_behind() {
+        let geoip_path = "/tmp/loca-test-write-behind.mmdb";
+        let analytics_path = "/tmp/loca-test-write-behind.db";
+        TestDb::new()
+            .country("1.2.3.0/24", "US", "NA")
+            .write(geoip_path);
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
+
+        let locat = Locat::new(geoip_path, analytics_path)
+            .await
+            .unwrap()
+            .with_time_buckets(Duration::from_secs(3600))
+            .with_write_behind(Duration::from_secs(3600), 3);
+        let addr = "1.2.3.4".parse().unwrap();
+        locat.ip_to_iso_code(addr).await;
+        locat.lookup("10.0.0.1".parse().unwrap()).await;
+        assert!(locat.get_analytics().await.unwrap().is_empty());
+
+        locat.flush().await.unwrap();
+        assert_eq!(
+            locat.get_analytics().await.unwrap(),
+            vec![("US".to_string(), 1)]
+        );
+        assert_eq!(locat.get_bogon_count().await.unwrap(), 1);
+        let now = std::time::SystemTime::now();
+        assert_eq!(
+            locat
+                .get_analytics_between(now - Duration::from_secs(3600)..now)
+                .await
+                .unwrap(),
+            vec![("US".to_string(), 1)]
+        );
+
+        // a full batch is written without waiting for the interval
+        for _ in 0..3 {
+            locat.ip_to_iso_code(addr).await;
+        }
+        for _ in 0..100 {
+            if locat.get_analytics().await.unwrap() == vec![("US".to_string(), 4)] {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(
+            locat.get_analytics().await.unwrap(),
+            vec![("US".to_string(), 4)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ip_to_iso_codes() {
+        let geoip_path = "/tmp/loca-test-bulk.mmdb";
+        let analytics_path = "/tmp/loca-test-bulk.db";
+        TestDb::new()
+            .country("1.2.3.0/24", "US", "NA")
+            .country("5.6.7.0/24", "DE", "EU")
+            .write(geoip_path);
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
+
+        let locat = Locat::new(geoip_path, analytics_path)
+            .await
+            .unwrap()
+            .with_time_buckets(Duration::from_secs(3600));
+        let addrs = ["1.2.3.4", "5.6.7.8", "10.0.0.1", "8.8.8.8", "1.2.3.5"]
+            .map(|addr| addr.parse().unwrap());
+        assert_eq!(
+            locat.ip_to_iso_codes(&addrs).await,
+            [Some("US"), Some("DE"), None, None, Some("US")]
+        );
+        assert_eq!(
+            locat.get_analytics().await.unwrap(),
+            vec![("DE".to_string(), 1), ("US".to_string(), 2)]
+        );
+        assert_eq!(locat.get_bogon_count().await.unwrap(), 1);
+        let now = std::time::SystemTime::now();
+        assert_eq!(
+            locat
+                .get_analytics_between(now - Duration::from_secs(3600)..now)
+                .await
+                .unwrap(),
+            vec![("DE".to_string(), 1), ("US".to_string(), 2)]
+        );
+
+        // nothing changes, visitors included
+        let locat = Locat::builder()
+            .with_geoip_path(geoip_path)
+            .with_miss_recording(false)
+            .build()
+            .await
+            .unwrap()
+            .with_dedup_window(Duration::from_secs(3600));
+        assert_eq!(
+            locat.ip_to_iso_codes_dry_run(&addrs),
+            DryRun {
+                counts: vec![("DE".to_string(), 1), ("US".to_string(), 2)],
+                counters: vec![("bogon".to_string(), 1)],
+                skipped: 1,
+            }
+        );
+        let repeated = ["1.2.3.4".parse().unwrap(); 2];
+        let dry_run = locat.ip_to_iso_codes_dry_run(&repeated);
+        assert_eq!(dry_run.counts, vec![("US".to_string(), 1)]);
+        assert_eq!(dry_run.skipped, 1);
+        assert!(locat.get_analytics().await.unwrap().is_empty());
+        locat.ip_to_iso_codes(&repeated).await;
+        assert_eq!(
+            locat.get_analytics().await.unwrap(),
+            vec![("US".to_string(), 1)]
+        );
+    }
+}
@@ -0,0 +1,534 @@
+//! Lookups and analytics from the command line:
+//!
+//! ```text
+//! locat lookup 8.8.8.8 --db GeoLite2-Country.mmdb
+//! locat bulk --input ips.txt --format json --db GeoLite2-Country.mmdb
+//! locat bulk --input ips.txt --dry-run --dedup-window 30min --db GeoLite2-Country.mmdb
+//! locat analytics --db analytics.db --top 10
+//! locat verify --db GeoLite2-Country.mmdb
+//! ```
+//!
+//! Nothing is counted: lookups are untracked, and analytics are opened
+//! read-only. `bulk --dry-run` prints what counting them would add up to
+//! instead, see `Locat::ip_to_iso_codes_dry_run`.
+//!
+//! With `--log-format json`, every lookup is logged to stderr as a JSON
+//! line, along with its request ID, outcome, country and duration, e.g.
+//!
+//! ```text
+//! {"iso_code":"US","level":"INFO","message":"lookup","micros":4,"outcome":"located","request_id":"3f2a9c1e-1","span":"lookup","target":"locat","timestamp":"2026-10-15T09:00:00.000012Z"}
+//! ```
+//!
+//! Addresses are left out. Warnings from the library, and errors, are
+//! logged the same way.
+
+use std::{
+    collections::HashMap,
+    fmt,
+    io::{BufRead, Write},
+    net::IpAddr,
+    process::ExitCode,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+    time::{Instant, SystemTime, UNIX_EPOCH},
+};
+
+use clap::{value_parser, Arg, ArgAction, ArgMatches, Command};
+use locat::{Locat, Outcome};
+use rusqlite::{Connection, OpenFlags};
+use serde_json::{Map, Value};
+use tracing::{
+    field::{Field, Visit},
+    span::{Attributes, Id, Record},
+    Event, Level, Metadata, Subscriber,
+};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+fn cli() -> Command {
+    let db = || {
+        Arg::new("db")
+            .long("db")
+            .required(true)
+            .help("GeoIP database (.mmdb)")
+    };
+    Command::new("locat")
+        .about("Geolocates IP addresses and inspects analytics")
+        .subcommand_required(true)
+        .arg(
+            Arg::new("log-format")
+                .long("log-format")
+                .global(true)
+                .default_value("text")
+                .value_parser(["text", "json"])
+                .help("How to log to stderr: json logs every lookup too"),
+        )
+        .subcommand(
+            Command::new("lookup")
+                .about("Prints the country of each address")
+                .arg(
+                    Arg::new("addr")
+                        .required(true)
+                        .num_args(1..)
+                        .value_parser(value_parser!(IpAddr)),
+                )
+                .arg(db()),
+        )
+        .subcommand(
+            Command::new("bulk")
+                .about("Geolocates a file of addresses, one per line")
+                .arg(
+                    Arg::new("input")
+                        .long("input")
+                        .default_value("-")
+                        .help("File to read, - for stdin"),
+                )
+                .arg(
+                    Arg::new("format")
+                        .long("format")
+                        .default_value("text")
+                        .value_parser(["text", "csv", "json"]),
+                )
+                .arg(
+                    Arg::new("dry-run")
+                        .long("dry-run")
+                        .action(ArgAction::SetTrue)
+                        .help("Prints what would be counted, by country, instead"),
+                )
+                .arg(
+                    Arg::new("dedup-window")
+                        .long("dedup-window")
+                        .requires("dry-run")
+                        .help("Counts each visitor once per window, e.g. 30min"),
+                )
+                .arg(db()),
+        )
+        .subcommand(
+            Command::new("analytics")
+                .about("Prints the most looked-up countries")
+                .arg(
+                    Arg::new("db")
+                        .long("db")
+                        .required(true)
+                        .help("Analytics database (SQLite)"),
+                )
+                .arg(
+                    Arg::new("top")
+                        .long("top")
+                        .default_value("10")
+                        .value_parser(value_parser!(u32)),
+                ),
+        )
+        .subcommand(
+            Command::new("verify")
+                .about("Smoke-tests a GeoIP database before deploying it")
+                .arg(db()),
+        )
+}
+
+fn main() -> ExitCode {
+    let matches = cli().get_matches();
+    let json = matches.get_one::<String>("log-format").unwrap() == "json";
+    if json {
+        tracing::subscriber::set_global_default(JsonLogs::new(std::io::stderr()))
+            .expect("no other subscriber");
+    }
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .expect("a runtime");
+    let result = runtime.block_on(async {
+        match matches.subcommand() {
+            Some(("lookup", args)) => lookup(args).await,
+            Some(("bulk", args)) => bulk(args).await,
+            Some(("analytics", args)) => analytics(args),
+            Some(("verify", args)) => verify(args).await,
+            _ => unreachable!("a subcommand is required"),
+        }
+    });
+    match result {
+        Ok(code) => code,
+        Err(e) if json => {
+            tracing::error!(error = %e, "locat failed");
+            ExitCode::FAILURE
+        }
+        Err(e) => {
+            eprintln!("locat: {e}");
+            ExitCode::FAILURE
+        }
+    }
+}
+
+async fn open(args: &ArgMatches) -> Result<Locat> {
+    let path = args.get_one::<String>("db").unwrap();
+    Ok(Locat::builder()
+        .with_geoip_path(path)
+        .with_recording(false)
+        .build()
+        .await?)
+}
+
+async fn lookup(args: &ArgMatches) -> Result<ExitCode> {
+    let locat = open(args).await?;
+    let mut request_ids = request_ids();
+    for addr in args.get_many::<IpAddr>("addr").unwrap() {
+        let outcome = traced(
+            request_ids(),
+            || locat.lookup_untracked(*addr),
+            |outcome| match outcome {
+                Outcome::Located(resolution) => ("located", Some(&resolution.iso_code)),
+                Outcome::Bogon => ("bogon", None),
+                _ => ("not_found", None),
+            },
+        );
+        let answer = match outcome {
+            Outcome::Located(resolution) => resolution.iso_code,
+            Outcome::Bogon => "bogon".to_owned(),
+            _ => "not found".to_owned(),
+        };
+        println!("{addr}\t{answer}");
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn bulk(args: &ArgMatches) -> Result<ExitCode> {
+    let input: Box<dyn BufRead> = match args.get_one::<String>("input").unwrap().as_str() {
+        "-" => Box::new(std::io::stdin().lock()),
+        path => Box::new(std::io::BufReader::new(std::fs::File::open(path)?)),
+    };
+    if args.get_flag("dry-run") {
+        return dry_run(args, input).await;
+    }
+    let locat = open(args).await?;
+    let format = args.get_one::<String>("format").unwrap();
+    let mut out = std::io::BufWriter::new(std::io::stdout().lock());
+    let mut rows = Vec::new();
+    let mut request_ids = request_ids();
+    if format == "csv" {
+        writeln!(out, "addr,iso_code")?;
+    }
+    for line in input.lines() {
+        let Some(addr) = parse_line(&line?)? else {
+            continue;
+        };
+        let iso_code = traced(
+            request_ids(),
+            || locat.ip_to_iso_code_untracked(addr),
+            |iso_code| match iso_code {
+                Some(iso_code) => ("located", Some(iso_code)),
+                None => ("not_found", None),
+            },
+        );
+        match format.as_str() {
+            "csv" => writeln!(out, "{addr},{}", iso_code.unwrap_or(""))?,
+            "json" => rows.push(serde_json::json!({ "addr": addr, "iso_code": iso_code })),
+            _ => writeln!(out, "{addr}\t{}", iso_code.unwrap_or("-"))?,
+        }
+    }
+    if format == "json" {
+        serde_json::to_writer_pretty(&mut out, &rows)?;
+        writeln!(out)?;
+    }
+    out.flush()?;
+    Ok(ExitCode::SUCCESS)
+}
+
+/// `None` for blank lines
+fn parse_line(line: &str) -> Result<Option<IpAddr>> {
+    let line = line.trim();
+    if line.is_empty() {
+        return Ok(None);
+    }
+    let addr = line
+        .parse()
+        .map_err(|_| format!("not an IP address: {line:?}"))?;
+    Ok(Some(addr))
+}
+
+async fn dry_run(args: &ArgMatches, input: Box<dyn BufRead>) -> Result<ExitCode> {
+    let path = args.get_one::<String>("db").unwrap();
+    // counting in memory, for the dry run to see what would be counted
+    let mut locat = Locat::builder().with_geoip_path(path).build().await?;
+    if let Some(window) = args.get_one::<String>("dedup-window") {
+        locat = locat.with_dedup_window(humantime::parse_duration(window)?);
+    }
+    let mut addrs = Vec::new();
+    for line in input.lines() {
+        addrs.extend(parse_line(&line?)?);
+    }
+    let dry_run = locat.ip_to_iso_codes_dry_run(&addrs);
+    for (name, count) in dry_run.counts.iter().chain(&dry_run.counters) {
+        println!("{name}\t{count}");
+    }
+    println!("skipped\t{}", dry_run.skipped);
+    Ok(ExitCode::SUCCESS)
+}
+
+fn analytics(args: &ArgMatches) -> Result<ExitCode> {
+    let path = args.get_one::<String>("db").unwrap();
+    let top = *args.get_one::<u32>("top").unwrap();
+    // read-only, so that a live file isn't migrated or locked for writing
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY)?;
+    let mut stmt = conn.prepare(
+        "SELECT iso_code, count FROM analytics WHERE count > 0
+         ORDER BY count DESC, iso_code LIMIT ?1",
+    )?;
+    let rows = stmt.query_map([top], |row| {
+        Ok((row.get::<_, String>(0)?, row.get::<_, u64>(1)?))
+    })?;
+    for row in rows {
+        let (iso_code, count) = row?;
+        println!("{iso_code}\t{count}");
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+async fn verify(args: &ArgMatches) -> Result<ExitCode> {
+    let locat = open(args).await?;
+    let metadata = locat.geoip_metadata();
+    let days = metadata.age().as_secs() / 86_400;
+    println!("{}, built {days}d ago", metadata.database_type);
+    let verification = locat.verify().await;
+    print!("{verification}");
+    Ok(if verification.passed() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    })
+}
+
+/// Request IDs for the lookups of this run, `<run>-<n>`: the run part
+/// tells apart runs logging to the same place
+fn request_ids() -> impl FnMut() -> String {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .subsec_nanos();
+    let run = format!("{:08x}", std::process::id() ^ nanos);
+    let mut n = 0;
+    move || {
+        n += 1;
+        format!("{run}-{n}")
+    }
+}
+
+/// Runs a lookup in a span carrying its request ID, then logs how it went
+/// with the outcome and country `describe` gives
+fn traced<T>(
+    request_id: String,
+    lookup: impl FnOnce() -> T,
+    describe: impl Fn(&T) -> (&'static str, Option<&str>),
+) -> T {
+    tracing::info_span!("lookup", request_id).in_scope(|| {
+        let started = Instant::now();
+        let result = lookup();
+        let micros = u64::try_from(started.elapsed().as_micros()).unwrap_or(u64::MAX);
+        let (outcome, iso_code) = describe(&result);
+        tracing::info!(outcome, iso_code, micros, "lookup");
+        result
+    })
+}
+
+/// Writes events of level `INFO` and up as JSON lines, along with the
+/// fields of the spans they happen in
+struct JsonLogs<W> {
+    out: Mutex<W>,
+    next_id: AtomicU64,
+    spans: Mutex<HashMap<u64, SpanData>>,
+    // innermost last: the runtime only has the one thread
+    entered: Mutex<Vec<u64>>,
+}
+
+struct SpanData {
+    name: &'static str,
+    fields: Map<String, Value>,
+    refs: usize,
+}
+
+impl<W: Write> JsonLogs<W> {
+    fn new(out: W) -> Self {
+        Self {
+            out: Mutex::new(out),
+            // span IDs can't be zero
+            next_id: AtomicU64::new(1),
+            spans: Mutex::new(HashMap::new()),
+            entered: Mutex::new(Vec::new()),
+        }
+    }
+}
+
+/// Collects fields into a JSON object
+struct Fields<'a>(&'a mut Map<String, Value>);
+
+impl Visit for Fields<'_> {
+    fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+        self.0
+            .insert(field.name().to_owned(), format!("{value:?}").into());
+    }
+
+    fn record_str(&mut self, field: &Field, value: &str) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_u64(&mut self, field: &Field, value: u64) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_i64(&mut self, field: &Field, value: i64) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_f64(&mut self, field: &Field, value: f64) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+
+    fn record_bool(&mut self, field: &Field, value: bool) {
+        self.0.insert(field.name().to_owned(), value.into());
+    }
+}
+
+impl<W: Write + Send + 'static> Subscriber for JsonLogs<W> {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        *metadata.level() <= Level::INFO
+    }
+
+    fn new_span(&self, span: &Attributes<'_>) -> Id {
+        let mut fields = Map::new();
+        span.record(&mut Fields(&mut fields));
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let data = SpanData {
+            name: span.metadata().name(),
+            fields,
+            refs: 1,
+        };
+        self.spans.lock().unwrap().insert(id, data);
+        Id::from_u64(id)
+    }
+
+    fn record(&self, span: &Id, values: &Record<'_>) {
+        if let Some(data) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            values.record(&mut Fields(&mut data.fields));
+        }
+    }
+
+    fn record_follows_from(&self, _span: &Id, _follows: &Id) {}
+
+    fn event(&self, event: &Event<'_>) {
+        let metadata = event.metadata();
+        let mut line = Map::new();
+        let now = humantime::format_rfc3339_micros(SystemTime::now());
+        line.insert("timestamp".to_owned(), now.to_string().into());
+        line.insert("level".to_owned(), metadata.level().as_str().into());
+        line.insert("target".to_owned(), metadata.target().into());
+        {
+            // outer spans first, so that inner ones win
+            let spans = self.spans.lock().unwrap();
+            for id in self.entered.lock().unwrap().iter() {
+                if let Some(span) = spans.get(id) {
+                    line.insert("span".to_owned(), span.name.into());
+                    line.extend(span.fields.clone());
+                }
+            }
+        }
+        event.record(&mut Fields(&mut line));
+        let mut out = self.out.lock().unwrap();
+        // nowhere left to report a failure to log
+        _ = writeln!(out, "{}", Value::Object(line));
+    }
+
+    fn enter(&self, span: &Id) {
+        self.entered.lock().unwrap().push(span.into_u64());
+    }
+
+    fn exit(&self, span: &Id) {
+        let mut entered = self.entered.lock().unwrap();
+        if let Some(at) = entered.iter().rposition(|id| *id == span.into_u64()) {
+            entered.remove(at);
+        }
+    }
+
+    fn clone_span(&self, span: &Id) -> Id {
+        if let Some(data) = self.spans.lock().unwrap().get_mut(&span.into_u64()) {
+            data.refs += 1;
+        }
+        span.clone()
+    }
+
+    fn try_close(&self, span: Id) -> bool {
+        let mut spans = self.spans.lock().unwrap();
+        let Some(data) = spans.get_mut(&span.into_u64()) else {
+            return false;
+        };
+        data.refs -= 1;
+        if data.refs > 0 {
+            return false;
+        }
+        spans.remove(&span.into_u64());
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        sync::{Arc, Mutex},
+    };
+
+    use serde_json::Value;
+
+    use super::{request_ids, traced, JsonLogs};
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_json_logs() {
+        let out = Shared::default();
+        let mut request_ids = request_ids();
+        tracing::subscriber::with_default(JsonLogs::new(out.clone()), || {
+            traced(
+                request_ids(),
+                || Some("US"),
+                |iso_code| ("located", *iso_code),
+            );
+            traced(request_ids(), || None::<&str>, |_| ("not_found", None));
+            tracing::debug!("left out");
+            tracing::warn!(error = "disk full", "outside of lookups");
+        });
+
+        let out = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<Value> = out
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["level"], "INFO");
+        assert_eq!(lines[0]["span"], "lookup");
+        assert_eq!(lines[0]["message"], "lookup");
+        assert_eq!(lines[0]["outcome"], "located");
+        assert_eq!(lines[0]["iso_code"], "US");
+        assert!(lines[0]["micros"].is_u64());
+        let request_id = |line: &Value| line["request_id"].as_str().unwrap().to_owned();
+        assert!(request_id(&lines[0]).ends_with("-1"));
+        assert_eq!(
+            request_id(&lines[1]),
+            request_id(&lines[0]).replace("-1", "-2")
+        );
+        assert_eq!(lines[1]["iso_code"], Value::Null);
+        // no span left entered
+        assert_eq!(lines[2]["span"], Value::Null);
+        assert_eq!(lines[2]["error"], "disk full");
+    }
+}
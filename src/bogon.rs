@@ -0,0 +1,121 @@
+use std::net::IpAddr;
+
+use ipnetwork::IpNetwork;
+
+use crate::Error;
+
+// reserved and special-purpose ranges (RFC 6890 and friends) that should
+// never show up as the source of traffic from the internet
+const EMBEDDED: &[&str] = &[
+    "0.0.0.0/8",
+    "10.0.0.0/8",
+    "100.64.0.0/10",
+    "127.0.0.0/8",
+    "169.254.0.0/16",
+    "172.16.0.0/12",
+    "192.0.0.0/24",
+    "192.0.2.0/24",
+    "192.168.0.0/16",
+    "198.18.0.0/15",
+    "198.51.100.0/24",
+    "203.0.113.0/24",
+    "224.0.0.0/4",
+    "240.0.0.0/4",
+    "::/128",
+    "::1/128",
+    "100::/64",
+    "2001:db8::/32",
+    "fc00::/7",
+    "fe80::/10",
+    "ff00::/8",
+];
+
+/// A list of bogon networks: reserved or unallocated address space that
+/// traffic shouldn't come from, hinting at spoofing or misconfiguration.
+///
+/// The embedded list only covers reserved ranges. Lists of unallocated
+/// space change over time; load them with [`Bogons::parse`] (e.g. Team
+/// Cymru's "fullbogons") and swap them in with
+/// [`crate::Locat::set_bogons`].
+#[derive(Debug, Clone)]
+pub struct Bogons {
+    networks: Vec<IpNetwork>,
+    // anything outside of 2000::/3 hasn't been allocated by IANA
+    v6_global_unicast_only: bool,
+}
+
+impl Bogons {
+    pub(crate) fn len(&self) -> usize {
+        self.networks.len()
+    }
+
+    /// The reserved ranges compiled into the crate
+    pub fn embedded() -> Self {
+        Self {
+            networks: EMBEDDED
+                .iter()
+                .map(|network| network.parse().unwrap())
+                .collect(),
+            v6_global_unicast_only: true,
+        }
+    }
+
+    /// Parses one network per line, ignoring blank lines and `#` comments
+    pub fn parse(list: &str) -> Result<Self, Error> {
+        let networks = list
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or_default().trim())
+            .filter(|line| !line.is_empty())
+            .map(str::parse)
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            networks,
+            v6_global_unicast_only: false,
+        })
+    }
+
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        if let IpAddr::V6(v6) = addr {
+            if self.v6_global_unicast_only
+                && v6.segments()[0] & 0xe000 != 0x2000
+                && v6.to_ipv4_mapped().is_none()
+            {
+                return true;
+            }
+        }
+        self.networks.iter().any(|network| network.contains(addr))
+    }
+}
+
+impl Default for Bogons {
+    fn default() -> Self {
+        Self::embedded()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Bogons;
+
+    #[test]
+    fn test_embedded() {
+        let bogons = Bogons::embedded();
+        for addr in ["10.1.2.3", "192.168.0.1", "::1", "fe80::1", "4000::1"] {
+            assert!(bogons.contains(addr.parse().unwrap()), "{addr}");
+        }
+        for addr in ["81.2.69.160", "2a02:ecc0::1", "::ffff:81.2.69.160"] {
+            assert!(!bogons.contains(addr.parse().unwrap()), "{addr}");
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let bogons =
+            Bogons::parse("# fullbogons\n41.62.0.0/16\n\n102.0.0.0/8 # unallocated\n").unwrap();
+        assert!(bogons.contains("41.62.1.1".parse().unwrap()));
+        assert!(bogons.contains("102.1.1.1".parse().unwrap()));
+        assert!(!bogons.contains("10.0.0.1".parse().unwrap()));
+
+        assert!(Bogons::parse("not a network").is_err());
+    }
+}
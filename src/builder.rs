@@ -0,0 +1,523 @@
+use std::path::PathBuf;
+#[cfg(feature = "analytics")]
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(feature = "analytics")]
+use crate::db::{Db, Pragmas};
+use crate::{Cache, Error, GeoIp, Locat};
+
+/// Everything [`Locat::new`] takes and then some, see [`Locat::builder`]:
+///
+/// ```no_run
+/// # async fn run() -> Result<(), locat::Error> {
+/// let builder = locat::Locat::builder()
+///     .with_geoip_path("/var/lib/GeoLite2-Country.mmdb")
+///     .with_cache_size(10_000);
+/// // with the `analytics` feature, on by default
+/// # #[cfg(feature = "analytics")]
+/// let builder = builder
+///     .with_analytics_path("/var/lib/locat/analytics.db")
+///     .with_wal(true)
+///     .with_busy_timeout(std::time::Duration::from_secs(5));
+/// let locat = builder.build().await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Debug, Clone)]
+pub struct LocatBuilder {
+    geoip: Option<GeoIpSource>,
+    #[cfg(feature = "analytics")]
+    analytics: String,
+    #[cfg(feature = "analytics")]
+    pragmas: Pragmas,
+    #[cfg(feature = "analytics")]
+    recording: bool,
+    #[cfg(feature = "analytics")]
+    record_misses: bool,
+    #[cfg(feature = "analytics")]
+    retention: Option<Duration>,
+    #[cfg(feature = "analytics")]
+    time_buckets: Option<Duration>,
+    #[cfg(feature = "webhook")]
+    report: Option<crate::WebhookReport>,
+    cache_size: Option<usize>,
+    cache_ttl: Option<Duration>,
+    #[cfg(feature = "mmap")]
+    mmap: bool,
+    filesystem: bool,
+}
+
+#[derive(Debug, Clone)]
+enum GeoIpSource {
+    Path(PathBuf),
+    Bytes(Vec<u8>),
+}
+
+impl Locat {
+    /// Starts configuring a `Locat` step by step, for when [`Locat::new`]
+    /// isn't enough
+    pub fn builder() -> LocatBuilder {
+        LocatBuilder {
+            geoip: None,
+            #[cfg(feature = "analytics")]
+            analytics: ":memory:".to_owned(),
+            #[cfg(feature = "analytics")]
+            pragmas: Pragmas::default(),
+            #[cfg(feature = "analytics")]
+            recording: true,
+            #[cfg(feature = "analytics")]
+            record_misses: true,
+            #[cfg(feature = "analytics")]
+            retention: None,
+            #[cfg(feature = "analytics")]
+            time_buckets: None,
+            #[cfg(feature = "webhook")]
+            report: None,
+            cache_size: None,
+            cache_ttl: None,
+            #[cfg(feature = "mmap")]
+            mmap: false,
+            filesystem: true,
+        }
+    }
+}
+
+impl LocatBuilder {
+    /// The GeoIP database to read, this or [`LocatBuilder::with_geoip_bytes`]
+    /// is required
+    pub fn with_geoip_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.geoip = Some(GeoIpSource::Path(path.into()));
+        self
+    }
+
+    /// A GeoIP database that's already in memory. [`Locat::reload_geoip`]
+    /// doesn't apply.
+    pub fn with_geoip_bytes(mut self, data: impl Into<Vec<u8>>) -> Self {
+        self.geoip = Some(GeoIpSource::Bytes(data.into()));
+        self
+    }
+
+    #[cfg(feature = "analytics")]
+    /// Where analytics are kept, `:memory:` (the default) to keep them in
+    /// memory only. May contain date placeholders, see [`Locat::new`].
+    pub fn with_analytics_path(mut self, path: impl Into<String>) -> Self {
+        self.analytics = path.into();
+        self
+    }
+
+    #[cfg(feature = "analytics")]
+    /// Puts the analytics file in write-ahead logging mode, so readers
+    /// don't block lookups being counted
+    pub fn with_wal(mut self, wal: bool) -> Self {
+        self.pragmas.wal = wal;
+        self
+    }
+
+    #[cfg(feature = "analytics")]
+    /// How long to wait for other connections holding a lock on the
+    /// analytics file before giving up
+    pub fn with_busy_timeout(mut self, timeout: Duration) -> Self {
+        self.pragmas.busy_timeout = Some(timeout);
+        self
+    }
+
+    #[cfg(feature = "analytics")]
+    /// Whether lookups are counted at all, `true` by default
+    pub fn with_recording(mut self, recording: bool) -> Self {
+        self.recording = recording;
+        self
+    }
+
+    #[cfg(feature = "analytics")]
+    /// Whether addresses that aren't in the GeoIP database are counted, as
+    /// `not_found` in [`Locat::get_lookup_stats`]. `true` by default.
+    pub fn with_miss_recording(mut self, record_misses: bool) -> Self {
+        self.record_misses = record_misses;
+        self
+    }
+
+    #[cfg(feature = "analytics")]
+    /// See [`Locat::with_retention`]
+    pub fn with_retention(mut self, age: Duration) -> Self {
+        self.retention = Some(age);
+        self
+    }
+
+    #[cfg(feature = "analytics")]
+    /// See [`Locat::with_time_buckets`]. Buckets shorter than a second are
+    /// an [`Error::InvalidConfig`] rather than a panic here.
+    pub fn with_time_buckets(mut self, size: Duration) -> Self {
+        self.time_buckets = Some(size);
+        self
+    }
+
+    #[cfg(feature = "webhook")]
+    /// See [`Locat::with_webhook_report`]. Needs
+    /// [`LocatBuilder::with_time_buckets`].
+    pub fn with_webhook_report(mut self, report: crate::WebhookReport) -> Self {
+        self.report = Some(report);
+        self
+    }
+
+    #[cfg(feature = "mmap")]
+    /// Maps GeoIP databases into memory instead of reading them whole,
+    /// which keeps startup fast and resident memory low with large City
+    /// databases, see [`GeoIp::open_mmap`]. Applies to databases added and
+    /// reloaded later on too. Off by default, since mapped files must never
+    /// be modified in place.
+    pub fn with_mmap(mut self, mmap: bool) -> Self {
+        self.mmap = mmap;
+        self
+    }
+
+    /// Remembers the country of this many addresses, see
+    /// [`Locat::with_lookup_cache`]
+    pub fn with_cache_size(mut self, size: usize) -> Self {
+        self.cache_size = Some(size);
+        self
+    }
+
+    /// How long addresses stay cached, forever by default. Only applies
+    /// along with [`LocatBuilder::with_cache_size`].
+    pub fn with_cache_ttl(mut self, ttl: Duration) -> Self {
+        self.cache_ttl = Some(ttl);
+        self
+    }
+
+    /// With `false`, nothing touches the file system, for sandboxes without
+    /// one: the GeoIP database must come from
+    /// [`LocatBuilder::with_geoip_bytes`] and analytics stay in memory,
+    /// temporary tables included. What needs files, like
+    /// [`Locat::reload_geoip`] or snapshots, fails with
+    /// [`Error::NoFilesystem`]. `true` by default.
+    pub fn with_filesystem(mut self, filesystem: bool) -> Self {
+        self.filesystem = filesystem;
+        self
+    }
+
+    pub async fn build(mut self) -> Result<Locat, Error> {
+        if !self.filesystem {
+            self.check_no_filesystem()?;
+        }
+        let (geoip_path, geoip) = match self.geoip {
+            Some(GeoIpSource::Path(path)) => {
+                #[cfg(feature = "mmap")]
+                let geoip = if self.mmap {
+                    GeoIp::open_mmap(&path)?
+                } else {
+                    GeoIp::load(tokio::fs::read(&path).await?)?
+                };
+                #[cfg(not(feature = "mmap"))]
+                let geoip = GeoIp::load(tokio::fs::read(&path).await?)?;
+                let path = path.into_os_string().into_string().map_err(|path| {
+                    Error::InvalidConfig(format!("geoip path is not valid UTF-8: {path:?}"))
+                })?;
+                (path, geoip)
+            }
+            Some(GeoIpSource::Bytes(data)) => ("<memory>".to_owned(), GeoIp::load(data)?),
+            None => {
+                return Err(Error::InvalidConfig(
+                    "no GeoIP database was given".to_owned(),
+                ))
+            }
+        };
+
+        #[cfg(feature = "analytics")]
+        let mut locat = {
+            let db = Arc::new(Db::open_with(&self.analytics, self.pragmas).await?);
+            let mut locat = Locat::from_parts(&geoip_path, geoip, db.clone(), Some(db));
+            let inner = locat.inner_mut();
+            inner.filesystem = self.filesystem;
+            inner.recording = self.recording;
+            inner.record_misses = self.record_misses;
+            locat.check_epoch().await?;
+            if let Some(age) = self.retention {
+                locat = locat.with_retention(age);
+            }
+            if let Some(size) = self.time_buckets {
+                if size.as_secs() == 0 {
+                    return Err(Error::InvalidConfig(
+                        "time buckets must last at least a second".to_owned(),
+                    ));
+                }
+                locat = locat.with_time_buckets(size);
+            }
+            locat
+        };
+        #[cfg(not(feature = "analytics"))]
+        let mut locat = Locat::from_parts(&geoip_path, geoip);
+        #[cfg(not(feature = "analytics"))]
+        {
+            locat.inner_mut().filesystem = self.filesystem;
+        }
+        #[cfg(feature = "mmap")]
+        {
+            locat.inner_mut().mmap = self.mmap;
+        }
+        if let Some(size) = self.cache_size {
+            let mut cache = Cache::new(size);
+            if let Some(ttl) = self.cache_ttl {
+                cache = cache.with_ttl(ttl);
+            }
+            locat = locat.with_lookup_cache(cache);
+        }
+        #[cfg(feature = "webhook")]
+        if let Some(report) = self.report {
+            locat.check_reports()?;
+            locat = locat.with_webhook_report(report);
+        }
+        Ok(locat)
+    }
+
+    /// Whether everything asked for can do without files, see
+    /// [`LocatBuilder::with_filesystem`]
+    fn check_no_filesystem(&mut self) -> Result<(), Error> {
+        let invalid = |what: &str| {
+            Err(Error::InvalidConfig(format!(
+                "{what} without a file system"
+            )))
+        };
+        if !matches!(self.geoip, Some(GeoIpSource::Bytes(_))) {
+            return invalid("a GeoIP database that isn't in memory");
+        }
+        #[cfg(feature = "analytics")]
+        {
+            if self.analytics != ":memory:" {
+                return invalid("analytics that aren't in memory");
+            }
+            self.pragmas.memory_temp_store = true;
+        }
+        #[cfg(feature = "mmap")]
+        if self.mmap {
+            return invalid("a mapped GeoIP database");
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "analytics")]
+    use std::time::Duration;
+
+    #[cfg(any(feature = "analytics", feature = "mmap"))]
+    use crate::testing::RemoveOnDrop;
+    use crate::{testing::TestDb, Error, Locat};
+
+    #[cfg(not(feature = "analytics"))]
+    #[tokio::test]
+    async fn test_lookup_only() {
+        let data = TestDb::new().country("1.2.3.0/24", "US", "NA").build();
+        let locat = Locat::builder()
+            .with_geoip_bytes(data)
+            .with_cache_size(16)
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(
+            locat.ip_to_iso_code("1.2.3.4".parse().unwrap()).await,
+            Some("US")
+        );
+        assert!(matches!(
+            Locat::builder().build().await,
+            Err(Error::InvalidConfig(_))
+        ));
+    }
+
+    #[cfg(feature = "analytics")]
+    #[tokio::test]
+    async fn test_builder() {
+        let analytics_path = "/tmp/loca-test-builder.db";
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
+        let _remove_wal = RemoveOnDrop {
+            path: "/tmp/loca-test-builder.db-wal",
+        };
+        let _remove_shm = RemoveOnDrop {
+            path: "/tmp/loca-test-builder.db-shm",
+        };
+        let data = TestDb::new().country("1.2.3.0/24", "US", "NA").build();
+        let addr = "1.2.3.4".parse().unwrap();
+
+        let locat = Locat::builder()
+            .with_geoip_bytes(data.clone())
+            .with_analytics_path(analytics_path)
+            .with_wal(true)
+            .with_busy_timeout(Duration::from_secs(1))
+            .with_cache_size(16)
+            .with_cache_ttl(Duration::from_secs(60))
+            .with_retention(Duration::from_secs(90 * 24 * 3600))
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(locat.ip_to_iso_code(addr).await, Some("US"));
+        // cached, and still counted
+        assert_eq!(locat.ip_to_iso_code(addr).await, Some("US"));
+        assert_eq!(
+            locat.get_analytics().await.unwrap(),
+            vec![("US".to_string(), 2)]
+        );
+        let stats = locat.cache_stats().unwrap();
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+        let conn = rusqlite::Connection::open(analytics_path).unwrap();
+        let mode: String = conn
+            .pragma_query_value(None, "journal_mode", |row| row.get(0))
+            .unwrap();
+        assert_eq!(mode, "wal");
+
+        // in memory, not counting anything
+        let locat = Locat::builder()
+            .with_geoip_bytes(data)
+            .with_recording(false)
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(locat.ip_to_iso_code(addr).await, Some("US"));
+        locat.lookup("10.0.0.1".parse().unwrap()).await;
+        assert!(locat.get_analytics().await.unwrap().is_empty());
+        assert_eq!(locat.get_bogon_count().await.unwrap(), 0);
+
+        assert!(matches!(
+            Locat::builder().build().await,
+            Err(Error::InvalidConfig(_))
+        ));
+    }
+
+    /// Set in the process [`test_without_filesystem`] runs itself in
+    #[cfg(feature = "analytics")]
+    const NO_FS_CHILD: &str = "LOCAT_TEST_NO_FS_CHILD";
+
+    #[cfg(all(feature = "analytics", unix))]
+    #[test]
+    fn test_without_filesystem() {
+        use std::{fs, os::unix::fs::PermissionsExt, process::Command};
+
+        if std::env::var_os(NO_FS_CHILD).is_some() {
+            return tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap()
+                .block_on(without_filesystem());
+        }
+
+        // run again in a process whose temporary and working directories
+        // can't be written to, and that has to leave them empty: root
+        // ignores the permissions, but not the check
+        let dir = std::env::temp_dir().join(format!("loca-test-no-fs-{}", std::process::id()));
+        fs::create_dir(&dir).unwrap();
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o555)).unwrap();
+        let output = Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "builder::tests::test_without_filesystem"])
+            .args(["--test-threads", "1", "--nocapture"])
+            .env(NO_FS_CHILD, "1")
+            .env("TMPDIR", &dir)
+            .env("SQLITE_TMPDIR", &dir)
+            .current_dir(&dir)
+            .output()
+            .unwrap();
+        let created = fs::read_dir(&dir).unwrap().count();
+        fs::set_permissions(&dir, fs::Permissions::from_mode(0o755)).unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stdout)
+        );
+        assert!(String::from_utf8_lossy(&output.stdout).contains("1 passed"));
+        assert_eq!(created, 0);
+    }
+
+    #[cfg(feature = "analytics")]
+    async fn without_filesystem() {
+        let data = TestDb::new().country("1.2.3.0/24", "US", "NA").build();
+        let locat = Locat::builder()
+            .with_geoip_bytes(data.clone())
+            .with_filesystem(false)
+            .with_cache_size(16)
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(
+            locat.ip_to_iso_code("1.2.3.4".parse().unwrap()).await,
+            Some("US")
+        );
+        assert_eq!(
+            locat.get_analytics().await.unwrap(),
+            vec![("US".to_string(), 1)]
+        );
+        // sorts that don't fit in memory would otherwise spill into files
+        let temp_store = locat
+            .query_raw_readonly("SELECT * FROM pragma_temp_store", vec![])
+            .await
+            .unwrap();
+        assert_eq!(
+            temp_store.rows,
+            vec![vec![rusqlite::types::Value::Integer(2)]]
+        );
+        let sorted = locat
+            .query_raw_readonly(
+                "WITH RECURSIVE n(x) AS (SELECT 1 UNION ALL SELECT x + 1 FROM n WHERE x < 200000)
+                SELECT x FROM n ORDER BY -x LIMIT 1",
+                vec![],
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            sorted.rows,
+            vec![vec![rusqlite::types::Value::Integer(200_000)]]
+        );
+        locat.export_portable().await.unwrap();
+
+        assert!(matches!(
+            locat.reload_geoip().await,
+            Err(Error::NoFilesystem(_))
+        ));
+        assert!(matches!(
+            locat.write_snapshot("/tmp/loca-test-no-fs.bin").await,
+            Err(Error::NoFilesystem(_))
+        ));
+        assert!(!std::path::Path::new("/tmp/loca-test-no-fs.bin").exists());
+
+        for builder in [
+            Locat::builder().with_geoip_path("/tmp/loca-test-no-fs.mmdb"),
+            Locat::builder()
+                .with_geoip_bytes(data)
+                .with_analytics_path("/tmp/loca-test-no-fs.db"),
+        ] {
+            assert!(matches!(
+                builder.with_filesystem(false).build().await,
+                Err(Error::InvalidConfig(_))
+            ));
+        }
+    }
+
+    #[cfg(feature = "mmap")]
+    #[tokio::test]
+    async fn test_mmap() {
+        let geoip_path = "/tmp/loca-test-mmap.mmdb";
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+        TestDb::new()
+            .country("1.2.3.0/24", "US", "NA")
+            .write(geoip_path);
+        let addr = "1.2.3.4".parse().unwrap();
+
+        let locat = Locat::builder()
+            .with_geoip_path(geoip_path)
+            .with_mmap(true)
+            .build()
+            .await
+            .unwrap();
+        assert_eq!(locat.ip_to_iso_code(addr).await, Some("US"));
+
+        // replaced, not modified in place
+        let next_path = "/tmp/loca-test-mmap.mmdb.next";
+        TestDb::new()
+            .country("1.2.3.0/24", "CA", "NA")
+            .write(next_path);
+        std::fs::rename(next_path, geoip_path).unwrap();
+        locat.reload_geoip().await.unwrap();
+        assert_eq!(locat.ip_to_iso_code(addr).await, Some("CA"));
+    }
+}
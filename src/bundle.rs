@@ -0,0 +1,128 @@
+//! Override tables signed centrally, for edge nodes to pick up corrections
+//! without a deploy:
+//!
+//! ```text
+//! locat-overrides 1
+//! 1.2.3.0/24      US
+//! 2001:db8::/32   DE
+//! signature       <ed25519 signature of every line above, hex-encoded>
+//! ```
+//!
+//! Fields are separated by tabs. Anything after the signature line is
+//! rejected, so nothing can be appended to a signed bundle.
+
+use std::path::Path;
+
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+
+use crate::{Error, Overrides};
+
+const MAGIC: &str = "locat-overrides";
+const FORMAT_VERSION: u32 = 1;
+
+impl Overrides {
+    /// Parses a bundle made with [`Overrides::to_signed_bundle`], rejecting
+    /// it unless it was signed by the holder of `key`
+    pub fn from_signed_bundle(bundle: &str, key: &VerifyingKey) -> Result<Self, Error> {
+        let (body, signature) = bundle
+            .trim_end_matches('\n')
+            .rsplit_once('\n')
+            .and_then(|(body, last)| Some((body, last.strip_prefix("signature\t")?)))
+            .ok_or_else(|| invalid("missing signature"))?;
+        let signature: Signature = signature
+            .parse()
+            .map_err(|_| invalid("malformed signature"))?;
+        // the newline before the signature line is part of what's signed
+        let body = &bundle[..body.len() + 1];
+        key.verify(body.as_bytes(), &signature)
+            .map_err(|_| Error::BadSignature)?;
+
+        let mut lines = body.lines();
+        match lines.next().and_then(|header| header.split_once('\t')) {
+            Some((MAGIC, version)) if version.parse() == Ok(FORMAT_VERSION) => {}
+            _ => return Err(invalid("missing bundle header")),
+        }
+        let mut overrides = Overrides::new();
+        for line in lines.filter(|line| !line.is_empty()) {
+            let (network, iso_code) = line
+                .split_once('\t')
+                .ok_or_else(|| invalid(&format!("expected `network<TAB>country`: {line:?}")))?;
+            let network = network
+                .parse()
+                .map_err(|_| invalid(&format!("bad network: {network:?}")))?;
+            overrides = overrides.rule(network, iso_code);
+        }
+        Ok(overrides)
+    }
+
+    /// Reads a signed bundle from a file, see [`Overrides::from_signed_bundle`]
+    pub async fn load_signed_bundle(
+        path: impl AsRef<Path>,
+        key: &VerifyingKey,
+    ) -> Result<Self, Error> {
+        let bundle = tokio::fs::read_to_string(path).await?;
+        Self::from_signed_bundle(&bundle, key)
+    }
+
+    /// Renders these overrides as a bundle signed with `key`, for
+    /// distribution to nodes holding the matching verifying key
+    pub fn to_signed_bundle(&self, key: &SigningKey) -> String {
+        let mut bundle = format!("{MAGIC}\t{FORMAT_VERSION}\n");
+        for (network, iso_code) in &self.rules {
+            bundle.push_str(&format!("{network}\t{iso_code}\n"));
+        }
+        let signature = key.sign(bundle.as_bytes());
+        bundle.push_str(&format!("signature\t{signature:x}\n"));
+        bundle
+    }
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::InvalidBundle(reason.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use ed25519_dalek::SigningKey;
+
+    use crate::{Error, Overrides, Resolver};
+
+    #[test]
+    fn test_signed_bundle() {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let overrides = Overrides::new()
+            .rule("1.2.3.0/24".parse().unwrap(), "US")
+            .rule("2001:db8::/32".parse().unwrap(), "DE");
+        let bundle = overrides.to_signed_bundle(&key);
+
+        let loaded = Overrides::from_signed_bundle(&bundle, &key.verifying_key()).unwrap();
+        assert_eq!(
+            loaded.resolve("1.2.3.4".parse().unwrap()).unwrap().iso_code,
+            "US"
+        );
+        assert_eq!(
+            loaded
+                .resolve("2001:db8::1".parse().unwrap())
+                .unwrap()
+                .iso_code,
+            "DE"
+        );
+
+        let tampered = bundle.replace("\tUS", "\tFR");
+        assert!(matches!(
+            Overrides::from_signed_bundle(&tampered, &key.verifying_key()),
+            Err(Error::BadSignature)
+        ));
+        let other = SigningKey::from_bytes(&[8; 32]);
+        assert!(matches!(
+            Overrides::from_signed_bundle(&bundle, &other.verifying_key()),
+            Err(Error::BadSignature)
+        ));
+        let appended = format!("{bundle}5.6.7.0/24\tFR\n");
+        assert!(Overrides::from_signed_bundle(&appended, &key.verifying_key()).is_err());
+        assert!(matches!(
+            Overrides::from_signed_bundle("1.2.3.0/24\tUS\n", &key.verifying_key()),
+            Err(Error::InvalidBundle(_))
+        ));
+    }
+}
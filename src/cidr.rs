@@ -0,0 +1,169 @@
+use std::fmt::Write;
+
+use ipnet::IpNet;
+use ipnetwork::IpNetwork;
+
+use crate::Locat;
+
+/// How [`Locat::export_cidrs`] writes networks out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum CidrFormat {
+    /// One network per line
+    Plain,
+    /// `locat_v4` and `locat_v6` set definitions, for an nftables table
+    Nftables,
+    /// Input for `ipset restore`, creating `locat_v4` and `locat_v6`
+    Ipset,
+}
+
+/// Merges adjacent and overlapping networks into the fewest networks that
+/// cover exactly the same addresses, IPv4 first, each family sorted.
+///
+/// GeoIP databases split countries into many small networks; this is what
+/// turns them into a list a firewall can reasonably load.
+pub fn aggregate_networks(networks: impl IntoIterator<Item = IpNetwork>) -> Vec<IpNetwork> {
+    let networks: Vec<IpNet> = networks.into_iter().map(to_ipnet).collect();
+    IpNet::aggregate(&networks)
+        .into_iter()
+        .map(|network| {
+            // an aggregate is always a valid network
+            IpNetwork::new(network.network(), network.prefix_len()).unwrap()
+        })
+        .collect()
+}
+
+fn to_ipnet(network: IpNetwork) -> IpNet {
+    // ipnetwork already checked the prefix length
+    IpNet::new(network.network(), network.prefix()).unwrap()
+}
+
+impl Locat {
+    /// Every network the GeoIP database places in one of `iso_codes`,
+    /// merged into as few networks as possible, e.g. for firewall rules.
+    ///
+    /// This walks the whole database, which takes a moment with a full-size
+    /// one.
+    pub fn export_cidrs(&self, iso_codes: &[&str], format: CidrFormat) -> String {
+        let geoip = self.geoip();
+        let networks = aggregate_networks(
+            geoip
+                .networks()
+                .filter(|(_, country)| {
+                    country
+                        .country
+                        .as_ref()
+                        .and_then(|country| country.iso_code)
+                        .is_some_and(|iso_code| iso_codes.contains(&iso_code))
+                })
+                .map(|(network, _)| network),
+        );
+
+        let (v4, v6): (Vec<_>, Vec<_>) = networks.iter().partition(|network| network.is_ipv4());
+        render(format, &v4, &v6)
+    }
+}
+
+fn render(format: CidrFormat, v4: &[&IpNetwork], v6: &[&IpNetwork]) -> String {
+    let mut out = String::new();
+    for (family, networks) in [("v4", v4), ("v6", v6)] {
+        if networks.is_empty() {
+            continue;
+        }
+        // writing to a String can't fail
+        match format {
+            CidrFormat::Plain => {
+                for network in networks {
+                    writeln!(out, "{network}").unwrap();
+                }
+            }
+            CidrFormat::Nftables => {
+                let elements: Vec<_> = networks.iter().map(ToString::to_string).collect();
+                writeln!(
+                    out,
+                    "set locat_{family} {{\n    type ip{family}_addr\n    flags interval\n    elements = {{ {} }}\n}}",
+                    elements.join(", ")
+                )
+                .unwrap();
+            }
+            CidrFormat::Ipset => {
+                let inet = if family == "v4" { "inet" } else { "inet6" };
+                writeln!(out, "create locat_{family} hash:net family {inet} -exist").unwrap();
+                for network in networks {
+                    writeln!(out, "add locat_{family} {network} -exist").unwrap();
+                }
+            }
+        }
+    }
+    out
+}
+
+#[cfg(all(test, feature = "analytics"))]
+mod tests {
+    use crate::{
+        testing::{RemoveOnDrop, TestDb},
+        Locat,
+    };
+
+    use super::{aggregate_networks, CidrFormat};
+
+    #[test]
+    fn test_aggregate_networks() {
+        let networks = [
+            "10.0.1.0/24",
+            "10.0.0.0/24",
+            // inside the one above
+            "10.0.0.128/25",
+            "10.0.3.0/24",
+            "2001:db8::/33",
+            "2001:db8:8000::/33",
+            "192.168.0.0/16",
+        ]
+        .map(|network| network.parse().unwrap());
+        let aggregated: Vec<_> = aggregate_networks(networks)
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            aggregated,
+            [
+                "10.0.0.0/23",
+                "10.0.3.0/24",
+                "192.168.0.0/16",
+                "2001:db8::/32"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_export_cidrs() {
+        let geoip_path = "/tmp/loca-test-cidrs.mmdb";
+        let analytics_path = "/tmp/loca-test-cidrs.db";
+        TestDb::new()
+            .country("1.2.2.0/24", "US", "NA")
+            .country("1.2.3.0/24", "US", "NA")
+            .country("1.2.4.0/24", "DE", "EU")
+            .country("5.6.7.0/24", "FR", "EU")
+            .write(geoip_path);
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
+        let locat = Locat::new(geoip_path, analytics_path).await.unwrap();
+
+        // the two adjacent /24s merge
+        assert_eq!(
+            locat.export_cidrs(&["US", "FR"], CidrFormat::Plain),
+            "1.2.2.0/23\n5.6.7.0/24\n"
+        );
+        assert_eq!(
+            locat.export_cidrs(&["DE"], CidrFormat::Ipset),
+            "create locat_v4 hash:net family inet -exist\nadd locat_v4 1.2.4.0/24 -exist\n"
+        );
+        assert_eq!(
+            locat.export_cidrs(&["DE"], CidrFormat::Nftables),
+            "set locat_v4 {\n    type ipv4_addr\n    flags interval\n    elements = { 1.2.4.0/24 }\n}\n"
+        );
+        assert_eq!(locat.export_cidrs(&["JP"], CidrFormat::Plain), "");
+    }
+}
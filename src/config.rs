@@ -0,0 +1,411 @@
+//! Everything [`Locat`] can be configured with, in one place, for services
+//! that keep it in a file:
+//!
+//! ```text
+//! # where the databases are
+//! geoip = "/var/lib/GeoLite2-Country.mmdb"
+//! analytics = "/var/lib/locat/analytics-%Y-%m.db"
+//! city = "/var/lib/GeoLite2-City.mmdb"
+//! asn = "/var/lib/GeoLite2-ASN.mmdb"
+//! database.acme = "/var/lib/acme/GeoIP2-Country.mmdb"
+//!
+//! dedup_window = "30min"
+//! time_buckets = "1h"
+//! retention = "90days"
+//! cache_size = "10k"
+//!
+//! # with the `webhook` feature, see `Locat::with_webhook_report`
+//! webhook.url = "https://hooks.slack.com/services/..."
+//! webhook.schedule = "0 9 * * 1-5"
+//! webhook.period = "1day"
+//! webhook.top = 10
+//! ```
+//!
+//! Durations are written the humantime way (`90s`, `1h 30m`, `2days`),
+//! sizes are plain counts with an optional `k` or `M` suffix.
+
+use std::{collections::HashMap, path::PathBuf, time::Duration};
+
+use crate::{Cache, Error, Locat, Resolver};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LocatConfig {
+    pub geoip: PathBuf,
+    /// May contain date placeholders, see [`Locat::new`]
+    pub analytics: String,
+    /// See [`Locat::with_city_database`]
+    pub city: Option<PathBuf>,
+    /// See [`Locat::with_asn_database`]
+    pub asn: Option<PathBuf>,
+    /// Named databases, see [`Locat::with_databases`]
+    pub databases: HashMap<String, PathBuf>,
+    /// See [`Locat::with_dedup_window`]
+    pub dedup_window: Option<Duration>,
+    /// See [`Locat::with_time_buckets`]
+    pub time_buckets: Option<Duration>,
+    /// Number of addresses whose resolution is remembered
+    pub cache_size: Option<usize>,
+    /// See [`Locat::with_retention`]
+    pub retention: Option<Duration>,
+    /// See [`Locat::with_webhook_report`]
+    #[cfg(feature = "webhook")]
+    pub webhook: Option<crate::WebhookReport>,
+}
+
+/// Kinds of deployment, see [`LocatConfig::recommended_for`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Profile {
+    /// Short-lived, memory-constrained instances serving live traffic
+    EdgeWorker,
+    /// A long-running service serving live traffic
+    Server,
+    /// Offline processing of logs, where every line should count
+    BatchJob,
+}
+
+impl LocatConfig {
+    pub fn new(geoip: impl Into<PathBuf>, analytics: impl Into<String>) -> Self {
+        Self {
+            geoip: geoip.into(),
+            analytics: analytics.into(),
+            city: None,
+            asn: None,
+            databases: HashMap::new(),
+            dedup_window: None,
+            time_buckets: None,
+            cache_size: None,
+            retention: None,
+            #[cfg(feature = "webhook")]
+            webhook: None,
+        }
+    }
+
+    /// A starting point for the given kind of deployment, to be adjusted
+    /// from there
+    pub fn recommended_for(
+        profile: Profile,
+        geoip: impl Into<PathBuf>,
+        analytics: impl Into<String>,
+    ) -> Self {
+        let config = Self::new(geoip, analytics);
+        match profile {
+            // little memory to spare, and visitors rarely stick around long
+            // enough on a single instance for a long window to matter
+            Profile::EdgeWorker => Self {
+                dedup_window: Some(Duration::from_secs(5 * 60)),
+                cache_size: Some(1_000),
+                ..config
+            },
+            Profile::Server => Self {
+                dedup_window: Some(Duration::from_secs(30 * 60)),
+                cache_size: Some(100_000),
+                ..config
+            },
+            // logs replay the same addresses over and over, and each line is
+            // a request that should be counted
+            Profile::BatchJob => Self {
+                dedup_window: None,
+                cache_size: Some(1_000_000),
+                ..config
+            },
+        }
+    }
+
+    /// Parses and validates a config file, see the module docs for the format
+    pub fn parse(data: &str) -> Result<Self, Error> {
+        let (mut geoip, mut analytics) = (None, None);
+        let mut config = Self::new("", "");
+        #[cfg(feature = "webhook")]
+        let mut webhook = Webhook::default();
+
+        for (i, line) in data.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let at = |reason: String| invalid(format!("line {}: {reason}", i + 1));
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| at("expected `key = value`".to_owned()))?;
+            let (key, value) = (key.trim(), unquote(value.trim()));
+
+            match key {
+                "geoip" => geoip = Some(PathBuf::from(value)),
+                "analytics" => analytics = Some(value.to_owned()),
+                "city" => config.city = Some(PathBuf::from(value)),
+                "asn" => config.asn = Some(PathBuf::from(value)),
+                "dedup_window" => {
+                    let window = humantime::parse_duration(value)
+                        .map_err(|e| at(format!("bad duration {value:?}: {e}")))?;
+                    config.dedup_window = Some(window);
+                }
+                "time_buckets" => {
+                    let size = humantime::parse_duration(value)
+                        .map_err(|e| at(format!("bad duration {value:?}: {e}")))?;
+                    config.time_buckets = Some(size);
+                }
+                "retention" => {
+                    let age = humantime::parse_duration(value)
+                        .map_err(|e| at(format!("bad duration {value:?}: {e}")))?;
+                    config.retention = Some(age);
+                }
+                "cache_size" => {
+                    let size =
+                        parse_size(value).ok_or_else(|| at(format!("bad size {value:?}")))?;
+                    config.cache_size = Some(size);
+                }
+                #[cfg(feature = "webhook")]
+                "webhook.url" => webhook.url = Some(value.to_owned()),
+                #[cfg(feature = "webhook")]
+                "webhook.schedule" => {
+                    let schedule = crate::Schedule::parse(value).map_err(|e| at(e.to_string()))?;
+                    webhook.schedule = Some(schedule);
+                }
+                #[cfg(feature = "webhook")]
+                "webhook.period" => {
+                    let period = humantime::parse_duration(value)
+                        .map_err(|e| at(format!("bad duration {value:?}: {e}")))?;
+                    webhook.period = Some(period);
+                }
+                #[cfg(feature = "webhook")]
+                "webhook.top" => {
+                    let top = value
+                        .parse()
+                        .map_err(|_| at(format!("bad count {value:?}")))?;
+                    webhook.top = Some(top);
+                }
+                _ => match key.strip_prefix("database.") {
+                    Some(name) if !name.is_empty() => {
+                        config.databases.insert(name.to_owned(), value.into());
+                    }
+                    _ => return Err(at(format!("unknown setting {key:?}"))),
+                },
+            }
+        }
+
+        config.geoip = geoip.ok_or_else(|| invalid("missing geoip".to_owned()))?;
+        config.analytics = analytics.ok_or_else(|| invalid("missing analytics".to_owned()))?;
+        #[cfg(feature = "webhook")]
+        {
+            config.webhook = webhook.build()?;
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Rejects settings that would be accepted but can't be what was meant
+    pub fn validate(&self) -> Result<(), Error> {
+        if self.geoip.as_os_str().is_empty() {
+            return Err(invalid("geoip path is empty".to_owned()));
+        }
+        if self.analytics.is_empty() {
+            return Err(invalid("analytics path is empty".to_owned()));
+        }
+        if self.dedup_window == Some(Duration::ZERO) {
+            // would silently count everything, leave it out instead
+            return Err(invalid("dedup_window must not be zero".to_owned()));
+        }
+        if self.time_buckets.is_some_and(|size| size.as_secs() == 0) {
+            return Err(invalid(
+                "time_buckets must last at least a second".to_owned(),
+            ));
+        }
+        if self.retention == Some(Duration::ZERO) {
+            // would delete buckets as soon as they're written
+            return Err(invalid("retention must not be zero".to_owned()));
+        }
+        if self.cache_size == Some(0) {
+            return Err(invalid("cache_size must not be zero".to_owned()));
+        }
+        #[cfg(feature = "webhook")]
+        if self.webhook.is_some() && self.time_buckets.is_none() {
+            // there would be nothing to report
+            return Err(invalid("webhook needs time_buckets".to_owned()));
+        }
+        Ok(())
+    }
+}
+
+impl Locat {
+    /// Validates `config`, then loads everything it points to
+    pub async fn from_config(config: &LocatConfig) -> Result<Self, Error> {
+        config.validate()?;
+
+        let geoip = config
+            .geoip
+            .to_str()
+            .ok_or_else(|| invalid(format!("geoip path is not valid UTF-8: {:?}", config.geoip)))?;
+        let mut locat = Locat::new(geoip, &config.analytics)
+            .await?
+            .with_databases(config.databases.clone())
+            .await?;
+        if let Some(city) = &config.city {
+            locat = locat.with_city_database(city).await?;
+        }
+        if let Some(asn) = &config.asn {
+            locat = locat.with_asn_database(asn).await?;
+        }
+        if let Some(window) = config.dedup_window {
+            locat = locat.with_dedup_window(window);
+        }
+        if let Some(size) = config.time_buckets {
+            locat = locat.with_time_buckets(size);
+        }
+        if let Some(size) = config.cache_size {
+            let resolver = locat.live_geoip().cached(Cache::new(size));
+            locat = locat.with_resolver(resolver);
+        }
+        if let Some(age) = config.retention {
+            locat = locat.with_retention(age);
+        }
+        #[cfg(feature = "webhook")]
+        if let Some(report) = &config.webhook {
+            locat = locat.with_webhook_report(report.clone());
+        }
+        Ok(locat)
+    }
+}
+
+/// The `webhook.*` settings, which only make a report together
+#[cfg(feature = "webhook")]
+#[derive(Default)]
+struct Webhook {
+    url: Option<String>,
+    schedule: Option<crate::Schedule>,
+    period: Option<Duration>,
+    top: Option<usize>,
+}
+
+#[cfg(feature = "webhook")]
+impl Webhook {
+    fn build(self) -> Result<Option<crate::WebhookReport>, Error> {
+        let (url, schedule) = match (self.url, self.schedule) {
+            (Some(url), Some(schedule)) => (url, schedule),
+            (None, None) if self.period.is_none() && self.top.is_none() => return Ok(None),
+            _ => {
+                return Err(invalid(
+                    "webhook needs both webhook.url and webhook.schedule".to_owned(),
+                ))
+            }
+        };
+        let mut report = crate::WebhookReport::new(url, schedule);
+        if let Some(period) = self.period {
+            report = report.with_period(period);
+        }
+        if let Some(top) = self.top {
+            report = report.with_top(top);
+        }
+        Ok(Some(report))
+    }
+}
+
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+fn parse_size(value: &str) -> Option<usize> {
+    let value = value.replace('_', "");
+    let (digits, multiplier) = match value.as_bytes().last()? {
+        b'k' | b'K' => (&value[..value.len() - 1], 1_000),
+        b'M' => (&value[..value.len() - 1], 1_000_000),
+        _ => (&value[..], 1),
+    };
+    digits.trim().parse::<usize>().ok()?.checked_mul(multiplier)
+}
+
+fn invalid(reason: String) -> Error {
+    Error::InvalidConfig(reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{parse_size, LocatConfig, Profile};
+
+    #[test]
+    fn test_parse() {
+        let config = LocatConfig::parse(
+            r#"
+            # comment
+            geoip = "/var/lib/country.mmdb"
+            analytics = analytics-%Y-%m.db
+            database.acme = /var/lib/acme.mmdb
+            dedup_window = "1h 30m"
+            cache_size = 10_000
+            retention = 90days
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.geoip.to_str(), Some("/var/lib/country.mmdb"));
+        assert_eq!(config.analytics, "analytics-%Y-%m.db");
+        assert_eq!(
+            config.databases["acme"].to_str(),
+            Some("/var/lib/acme.mmdb")
+        );
+        assert_eq!(config.dedup_window, Some(Duration::from_secs(5400)));
+        assert_eq!(config.cache_size, Some(10_000));
+        assert_eq!(config.retention, Some(Duration::from_secs(90 * 86400)));
+
+        assert_eq!(parse_size("10k"), Some(10_000));
+        assert_eq!(parse_size("2M"), Some(2_000_000));
+        assert_eq!(parse_size("lots"), None);
+
+        for bad in [
+            "analytics = a.db",
+            "geoip = g.mmdb\nanalytics = a.db\ndedup_window = 30",
+            "geoip = g.mmdb\nanalytics = a.db\ndedup_window = 0s",
+            "geoip = g.mmdb\nanalytics = a.db\ncache_size = 0",
+            "geoip = g.mmdb\nanalytics = a.db\nretention = 0s",
+            "geoip = g.mmdb\nanalytics = a.db\ntime_buckets = 500ms",
+            "geoip = g.mmdb\nanalytics = a.db\ncolour = blue",
+        ] {
+            assert!(LocatConfig::parse(bad).is_err(), "{bad}");
+        }
+    }
+
+    #[test]
+    fn test_recommended() {
+        for profile in [Profile::EdgeWorker, Profile::Server, Profile::BatchJob] {
+            let config = LocatConfig::recommended_for(profile, "g.mmdb", "a.db");
+            config.validate().unwrap();
+        }
+        let edge = LocatConfig::recommended_for(Profile::EdgeWorker, "g.mmdb", "a.db");
+        let server = LocatConfig::recommended_for(Profile::Server, "g.mmdb", "a.db");
+        assert!(edge.cache_size < server.cache_size);
+    }
+
+    #[cfg(feature = "webhook")]
+    #[test]
+    fn test_parse_webhook() {
+        let base = "geoip = g.mmdb\nanalytics = a.db\ntime_buckets = 1h\n";
+        let config = LocatConfig::parse(&format!(
+            "{base}webhook.url = http://localhost/hook\nwebhook.schedule = @daily\nwebhook.top = 3"
+        ))
+        .unwrap();
+        assert_eq!(
+            config.webhook,
+            Some(
+                crate::WebhookReport::new(
+                    "http://localhost/hook",
+                    crate::Schedule::parse("0 0 * * *").unwrap()
+                )
+                .with_top(3)
+            )
+        );
+
+        for bad in [
+            format!("{base}webhook.url = http://localhost/hook"),
+            format!("{base}webhook.top = 3"),
+            format!("{base}webhook.url = http://localhost/hook\nwebhook.schedule = 61 * * * *"),
+            "geoip = g.mmdb\nanalytics = a.db\nwebhook.url = u\nwebhook.schedule = @daily"
+                .to_owned(),
+        ] {
+            assert!(LocatConfig::parse(&bad).is_err(), "{bad}");
+        }
+    }
+}
@@ -0,0 +1,107 @@
+//! Addresses whose country is known from MaxMind's `GeoIP2-Country-Test`
+//! database, which real databases agree with. Running them after a
+//! database update or an upgrade of this crate catches classifications that
+//! shift unexpectedly.
+
+use std::net::IpAddr;
+
+use crate::Locat;
+
+/// `(address, expected ISO 3166-1 alpha-2 code)` pairs
+pub const CORPUS: &[(&str, &str)] = &[
+    ("2.125.160.216", "GB"),
+    ("81.2.69.142", "GB"),
+    ("81.2.69.160", "GB"),
+    ("81.2.69.192", "GB"),
+    ("89.160.20.112", "SE"),
+    ("89.160.20.128", "SE"),
+    ("175.16.199.0", "CN"),
+    ("202.196.224.0", "PH"),
+    ("216.160.83.56", "US"),
+    ("2001:218::", "JP"),
+    ("2001:220::", "KR"),
+];
+
+/// An address that didn't resolve to what the corpus expects
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mismatch {
+    pub addr: IpAddr,
+    pub expected: String,
+    pub actual: Option<String>,
+}
+
+/// See [`Locat::run_corpus`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CorpusReport {
+    pub total: usize,
+    pub mismatches: Vec<Mismatch>,
+}
+
+impl CorpusReport {
+    pub fn passed(&self) -> bool {
+        self.mismatches.is_empty()
+    }
+}
+
+impl Locat {
+    /// Looks up every address in [`CORPUS`] in the GeoIP database. Lookups
+    /// made here aren't counted.
+    pub fn run_corpus(&self) -> CorpusReport {
+        self.run_corpus_with(CORPUS)
+    }
+
+    /// Like [`Locat::run_corpus`], with your own `(address, country)` pairs,
+    /// e.g. ones collected from production
+    pub fn run_corpus_with(&self, corpus: &[(&str, &str)]) -> CorpusReport {
+        let geoip = self.geoip();
+        let mismatches = corpus
+            .iter()
+            .filter_map(|(addr, expected)| {
+                let addr: IpAddr = addr.parse().ok()?;
+                let actual = geoip.lookup(addr);
+                (actual != Some(*expected)).then(|| Mismatch {
+                    addr,
+                    expected: expected.to_string(),
+                    actual: actual.map(str::to_owned),
+                })
+            })
+            .collect();
+
+        CorpusReport {
+            total: corpus.len(),
+            mismatches,
+        }
+    }
+}
+
+#[cfg(all(test, feature = "analytics"))]
+mod tests {
+    use crate::{
+        testing::{RemoveOnDrop, TestDb},
+        Locat,
+    };
+
+    #[tokio::test]
+    async fn test_run_corpus() {
+        let geoip_path = "/tmp/loca-test-corpus.mmdb";
+        let analytics_path = "/tmp/loca-test-corpus.db";
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
+
+        TestDb::new()
+            .country("81.2.69.0/24", "GB", "EU")
+            .country("89.160.20.0/24", "DE", "EU")
+            .write(geoip_path);
+        let locat = Locat::new(geoip_path, analytics_path).await.unwrap();
+
+        let report = locat.run_corpus_with(&[("81.2.69.160", "GB"), ("89.160.20.112", "SE")]);
+        assert_eq!(report.total, 2);
+        assert_eq!(report.mismatches.len(), 1);
+        assert_eq!(report.mismatches[0].actual.as_deref(), Some("DE"));
+
+        assert!(!locat.run_corpus().passed());
+        assert_eq!(locat.get_analytics().await.unwrap(), vec![]);
+    }
+}
@@ -0,0 +1,398 @@
+use std::{fmt, str::FromStr};
+
+use crate::Error;
+
+/// An ISO 3166-1 alpha-2 country code, e.g. `US`. Only assigned codes
+/// exist, along with `XK` for Kosovo, which GeoIP databases use.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CountryCode(u8);
+
+// alpha-2, alpha-3 and numeric codes, sorted by alpha-2. 0 is no numeric
+// code.
+const COUNTRIES: [(&str, &str, u16); 250] = [
+    ("AD", "AND", 20),
+    ("AE", "ARE", 784),
+    ("AF", "AFG", 4),
+    ("AG", "ATG", 28),
+    ("AI", "AIA", 660),
+    ("AL", "ALB", 8),
+    ("AM", "ARM", 51),
+    ("AO", "AGO", 24),
+    ("AQ", "ATA", 10),
+    ("AR", "ARG", 32),
+    ("AS", "ASM", 16),
+    ("AT", "AUT", 40),
+    ("AU", "AUS", 36),
+    ("AW", "ABW", 533),
+    ("AX", "ALA", 248),
+    ("AZ", "AZE", 31),
+    ("BA", "BIH", 70),
+    ("BB", "BRB", 52),
+    ("BD", "BGD", 50),
+    ("BE", "BEL", 56),
+    ("BF", "BFA", 854),
+    ("BG", "BGR", 100),
+    ("BH", "BHR", 48),
+    ("BI", "BDI", 108),
+    ("BJ", "BEN", 204),
+    ("BL", "BLM", 652),
+    ("BM", "BMU", 60),
+    ("BN", "BRN", 96),
+    ("BO", "BOL", 68),
+    ("BQ", "BES", 535),
+    ("BR", "BRA", 76),
+    ("BS", "BHS", 44),
+    ("BT", "BTN", 64),
+    ("BV", "BVT", 74),
+    ("BW", "BWA", 72),
+    ("BY", "BLR", 112),
+    ("BZ", "BLZ", 84),
+    ("CA", "CAN", 124),
+    ("CC", "CCK", 166),
+    ("CD", "COD", 180),
+    ("CF", "CAF", 140),
+    ("CG", "COG", 178),
+    ("CH", "CHE", 756),
+    ("CI", "CIV", 384),
+    ("CK", "COK", 184),
+    ("CL", "CHL", 152),
+    ("CM", "CMR", 120),
+    ("CN", "CHN", 156),
+    ("CO", "COL", 170),
+    ("CR", "CRI", 188),
+    ("CU", "CUB", 192),
+    ("CV", "CPV", 132),
+    ("CW", "CUW", 531),
+    ("CX", "CXR", 162),
+    ("CY", "CYP", 196),
+    ("CZ", "CZE", 203),
+    ("DE", "DEU", 276),
+    ("DJ", "DJI", 262),
+    ("DK", "DNK", 208),
+    ("DM", "DMA", 212),
+    ("DO", "DOM", 214),
+    ("DZ", "DZA", 12),
+    ("EC", "ECU", 218),
+    ("EE", "EST", 233),
+    ("EG", "EGY", 818),
+    ("EH", "ESH", 732),
+    ("ER", "ERI", 232),
+    ("ES", "ESP", 724),
+    ("ET", "ETH", 231),
+    ("FI", "FIN", 246),
+    ("FJ", "FJI", 242),
+    ("FK", "FLK", 238),
+    ("FM", "FSM", 583),
+    ("FO", "FRO", 234),
+    ("FR", "FRA", 250),
+    ("GA", "GAB", 266),
+    ("GB", "GBR", 826),
+    ("GD", "GRD", 308),
+    ("GE", "GEO", 268),
+    ("GF", "GUF", 254),
+    ("GG", "GGY", 831),
+    ("GH", "GHA", 288),
+    ("GI", "GIB", 292),
+    ("GL", "GRL", 304),
+    ("GM", "GMB", 270),
+    ("GN", "GIN", 324),
+    ("GP", "GLP", 312),
+    ("GQ", "GNQ", 226),
+    ("GR", "GRC", 300),
+    ("GS", "SGS", 239),
+    ("GT", "GTM", 320),
+    ("GU", "GUM", 316),
+    ("GW", "GNB", 624),
+    ("GY", "GUY", 328),
+    ("HK", "HKG", 344),
+    ("HM", "HMD", 334),
+    ("HN", "HND", 340),
+    ("HR", "HRV", 191),
+    ("HT", "HTI", 332),
+    ("HU", "HUN", 348),
+    ("ID", "IDN", 360),
+    ("IE", "IRL", 372),
+    ("IL", "ISR", 376),
+    ("IM", "IMN", 833),
+    ("IN", "IND", 356),
+    ("IO", "IOT", 86),
+    ("IQ", "IRQ", 368),
+    ("IR", "IRN", 364),
+    ("IS", "ISL", 352),
+    ("IT", "ITA", 380),
+    ("JE", "JEY", 832),
+    ("JM", "JAM", 388),
+    ("JO", "JOR", 400),
+    ("JP", "JPN", 392),
+    ("KE", "KEN", 404),
+    ("KG", "KGZ", 417),
+    ("KH", "KHM", 116),
+    ("KI", "KIR", 296),
+    ("KM", "COM", 174),
+    ("KN", "KNA", 659),
+    ("KP", "PRK", 408),
+    ("KR", "KOR", 410),
+    ("KW", "KWT", 414),
+    ("KY", "CYM", 136),
+    ("KZ", "KAZ", 398),
+    ("LA", "LAO", 418),
+    ("LB", "LBN", 422),
+    ("LC", "LCA", 662),
+    ("LI", "LIE", 438),
+    ("LK", "LKA", 144),
+    ("LR", "LBR", 430),
+    ("LS", "LSO", 426),
+    ("LT", "LTU", 440),
+    ("LU", "LUX", 442),
+    ("LV", "LVA", 428),
+    ("LY", "LBY", 434),
+    ("MA", "MAR", 504),
+    ("MC", "MCO", 492),
+    ("MD", "MDA", 498),
+    ("ME", "MNE", 499),
+    ("MF", "MAF", 663),
+    ("MG", "MDG", 450),
+    ("MH", "MHL", 584),
+    ("MK", "MKD", 807),
+    ("ML", "MLI", 466),
+    ("MM", "MMR", 104),
+    ("MN", "MNG", 496),
+    ("MO", "MAC", 446),
+    ("MP", "MNP", 580),
+    ("MQ", "MTQ", 474),
+    ("MR", "MRT", 478),
+    ("MS", "MSR", 500),
+    ("MT", "MLT", 470),
+    ("MU", "MUS", 480),
+    ("MV", "MDV", 462),
+    ("MW", "MWI", 454),
+    ("MX", "MEX", 484),
+    ("MY", "MYS", 458),
+    ("MZ", "MOZ", 508),
+    ("NA", "NAM", 516),
+    ("NC", "NCL", 540),
+    ("NE", "NER", 562),
+    ("NF", "NFK", 574),
+    ("NG", "NGA", 566),
+    ("NI", "NIC", 558),
+    ("NL", "NLD", 528),
+    ("NO", "NOR", 578),
+    ("NP", "NPL", 524),
+    ("NR", "NRU", 520),
+    ("NU", "NIU", 570),
+    ("NZ", "NZL", 554),
+    ("OM", "OMN", 512),
+    ("PA", "PAN", 591),
+    ("PE", "PER", 604),
+    ("PF", "PYF", 258),
+    ("PG", "PNG", 598),
+    ("PH", "PHL", 608),
+    ("PK", "PAK", 586),
+    ("PL", "POL", 616),
+    ("PM", "SPM", 666),
+    ("PN", "PCN", 612),
+    ("PR", "PRI", 630),
+    ("PS", "PSE", 275),
+    ("PT", "PRT", 620),
+    ("PW", "PLW", 585),
+    ("PY", "PRY", 600),
+    ("QA", "QAT", 634),
+    ("RE", "REU", 638),
+    ("RO", "ROU", 642),
+    ("RS", "SRB", 688),
+    ("RU", "RUS", 643),
+    ("RW", "RWA", 646),
+    ("SA", "SAU", 682),
+    ("SB", "SLB", 90),
+    ("SC", "SYC", 690),
+    ("SD", "SDN", 729),
+    ("SE", "SWE", 752),
+    ("SG", "SGP", 702),
+    ("SH", "SHN", 654),
+    ("SI", "SVN", 705),
+    ("SJ", "SJM", 744),
+    ("SK", "SVK", 703),
+    ("SL", "SLE", 694),
+    ("SM", "SMR", 674),
+    ("SN", "SEN", 686),
+    ("SO", "SOM", 706),
+    ("SR", "SUR", 740),
+    ("SS", "SSD", 728),
+    ("ST", "STP", 678),
+    ("SV", "SLV", 222),
+    ("SX", "SXM", 534),
+    ("SY", "SYR", 760),
+    ("SZ", "SWZ", 748),
+    ("TC", "TCA", 796),
+    ("TD", "TCD", 148),
+    ("TF", "ATF", 260),
+    ("TG", "TGO", 768),
+    ("TH", "THA", 764),
+    ("TJ", "TJK", 762),
+    ("TK", "TKL", 772),
+    ("TL", "TLS", 626),
+    ("TM", "TKM", 795),
+    ("TN", "TUN", 788),
+    ("TO", "TON", 776),
+    ("TR", "TUR", 792),
+    ("TT", "TTO", 780),
+    ("TV", "TUV", 798),
+    ("TW", "TWN", 158),
+    ("TZ", "TZA", 834),
+    ("UA", "UKR", 804),
+    ("UG", "UGA", 800),
+    ("UM", "UMI", 581),
+    ("US", "USA", 840),
+    ("UY", "URY", 858),
+    ("UZ", "UZB", 860),
+    ("VA", "VAT", 336),
+    ("VC", "VCT", 670),
+    ("VE", "VEN", 862),
+    ("VG", "VGB", 92),
+    ("VI", "VIR", 850),
+    ("VN", "VNM", 704),
+    ("VU", "VUT", 548),
+    ("WF", "WLF", 876),
+    ("WS", "WSM", 882),
+    ("XK", "XKX", 0),
+    ("YE", "YEM", 887),
+    ("YT", "MYT", 175),
+    ("ZA", "ZAF", 710),
+    ("ZM", "ZMB", 894),
+    ("ZW", "ZWE", 716),
+];
+
+// codes people use that ISO doesn't
+const ALIASES: [(&str, &str); 3] = [
+    // the United Kingdom's own top-level domain
+    ("UK", "GB"),
+    // Greece, in EU statistics
+    ("EL", "GR"),
+    ("KOS", "XK"),
+];
+
+impl CountryCode {
+    /// Makes sense of a country code typed by a person: any case, alpha-2,
+    /// alpha-3 or numeric (`"us"`, `"USA"`, `"840"`), and common aliases
+    /// like `"UK"` for `GB`
+    pub fn parse_lenient(input: &str) -> Result<Self, Error> {
+        let code = input.trim().to_ascii_uppercase();
+        let code = ALIASES
+            .iter()
+            .find(|(alias, _)| *alias == code)
+            .map_or(code.as_str(), |(_, alpha2)| alpha2);
+
+        let found = if code.bytes().all(|b| b.is_ascii_digit()) {
+            code.parse::<u16>().ok().and_then(|numeric| {
+                COUNTRIES
+                    .iter()
+                    .position(|entry| entry.2 == numeric && numeric != 0)
+            })
+        } else if code.len() == 3 {
+            COUNTRIES.iter().position(|entry| entry.1 == code)
+        } else {
+            Self::position(code)
+        };
+        found
+            .map(|index| Self(index as u8))
+            .ok_or_else(|| Error::UnknownCountry(input.to_owned()))
+    }
+
+    fn position(alpha2: &str) -> Option<usize> {
+        COUNTRIES.binary_search_by(|entry| entry.0.cmp(alpha2)).ok()
+    }
+
+    /// The alpha-2 code, as returned by lookups
+    pub fn as_str(&self) -> &'static str {
+        COUNTRIES[self.0 as usize].0
+    }
+
+    pub fn alpha3(&self) -> &'static str {
+        COUNTRIES[self.0 as usize].1
+    }
+
+    /// `None` for `XK`, which has none
+    pub fn numeric(&self) -> Option<u16> {
+        match COUNTRIES[self.0 as usize].2 {
+            0 => None,
+            numeric => Some(numeric),
+        }
+    }
+}
+
+/// Strict: only the alpha-2 code in upper case, see
+/// [`CountryCode::parse_lenient`] for anything else
+impl FromStr for CountryCode {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::position(s)
+            .map(|index| Self(index as u8))
+            .ok_or_else(|| Error::UnknownCountry(s.to_owned()))
+    }
+}
+
+impl fmt::Display for CountryCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for CountryCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("CountryCode").field(&self.as_str()).finish()
+    }
+}
+
+impl AsRef<str> for CountryCode {
+    fn as_ref(&self) -> &str {
+        self.as_str()
+    }
+}
+
+// as the alpha-2 code, strictly parsed back
+#[cfg(feature = "serde")]
+impl serde::Serialize for CountryCode {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for CountryCode {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let code = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        code.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CountryCode, COUNTRIES};
+
+    #[test]
+    fn test_parse_lenient() {
+        assert!(COUNTRIES.windows(2).all(|pair| pair[0].0 < pair[1].0));
+
+        for input in ["GB", "gb", " Gb ", "GBR", "gbr", "826", "UK", "uk"] {
+            assert_eq!(
+                CountryCode::parse_lenient(input).unwrap().as_str(),
+                "GB",
+                "{input}"
+            );
+        }
+        let us = CountryCode::parse_lenient("usa").unwrap();
+        assert_eq!((us.alpha3(), us.numeric()), ("USA", Some(840)));
+        assert_eq!(CountryCode::parse_lenient("4").unwrap().as_str(), "AF");
+        assert_eq!(CountryCode::parse_lenient("EL").unwrap().as_str(), "GR");
+        assert_eq!(CountryCode::parse_lenient("xk").unwrap().numeric(), None);
+
+        for input in ["", "ZZ", "U", "USAA", "0", "999", "EU"] {
+            assert!(CountryCode::parse_lenient(input).is_err(), "{input}");
+        }
+        // strict parsing takes alpha-2 in upper case only
+        assert!("US".parse::<CountryCode>().is_ok());
+        assert!("us".parse::<CountryCode>().is_err());
+        assert!("UK".parse::<CountryCode>().is_err());
+    }
+}
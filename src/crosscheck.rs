@@ -0,0 +1,143 @@
+use std::net::IpAddr;
+
+use crate::{Locat, Outcome, Resolution, Resolver};
+
+/// What [`Locat`] and another source make of one address, see
+/// [`Locat::crosscheck`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Crosscheck {
+    pub addr: IpAddr,
+    /// What [`Locat::lookup_untracked`] found
+    pub primary: Option<Resolution>,
+    pub other: Option<Resolution>,
+}
+
+/// How the two answers of a [`Crosscheck`] relate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Agreement {
+    /// Same country
+    Agree,
+    /// Both found a country, but not the same one
+    Disagree,
+    OnlyPrimary,
+    OnlyOther,
+    /// Neither found a country
+    Unknown,
+}
+
+impl Crosscheck {
+    pub fn agreement(&self) -> Agreement {
+        match (&self.primary, &self.other) {
+            (Some(primary), Some(other)) if primary.iso_code == other.iso_code => Agreement::Agree,
+            (Some(_), Some(_)) => Agreement::Disagree,
+            (Some(_), None) => Agreement::OnlyPrimary,
+            (None, Some(_)) => Agreement::OnlyOther,
+            (None, None) => Agreement::Unknown,
+        }
+    }
+}
+
+/// Crosschecks of many addresses, see [`Locat::crosscheck_all`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CrosscheckReport {
+    pub agree: usize,
+    pub only_primary: usize,
+    pub only_other: usize,
+    pub unknown: usize,
+    /// Addresses the two sources put in different countries
+    pub disagreements: Vec<Crosscheck>,
+}
+
+impl CrosscheckReport {
+    /// The fraction of addresses both sources located that they located in
+    /// the same country, 1 if there were none
+    pub fn agreement_rate(&self) -> f64 {
+        match self.agree + self.disagreements.len() {
+            0 => 1.0,
+            both => self.agree as f64 / both as f64,
+        }
+    }
+}
+
+impl Locat {
+    /// Compares this `Locat`'s answer for `addr` with another source's,
+    /// e.g. a second vendor's database, to keep an eye on accuracy. This
+    /// doesn't count towards analytics.
+    pub fn crosscheck(&self, addr: IpAddr, other: &dyn Resolver) -> Crosscheck {
+        let primary = match self.lookup_untracked(addr) {
+            Outcome::Located(resolution) => Some(resolution),
+            _ => None,
+        };
+        Crosscheck {
+            addr,
+            primary,
+            other: other.resolve(addr),
+        }
+    }
+
+    /// [`Locat::crosscheck`]s every address, e.g. a sample of recent
+    /// traffic, keeping the details of disagreements only
+    pub fn crosscheck_all(&self, addrs: &[IpAddr], other: &dyn Resolver) -> CrosscheckReport {
+        let mut report = CrosscheckReport::default();
+        for &addr in addrs {
+            let check = self.crosscheck(addr, other);
+            match check.agreement() {
+                Agreement::Agree => report.agree += 1,
+                Agreement::Disagree => report.disagreements.push(check),
+                Agreement::OnlyPrimary => report.only_primary += 1,
+                Agreement::OnlyOther => report.only_other += 1,
+                Agreement::Unknown => report.unknown += 1,
+            }
+        }
+        report
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{testing::TestDb, Agreement, GeoIp, Locat};
+
+    #[tokio::test]
+    async fn test_crosscheck() {
+        let locat = Locat::builder()
+            .with_geoip_bytes(
+                TestDb::new()
+                    .country("1.2.3.0/24", "US", "NA")
+                    .country("5.6.7.0/24", "FR", "EU")
+                    .country("9.9.9.0/24", "DE", "EU")
+                    .build(),
+            )
+            .build()
+            .await
+            .unwrap();
+        let other = GeoIp::load(
+            TestDb::new()
+                .country("1.2.3.0/24", "US", "NA")
+                .country("5.6.7.0/24", "BE", "EU")
+                .country("8.8.8.0/24", "US", "NA")
+                .build(),
+        )
+        .unwrap();
+
+        let check = locat.crosscheck("5.6.7.8".parse().unwrap(), &other);
+        assert_eq!(check.agreement(), Agreement::Disagree);
+        assert_eq!(check.primary.unwrap().iso_code, "FR");
+        assert_eq!(check.other.unwrap().iso_code, "BE");
+
+        let addrs = ["1.2.3.4", "5.6.7.8", "9.9.9.9", "8.8.8.8", "7.7.7.7"]
+            .map(|addr| addr.parse().unwrap());
+        let report = locat.crosscheck_all(&addrs, &other);
+        assert_eq!(
+            (
+                report.agree,
+                report.only_primary,
+                report.only_other,
+                report.unknown
+            ),
+            (1, 1, 1, 1)
+        );
+        assert_eq!(report.disagreements.len(), 1);
+        assert_eq!(report.agreement_rate(), 0.5);
+    }
+}
@@ -0,0 +1,30 @@
+use polars::prelude::{Column, DataFrame};
+
+use crate::{Error, Locat};
+
+impl Locat {
+    /// Returns analytics as a polars `DataFrame`, one row per country with
+    /// `iso_code`, `count` and `sum` columns
+    pub async fn to_dataframe(&self) -> Result<DataFrame, Error> {
+        let analytics = self.inner.analytics.measures().await?;
+
+        let height = analytics.len();
+        let mut iso_codes = Vec::with_capacity(analytics.len());
+        let mut counts = Vec::with_capacity(analytics.len());
+        let mut sums = Vec::with_capacity(analytics.len());
+        for (iso_code, measures) in analytics {
+            iso_codes.push(iso_code);
+            counts.push(measures.count);
+            sums.push(measures.sum);
+        }
+
+        Ok(DataFrame::new(
+            height,
+            vec![
+                Column::new("iso_code".into(), iso_codes),
+                Column::new("count".into(), counts),
+                Column::new("sum".into(), sums),
+            ],
+        )?)
+    }
+}
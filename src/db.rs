@@ -0,0 +1,2120 @@
+use std::{
+    collections::{hash_map::RandomState, BTreeMap},
+    hash::BuildHasher,
+    ops::Range,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+// We're using tokio-rusqlite's own Connection type now
+use async_trait::async_trait;
+use rusqlite::{types::Value, OpenFlags, OptionalExtension};
+use tokio_rusqlite::Connection;
+
+use crate::{
+    audit::AuditEntry,
+    batch::Pending,
+    exemplars::NewExemplar,
+    portable::{Export, Weight, LEGACY_MEASURE},
+    rollover::PathTemplate,
+    rollups::{CONTINENTS, UNKNOWN_CONTINENT},
+    sketch::Sketch,
+    subnets::SubnetCount,
+    transaction::Change,
+    AnalyticsQuery, AnalyticsStore, Error, Measures,
+};
+
+/// Rows returned by [`crate::Locat::query_raw_readonly`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawRows {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+}
+
+/// What the analytics file knows about the software that wrote it, see
+/// [`crate::Locat::analytics_info`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnalyticsInfo {
+    /// Crate version that created the file, `None` for files created before
+    /// this was recorded
+    pub created_by: Option<String>,
+    /// When the file was created, `None` for files created before this was
+    /// recorded
+    pub created_at: Option<SystemTime>,
+    /// Crate version that opened the file most recently
+    pub last_opened_by: String,
+    pub schema_version: i64,
+}
+
+// bump along with each new migration in `Db::connect`
+const SCHEMA_VERSION: i64 = 14;
+
+// statements whose query plans `test_db_query_plans` checks
+const SELECT_BUCKETS: &str = "SELECT bucket_start, iso_code, count FROM buckets
+    WHERE bucket_start >= ? AND bucket_start < ?";
+const SELECT_SUBNETS: &str = "SELECT subnet, iso_code, count, last_seen FROM subnets
+    ORDER BY count DESC, subnet, iso_code";
+const SELECT_CONTINENT_TOTALS: &str = "SELECT continent, SUM(count) FROM daily_totals
+    GROUP BY continent HAVING SUM(count) > 0 ORDER BY continent";
+const SELECT_DAILY_TOTALS: &str = "SELECT day, continent, count FROM daily_totals
+    WHERE day >= ? AND day < ? AND count > 0 ORDER BY day, continent";
+const SELECT_STATUS_CLASSES: &str = "SELECT iso_code, class, count FROM statuses
+    ORDER BY iso_code, class";
+const ADD_DAILY_TOTAL: &str = "INSERT INTO daily_totals (day, continent, count)
+    VALUES (?1, COALESCE((SELECT continent FROM continents WHERE iso_code = ?2), ?3), ?4)
+    ON CONFLICT (day, continent) DO UPDATE SET count = count + excluded.count";
+const DELETE_BUCKETS: &str = "DELETE FROM buckets WHERE bucket_start < ?";
+const DELETE_SUBNETS: &str = "DELETE FROM subnets WHERE last_seen < ?";
+const SELECT_EXEMPLARS: &str = "SELECT day, exemplar FROM exemplars
+    WHERE iso_code = ? AND day >= ? ORDER BY day, exemplar";
+const COUNT_EXEMPLARS: &str = "SELECT COUNT(*) FROM exemplars WHERE iso_code = ? AND day = ?";
+const DELETE_EXEMPLARS: &str = "DELETE FROM exemplars WHERE day < ?";
+
+/// Connection settings applied to every file, see [`crate::LocatBuilder`]
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Pragmas {
+    pub(crate) wal: bool,
+    pub(crate) busy_timeout: Option<Duration>,
+    // temporary tables and indices in memory rather than in files
+    pub(crate) memory_temp_store: bool,
+}
+
+pub(crate) struct Db {
+    path: PathTemplate,
+    pragmas: Pragmas,
+    // the path the connection was opened with: when the template renders to
+    // something else, it's time to roll over to a new file
+    current: Mutex<(String, Connection)>,
+    // see `Db::close`
+    closed: AtomicBool,
+}
+
+// what calls fail with once closed, turned into `Error::Closed`
+const CLOSED: &str = "analytics store closed";
+
+fn closed() -> rusqlite::Error {
+    rusqlite::Error::SqliteFailure(
+        rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_MISUSE),
+        Some(CLOSED.to_owned()),
+    )
+}
+
+/// Whether `e` is from a call made after [`Db::close`]
+pub(crate) fn is_closed(e: &rusqlite::Error) -> bool {
+    matches!(e, rusqlite::Error::SqliteFailure(_, Some(message)) if message == CLOSED)
+}
+
+impl Db {
+    /// `path` may contain date placeholders like `analytics-%Y-%m.db`, in
+    /// which case a new database is started every period
+    pub(crate) async fn open(path: &str) -> Result<Self, rusqlite::Error> {
+        Self::open_with(path, Pragmas::default()).await
+    }
+
+    pub(crate) async fn open_with(path: &str, pragmas: Pragmas) -> Result<Self, rusqlite::Error> {
+        let path = PathTemplate::new(path);
+        let current = path.render(SystemTime::now()).into_owned();
+        let conn = Self::connect(&current, pragmas).await?;
+
+        Ok(Self {
+            path,
+            pragmas,
+            current: Mutex::new((current, conn)),
+            closed: AtomicBool::new(false),
+        })
+    }
+
+    /// The file analytics are currently written to
+    pub(crate) fn current_path(&self) -> String {
+        self.current.lock().unwrap().0.clone()
+    }
+
+    async fn conn(&self) -> Result<Connection, rusqlite::Error> {
+        if self.closed.load(Ordering::Acquire) {
+            return Err(closed());
+        }
+        let path = self.path.render(SystemTime::now());
+        {
+            let current = self.current.lock().unwrap();
+            if current.0 == path {
+                return Ok(current.1.clone());
+            }
+        }
+
+        // the lock can't be held across an await: if two calls race to roll
+        // over, both open the same file, which is harmless
+        let conn = Self::connect(&path, self.pragmas).await?;
+        *self.current.lock().unwrap() = (path.into_owned(), conn.clone());
+        Ok(conn)
+    }
+
+    async fn connect(path: &str, pragmas: Pragmas) -> Result<Connection, rusqlite::Error> {
+        // open and migrate a db in a non-blocking way
+        let conn = Connection::open(path).await?;
+
+        // this is how operations are run on a thread pool: we pass a
+        // closure. not that it must be `'static`, so we can't borrow
+        // anything from the outside: owned types only.
+        conn.call(move |conn| {
+            if let Some(timeout) = pragmas.busy_timeout {
+                conn.busy_timeout(timeout)?;
+            }
+            if pragmas.memory_temp_store {
+                conn.pragma_update(None, "temp_store", "MEMORY")?;
+            }
+            if pragmas.wal {
+                // in-memory databases stay in `memory` mode, which is fine
+                conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| {
+                    row.get::<_, String>(0)
+                })?;
+            }
+
+            // files from before `user_version` was set are at version 0 too,
+            // only a file without the analytics table is new
+            let created: bool = conn.query_row(
+                "SELECT NOT EXISTS (
+                    SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = 'analytics'
+                )",
+                [],
+                |row| row.get(0),
+            )?;
+
+            // create analytics table
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS analytics (
+                iso_code TEXT PRIMARY KEY,
+                count INTEGER NOT NULL
+            )",
+                [],
+            )?;
+
+            // later additions to the schema are applied in order, and
+            // `user_version` remembers how far along a given file is. each
+            // one is bumped in the same transaction as the change, so a
+            // crash can't leave a file half migrated
+            let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+            if version < 1 {
+                let tx = conn.transaction()?;
+                tx.execute(
+                    "ALTER TABLE analytics ADD COLUMN sum INTEGER NOT NULL DEFAULT 0",
+                    [],
+                )?;
+                tx.pragma_update(None, "user_version", 1)?;
+                tx.commit()?;
+            }
+            if version < 2 {
+                // counters that aren't tied to a country
+                let tx = conn.transaction()?;
+                tx.execute(
+                    "CREATE TABLE counters (
+                    name TEXT PRIMARY KEY,
+                    count INTEGER NOT NULL
+                )",
+                    [],
+                )?;
+                tx.pragma_update(None, "user_version", 2)?;
+                tx.commit()?;
+            }
+            if version < 3 {
+                // which versions of this crate touched the file, so outdated
+                // nodes can be spotted from their data alone
+                let tx = conn.transaction()?;
+                tx.execute(
+                    "CREATE TABLE metadata (
+                        key TEXT PRIMARY KEY,
+                        value TEXT NOT NULL
+                    )",
+                    [],
+                )?;
+                // older files were created by who knows what
+                if created {
+                    let now = SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .unwrap_or_default()
+                        .as_secs();
+                    tx.execute(
+                        "INSERT INTO metadata (key, value) VALUES ('created_by', ?), ('created_at', ?)",
+                        [env!("CARGO_PKG_VERSION"), &now.to_string()],
+                    )?;
+                }
+                tx.pragma_update(None, "user_version", 3)?;
+                tx.commit()?;
+            }
+            if version < 4 {
+                // distributions of weights, see `Sketch`
+                let tx = conn.transaction()?;
+                tx.execute(
+                    "CREATE TABLE sketches (
+                        iso_code TEXT PRIMARY KEY,
+                        sketch BLOB NOT NULL
+                    )",
+                    [],
+                )?;
+                tx.pragma_update(None, "user_version", 4)?;
+                tx.commit()?;
+            }
+            if version < 5 {
+                // counts over time, see `Locat::with_time_buckets`
+                let tx = conn.transaction()?;
+                tx.execute(
+                    "CREATE TABLE buckets (
+                        iso_code TEXT NOT NULL,
+                        bucket_start INTEGER NOT NULL,
+                        count INTEGER NOT NULL,
+                        PRIMARY KEY (iso_code, bucket_start)
+                    )",
+                    [],
+                )?;
+                tx.pragma_update(None, "user_version", 5)?;
+                tx.commit()?;
+            }
+            if version < 6 {
+                // administrative actions, see `Locat::audit_log`. rows can be
+                // added, but neither changed nor removed
+                let tx = conn.transaction()?;
+                tx.execute_batch(
+                    "CREATE TABLE audit (
+                        id INTEGER PRIMARY KEY,
+                        at INTEGER NOT NULL,
+                        action TEXT NOT NULL,
+                        actor TEXT,
+                        detail TEXT
+                    );
+                    CREATE TRIGGER audit_no_update BEFORE UPDATE ON audit
+                    BEGIN SELECT RAISE(ABORT, 'the audit log is append-only'); END;
+                    CREATE TRIGGER audit_no_delete BEFORE DELETE ON audit
+                    BEGIN SELECT RAISE(ABORT, 'the audit log is append-only'); END;",
+                )?;
+                tx.pragma_update(None, "user_version", 6)?;
+                tx.commit()?;
+            }
+            if version < 7 {
+                // counts per subnet, see `Locat::with_subnet_analytics`
+                let tx = conn.transaction()?;
+                tx.execute_batch(
+                    "CREATE TABLE subnets (
+                        subnet TEXT NOT NULL,
+                        iso_code TEXT NOT NULL,
+                        count INTEGER NOT NULL,
+                        last_seen INTEGER NOT NULL,
+                        PRIMARY KEY (subnet, iso_code)
+                    );
+                    CREATE INDEX subnets_last_seen ON subnets (last_seen);",
+                )?;
+                tx.pragma_update(None, "user_version", 7)?;
+                tx.commit()?;
+            }
+            if version < 8 {
+                // totals per day and continent, see
+                // `Locat::get_analytics_by_continent`. triggers keep them up
+                // to date however counts are written
+                let tx = conn.transaction()?;
+                tx.execute_batch(
+                    "CREATE TABLE continents (
+                        iso_code TEXT PRIMARY KEY,
+                        continent TEXT NOT NULL
+                    );
+                    CREATE TABLE daily_totals (
+                        day INTEGER NOT NULL,
+                        continent TEXT NOT NULL,
+                        count INTEGER NOT NULL,
+                        PRIMARY KEY (day, continent)
+                    );
+                    CREATE TRIGGER daily_totals_insert AFTER INSERT ON analytics
+                    BEGIN
+                        INSERT INTO daily_totals (day, continent, count)
+                        VALUES (
+                            CAST(strftime('%s', 'now') AS INTEGER) / 86400,
+                            COALESCE((SELECT continent FROM continents WHERE iso_code = NEW.iso_code), '--'),
+                            NEW.count
+                        )
+                        ON CONFLICT (day, continent) DO UPDATE SET count = count + excluded.count;
+                    END;
+                    CREATE TRIGGER daily_totals_update AFTER UPDATE OF count ON analytics
+                    WHEN NEW.count != OLD.count
+                    BEGIN
+                        INSERT INTO daily_totals (day, continent, count)
+                        VALUES (
+                            CAST(strftime('%s', 'now') AS INTEGER) / 86400,
+                            COALESCE((SELECT continent FROM continents WHERE iso_code = NEW.iso_code), '--'),
+                            NEW.count - OLD.count
+                        )
+                        ON CONFLICT (day, continent) DO UPDATE SET count = count + excluded.count;
+                    END;",
+                )?;
+                {
+                    let mut stmt =
+                        tx.prepare("INSERT INTO continents (iso_code, continent) VALUES (?, ?)")?;
+                    for (continent, countries) in CONTINENTS {
+                        for iso_code in *countries {
+                            stmt.execute([iso_code, continent])?;
+                        }
+                    }
+                }
+                // what was counted before goes to today
+                tx.execute(
+                    "INSERT INTO daily_totals (day, continent, count)
+                    SELECT CAST(strftime('%s', 'now') AS INTEGER) / 86400,
+                        COALESCE(continent, ?), SUM(count)
+                    FROM analytics LEFT JOIN continents USING (iso_code)
+                    GROUP BY 2",
+                    [UNKNOWN_CONTINENT],
+                )?;
+                tx.pragma_update(None, "user_version", 8)?;
+                tx.commit()?;
+            }
+            if version < 9 {
+                // for time ranges, top-N and totals without scanning, see
+                // `test_db_query_plans`
+                let tx = conn.transaction()?;
+                tx.execute_batch(
+                    "CREATE INDEX buckets_bucket_start ON buckets (bucket_start);
+                    CREATE INDEX analytics_count ON analytics (count DESC, iso_code);
+                    CREATE INDEX subnets_count ON subnets (count DESC, subnet, iso_code);
+                    CREATE INDEX daily_totals_continent ON daily_totals (continent, count);",
+                )?;
+                tx.pragma_update(None, "user_version", 9)?;
+                tx.commit()?;
+            }
+            if version < 10 {
+                // response times per country, see `Locat::record_latency`
+                let tx = conn.transaction()?;
+                tx.execute(
+                    "CREATE TABLE latencies (
+                        iso_code TEXT PRIMARY KEY,
+                        sketch BLOB NOT NULL
+                    )",
+                    [],
+                )?;
+                tx.pragma_update(None, "user_version", 10)?;
+                tx.commit()?;
+            }
+            if version < 11 {
+                // responses per country and status class, see
+                // `Locat::record_status`
+                let tx = conn.transaction()?;
+                tx.execute(
+                    "CREATE TABLE statuses (
+                        iso_code TEXT NOT NULL,
+                        class TEXT NOT NULL,
+                        count INTEGER NOT NULL,
+                        PRIMARY KEY (iso_code, class)
+                    )",
+                    [],
+                )?;
+                tx.pragma_update(None, "user_version", 11)?;
+                tx.commit()?;
+            }
+            if version < 12 {
+                // weighted measures kept apart from lookups, see
+                // `Locat::record_weighted`. what was recorded before was a
+                // single measure, whose events were also counted as lookups
+                // and stay counted
+                let tx = conn.transaction()?;
+                tx.execute(
+                    "CREATE TABLE weights (
+                        iso_code TEXT NOT NULL,
+                        measure TEXT NOT NULL,
+                        count INTEGER NOT NULL,
+                        sum INTEGER NOT NULL,
+                        sketch BLOB NOT NULL,
+                        PRIMARY KEY (iso_code, measure)
+                    )",
+                    [],
+                )?;
+                let recorded = tx
+                    .prepare(
+                        "SELECT iso_code, sum, sketch FROM analytics LEFT JOIN sketches USING (iso_code)
+                        WHERE sum > 0 OR sketch IS NOT NULL",
+                    )?
+                    .query_map([], |row| {
+                        Ok((
+                            row.get::<_, String>(0)?,
+                            row.get::<_, u64>(1)?,
+                            row.get::<_, Option<Vec<u8>>>(2)?,
+                        ))
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+                for (iso_code, sum, sketch) in recorded {
+                    let sketch = sketch
+                        .and_then(|bytes| Sketch::from_bytes(&bytes))
+                        .unwrap_or_default();
+                    tx.execute(
+                        "INSERT INTO weights (iso_code, measure, count, sum, sketch) VALUES (?, ?, ?, ?, ?)",
+                        rusqlite::params![iso_code, LEGACY_MEASURE, sketch.count(), sum, sketch.to_bytes()],
+                    )?;
+                }
+                tx.execute_batch(
+                    "UPDATE analytics SET sum = 0;
+                    DROP TABLE sketches;",
+                )?;
+                tx.pragma_update(None, "user_version", 12)?;
+                tx.commit()?;
+            }
+            if version < 13 {
+                // daily totals are written along with lookups, for the day
+                // they were made on, see `add_daily_total`. the triggers
+                // credited imports and corrections to the day they happened
+                let tx = conn.transaction()?;
+                tx.execute_batch(
+                    "DROP TRIGGER daily_totals_insert;
+                    DROP TRIGGER daily_totals_update;",
+                )?;
+                tx.pragma_update(None, "user_version", 13)?;
+                tx.commit()?;
+            }
+            if version < 14 {
+                // hashed addresses, see `Locat::with_exemplars`
+                let tx = conn.transaction()?;
+                tx.execute_batch(
+                    "CREATE TABLE exemplars (
+                        iso_code TEXT NOT NULL,
+                        day INTEGER NOT NULL,
+                        exemplar TEXT NOT NULL,
+                        PRIMARY KEY (iso_code, day, exemplar)
+                    );
+                    CREATE INDEX exemplars_day ON exemplars (day);",
+                )?;
+                tx.pragma_update(None, "user_version", SCHEMA_VERSION)?;
+                tx.commit()?;
+            }
+            conn.execute(
+                "INSERT INTO metadata (key, value) VALUES ('last_opened_by', ?)
+                ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+                [env!("CARGO_PKG_VERSION")],
+            )?;
+
+            Ok::<_, rusqlite::Error>(())
+        })
+        .await?;
+
+        Ok(conn)
+    }
+
+    // `Locat` goes through `AnalyticsStore::measures`
+    #[cfg(test)]
+    pub(crate) async fn list(&self) -> Result<Vec<(String, u64)>, rusqlite::Error> {
+        self.conn()
+            .await?
+            .call(|conn| {
+                let mut stmt = conn.prepare("SELECT iso_code, count FROM analytics")?;
+                let mut rows = stmt.query([])?;
+                let mut analytics = Vec::new();
+                while let Some(row) = rows.next()? {
+                    let iso_code: String = row.get(0)?;
+                    let count: u64 = row.get(1)?;
+                    analytics.push((iso_code, count));
+                }
+                Ok(analytics)
+            })
+            .await
+    }
+
+    /// Counts summed over every file the path template rendered to within
+    /// `range`. Files are only read, never migrated, and missing ones are
+    /// skipped.
+    pub(crate) async fn list_period(
+        &self,
+        range: Range<SystemTime>,
+    ) -> Result<Vec<(String, u64)>, rusqlite::Error> {
+        let mut totals: BTreeMap<String, u64> = BTreeMap::new();
+        for path in self.path.paths(range) {
+            for (iso_code, count) in read_counts(path).await? {
+                *totals.entry(iso_code).or_default() += count;
+            }
+        }
+        Ok(totals.into_iter().collect())
+    }
+
+    /// The counts of every hour within `range`, or `None` if the path
+    /// template doesn't roll over every hour
+    pub(crate) async fn list_hourly(
+        &self,
+        range: Range<SystemTime>,
+    ) -> Result<Option<Vec<(SystemTime, Vec<(String, u64)>)>>, rusqlite::Error> {
+        if !self.path.is_hourly() {
+            return Ok(None);
+        }
+        let mut hours = Vec::new();
+        for (hour, path) in self.path.hours(range) {
+            hours.push((hour, read_counts(path).await?));
+        }
+        Ok(Some(hours))
+    }
+
+    /// The buckets that start within `range`, from every file the path
+    /// template rendered to within it
+    pub(crate) async fn list_buckets(
+        &self,
+        range: Range<SystemTime>,
+    ) -> Result<Vec<(SystemTime, String, u64)>, rusqlite::Error> {
+        let secs = |at: SystemTime| at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let (start, end) = (secs(range.start), secs(range.end));
+        let mut buckets = Vec::new();
+        for path in self.path.paths(range) {
+            buckets.extend(read_buckets(path, start, end).await?);
+        }
+        Ok(buckets)
+    }
+
+    pub(crate) async fn list_measures(&self) -> Result<Vec<(String, Measures)>, rusqlite::Error> {
+        self.conn()
+            .await?
+            .call(|conn| {
+                let mut stmt = conn.prepare("SELECT iso_code, count, sum FROM analytics")?;
+                let mut rows = stmt.query([])?;
+                let mut analytics = Vec::new();
+                while let Some(row) = rows.next()? {
+                    let iso_code: String = row.get(0)?;
+                    let count: u64 = row.get(1)?;
+                    let sum: u64 = row.get(2)?;
+                    analytics.push((iso_code, Measures { count, sum }));
+                }
+                Ok(analytics)
+            })
+            .await
+    }
+
+    pub(crate) async fn query_analytics(
+        &self,
+        query: AnalyticsQuery,
+    ) -> Result<Vec<(String, u64)>, rusqlite::Error> {
+        let (sql, params) = query_sql(&query);
+        self.conn()
+            .await?
+            .call(move |conn| {
+                let mut stmt = conn.prepare(&sql)?;
+                let rows = stmt.query_map(rusqlite::params_from_iter(params), |row| {
+                    Ok((row.get(0)?, row.get(1)?))
+                })?;
+                rows.collect()
+            })
+            .await
+    }
+
+    pub(crate) async fn increment(&self, iso_code: &str) -> Result<(), rusqlite::Error> {
+        self.record(iso_code, 0).await
+    }
+
+    pub(crate) async fn record(&self, iso_code: &str, weight: u64) -> Result<(), rusqlite::Error> {
+        // we have to use `iso_code` from within the closure and the closure
+        // must be 'static, so:
+        let iso_code = iso_code.to_owned();
+
+        self.conn().await?.call(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute(
+                "INSERT INTO analytics (iso_code, count, sum) VALUES (?, 1, ?) ON CONFLICT (iso_code) DO UPDATE SET count = count + 1, sum = sum + excluded.sum",
+                rusqlite::params![iso_code, weight],
+            )?;
+            add_daily_total(&tx, today(), &iso_code, 1)?;
+            tx.commit()
+        }).await
+    }
+
+    /// Like `increment`, also counting towards the bucket starting at
+    /// `bucket_start` (in seconds since the epoch)
+    pub(crate) async fn increment_bucketed(
+        &self,
+        iso_code: &str,
+        bucket_start: u64,
+    ) -> Result<(), rusqlite::Error> {
+        let iso_code = iso_code.to_owned();
+
+        self.conn().await?.call(move |conn| {
+            let tx = conn.transaction()?;
+            tx.execute(
+                "INSERT INTO analytics (iso_code, count, sum) VALUES (?, 1, 0) ON CONFLICT (iso_code) DO UPDATE SET count = count + 1",
+                [&iso_code],
+            )?;
+            tx.execute(
+                "INSERT INTO buckets (iso_code, bucket_start, count) VALUES (?, ?, 1) ON CONFLICT (iso_code, bucket_start) DO UPDATE SET count = count + 1",
+                rusqlite::params![iso_code, bucket_start],
+            )?;
+            add_daily_total(&tx, today(), &iso_code, 1)?;
+            tx.commit()
+        }).await
+    }
+
+    /// Adds `weight` to a measure of a country, keeping track of the
+    /// distribution of weights too
+    pub(crate) async fn record_weighted(
+        &self,
+        iso_code: &str,
+        measure: &str,
+        weight: u64,
+    ) -> Result<(), rusqlite::Error> {
+        let (iso_code, measure) = (iso_code.to_owned(), measure.to_owned());
+
+        self.conn().await?.call(move |conn| {
+            let tx = conn.transaction()?;
+            let mut sketch = tx
+                .query_row(
+                    "SELECT sketch FROM weights WHERE iso_code = ? AND measure = ?",
+                    [&iso_code, &measure],
+                    |row| row.get::<_, Vec<u8>>(0),
+                )
+                .optional()?
+                .and_then(|bytes| Sketch::from_bytes(&bytes))
+                .unwrap_or_default();
+            sketch.insert(weight);
+            tx.execute(
+                "INSERT INTO weights (iso_code, measure, count, sum, sketch) VALUES (?, ?, 1, ?, ?) ON CONFLICT (iso_code, measure) DO UPDATE SET count = count + 1, sum = sum + excluded.sum, sketch = excluded.sketch",
+                rusqlite::params![iso_code, measure, weight, sketch.to_bytes()],
+            )?;
+            tx.commit()
+        }).await
+    }
+
+    pub(crate) async fn sketch(
+        &self,
+        iso_code: &str,
+        measure: &str,
+    ) -> Result<Option<Sketch>, rusqlite::Error> {
+        let (iso_code, measure) = (iso_code.to_owned(), measure.to_owned());
+        self.conn()
+            .await?
+            .call(move |conn| {
+                let bytes = conn
+                    .query_row(
+                        "SELECT sketch FROM weights WHERE iso_code = ? AND measure = ?",
+                        [iso_code, measure],
+                        |row| row.get::<_, Vec<u8>>(0),
+                    )
+                    .optional()?;
+                Ok(bytes.and_then(|bytes| Sketch::from_bytes(&bytes)))
+            })
+            .await
+    }
+
+    /// Every weighted measure, by country then measure
+    pub(crate) async fn list_weights(&self) -> Result<Vec<Weight>, rusqlite::Error> {
+        self.conn()
+            .await?
+            .call(|conn| {
+                let mut stmt = conn.prepare(
+                    "SELECT iso_code, measure, count, sum, sketch FROM weights ORDER BY iso_code, measure",
+                )?;
+                let rows = stmt.query_map([], |row| {
+                    Ok(Weight {
+                        iso_code: row.get(0)?,
+                        measure: row.get(1)?,
+                        measures: Measures {
+                            count: row.get(2)?,
+                            sum: row.get(3)?,
+                        },
+                        sketch: Sketch::from_bytes(&row.get::<_, Vec<u8>>(4)?).unwrap_or_default(),
+                    })
+                })?;
+                rows.collect()
+            })
+            .await
+    }
+
+    /// Adds a latency, in microseconds, to the distribution of a country
+    pub(crate) async fn record_latency(
+        &self,
+        iso_code: &str,
+        micros: u64,
+    ) -> Result<(), rusqlite::Error> {
+        let iso_code = iso_code.to_owned();
+
+        let mut sketch = Sketch::default();
+        sketch.insert(micros);
+        self.conn()
+            .await?
+            .call(move |conn| {
+                let tx = conn.transaction()?;
+                merge_latencies(&tx, &iso_code, &sketch)?;
+                tx.commit()
+            })
+            .await
+    }
+
+    pub(crate) async fn latencies(
+        &self,
+        iso_code: &str,
+    ) -> Result<Option<Sketch>, rusqlite::Error> {
+        let iso_code = iso_code.to_owned();
+        self.conn()
+            .await?
+            .call(move |conn| {
+                let bytes = conn
+                    .query_row(
+                        "SELECT sketch FROM latencies WHERE iso_code = ?",
+                        [iso_code],
+                        |row| row.get::<_, Vec<u8>>(0),
+                    )
+                    .optional()?;
+                Ok(bytes.and_then(|bytes| Sketch::from_bytes(&bytes)))
+            })
+            .await
+    }
+
+    /// Counts a response of `class` (`"2xx"`, ...) to a client in `iso_code`
+    pub(crate) async fn increment_status(
+        &self,
+        iso_code: &str,
+        class: &'static str,
+    ) -> Result<(), rusqlite::Error> {
+        let iso_code = iso_code.to_owned();
+        self.conn()
+            .await?
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO statuses (iso_code, class, count) VALUES (?, ?, 1) ON CONFLICT (iso_code, class) DO UPDATE SET count = count + 1",
+                    [iso_code.as_str(), class],
+                )?;
+                Ok(())
+            })
+            .await
+    }
+
+    /// Responses per country and status class, by country then class
+    pub(crate) async fn list_status_classes(
+        &self,
+    ) -> Result<Vec<(String, String, u64)>, rusqlite::Error> {
+        self.conn()
+            .await?
+            .call(|conn| {
+                let mut stmt = conn.prepare(SELECT_STATUS_CLASSES)?;
+                let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+                rows.collect()
+            })
+            .await
+    }
+
+    /// Exemplars of a country from `since_day` on, oldest first
+    pub(crate) async fn list_exemplars(
+        &self,
+        iso_code: &str,
+        since_day: u64,
+    ) -> Result<Vec<(u64, String)>, rusqlite::Error> {
+        let iso_code = iso_code.to_owned();
+        self.conn()
+            .await?
+            .call(move |conn| {
+                let mut stmt = conn.prepare(SELECT_EXEMPLARS)?;
+                let rows = stmt.query_map(rusqlite::params![iso_code, since_day], |row| {
+                    Ok((row.get(0)?, row.get(1)?))
+                })?;
+                rows.collect()
+            })
+            .await
+    }
+
+    pub(crate) async fn increment_counter(&self, name: &str) -> Result<(), rusqlite::Error> {
+        let name = name.to_owned();
+        self.conn()
+            .await?
+            .call(move |conn| {
+                conn.execute(
+                    "INSERT INTO counters (name, count) VALUES (?, 1) ON CONFLICT (name) DO UPDATE SET count = count + 1",
+                    [name],
+                )?;
+                Ok(())
+            })
+            .await
+    }
+
+    pub(crate) async fn counter(&self, name: &str) -> Result<u64, rusqlite::Error> {
+        let name = name.to_owned();
+        self.conn()
+            .await?
+            .call(move |conn| {
+                conn.query_row("SELECT count FROM counters WHERE name = ?", [name], |row| {
+                    row.get(0)
+                })
+                .optional()
+                .map(Option::unwrap_or_default)
+            })
+            .await
+    }
+
+    /// Removes every count, leaving the audit log alone
+    pub(crate) async fn clear(&self) -> Result<(), rusqlite::Error> {
+        self.conn()
+            .await?
+            .call(|conn| {
+                let tx = conn.transaction()?;
+                tx.execute_batch(
+                    "DELETE FROM analytics;
+                    DELETE FROM counters;
+                    DELETE FROM weights;
+                    DELETE FROM buckets;
+                    DELETE FROM subnets;
+                    DELETE FROM daily_totals;
+                    DELETE FROM latencies;
+                    DELETE FROM statuses;
+                    DELETE FROM exemplars;",
+                )?;
+                start_epoch(&tx)?;
+                tx.commit()
+            })
+            .await
+    }
+
+    /// Sums of the daily totals per continent
+    pub(crate) async fn list_continent_totals(
+        &self,
+    ) -> Result<Vec<(String, u64)>, rusqlite::Error> {
+        self.conn()
+            .await?
+            .call(|conn| {
+                let mut stmt = conn.prepare(SELECT_CONTINENT_TOTALS)?;
+                let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+                rows.collect()
+            })
+            .await
+    }
+
+    /// Daily totals per continent of the days (since the epoch) in `days`.
+    /// Corrections can take a day below zero, such days are left out.
+    pub(crate) async fn list_daily_totals(
+        &self,
+        days: Range<u64>,
+    ) -> Result<Vec<(u64, String, u64)>, rusqlite::Error> {
+        self.conn()
+            .await?
+            .call(move |conn| {
+                let mut stmt = conn.prepare(SELECT_DAILY_TOTALS)?;
+                let rows = stmt.query_map([days.start, days.end], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+                })?;
+                rows.collect()
+            })
+            .await
+    }
+
+    /// Counts per subnet and country, most counted first
+    pub(crate) async fn list_subnets(&self) -> Result<Vec<SubnetCount>, rusqlite::Error> {
+        self.conn()
+            .await?
+            .call(|conn| {
+                let mut stmt = conn.prepare(SELECT_SUBNETS)?;
+                let rows = stmt.query_map([], |row| {
+                    Ok(SubnetCount {
+                        subnet: row.get(0)?,
+                        iso_code: row.get(1)?,
+                        count: row.get(2)?,
+                        last_seen: UNIX_EPOCH + Duration::from_secs(row.get(3)?),
+                    })
+                })?;
+                rows.collect()
+            })
+            .await
+    }
+
+    /// Deletes the subnets last seen before `cutoff`, returning how many
+    pub(crate) async fn prune_subnets(&self, cutoff: SystemTime) -> Result<usize, rusqlite::Error> {
+        let cutoff = cutoff
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.conn()
+            .await?
+            .call(move |conn| conn.execute(DELETE_SUBNETS, [cutoff]))
+            .await
+    }
+
+    /// Deletes the time buckets that started, and the subnets last seen,
+    /// before `cutoff`, returning how many rows went
+    pub(crate) async fn prune_before(&self, cutoff: SystemTime) -> Result<usize, rusqlite::Error> {
+        let cutoff = cutoff
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.conn()
+            .await?
+            .call(move |conn| {
+                let tx = conn.transaction()?;
+                let pruned = delete_before(&tx, cutoff)?;
+                tx.commit()?;
+                Ok(pruned)
+            })
+            .await
+    }
+
+    #[cfg(feature = "archive")]
+    /// [`Db::prune_before`], appending the rows to the day's archive in
+    /// `dir` first. Nothing is deleted if that fails.
+    pub(crate) async fn archive_before(
+        &self,
+        cutoff: SystemTime,
+        dir: std::path::PathBuf,
+    ) -> Result<usize, Error> {
+        let cutoff = cutoff
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.conn()
+            .await?
+            .call(move |conn| -> Result<std::io::Result<usize>, rusqlite::Error> {
+                let tx = conn.transaction()?;
+                let mut rows = Vec::new();
+                {
+                    let mut stmt = tx.prepare(
+                        "SELECT iso_code, bucket_start, count FROM buckets WHERE bucket_start < ?",
+                    )?;
+                    let buckets = stmt.query_map([cutoff], |row| {
+                        Ok(serde_json::json!({
+                            "table": "buckets",
+                            "iso_code": row.get::<_, String>(0)?,
+                            "bucket_start": row.get::<_, u64>(1)?,
+                            "count": row.get::<_, u64>(2)?,
+                        }))
+                    })?;
+                    for row in buckets {
+                        rows.push(row?);
+                    }
+                    let mut stmt = tx.prepare(
+                        "SELECT subnet, iso_code, count, last_seen FROM subnets WHERE last_seen < ?",
+                    )?;
+                    let subnets = stmt.query_map([cutoff], |row| {
+                        Ok(serde_json::json!({
+                            "table": "subnets",
+                            "subnet": row.get::<_, String>(0)?,
+                            "iso_code": row.get::<_, String>(1)?,
+                            "count": row.get::<_, u64>(2)?,
+                            "last_seen": row.get::<_, u64>(3)?,
+                        }))
+                    })?;
+                    for row in subnets {
+                        rows.push(row?);
+                    }
+                }
+                if rows.is_empty() {
+                    return Ok(Ok(0));
+                }
+                // the transaction rolls back when dropped
+                if let Err(e) = crate::archive::append(&dir, &rows) {
+                    return Ok(Err(e));
+                }
+                let pruned = delete_before(&tx, cutoff)?;
+                tx.commit()?;
+                Ok(Ok(pruned))
+            })
+            .await?
+            .map_err(Error::Io)
+    }
+
+    /// Rewrites the file to give the space of deleted rows back to the
+    /// file system
+    pub(crate) async fn vacuum(&self) -> Result<(), rusqlite::Error> {
+        self.conn()
+            .await?
+            .call(|conn| conn.execute_batch("VACUUM"))
+            .await
+    }
+
+    /// Moves what's in the write-ahead log into the file and closes it.
+    /// Everything run afterwards fails with [`crate::Error::Closed`],
+    /// rather than going nowhere.
+    pub(crate) async fn close(&self) -> Result<(), rusqlite::Error> {
+        if self.closed.swap(true, Ordering::AcqRel) {
+            return Err(closed());
+        }
+        let conn = self.current.lock().unwrap().1.clone();
+        conn.call(|conn| {
+            // a no-op outside WAL mode
+            conn.query_row("PRAGMA wal_checkpoint(TRUNCATE)", [], |_| Ok(()))?;
+            // the connection's thread only ends once every handle is gone,
+            // but the file can be closed right away
+            let file = std::mem::replace(conn, rusqlite::Connection::open_in_memory()?);
+            file.close().map_err(|(_, e)| e)
+        })
+        .await
+    }
+
+    /// A token that changes whenever analytics are reset or replaced
+    /// wholesale, 0 until the first time
+    pub(crate) async fn epoch(&self) -> Result<u64, rusqlite::Error> {
+        self.conn()
+            .await?
+            .call(|conn| {
+                let epoch: Option<String> = conn
+                    .query_row(
+                        "SELECT value FROM metadata WHERE key = 'epoch'",
+                        [],
+                        |row| row.get(0),
+                    )
+                    .optional()?;
+                Ok(epoch.and_then(|epoch| epoch.parse().ok()).unwrap_or(0))
+            })
+            .await
+    }
+
+    /// Adds `delta` to a country's count, not going below zero. Returns the
+    /// count before and after.
+    pub(crate) async fn adjust(
+        &self,
+        iso_code: &str,
+        delta: i64,
+    ) -> Result<(u64, u64), rusqlite::Error> {
+        let iso_code = iso_code.to_owned();
+
+        self.conn()
+            .await?
+            .call(move |conn| {
+                let tx = conn.transaction()?;
+                let counts = adjust_count(&tx, &iso_code, delta)?;
+                tx.commit()?;
+                Ok(counts)
+            })
+            .await
+    }
+
+    /// Applies every change in order, all or nothing. Increments also count
+    /// towards the bucket starting at `bucket_start`, and adjustments are
+    /// audited.
+    pub(crate) async fn apply(
+        &self,
+        changes: Vec<Change>,
+        bucket_start: Option<u64>,
+        actor: Option<String>,
+    ) -> Result<(), rusqlite::Error> {
+        self.conn()
+            .await?
+            .call(move |conn| {
+                let tx = conn.transaction()?;
+                for change in &changes {
+                    match change {
+                        Change::Increment(iso_code) => {
+                            tx.execute(
+                                "INSERT INTO analytics (iso_code, count, sum) VALUES (?, 1, 0) ON CONFLICT (iso_code) DO UPDATE SET count = count + 1",
+                                [iso_code],
+                            )?;
+                            if let Some(bucket_start) = bucket_start {
+                                tx.execute(
+                                    "INSERT INTO buckets (iso_code, bucket_start, count) VALUES (?, ?, 1) ON CONFLICT (iso_code, bucket_start) DO UPDATE SET count = count + 1",
+                                    rusqlite::params![iso_code, bucket_start],
+                                )?;
+                            }
+                            add_daily_total(&tx, today(), iso_code, 1)?;
+                        }
+                        Change::Adjust(iso_code, delta) => {
+                            let (before, after) = adjust_count(&tx, iso_code, *delta)?;
+                            insert_audit(
+                                &tx,
+                                "adjust",
+                                actor.clone(),
+                                Some(format!("{iso_code} {delta:+}: {before} -> {after}")),
+                            )?;
+                        }
+                        Change::IncrementCounter(name) => {
+                            tx.execute(
+                                "INSERT INTO counters (name, count) VALUES (?, 1) ON CONFLICT (name) DO UPDATE SET count = count + 1",
+                                [name],
+                            )?;
+                        }
+                    }
+                }
+                tx.commit()
+            })
+            .await
+    }
+
+    pub(crate) async fn append_audit(
+        &self,
+        action: &str,
+        actor: Option<String>,
+        detail: Option<String>,
+    ) -> Result<(), rusqlite::Error> {
+        let action = action.to_owned();
+        self.conn()
+            .await?
+            .call(move |conn| insert_audit(conn, &action, actor, detail))
+            .await
+    }
+
+    /// The audit log, oldest first
+    pub(crate) async fn list_audit(&self) -> Result<Vec<AuditEntry>, rusqlite::Error> {
+        self.conn()
+            .await?
+            .call(|conn| {
+                let mut stmt =
+                    conn.prepare("SELECT at, action, actor, detail FROM audit ORDER BY id")?;
+                let mut rows = stmt.query([])?;
+                let mut entries = Vec::new();
+                while let Some(row) = rows.next()? {
+                    entries.push(AuditEntry {
+                        at: UNIX_EPOCH + Duration::from_secs(row.get(0)?),
+                        action: row.get(1)?,
+                        actor: row.get(2)?,
+                        detail: row.get(3)?,
+                    });
+                }
+                Ok(entries)
+            })
+            .await
+    }
+
+    pub(crate) async fn info(&self) -> Result<AnalyticsInfo, rusqlite::Error> {
+        self.conn()
+            .await?
+            .call(|conn| {
+                let get = |key: &str| {
+                    conn.query_row("SELECT value FROM metadata WHERE key = ?", [key], |row| {
+                        row.get(0)
+                    })
+                    .optional()
+                };
+                Ok(AnalyticsInfo {
+                    created_by: get("created_by")?,
+                    created_at: get("created_at")?
+                        .and_then(|secs: String| secs.parse().ok())
+                        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs)),
+                    last_opened_by: get("last_opened_by")?.unwrap_or_default(),
+                    schema_version: conn
+                        .pragma_query_value(None, "user_version", |row| row.get(0))?,
+                })
+            })
+            .await
+    }
+
+    /// Makes a write and rolls it back
+    pub(crate) async fn check_writable(&self) -> Result<(), rusqlite::Error> {
+        self.conn()
+            .await?
+            .call(|conn| {
+                let tx = conn.transaction()?;
+                tx.execute(
+                    "INSERT INTO counters (name, count) VALUES ('verify', 0) ON CONFLICT (name) DO NOTHING",
+                    [],
+                )?;
+                // dropping the transaction rolls it back
+                Ok(())
+            })
+            .await
+    }
+
+    pub(crate) async fn export(&self) -> Result<Export, rusqlite::Error> {
+        let analytics = self.list_measures().await?;
+        let weights = self.list_weights().await?;
+        let subnets = self.list_subnets().await?;
+        let statuses = self.list_status_classes().await?;
+        let (counters, buckets, latencies) = self
+            .conn()
+            .await?
+            .call(|conn| {
+                let mut stmt = conn.prepare("SELECT name, count FROM counters")?;
+                let counters = stmt
+                    .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                    .collect::<Result<_, _>>()?;
+
+                let mut stmt = conn.prepare("SELECT iso_code, bucket_start, count FROM buckets")?;
+                let buckets = stmt
+                    .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+                    .collect::<Result<_, _>>()?;
+
+                let mut stmt = conn.prepare("SELECT iso_code, sketch FROM latencies")?;
+                let latencies = stmt
+                    .query_map([], |row| {
+                        Ok((row.get::<_, String>(0)?, row.get::<_, Vec<u8>>(1)?))
+                    })?
+                    .filter_map(|row| match row {
+                        Ok((iso_code, bytes)) => Some(Ok((iso_code, Sketch::from_bytes(&bytes)?))),
+                        Err(e) => Some(Err(e)),
+                    })
+                    .collect::<Result<_, _>>()?;
+                Ok::<_, rusqlite::Error>((counters, buckets, latencies))
+            })
+            .await?;
+        Ok(Export {
+            analytics,
+            counters,
+            weights,
+            buckets,
+            subnets,
+            latencies,
+            statuses,
+        })
+    }
+
+    /// Adds everything in `export` to what's already there, all or nothing
+    pub(crate) async fn import(&self, export: Export) -> Result<(), rusqlite::Error> {
+        self.import_in_epoch(export, false).await
+    }
+
+    /// Like `import`, starting a new epoch in the same transaction, see
+    /// `Db::epoch`
+    pub(crate) async fn import_as_new_epoch(&self, export: Export) -> Result<(), rusqlite::Error> {
+        self.import_in_epoch(export, true).await
+    }
+
+    async fn import_in_epoch(
+        &self,
+        export: Export,
+        new_epoch: bool,
+    ) -> Result<(), rusqlite::Error> {
+        self.conn()
+            .await?
+            .call(move |conn| {
+                let tx = conn.transaction()?;
+                {
+                    let mut stmt = tx.prepare(
+                        "INSERT INTO analytics (iso_code, count, sum) VALUES (?, ?, ?) ON CONFLICT (iso_code) DO UPDATE SET count = count + excluded.count, sum = sum + excluded.sum",
+                    )?;
+                    for (iso_code, measures) in &export.analytics {
+                        stmt.execute(rusqlite::params![iso_code, measures.count, measures.sum])?;
+                    }
+
+                    let mut stmt = tx.prepare(
+                        "INSERT INTO counters (name, count) VALUES (?, ?) ON CONFLICT (name) DO UPDATE SET count = count + excluded.count",
+                    )?;
+                    for (name, count) in &export.counters {
+                        stmt.execute(rusqlite::params![name, count])?;
+                    }
+
+                    for weight in &export.weights {
+                        let mut merged = tx
+                            .query_row(
+                                "SELECT sketch FROM weights WHERE iso_code = ? AND measure = ?",
+                                [&weight.iso_code, &weight.measure],
+                                |row| row.get::<_, Vec<u8>>(0),
+                            )
+                            .optional()?
+                            .and_then(|bytes| Sketch::from_bytes(&bytes))
+                            .unwrap_or_default();
+                        merged.merge(&weight.sketch);
+                        tx.execute(
+                            "INSERT INTO weights (iso_code, measure, count, sum, sketch) VALUES (?, ?, ?, ?, ?) ON CONFLICT (iso_code, measure) DO UPDATE SET count = count + excluded.count, sum = sum + excluded.sum, sketch = excluded.sketch",
+                            rusqlite::params![
+                                weight.iso_code,
+                                weight.measure,
+                                weight.measures.count,
+                                weight.measures.sum,
+                                merged.to_bytes()
+                            ],
+                        )?;
+                    }
+
+                    let mut stmt = tx.prepare(
+                        "INSERT INTO buckets (iso_code, bucket_start, count) VALUES (?, ?, ?) ON CONFLICT (iso_code, bucket_start) DO UPDATE SET count = count + excluded.count",
+                    )?;
+                    for (iso_code, bucket_start, count) in &export.buckets {
+                        stmt.execute(rusqlite::params![iso_code, bucket_start, count])?;
+                    }
+
+                    let mut stmt = tx.prepare(
+                        "INSERT INTO subnets (subnet, iso_code, count, last_seen) VALUES (?, ?, ?, ?) ON CONFLICT (subnet, iso_code) DO UPDATE SET count = count + excluded.count, last_seen = MAX(last_seen, excluded.last_seen)",
+                    )?;
+                    for subnet in &export.subnets {
+                        let last_seen = subnet
+                            .last_seen
+                            .duration_since(UNIX_EPOCH)
+                            .unwrap_or_default()
+                            .as_secs();
+                        stmt.execute(rusqlite::params![
+                            subnet.subnet,
+                            subnet.iso_code,
+                            subnet.count,
+                            last_seen
+                        ])?;
+                    }
+
+                    for (iso_code, sketch) in &export.latencies {
+                        merge_latencies(&tx, iso_code, sketch)?;
+                    }
+
+                    let mut stmt = tx.prepare(
+                        "INSERT INTO statuses (iso_code, class, count) VALUES (?, ?, ?) ON CONFLICT (iso_code, class) DO UPDATE SET count = count + excluded.count",
+                    )?;
+                    for (iso_code, class, count) in &export.statuses {
+                        stmt.execute(rusqlite::params![iso_code, class, count])?;
+                    }
+                }
+                if new_epoch {
+                    start_epoch(&tx)?;
+                }
+                tx.commit()
+            })
+            .await
+    }
+
+    /// Writes counts added up with write-behind or in bulk, in a single
+    /// transaction
+    pub(crate) async fn write_pending(&self, pending: Pending) -> Result<(), rusqlite::Error> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.conn()
+            .await?
+            .call(move |conn| {
+                let tx = conn.transaction()?;
+                {
+                    let mut stmt = tx.prepare(
+                        "INSERT INTO analytics (iso_code, count, sum) VALUES (?, ?, 0) ON CONFLICT (iso_code) DO UPDATE SET count = count + excluded.count",
+                    )?;
+                    for (iso_code, count) in &pending.analytics {
+                        stmt.execute(rusqlite::params![iso_code, count])?;
+                    }
+
+                    let mut stmt = tx.prepare(
+                        "INSERT INTO counters (name, count) VALUES (?, ?) ON CONFLICT (name) DO UPDATE SET count = count + excluded.count",
+                    )?;
+                    for (name, count) in &pending.counters {
+                        stmt.execute(rusqlite::params![name, count])?;
+                    }
+
+                    let mut stmt = tx.prepare(
+                        "INSERT INTO buckets (iso_code, bucket_start, count) VALUES (?, ?, ?) ON CONFLICT (iso_code, bucket_start) DO UPDATE SET count = count + excluded.count",
+                    )?;
+                    for ((iso_code, bucket_start), count) in &pending.buckets {
+                        stmt.execute(rusqlite::params![iso_code, bucket_start, count])?;
+                    }
+
+                    for ((iso_code, day), count) in &pending.days {
+                        add_daily_total(&tx, *day, iso_code, *count)?;
+                    }
+
+                    let mut stmt = tx.prepare(
+                        "INSERT INTO subnets (subnet, iso_code, count, last_seen) VALUES (?, ?, ?, ?) ON CONFLICT (subnet, iso_code) DO UPDATE SET count = count + excluded.count, last_seen = excluded.last_seen",
+                    )?;
+                    for ((subnet, iso_code), count) in &pending.subnets {
+                        stmt.execute(rusqlite::params![subnet, iso_code, count, now])?;
+                    }
+
+                    for (iso_code, sketch) in &pending.latencies {
+                        merge_latencies(&tx, iso_code, sketch)?;
+                    }
+
+                    let mut stmt = tx.prepare(
+                        "INSERT INTO statuses (iso_code, class, count) VALUES (?, ?, ?) ON CONFLICT (iso_code, class) DO UPDATE SET count = count + excluded.count",
+                    )?;
+                    for ((iso_code, class), count) in &pending.statuses {
+                        stmt.execute(rusqlite::params![iso_code, class, count])?;
+                    }
+
+                    for exemplar in &pending.exemplars {
+                        add_exemplar(&tx, exemplar)?;
+                    }
+                }
+                tx.commit()
+            })
+            .await
+    }
+
+    /// Runs one statement with writes disabled
+    pub(crate) async fn query_readonly(
+        &self,
+        sql: String,
+        params: Vec<Value>,
+    ) -> Result<RawRows, rusqlite::Error> {
+        self.conn()
+            .await?
+            .call(move |conn| {
+                conn.pragma_update(None, "query_only", true)?;
+                let result = (|| {
+                    let mut stmt = conn.prepare(&sql)?;
+                    let columns = stmt
+                        .column_names()
+                        .into_iter()
+                        .map(str::to_owned)
+                        .collect::<Vec<_>>();
+                    let rows = stmt
+                        .query_map(rusqlite::params_from_iter(params), |row| {
+                            (0..columns.len()).map(|i| row.get(i)).collect()
+                        })?
+                        .collect::<Result<_, _>>()?;
+                    Ok(RawRows { columns, rows })
+                })();
+                // whatever happened, the connection goes back to normal
+                conn.pragma_update(None, "query_only", false)?;
+                result
+            })
+            .await
+    }
+}
+
+#[async_trait]
+impl AnalyticsStore for Db {
+    async fn add(
+        &self,
+        analytics: &[(String, Measures)],
+        counters: &[(String, u64)],
+    ) -> Result<(), Error> {
+        let export = Export {
+            analytics: analytics.to_vec(),
+            counters: counters.to_vec(),
+            ..Default::default()
+        };
+        Ok(self.import(export).await?)
+    }
+
+    async fn measures(&self) -> Result<Vec<(String, Measures)>, Error> {
+        Ok(self.list_measures().await?)
+    }
+
+    async fn epoch(&self) -> Result<u64, Error> {
+        Ok(Db::epoch(self).await?)
+    }
+
+    async fn reset(&self) -> Result<(), Error> {
+        Ok(self.clear().await?)
+    }
+
+    async fn counters(&self) -> Result<Vec<(String, u64)>, Error> {
+        let counters = self
+            .conn()
+            .await?
+            .call(|conn| {
+                let mut stmt = conn.prepare("SELECT name, count FROM counters")?;
+                let counters = stmt
+                    .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                    .collect::<Result<_, _>>()?;
+                Ok::<_, rusqlite::Error>(counters)
+            })
+            .await?;
+        Ok(counters)
+    }
+
+    // single statements are cheaper than the transaction `add` needs
+    async fn record(&self, iso_code: &str, weight: u64) -> Result<(), Error> {
+        Ok(Db::record(self, iso_code, weight).await?)
+    }
+
+    async fn increment(&self, iso_code: &str) -> Result<(), Error> {
+        Ok(Db::increment(self, iso_code).await?)
+    }
+
+    async fn increment_counter(&self, name: &str) -> Result<(), Error> {
+        Ok(Db::increment_counter(self, name).await?)
+    }
+
+    async fn counter(&self, name: &str) -> Result<u64, Error> {
+        Ok(Db::counter(self, name).await?)
+    }
+
+    fn describe(&self) -> String {
+        format!("sqlite:{}", self.current_path())
+    }
+}
+
+// random rather than incremented, so that a file that was deleted and
+// created again doesn't come back with an epoch seen before
+fn start_epoch(conn: &rusqlite::Connection) -> Result<(), rusqlite::Error> {
+    let epoch = RandomState::new().hash_one(SystemTime::now()).max(1);
+    conn.execute(
+        "INSERT INTO metadata (key, value) VALUES ('epoch', ?) ON CONFLICT (key) DO UPDATE SET value = excluded.value",
+        [epoch.to_string()],
+    )?;
+    Ok(())
+}
+
+fn adjust_count(
+    conn: &rusqlite::Connection,
+    iso_code: &str,
+    delta: i64,
+) -> Result<(u64, u64), rusqlite::Error> {
+    let before: u64 = conn
+        .query_row(
+            "SELECT count FROM analytics WHERE iso_code = ?",
+            [iso_code],
+            |row| row.get(0),
+        )
+        .optional()?
+        .unwrap_or(0);
+    let after = before.saturating_add_signed(delta);
+    conn.execute(
+        "INSERT INTO analytics (iso_code, count, sum) VALUES (?1, ?2, 0) ON CONFLICT (iso_code) DO UPDATE SET count = ?2",
+        rusqlite::params![iso_code, after],
+    )?;
+    Ok((before, after))
+}
+
+fn insert_audit(
+    conn: &rusqlite::Connection,
+    action: &str,
+    actor: Option<String>,
+    detail: Option<String>,
+) -> Result<(), rusqlite::Error> {
+    let at = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    conn.execute(
+        "INSERT INTO audit (at, action, actor, detail) VALUES (?, ?, ?, ?)",
+        rusqlite::params![at, action, actor, detail],
+    )?;
+    Ok(())
+}
+
+/// Reads a rolled-over file without migrating it, a missing file has no
+/// counts
+async fn read_counts(path: String) -> Result<Vec<(String, u64)>, rusqlite::Error> {
+    if !tokio::fs::try_exists(&path).await.unwrap_or(false) {
+        return Ok(Vec::new());
+    }
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY).await?;
+    conn.call(|conn| {
+        let mut stmt = conn.prepare("SELECT iso_code, count FROM analytics")?;
+        let rows = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_, _>>()?;
+        Ok(rows)
+    })
+    .await
+}
+
+/// Reads the buckets of a rolled-over file that start between `start` and
+/// `end` (in seconds since the epoch), without migrating it. Missing files
+/// and files from before buckets have none.
+async fn read_buckets(
+    path: String,
+    start: u64,
+    end: u64,
+) -> Result<Vec<(SystemTime, String, u64)>, rusqlite::Error> {
+    if !tokio::fs::try_exists(&path).await.unwrap_or(false) {
+        return Ok(Vec::new());
+    }
+    let conn = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY).await?;
+    conn.call(move |conn| {
+        let version: i64 = conn.pragma_query_value(None, "user_version", |row| row.get(0))?;
+        if version < 5 {
+            return Ok(Vec::new());
+        }
+        let mut stmt = conn.prepare(SELECT_BUCKETS)?;
+        let rows = stmt
+            .query_map([start, end], |row| {
+                let bucket_start: u64 = row.get(0)?;
+                Ok((
+                    UNIX_EPOCH + Duration::from_secs(bucket_start),
+                    row.get(1)?,
+                    row.get(2)?,
+                ))
+            })?
+            .collect::<Result<_, _>>()?;
+        Ok(rows)
+    })
+    .await
+}
+
+/// The statement and parameters `query` runs
+fn query_sql(query: &AnalyticsQuery) -> (String, Vec<Value>) {
+    // `LIMIT -1` is no limit at all
+    let limit = query.top_n.map_or(-1, |n| n.min(i64::MAX as usize) as i64);
+    let offset = query.offset.min(i64::MAX as usize) as i64;
+    let min_count = query.min_count.min(i64::MAX as u64) as i64;
+    let mut params = vec![Value::Integer(min_count)];
+    let countries = if query.countries.is_empty() {
+        String::new()
+    } else {
+        let placeholders = vec!["?"; query.countries.len()].join(", ");
+        params.extend(
+            query
+                .countries
+                .iter()
+                .map(|code| Value::Text(code.to_string())),
+        );
+        format!(" AND iso_code IN ({placeholders})")
+    };
+    params.extend([Value::Integer(limit), Value::Integer(offset)]);
+    let sql = format!(
+        "SELECT iso_code, count FROM analytics WHERE count >= ?{countries} ORDER BY {} LIMIT ? OFFSET ?",
+        query.order_by.sql()
+    );
+    (sql, params)
+}
+
+/// Adds lookups made on `day` (since the epoch) to the totals of the
+/// continent of `iso_code`. Imports and corrections only change lifetime
+/// counts: there's no telling which day they belong to.
+fn add_daily_total(
+    conn: &rusqlite::Connection,
+    day: u64,
+    iso_code: &str,
+    count: u64,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(
+        ADD_DAILY_TOTAL,
+        rusqlite::params![day, iso_code, UNKNOWN_CONTINENT, count],
+    )?;
+    Ok(())
+}
+
+/// Keeps an exemplar unless its country's day is full, and deletes the
+/// expired ones. Within a transaction, the cap holds for every process
+/// writing to the file.
+fn add_exemplar(
+    conn: &rusqlite::Connection,
+    exemplar: &NewExemplar,
+) -> Result<(), rusqlite::Error> {
+    conn.execute(DELETE_EXEMPLARS, [exemplar.since_day])?;
+    let kept: u32 = conn.query_row(
+        COUNT_EXEMPLARS,
+        rusqlite::params![exemplar.iso_code, exemplar.day],
+        |row| row.get(0),
+    )?;
+    if kept < exemplar.per_day {
+        conn.execute(
+            "INSERT OR IGNORE INTO exemplars (iso_code, day, exemplar) VALUES (?, ?, ?)",
+            rusqlite::params![exemplar.iso_code, exemplar.day, exemplar.exemplar],
+        )?;
+    }
+    Ok(())
+}
+
+/// The day it is, in days since the epoch
+pub(crate) fn today() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+        / 86400
+}
+
+/// Merges `sketch` into the response times of a country
+fn merge_latencies(
+    tx: &rusqlite::Transaction,
+    iso_code: &str,
+    sketch: &Sketch,
+) -> Result<(), rusqlite::Error> {
+    let mut merged = tx
+        .query_row(
+            "SELECT sketch FROM latencies WHERE iso_code = ?",
+            [iso_code],
+            |row| row.get::<_, Vec<u8>>(0),
+        )
+        .optional()?
+        .and_then(|bytes| Sketch::from_bytes(&bytes))
+        .unwrap_or_default();
+    merged.merge(sketch);
+    tx.execute(
+        "INSERT INTO latencies (iso_code, sketch) VALUES (?, ?) ON CONFLICT (iso_code) DO UPDATE SET sketch = excluded.sketch",
+        rusqlite::params![iso_code, merged.to_bytes()],
+    )?;
+    Ok(())
+}
+
+fn delete_before(tx: &rusqlite::Transaction, cutoff: u64) -> Result<usize, rusqlite::Error> {
+    let buckets = tx.execute(DELETE_BUCKETS, [cutoff])?;
+    let subnets = tx.execute(DELETE_SUBNETS, [cutoff])?;
+    Ok(buckets + subnets)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use crate::{
+        batch::Pending, rollover::PathTemplate, testing::RemoveOnDrop, AnalyticsQuery, Error,
+        Measures, OrderBy,
+    };
+
+    use rusqlite::types::Value;
+
+    use super::{
+        query_sql, today, Db, Sketch, Weight, ADD_DAILY_TOTAL, COUNT_EXEMPLARS, DELETE_BUCKETS,
+        DELETE_EXEMPLARS, DELETE_SUBNETS, LEGACY_MEASURE, SCHEMA_VERSION, SELECT_BUCKETS,
+        SELECT_CONTINENT_TOTALS, SELECT_DAILY_TOTALS, SELECT_EXEMPLARS, SELECT_STATUS_CLASSES,
+        SELECT_SUBNETS,
+    };
+
+    // this test needs an async runtime now, hence, `tokio::test`
+    #[tokio::test]
+    async fn test_db() {
+        let path = "/tmp/loca-test.db";
+        let db = Db::open(path).await.unwrap();
+
+        let _remove_on_drop = RemoveOnDrop { path };
+
+        let analytics = db.list().await.unwrap();
+        assert_eq!(analytics.len(), 0);
+
+        db.increment("US").await.unwrap();
+        let analytics = db.list().await.unwrap();
+        assert_eq!(analytics.len(), 1);
+
+        db.increment("US").await.unwrap();
+        db.increment("FR").await.unwrap();
+        let analytics = db.list().await.unwrap();
+        assert_eq!(analytics.len(), 2);
+        // contains US at count 2
+        assert!(analytics.contains(&("US".to_string(), 2)));
+        // contains FR at count 1
+        assert!(analytics.contains(&("FR".to_string(), 1)));
+        // doesn't contain DE
+        assert!(!analytics.contains(&("DE".to_string(), 0)));
+    }
+
+    #[tokio::test]
+    async fn test_db_weighted() {
+        let path = "/tmp/loca-test-weighted.db";
+        let db = Db::open(path).await.unwrap();
+
+        let _remove_on_drop = RemoveOnDrop { path };
+
+        db.increment("US").await.unwrap();
+        db.record("US", 1500).await.unwrap();
+        db.record("FR", 200).await.unwrap();
+
+        let measures = db.list_measures().await.unwrap();
+        assert!(measures.contains(&(
+            "US".to_string(),
+            Measures {
+                count: 2,
+                sum: 1500
+            }
+        )));
+        assert!(measures.contains(&("FR".to_string(), Measures { count: 1, sum: 200 })));
+
+        // weighted measures are kept apart from counts and from each other
+        assert_eq!(db.sketch("US", "bytes").await.unwrap(), None);
+        db.record_weighted("US", "bytes", 100).await.unwrap();
+        db.record_weighted("US", "bytes", 300).await.unwrap();
+        db.record_weighted("US", "revenue", 7).await.unwrap();
+        let sketch = db.sketch("US", "bytes").await.unwrap().unwrap();
+        assert_eq!(sketch.count(), 2);
+        let weights: Vec<_> = db
+            .list_weights()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|weight| (weight.measure, weight.measures))
+            .collect();
+        assert_eq!(
+            weights,
+            vec![
+                ("bytes".to_string(), Measures { count: 2, sum: 400 }),
+                ("revenue".to_string(), Measures { count: 1, sum: 7 }),
+            ]
+        );
+        assert_eq!(db.list_measures().await.unwrap(), measures);
+
+        // reopening doesn't try to migrate twice
+        drop(db);
+        let db = Db::open(path).await.unwrap();
+        assert_eq!(db.list().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_db_counters() {
+        let path = "/tmp/loca-test-counters.db";
+        let db = Db::open(path).await.unwrap();
+
+        let _remove_on_drop = RemoveOnDrop { path };
+
+        assert_eq!(db.counter("bogon").await.unwrap(), 0);
+        db.increment_counter("bogon").await.unwrap();
+        db.increment_counter("bogon").await.unwrap();
+        assert_eq!(db.counter("bogon").await.unwrap(), 2);
+        // counters don't show up as countries
+        assert!(db.list().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_db_export_import() {
+        let from_path = "/tmp/loca-test-export-from.db";
+        let to_path = "/tmp/loca-test-export-to.db";
+        let from = Db::open(from_path).await.unwrap();
+        let to = Db::open(to_path).await.unwrap();
+
+        let _remove_from = RemoveOnDrop { path: from_path };
+        let _remove_to = RemoveOnDrop { path: to_path };
+
+        from.increment("US").await.unwrap();
+        from.record_weighted("US", "bytes", 10).await.unwrap();
+        from.increment_counter("bogon").await.unwrap();
+        to.increment("US").await.unwrap();
+        to.record_weighted("US", "bytes", 0).await.unwrap();
+        from.increment_bucketed("US", 3600).await.unwrap();
+        from.record_latency("US", 1_000).await.unwrap();
+        from.increment_status("US", "2xx").await.unwrap();
+        let mut pending = Pending::default();
+        pending
+            .subnets
+            .insert(("1.2.3.0/24".to_string(), "US".to_string()), 1);
+        from.write_pending(pending).await.unwrap();
+
+        to.import(from.export().await.unwrap()).await.unwrap();
+        let end = UNIX_EPOCH + Duration::from_secs(7200);
+        assert_eq!(
+            to.list_buckets(UNIX_EPOCH..end).await.unwrap(),
+            vec![(UNIX_EPOCH + Duration::from_secs(3600), "US".to_string(), 1)]
+        );
+        assert_eq!(to.latencies("US").await.unwrap().unwrap().count(), 1);
+        assert_eq!(
+            to.list_status_classes().await.unwrap(),
+            vec![("US".to_string(), "2xx".to_string(), 1)]
+        );
+        assert_eq!(to.list_subnets().await.unwrap()[0].subnet, "1.2.3.0/24");
+        assert_eq!(
+            to.list_measures().await.unwrap(),
+            vec![("US".to_string(), Measures { count: 3, sum: 0 })]
+        );
+        assert_eq!(to.counter("bogon").await.unwrap(), 1);
+        let weights = to.list_weights().await.unwrap();
+        assert_eq!(weights[0].measures, Measures { count: 2, sum: 10 });
+        // sketches merge rather than add up
+        assert_eq!(weights[0].sketch.count(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_db_close() {
+        let path = "/tmp/loca-test-db-close.db";
+        let _remove_on_drop = RemoveOnDrop { path };
+        let db = Db::open(path).await.unwrap();
+        db.increment("US").await.unwrap();
+        db.close().await.unwrap();
+
+        // later calls fail rather than write to nowhere
+        assert!(matches!(
+            db.increment("US").await.map_err(Error::from),
+            Err(Error::Closed)
+        ));
+        assert!(matches!(
+            db.close().await.map_err(Error::from),
+            Err(Error::Closed)
+        ));
+        let db = Db::open(path).await.unwrap();
+        assert_eq!(db.list_measures().await.unwrap()[0].1.count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_db_rollover() {
+        // `%%` renders to a known path, unlike the date placeholders
+        let db = Db::open("/tmp/loca-test-rollover-%%.db").await.unwrap();
+        let path = "/tmp/loca-test-rollover-%.db";
+        let _remove_on_drop = RemoveOnDrop { path };
+
+        db.increment("US").await.unwrap();
+
+        // pretend we were still on the previous period's file
+        db.current.lock().unwrap().0 = "/tmp/loca-test-rollover-old.db".to_string();
+        db.increment("US").await.unwrap();
+
+        assert_eq!(db.current.lock().unwrap().0, path);
+        assert_eq!(db.list().await.unwrap(), vec![("US".to_string(), 2)]);
+        assert!(!std::path::Path::new("/tmp/loca-test-rollover-old.db").exists());
+    }
+
+    #[tokio::test]
+    async fn test_db_list_period() {
+        let day = Duration::from_secs(86400);
+        // 2023-06-15 00:00:00 UTC
+        let start = UNIX_EPOCH + Duration::from_secs(1686787200);
+
+        let first = "/tmp/loca-test-period-2023-06-15.db";
+        let second = "/tmp/loca-test-period-2023-06-16.db";
+        let _remove_first = RemoveOnDrop { path: first };
+        let _remove_second = RemoveOnDrop { path: second };
+        Db::open(first)
+            .await
+            .unwrap()
+            .increment("US")
+            .await
+            .unwrap();
+        let db = Db::open(second).await.unwrap();
+        db.increment("US").await.unwrap();
+        db.increment("FR").await.unwrap();
+
+        let mut db = db;
+        db.path = PathTemplate::new("/tmp/loca-test-period-%Y-%m-%d.db");
+        assert_eq!(
+            db.list_period(start..start + day).await.unwrap(),
+            vec![("US".to_string(), 1)]
+        );
+        // 2023-06-17 was never written to
+        assert_eq!(
+            db.list_period(start..start + day * 3).await.unwrap(),
+            vec![("FR".to_string(), 1), ("US".to_string(), 2)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_db_query_plans() {
+        let path = "/tmp/loca-test-query-plans.db";
+        let _remove_on_drop = RemoveOnDrop { path };
+        drop(Db::open(path).await.unwrap());
+
+        let conn = rusqlite::Connection::open(path).unwrap();
+        let plan = |sql: &str| {
+            let mut stmt = conn.prepare(&format!("EXPLAIN QUERY PLAN {sql}")).unwrap();
+            let params = vec![Value::Integer(0); stmt.parameter_count()];
+            let details: Vec<String> = stmt
+                .query_map(rusqlite::params_from_iter(params), |row| row.get(3))
+                .unwrap()
+                .collect::<Result<_, _>>()
+                .unwrap();
+            details.join("; ")
+        };
+        let top_n = |order_by| {
+            query_sql(&AnalyticsQuery {
+                top_n: Some(10),
+                order_by,
+                ..Default::default()
+            })
+            .0
+        };
+        for (sql, index) in [
+            (SELECT_BUCKETS.to_owned(), "buckets_bucket_start"),
+            (DELETE_BUCKETS.to_owned(), "buckets_bucket_start"),
+            (DELETE_SUBNETS.to_owned(), "subnets_last_seen"),
+            (SELECT_SUBNETS.to_owned(), "subnets_count"),
+            (
+                SELECT_DAILY_TOTALS.to_owned(),
+                "sqlite_autoindex_daily_totals_1",
+            ),
+            (SELECT_CONTINENT_TOTALS.to_owned(), "daily_totals_continent"),
+            (
+                SELECT_STATUS_CLASSES.to_owned(),
+                "sqlite_autoindex_statuses_1",
+            ),
+            (ADD_DAILY_TOTAL.to_owned(), "sqlite_autoindex_continents_1"),
+            (SELECT_EXEMPLARS.to_owned(), "sqlite_autoindex_exemplars_1"),
+            (COUNT_EXEMPLARS.to_owned(), "sqlite_autoindex_exemplars_1"),
+            (DELETE_EXEMPLARS.to_owned(), "exemplars_day"),
+            (top_n(OrderBy::CountDesc), "analytics_count"),
+            (top_n(OrderBy::IsoCode), "sqlite_autoindex_analytics_1"),
+        ] {
+            let plan = plan(&sql);
+            assert!(plan.contains(index), "{sql}: {plan}");
+            // no sorting, and no going through a table in no useful order
+            assert!(!plan.contains("TEMP B-TREE"), "{sql}: {plan}");
+            assert!(
+                plan.split("; ")
+                    .all(|step| !step.starts_with("SCAN") || step.contains(" USING ")),
+                "{sql}: {plan}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_db_query_readonly() {
+        let path = "/tmp/loca-test-readonly.db";
+        let db = Db::open(path).await.unwrap();
+
+        let _remove_on_drop = RemoveOnDrop { path };
+
+        db.record("US", 3).await.unwrap();
+        let rows = db
+            .query_readonly(
+                "SELECT iso_code, count + sum AS total FROM analytics WHERE iso_code = ?".into(),
+                vec![Value::Text("US".into())],
+            )
+            .await
+            .unwrap();
+        assert_eq!(rows.columns, vec!["iso_code", "total"]);
+        assert_eq!(
+            rows.rows,
+            vec![vec![Value::Text("US".into()), Value::Integer(4)]]
+        );
+
+        // writes sneaking in through a CTE are refused
+        assert!(db
+            .query_readonly("WITH x AS (SELECT 1) DELETE FROM analytics".into(), vec![])
+            .await
+            .is_err());
+        // and the connection can still write afterwards
+        db.increment("US").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_db_info() {
+        let path = "/tmp/loca-test-info.db";
+        let _remove_on_drop = RemoveOnDrop { path };
+
+        // a file from before migrations, `user_version` was never set
+        let conn = rusqlite::Connection::open(path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE analytics (iso_code TEXT PRIMARY KEY, count INTEGER NOT NULL);
+            INSERT INTO analytics VALUES ('US', 3);",
+        )
+        .unwrap();
+        drop(conn);
+
+        let db = Db::open(path).await.unwrap();
+        // earlier counts make it into the daily totals
+        assert_eq!(
+            db.list_continent_totals().await.unwrap(),
+            vec![("NA".to_string(), 3)]
+        );
+        let info = db.info().await.unwrap();
+        assert_eq!(info.created_by, None);
+        assert_eq!(info.created_at, None);
+        assert_eq!(info.last_opened_by, env!("CARGO_PKG_VERSION"));
+        assert_eq!(info.schema_version, SCHEMA_VERSION);
+
+        std::fs::remove_file(path).unwrap();
+        let info = Db::open(path).await.unwrap().info().await.unwrap();
+        assert_eq!(info.created_by.as_deref(), Some(env!("CARGO_PKG_VERSION")));
+        assert!(info.created_at.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_db_daily_totals() {
+        let path = "/tmp/loca-test-daily-totals.db";
+        let db = Db::open(path).await.unwrap();
+        let _remove_on_drop = RemoveOnDrop { path };
+
+        // counted yesterday, written today
+        let yesterday = today() - 1;
+        let mut pending = Pending::default();
+        pending.analytics.insert("US".to_string(), 2);
+        pending.days.insert(("US".to_string(), yesterday), 2);
+        db.write_pending(pending).await.unwrap();
+        db.increment("FR").await.unwrap();
+        // not lookups
+        db.adjust("US", -5).await.unwrap();
+        db.import(db.export().await.unwrap()).await.unwrap();
+
+        assert_eq!(
+            db.list_daily_totals(yesterday..today() + 1).await.unwrap(),
+            vec![
+                (yesterday, "NA".to_string(), 2),
+                (today(), "EU".to_string(), 1)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_db_legacy_weights() {
+        let path = "/tmp/loca-test-legacy-weights.db";
+        let _remove_on_drop = RemoveOnDrop { path };
+
+        // a file from when weights were summed up with lookups
+        let mut sketch = Sketch::default();
+        sketch.insert(1500);
+        let conn = rusqlite::Connection::open(path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE analytics (iso_code TEXT PRIMARY KEY, count INTEGER NOT NULL, sum INTEGER NOT NULL DEFAULT 0);
+            CREATE TABLE counters (name TEXT PRIMARY KEY, count INTEGER NOT NULL);
+            CREATE TABLE metadata (key TEXT PRIMARY KEY, value TEXT NOT NULL);
+            CREATE TABLE sketches (iso_code TEXT PRIMARY KEY, sketch BLOB NOT NULL);
+            INSERT INTO analytics VALUES ('US', 3, 1500), ('FR', 1, 0);
+            PRAGMA user_version = 4;",
+        )
+        .unwrap();
+        conn.execute("INSERT INTO sketches VALUES ('US', ?)", [sketch.to_bytes()])
+            .unwrap();
+        drop(conn);
+
+        let db = Db::open(path).await.unwrap();
+        let mut measures = db.list_measures().await.unwrap();
+        measures.sort_by(|a, b| a.0.cmp(&b.0));
+        assert_eq!(
+            measures,
+            vec![
+                ("FR".to_string(), Measures { count: 1, sum: 0 }),
+                ("US".to_string(), Measures { count: 3, sum: 0 }),
+            ]
+        );
+        assert_eq!(
+            db.list_weights().await.unwrap(),
+            vec![Weight {
+                iso_code: "US".to_string(),
+                measure: LEGACY_MEASURE.to_string(),
+                measures: Measures {
+                    count: 1,
+                    sum: 1500
+                },
+                sketch,
+            }]
+        );
+    }
+}
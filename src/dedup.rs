@@ -0,0 +1,116 @@
+use std::{
+    collections::{hash_map::RandomState, HashMap, HashSet},
+    hash::BuildHasher,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+/// Remembers which (IP, country) pairs were counted recently, so each one
+/// is only counted once per window.
+pub(crate) struct Dedup {
+    window: Duration,
+    // we never keep raw addresses around: keys are hashed with a per-process
+    // random seed, which is good enough to tell visitors apart but not to
+    // recover who they were.
+    hasher: RandomState,
+    state: Mutex<State>,
+}
+
+struct State {
+    seen: HashMap<u64, Instant>,
+    last_prune: Instant,
+}
+
+impl Dedup {
+    pub(crate) fn new(window: Duration) -> Self {
+        Self {
+            window,
+            hasher: RandomState::new(),
+            state: Mutex::new(State {
+                seen: HashMap::new(),
+                last_prune: Instant::now(),
+            }),
+        }
+    }
+
+    pub(crate) fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Forgets every pair, see `Locat::check_epoch`
+    pub(crate) fn clear(&self) {
+        self.state.lock().unwrap().seen.clear();
+    }
+
+    /// Returns `true` if this (addr, iso_code) pair hasn't been counted
+    /// within the window, and marks it as counted.
+    pub(crate) fn should_count(&self, addr: IpAddr, iso_code: &str) -> bool {
+        self.should_count_at(addr, iso_code, Instant::now())
+    }
+
+    /// Like `should_count`, without marking anything as counted: pairs in
+    /// `batch` count as seen too, and new ones are added to it
+    pub(crate) fn would_count(
+        &self,
+        addr: IpAddr,
+        iso_code: &str,
+        batch: &mut HashSet<u64>,
+    ) -> bool {
+        let key = self.hasher.hash_one((addr, iso_code));
+        let now = Instant::now();
+        let seen = self
+            .state
+            .lock()
+            .unwrap()
+            .seen
+            .get(&key)
+            .is_some_and(|counted_at| now.duration_since(*counted_at) < self.window);
+        !seen && batch.insert(key)
+    }
+
+    fn should_count_at(&self, addr: IpAddr, iso_code: &str, now: Instant) -> bool {
+        let key = self.hasher.hash_one((addr, iso_code));
+        let mut state = self.state.lock().unwrap();
+
+        // drop expired entries every so often, otherwise the map only grows
+        if now.duration_since(state.last_prune) >= self.window {
+            let window = self.window;
+            state
+                .seen
+                .retain(|_, counted_at| now.duration_since(*counted_at) < window);
+            state.last_prune = now;
+        }
+
+        match state.seen.get(&key) {
+            Some(counted_at) if now.duration_since(*counted_at) < self.window => false,
+            _ => {
+                state.seen.insert(key, now);
+                true
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::Dedup;
+
+    #[test]
+    fn test_dedup_window() {
+        let dedup = Dedup::new(Duration::from_secs(60));
+        let a = "1.2.3.4".parse().unwrap();
+        let b = "5.6.7.8".parse().unwrap();
+        let start = Instant::now();
+
+        assert!(dedup.should_count_at(a, "US", start));
+        assert!(!dedup.should_count_at(a, "US", start + Duration::from_secs(30)));
+        // different address or country is a different visitor
+        assert!(dedup.should_count_at(b, "US", start));
+        assert!(dedup.should_count_at(a, "FR", start));
+        // once the window has passed, it counts again
+        assert!(dedup.should_count_at(a, "US", start + Duration::from_secs(61)));
+    }
+}
@@ -0,0 +1,182 @@
+use std::{collections::BTreeMap, net::IpAddr, path::Path};
+
+#[cfg(feature = "analytics")]
+use ipnetwork::IpNetwork;
+
+#[cfg(feature = "analytics")]
+use crate::Locat;
+use crate::{Error, GeoIp};
+
+/// How two builds of a GeoIP database disagree, see [`diff_databases`]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DatabaseDiff {
+    pub sampled: usize,
+    /// Addresses that resolve differently, including to or from nothing
+    pub changed: usize,
+    /// `(old country, new country)` to number of addresses, for the
+    /// addresses that changed
+    pub moves: BTreeMap<(Option<String>, Option<String>), usize>,
+}
+
+/// Resolves every address of `sample` with both databases and counts the
+/// ones that changed country, to review a database update before rolling
+/// it out. A sample of recent client addresses gives the most relevant
+/// picture.
+pub async fn diff_databases(
+    old_path: impl AsRef<Path>,
+    new_path: impl AsRef<Path>,
+    sample: &[IpAddr],
+) -> Result<DatabaseDiff, Error> {
+    let old = GeoIp::load(tokio::fs::read(old_path).await?)?;
+    let new = GeoIp::load(tokio::fs::read(new_path).await?)?;
+
+    let mut diff = DatabaseDiff {
+        sampled: sample.len(),
+        ..Default::default()
+    };
+    for addr in sample {
+        let (before, after) = (old.lookup(*addr), new.lookup(*addr));
+        if before != after {
+            diff.changed += 1;
+            *diff
+                .moves
+                .entry((before.map(str::to_owned), after.map(str::to_owned)))
+                .or_default() += 1;
+        }
+    }
+    Ok(diff)
+}
+
+/// How lookups counted per subnet would be located by another database,
+/// see [`Locat::replay_subnets`]. Counts are of lookups, not subnets.
+#[cfg(feature = "analytics")]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Replay {
+    /// Lookups whose subnet was resolved again
+    pub replayed: u64,
+    /// Of those, the ones located elsewhere, or nowhere
+    pub changed: u64,
+    /// `(country counted, new country)` to lookups, for the ones that
+    /// changed
+    pub moves: BTreeMap<(String, Option<String>), u64>,
+    /// `(replayed, changed)` lookups per country counted
+    pub by_country: BTreeMap<String, (u64, u64)>,
+    /// Lookups whose subnet was kept as a salted hash, which can't be
+    /// resolved again
+    pub hashed: u64,
+}
+
+#[cfg(feature = "analytics")]
+impl Locat {
+    /// Resolves the subnets counted with [`Locat::with_subnet_analytics`]
+    /// with the database at `new_path`, to see how a database update
+    /// would move past lookups between countries. Each subnet is resolved
+    /// at its first address; subnets salted with
+    /// [`crate::SubnetAnalytics::with_salt`] are only counted as hashed.
+    pub async fn replay_subnets(&self, new_path: impl AsRef<Path>) -> Result<Replay, Error> {
+        let db = self.sqlite("replay_subnets")?;
+        self.filesystem("replaying subnets")?;
+        let new = GeoIp::load(tokio::fs::read(new_path).await?)?;
+
+        let mut replay = Replay::default();
+        for row in db.list_subnets().await? {
+            let Ok(subnet) = row.subnet.parse::<IpNetwork>() else {
+                replay.hashed += row.count;
+                continue;
+            };
+            let after = new.lookup(subnet.network());
+            let changed = after != Some(row.iso_code.as_str());
+            replay.replayed += row.count;
+            let country = replay.by_country.entry(row.iso_code.clone()).or_default();
+            country.0 += row.count;
+            if changed {
+                country.1 += row.count;
+                replay.changed += row.count;
+                *replay
+                    .moves
+                    .entry((row.iso_code, after.map(str::to_owned)))
+                    .or_default() += row.count;
+            }
+        }
+        Ok(replay)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{RemoveOnDrop, TestDb};
+
+    use super::diff_databases;
+
+    #[tokio::test]
+    async fn test_diff_databases() {
+        let old_path = "/tmp/loca-test-diff-old.mmdb";
+        let new_path = "/tmp/loca-test-diff-new.mmdb";
+        let _remove_old = RemoveOnDrop { path: old_path };
+        let _remove_new = RemoveOnDrop { path: new_path };
+
+        TestDb::new()
+            .country("1.2.3.0/24", "US", "NA")
+            .country("5.6.7.0/24", "DE", "EU")
+            .write(old_path);
+        TestDb::new()
+            .country("1.2.3.0/24", "US", "NA")
+            .country("5.6.7.0/24", "FR", "EU")
+            .country("9.9.9.0/24", "CH", "EU")
+            .write(new_path);
+
+        let sample = ["1.2.3.4", "5.6.7.8", "5.6.7.9", "9.9.9.9"].map(|addr| addr.parse().unwrap());
+        let diff = diff_databases(old_path, new_path, &sample).await.unwrap();
+        assert_eq!(diff.sampled, 4);
+        assert_eq!(diff.changed, 3);
+        assert_eq!(
+            diff.moves[&(Some("DE".to_string()), Some("FR".to_string()))],
+            2
+        );
+        assert_eq!(diff.moves[&(None, Some("CH".to_string()))], 1);
+    }
+
+    #[cfg(feature = "analytics")]
+    #[tokio::test]
+    async fn test_replay_subnets() {
+        use crate::{Locat, SubnetAnalytics};
+
+        let old_path = "/tmp/loca-test-replay-old.mmdb";
+        let new_path = "/tmp/loca-test-replay-new.mmdb";
+        let analytics_path = "/tmp/loca-test-replay.db";
+        let _remove_old = RemoveOnDrop { path: old_path };
+        let _remove_new = RemoveOnDrop { path: new_path };
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
+        TestDb::new()
+            .country("1.2.3.0/24", "US", "NA")
+            .country("5.6.7.0/24", "DE", "EU")
+            .write(old_path);
+        TestDb::new()
+            .country("1.2.3.0/24", "US", "NA")
+            .country("5.6.7.0/24", "FR", "EU")
+            .write(new_path);
+
+        let locat = Locat::new(old_path, analytics_path)
+            .await
+            .unwrap()
+            .with_subnet_analytics(SubnetAnalytics::new());
+        for addr in ["1.2.3.4", "1.2.3.5", "5.6.7.8"] {
+            locat.ip_to_iso_code(addr.parse().unwrap()).await;
+        }
+        let salted = Locat::new(old_path, analytics_path)
+            .await
+            .unwrap()
+            .with_subnet_analytics(SubnetAnalytics::new().with_salt("salt"));
+        salted.ip_to_iso_code("5.6.7.9".parse().unwrap()).await;
+
+        let replay = locat.replay_subnets(new_path).await.unwrap();
+        assert_eq!(replay.replayed, 3);
+        assert_eq!(replay.changed, 1);
+        assert_eq!(replay.moves[&("DE".to_string(), Some("FR".to_string()))], 1);
+        assert_eq!(replay.by_country["US"], (2, 0));
+        assert_eq!(replay.by_country["DE"], (1, 1));
+        assert_eq!(replay.hashed, 1);
+    }
+}
@@ -0,0 +1,253 @@
+use std::{
+    collections::{BTreeSet, HashMap},
+    net::IpAddr,
+    sync::Mutex,
+    time::Instant,
+};
+
+use hickory_resolver::TokioResolver;
+
+use crate::{Error, Locat, Outcome, Reliability};
+
+/// Whether all addresses of a host are in the same country, see
+/// [`Locat::host_consensus`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Consensus {
+    pub addresses: usize,
+    pub countries: BTreeSet<String>,
+    /// Anycast operators among the addresses: their country says little
+    /// about where the host is served from (typically a CDN)
+    pub anycast: BTreeSet<&'static str>,
+    /// Addresses that couldn't be located (including bogons)
+    pub unlocated: usize,
+}
+
+impl Consensus {
+    fn new(outcomes: &[(IpAddr, Outcome)]) -> Self {
+        let mut consensus = Self {
+            addresses: outcomes.len(),
+            countries: BTreeSet::new(),
+            anycast: BTreeSet::new(),
+            unlocated: 0,
+        };
+        for (_, outcome) in outcomes {
+            match outcome {
+                Outcome::Located(resolution) => {
+                    consensus.countries.insert(resolution.iso_code.clone());
+                    if let Reliability::GeoUnreliable { operator } = resolution.reliability {
+                        consensus.anycast.insert(operator);
+                    }
+                }
+                _ => consensus.unlocated += 1,
+            }
+        }
+        consensus
+    }
+
+    /// The country every address agrees on, if they all do and none of them
+    /// is anycast or unknown
+    pub fn country(&self) -> Option<&str> {
+        if self.countries.len() != 1 || !self.anycast.is_empty() || self.unlocated > 0 {
+            return None;
+        }
+        self.countries.first().map(String::as_str)
+    }
+}
+
+/// Remembers what [`Locat::lookup_host`] found for a name, until its DNS
+/// records expire, see [`Locat::with_host_cache`]
+pub trait HostCache: Send + Sync {
+    /// The addresses of `name` and their outcomes, if they haven't expired
+    fn get(&self, name: &str) -> Option<Vec<(IpAddr, Outcome)>>;
+
+    fn insert(&self, name: &str, answers: Vec<(IpAddr, Outcome)>, valid_until: Instant);
+}
+
+type Answers = Vec<(IpAddr, Outcome)>;
+
+/// An in-memory [`HostCache`] holding up to `capacity` names
+pub struct TtlCache {
+    capacity: usize,
+    entries: Mutex<HashMap<String, (Instant, Answers)>>,
+}
+
+impl TtlCache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: Default::default(),
+        }
+    }
+}
+
+impl HostCache for TtlCache {
+    fn get(&self, name: &str) -> Option<Vec<(IpAddr, Outcome)>> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(name) {
+            Some((valid_until, answers)) if *valid_until > Instant::now() => Some(answers.clone()),
+            Some(_) => {
+                entries.remove(name);
+                None
+            }
+            None => None,
+        }
+    }
+
+    fn insert(&self, name: &str, answers: Vec<(IpAddr, Outcome)>, valid_until: Instant) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() >= self.capacity && !entries.contains_key(name) {
+            let now = Instant::now();
+            entries.retain(|_, (valid_until, _)| *valid_until > now);
+        }
+        if entries.len() >= self.capacity && !entries.contains_key(name) {
+            // still full, make room by dropping whatever expires first
+            let soonest = entries
+                .iter()
+                .min_by_key(|(_, (valid_until, _))| *valid_until)
+                .map(|(name, _)| name.clone());
+            if let Some(soonest) = soonest {
+                entries.remove(&soonest);
+            }
+        }
+        entries.insert(name.to_owned(), (valid_until, answers));
+    }
+}
+
+impl Locat {
+    /// Remembers the results of [`Locat::lookup_host`] for as long as the
+    /// DNS records they came from are valid
+    pub fn with_host_cache(mut self, cache: impl HostCache + 'static) -> Self {
+        self.inner_mut().host_cache = Some(Box::new(cache));
+        self
+    }
+
+    /// Resolves the A and AAAA records of `name` with the system's DNS
+    /// configuration, and looks up each address.
+    ///
+    /// Lookups made here aren't counted: the addresses belong to the host,
+    /// not to visitors.
+    pub async fn lookup_host(&self, name: &str) -> Result<Vec<(IpAddr, Outcome)>, Error> {
+        let cache = self.inner.host_cache.as_deref();
+        if let Some(answers) = cache.and_then(|cache| cache.get(name)) {
+            return Ok(answers);
+        }
+
+        let resolver = TokioResolver::builder_tokio()?.build()?;
+        let addrs = resolver.lookup_ip(name).await?;
+        let answers: Vec<_> = addrs
+            .iter()
+            .map(|addr| (addr, self.classify(addr).1))
+            .collect();
+        if let Some(cache) = cache {
+            cache.insert(name, answers.clone(), addrs.valid_until());
+        }
+        Ok(answers)
+    }
+
+    /// Whether every address of `name` is located in the same country, e.g.
+    /// to check where a vendor's service is hosted. Hosts behind a CDN
+    /// usually don't agree.
+    pub async fn host_consensus(&self, name: &str) -> Result<Consensus, Error> {
+        Ok(Consensus::new(&self.lookup_host(name).await?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(feature = "analytics")]
+    use crate::{
+        testing::{RemoveOnDrop, TestDb},
+        Locat,
+    };
+    use crate::{Outcome, Provenance, Reliability, Resolution};
+
+    use std::time::{Duration, Instant};
+
+    use super::{Consensus, HostCache, TtlCache};
+
+    #[test]
+    fn test_consensus() {
+        let located = |iso_code| Outcome::Located(Resolution::new(iso_code, Provenance::GeoIp));
+        let a = "1.2.3.4".parse().unwrap();
+        let b = "5.6.7.8".parse().unwrap();
+
+        let consensus = Consensus::new(&[(a, located("US")), (b, located("US"))]);
+        assert_eq!(consensus.country(), Some("US"));
+
+        let consensus = Consensus::new(&[(a, located("US")), (b, located("DE"))]);
+        assert_eq!(consensus.country(), None);
+
+        let mut anycast = Resolution::new("US", Provenance::GeoIp);
+        anycast.reliability = Reliability::GeoUnreliable {
+            operator: "Cloudflare",
+        };
+        let consensus = Consensus::new(&[(a, located("US")), (b, Outcome::Located(anycast))]);
+        assert_eq!(consensus.country(), None);
+        assert!(consensus.anycast.contains("Cloudflare"));
+
+        let consensus = Consensus::new(&[(a, located("US")), (b, Outcome::NotFound)]);
+        assert_eq!(consensus.unlocated, 1);
+        assert_eq!(consensus.country(), None);
+    }
+
+    #[test]
+    fn test_ttl_cache() {
+        let cache = TtlCache::new(2);
+        let answers = vec![("1.2.3.4".parse().unwrap(), Outcome::NotFound)];
+        let now = Instant::now();
+        let later = now + Duration::from_secs(60);
+
+        cache.insert("a.example", answers.clone(), later);
+        assert_eq!(cache.get("a.example"), Some(answers.clone()));
+        assert_eq!(cache.get("b.example"), None);
+
+        cache.insert("expired.example", answers.clone(), now);
+        assert_eq!(cache.get("expired.example"), None);
+
+        // full: the first to expire makes room
+        cache.insert(
+            "b.example",
+            answers.clone(),
+            later + Duration::from_secs(60),
+        );
+        cache.insert(
+            "c.example",
+            answers.clone(),
+            later + Duration::from_secs(120),
+        );
+        assert_eq!(cache.get("a.example"), None);
+        assert!(cache.get("b.example").is_some());
+        assert!(cache.get("c.example").is_some());
+    }
+
+    #[cfg(feature = "analytics")]
+    #[tokio::test]
+    async fn test_lookup_host() {
+        let geoip_path = "/tmp/loca-test-dns.mmdb";
+        let analytics_path = "/tmp/loca-test-dns.db";
+        TestDb::new()
+            .country("1.2.3.0/24", "US", "NA")
+            .write(geoip_path);
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
+        let locat = Locat::new(geoip_path, analytics_path)
+            .await
+            .unwrap()
+            .with_host_cache(TtlCache::new(16));
+
+        // answered from the hosts file, no network needed
+        let addrs = locat.lookup_host("localhost").await.unwrap();
+        assert!(!addrs.is_empty());
+        assert!(addrs
+            .iter()
+            .all(|(addr, outcome)| addr.is_loopback() && *outcome == Outcome::Bogon));
+        assert_eq!(locat.get_bogon_count().await.unwrap(), 0);
+        assert_eq!(locat.lookup_host("localhost").await.unwrap(), addrs);
+    }
+}
@@ -0,0 +1,55 @@
+use std::{io::Read, sync::Arc};
+
+use flate2::read::GzDecoder;
+use memmap2::MmapMut;
+
+use crate::{resolver::DatabaseBytes, Db, Error, GeoIp, Locat};
+
+// `GEOIP`, the compressed database, and `GEOIP_LEN`, its size once
+// decompressed, see build.rs
+include!(concat!(env!("OUT_DIR"), "/embedded.rs"));
+
+impl Locat {
+    /// Like [`Locat::new`], with the GeoIP database packed into the binary
+    /// at build time, for single-file deployments:
+    ///
+    /// ```text
+    /// LOCAT_EMBED_GEOIP=/var/lib/GeoLite2-Country.mmdb cargo build --features embedded
+    /// ```
+    ///
+    /// The database is decompressed into anonymous memory on every call.
+    /// [`Locat::reload_geoip`] doesn't apply.
+    pub async fn embedded(analytics_db_path: &str) -> Result<Self, Error> {
+        let db = Arc::new(Db::open(analytics_db_path).await?);
+        Ok(Self::from_parts(
+            "<embedded>",
+            embedded_geoip()?,
+            db.clone(),
+            Some(db),
+        ))
+    }
+}
+
+fn embedded_geoip() -> Result<GeoIp, Error> {
+    if GEOIP_LEN == 0 {
+        return Err(Error::NotEmbedded);
+    }
+    let mut map = MmapMut::map_anon(GEOIP_LEN)?;
+    GzDecoder::new(GEOIP).read_exact(&mut map)?;
+    GeoIp::load(DatabaseBytes::Mapped(map.make_read_only()?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{embedded_geoip, GEOIP_LEN};
+    use crate::Error;
+
+    #[test]
+    fn test_embedded_geoip() {
+        match embedded_geoip() {
+            Ok(geoip) => assert!(!geoip.reader.metadata.database_type.is_empty()),
+            Err(Error::NotEmbedded) => assert_eq!(GEOIP_LEN, 0),
+            Err(e) => panic!("{e}"),
+        }
+    }
+}
@@ -0,0 +1,92 @@
+use crate::{Error, Locat};
+
+impl Locat {
+    /// A token that changes whenever analytics are cleared or imported,
+    /// see [`crate::AnalyticsStore::epoch`]. Whatever was derived from
+    /// analytics of another epoch (a cached top 10, the previous export to
+    /// diff against, ...) is stale; [`crate::Manifest`]s carry it for that
+    /// reason.
+    pub async fn epoch(&self) -> Result<u64, Error> {
+        self.inner.analytics.epoch().await
+    }
+
+    /// Forgets which visitors were already counted when the epoch changed,
+    /// e.g. because another process cleared a shared analytics file.
+    /// Checked on every [`Locat::flush`].
+    pub(crate) async fn check_epoch(&self) -> Result<(), Error> {
+        let epoch = self.epoch().await?;
+        let previous = self.inner.epoch.lock().unwrap().replace(epoch);
+        if previous.is_some_and(|previous| previous != epoch) {
+            if let Some(dedup) = &self.inner.dedup {
+                dedup.clear();
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        testing::{RemoveOnDrop, TestDb},
+        Locat, Manifest,
+    };
+
+    #[tokio::test]
+    async fn test_epoch() {
+        let geoip_path = "/tmp/loca-test-epoch.mmdb";
+        let analytics_path = "/tmp/loca-test-epoch.db";
+        TestDb::new()
+            .country("1.2.3.0/24", "US", "NA")
+            .write(geoip_path);
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
+
+        let locat = Locat::new(geoip_path, analytics_path)
+            .await
+            .unwrap()
+            .with_dedup_window(Duration::from_secs(3600));
+        let addr = "1.2.3.4".parse().unwrap();
+        let initial = locat.epoch().await.unwrap();
+        locat.ip_to_iso_code(addr).await;
+        let (export, manifest) = locat.export_with_manifest().await.unwrap();
+        assert_eq!(manifest.epoch, Some(initial));
+        // a new file knows when it was created
+        assert!(manifest.from.is_some());
+        assert_eq!(
+            Manifest::parse(&manifest.render()).unwrap().epoch,
+            Some(initial)
+        );
+
+        // lookups aren't deduplicated against counts that are gone
+        locat.clear_analytics().await.unwrap();
+        let cleared = locat.epoch().await.unwrap();
+        assert_ne!(cleared, initial);
+        locat.ip_to_iso_code(addr).await;
+        assert_eq!(
+            locat.get_analytics().await.unwrap(),
+            vec![("US".to_string(), 1)]
+        );
+
+        locat.import_portable(&export).await.unwrap();
+        assert_ne!(locat.epoch().await.unwrap(), cleared);
+        // counting isn't a new epoch
+        let imported = locat.epoch().await.unwrap();
+        locat.ip_to_iso_code("1.2.3.5".parse().unwrap()).await;
+        assert_eq!(locat.epoch().await.unwrap(), imported);
+
+        // noticed on flush when it happens elsewhere
+        let other = Locat::new(geoip_path, analytics_path).await.unwrap();
+        other.clear_analytics().await.unwrap();
+        locat.flush().await.unwrap();
+        locat.ip_to_iso_code(addr).await;
+        assert_eq!(
+            locat.get_analytics().await.unwrap(),
+            vec![("US".to_string(), 1)]
+        );
+    }
+}
@@ -0,0 +1,259 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{db, subnets::salted_hash, Error, Field, Locat};
+
+/// Most exemplars ever kept per country and day, whatever
+/// [`Exemplars::with_per_day`] asks for
+pub const MAX_EXEMPLARS_PER_DAY: u32 = 100;
+
+// longest they're ever kept, in days
+const MAX_RETENTION_DAYS: u64 = 30;
+
+/// How [`Locat::with_exemplars`] keeps a few salted hashes of client
+/// addresses per country and day, to look into complaints about where
+/// someone was located.
+///
+/// By default 5 per country and day are kept, for 7 days.
+#[derive(Clone)]
+pub struct Exemplars {
+    salt: Vec<u8>,
+    per_day: u32,
+    retention_days: u64,
+}
+
+// the salt stays out of logs
+impl fmt::Debug for Exemplars {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Exemplars")
+            .field("per_day", &self.per_day)
+            .field("retention_days", &self.retention_days)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Exemplars {
+    /// Hashes addresses with `salt`: keep it secret, addresses are easily
+    /// brute-forced by whoever has it
+    pub fn new(salt: impl Into<Vec<u8>>) -> Self {
+        Self {
+            salt: salt.into(),
+            per_day: 5,
+            retention_days: 7,
+        }
+    }
+
+    /// How many are kept per country and day, at most
+    /// [`MAX_EXEMPLARS_PER_DAY`]
+    pub fn with_per_day(mut self, per_day: u32) -> Self {
+        self.per_day = per_day.min(MAX_EXEMPLARS_PER_DAY);
+        self
+    }
+
+    /// How long they're kept, in whole days from 1 to 30, counting the day
+    /// they were seen on
+    pub fn with_retention(mut self, retention: Duration) -> Self {
+        self.retention_days = (retention.as_secs() / 86400).clamp(1, MAX_RETENTION_DAYS);
+        self
+    }
+
+    /// What `addr` is kept as, to find a complaining client among
+    /// [`Locat::get_exemplars`]
+    pub fn hash(&self, addr: IpAddr) -> String {
+        salted_hash(&self.salt, &addr.to_string())
+    }
+
+    /// The first day whose exemplars are still kept
+    fn since_day(&self, today: u64) -> u64 {
+        (today + 1).saturating_sub(self.retention_days)
+    }
+}
+
+/// An exemplar on its way to the database, see `Db::write_pending`
+#[derive(Debug, Clone)]
+pub(crate) struct NewExemplar {
+    pub(crate) iso_code: String,
+    pub(crate) day: u64,
+    pub(crate) exemplar: String,
+    pub(crate) per_day: u32,
+    // older ones are deleted along the way
+    pub(crate) since_day: u64,
+}
+
+pub(crate) struct ExemplarState {
+    config: Exemplars,
+    // what was kept today, to skip hashing once a country is full
+    kept: Mutex<(u64, HashMap<String, HashSet<String>>)>,
+}
+
+impl Locat {
+    /// Keeps salted hashes of up to [`Exemplars::with_per_day`] addresses
+    /// located in each country each day, and deletes them after
+    /// [`Exemplars::with_retention`], see [`Locat::get_exemplars`]. Only
+    /// lookups that count are kept, and only as far as the privacy policy
+    /// allows: none without a [`Locat::with_scrubber`], none with a
+    /// [`crate::ScrubPolicy`] unless [`crate::ScrubPolicy::with_exemplars`],
+    /// and other scrubbers can clear [`Field::Exemplar`]. SQLite only,
+    /// and never exported.
+    pub fn with_exemplars(mut self, config: Exemplars) -> Self {
+        self.inner_mut().exemplars = Some(ExemplarState {
+            config,
+            kept: Mutex::new((0, HashMap::new())),
+        });
+        self
+    }
+
+    /// What to keep of a counted lookup, if anything
+    pub(crate) fn exemplar(&self, addr: IpAddr, iso_code: &str) -> Option<NewExemplar> {
+        let state = self.inner.exemplars.as_ref()?;
+        self.inner.sqlite.as_ref()?;
+        self.inner.scrubber.as_ref()?;
+        let today = db::today();
+        let full = |kept: &mut (u64, HashMap<String, HashSet<String>>)| {
+            if kept.0 != today {
+                *kept = (today, HashMap::new());
+            }
+            kept.1.get(iso_code).map_or(0, HashSet::len) >= state.config.per_day as usize
+        };
+        if full(&mut state.kept.lock().unwrap()) {
+            return None;
+        }
+        let mut exemplar = Some(state.config.hash(addr));
+        self.scrub(Field::Exemplar {
+            iso_code,
+            exemplar: &mut exemplar,
+        });
+        let exemplar = exemplar?;
+        let mut kept = state.kept.lock().unwrap();
+        if full(&mut kept)
+            || !kept
+                .1
+                .entry(iso_code.to_owned())
+                .or_default()
+                .insert(exemplar.clone())
+        {
+            return None;
+        }
+        Some(NewExemplar {
+            iso_code: iso_code.to_owned(),
+            day: today,
+            exemplar,
+            per_day: state.config.per_day,
+            since_day: state.config.since_day(today),
+        })
+    }
+
+    /// The exemplars kept for a country, oldest first, along with the day
+    /// they were seen on (at midnight UTC), see [`Locat::with_exemplars`].
+    /// Empty without it.
+    pub async fn get_exemplars(&self, iso_code: &str) -> Result<Vec<(SystemTime, String)>, Error> {
+        let db = self.sqlite("get_exemplars")?;
+        let Some(state) = &self.inner.exemplars else {
+            return Ok(Vec::new());
+        };
+        let since_day = state.config.since_day(db::today());
+        let rows = db.list_exemplars(iso_code, since_day).await?;
+        Ok(rows
+            .into_iter()
+            .map(|(day, exemplar)| (UNIX_EPOCH + Duration::from_secs(day * 86400), exemplar))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Exemplars, NewExemplar};
+    use crate::{
+        batch::Pending,
+        db,
+        testing::{RemoveOnDrop, TestDb},
+        Locat, ScrubPolicy,
+    };
+
+    #[tokio::test]
+    async fn test_exemplars() {
+        let geoip_path = "/tmp/loca-test-exemplars.mmdb";
+        let analytics_path = "/tmp/loca-test-exemplars.db";
+        TestDb::new()
+            .country("1.2.3.0/24", "US", "NA")
+            .write(geoip_path);
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
+        let config = Exemplars::new("salt").with_per_day(2);
+        let addrs: Vec<_> = (1..=3)
+            .map(|i| format!("1.2.3.{i}").parse().unwrap())
+            .collect();
+
+        // nothing is kept unless the privacy policy allows it
+        for locat in [
+            Locat::new(geoip_path, analytics_path)
+                .await
+                .unwrap()
+                .with_exemplars(config.clone()),
+            Locat::new(geoip_path, analytics_path)
+                .await
+                .unwrap()
+                .with_scrubber(ScrubPolicy::new())
+                .with_exemplars(config.clone()),
+        ] {
+            locat.ip_to_iso_code(addrs[0]).await;
+            assert!(locat.get_exemplars("US").await.unwrap().is_empty());
+        }
+
+        let locat = Locat::new(geoip_path, analytics_path)
+            .await
+            .unwrap()
+            .with_scrubber(ScrubPolicy::new().with_exemplars())
+            .with_exemplars(config.clone());
+        locat.ip_to_iso_code(addrs[0]).await;
+        locat.ip_to_iso_code(addrs[0]).await;
+        locat.ip_to_iso_codes(&addrs[1..]).await;
+        let kept: Vec<_> = locat
+            .get_exemplars("US")
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|(_, exemplar)| exemplar)
+            .collect();
+        let mut expected = vec![config.hash(addrs[0]), config.hash(addrs[1])];
+        expected.sort();
+        assert_eq!(kept, expected);
+        assert_eq!(locat.get_lookup_stats().await.unwrap().located, 6);
+
+        // caps hold even for whatever the in-memory check missed, and old
+        // days are deleted with the next write
+        let db = locat.sqlite("test").unwrap();
+        let today = db::today();
+        let mut pending = Pending::default();
+        for (day, exemplar) in [(today - 10, "old"), (today, "late")] {
+            pending.exemplars.push(NewExemplar {
+                iso_code: "US".to_owned(),
+                day,
+                exemplar: exemplar.to_owned(),
+                per_day: 2,
+                since_day: today - 6,
+            });
+        }
+        db.write_pending(pending).await.unwrap();
+        assert_eq!(locat.get_exemplars("US").await.unwrap().len(), 2);
+        let rows = db
+            .query_readonly("SELECT exemplar FROM exemplars".into(), vec![])
+            .await
+            .unwrap()
+            .rows;
+        assert_eq!(rows.len(), 2);
+
+        assert_eq!(config.clone().with_per_day(1000).per_day, 100);
+        let config = config.with_retention(Duration::ZERO);
+        assert_eq!(config.since_day(today), today);
+    }
+}
@@ -0,0 +1,215 @@
+use std::time::Duration;
+
+/// A point on the globe, in degrees
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Coordinates {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl Coordinates {
+    pub fn new(latitude: f64, longitude: f64) -> Self {
+        Self {
+            latitude,
+            longitude,
+        }
+    }
+
+    /// Great-circle distance to `other`, in kilometers
+    pub fn distance_km(&self, other: &Coordinates) -> f64 {
+        // mean earth radius, the haversine formula assumes a sphere anyway
+        const EARTH_RADIUS_KM: f64 = 6371.0;
+
+        let (lat1, lat2) = (self.latitude.to_radians(), other.latitude.to_radians());
+        let d_lat = lat2 - lat1;
+        let d_lon = (other.longitude - self.longitude).to_radians();
+
+        let a = (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_lon / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+    }
+}
+
+/// Everything a City database knows about where an address is, see
+/// [`crate::Locat::ip_to_location`]
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Location {
+    /// ISO 3166-1 alpha-2 country code
+    pub iso_code: Option<String>,
+    /// The largest subdivision (state, province, ...), as the part of its
+    /// ISO 3166-2 code after the country, e.g. `"CA"` for California
+    pub subdivision: Option<String>,
+    /// English name of the city
+    pub city: Option<String>,
+    pub coordinates: Option<Coordinates>,
+    /// How far off the coordinates may be, in kilometers
+    pub accuracy_km: Option<f64>,
+    /// IANA time zone, e.g. `"America/Los_Angeles"`
+    pub time_zone: Option<String>,
+}
+
+/// The network an address belongs to, see [`crate::Locat::ip_to_asn`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Asn {
+    /// Autonomous system number
+    pub number: u32,
+    pub organization: Option<String>,
+}
+
+/// How two addresses differ geographically, see [`crate::Locat::compare`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct GeoDelta {
+    pub country_a: Option<String>,
+    pub country_b: Option<String>,
+    /// Autonomous system numbers, if the database has them
+    pub asn_a: Option<u32>,
+    pub asn_b: Option<u32>,
+    /// Only known when both addresses have coordinates (City databases)
+    pub distance_km: Option<f64>,
+}
+
+impl GeoDelta {
+    /// Whether the two addresses are known to be in different countries
+    pub fn country_changed(&self) -> bool {
+        matches!((&self.country_a, &self.country_b), (Some(a), Some(b)) if a != b)
+    }
+
+    /// Whether the two addresses are known to belong to different networks
+    pub fn asn_changed(&self) -> bool {
+        matches!((self.asn_a, self.asn_b), (Some(a), Some(b)) if a != b)
+    }
+
+    /// Whether anything known about the two addresses differs. A session
+    /// hopping between these addresses deserves a second look.
+    pub fn changed(&self) -> bool {
+        self.country_changed() || self.asn_changed()
+    }
+}
+
+/// Whether a move between two locations could have happened in time
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TravelVerdict {
+    Possible,
+    Impossible,
+    /// At least one of the addresses has no coordinates
+    Unknown,
+}
+
+/// See [`crate::Locat::impossible_travel`]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Travel {
+    pub verdict: TravelVerdict,
+    /// Distance between the two locations, minus how far off each of them
+    /// may be according to the database
+    pub distance_km: Option<f64>,
+    pub speed_kmh: Option<f64>,
+}
+
+impl Travel {
+    pub(crate) fn unknown() -> Self {
+        Self {
+            verdict: TravelVerdict::Unknown,
+            distance_km: None,
+            speed_kmh: None,
+        }
+    }
+
+    /// `from` and `to` come with their accuracy radius in kilometers
+    pub(crate) fn evaluate(
+        from: (Coordinates, f64),
+        to: (Coordinates, f64),
+        elapsed: Duration,
+        max_speed_kmh: f64,
+    ) -> Self {
+        // give the benefit of the doubt: both locations might be off by
+        // their accuracy radius, towards each other
+        let distance_km = (from.0.distance_km(&to.0) - from.1 - to.1).max(0.0);
+        let hours = elapsed.as_secs_f64() / 3600.0;
+        let speed_kmh = if distance_km == 0.0 {
+            0.0
+        } else {
+            // zero elapsed time makes this infinite, which is impossible
+            distance_km / hours
+        };
+
+        Self {
+            verdict: if speed_kmh > max_speed_kmh {
+                TravelVerdict::Impossible
+            } else {
+                TravelVerdict::Possible
+            },
+            distance_km: Some(distance_km),
+            speed_kmh: Some(speed_kmh),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{Coordinates, GeoDelta, Travel, TravelVerdict};
+
+    #[test]
+    fn test_distance() {
+        let paris = Coordinates::new(48.8566, 2.3522);
+        let new_york = Coordinates::new(40.7128, -74.0060);
+
+        assert_eq!(paris.distance_km(&paris), 0.0);
+        let d = paris.distance_km(&new_york);
+        assert!((5830.0..5845.0).contains(&d), "{d}");
+        assert_eq!(d, new_york.distance_km(&paris));
+    }
+
+    #[test]
+    fn test_geo_delta() {
+        let mut delta = GeoDelta {
+            country_a: Some("FR".to_string()),
+            country_b: Some("FR".to_string()),
+            asn_a: Some(3215),
+            asn_b: None,
+            distance_km: None,
+        };
+        assert!(!delta.changed());
+
+        delta.asn_b = Some(12322);
+        assert!(delta.asn_changed());
+        assert!(!delta.country_changed());
+
+        delta.country_b = None;
+        assert!(!delta.country_changed());
+        delta.country_b = Some("US".to_string());
+        assert!(delta.country_changed());
+    }
+
+    #[test]
+    fn test_travel() {
+        let paris = Coordinates::new(48.8566, 2.3522);
+        let new_york = Coordinates::new(40.7128, -74.0060);
+        let hour = Duration::from_secs(3600);
+
+        let travel = Travel::evaluate((paris, 0.0), (new_york, 0.0), hour, 1000.0);
+        assert_eq!(travel.verdict, TravelVerdict::Impossible);
+        assert!(travel.speed_kmh.unwrap() > 5000.0);
+
+        // a long flight is fine
+        let travel = Travel::evaluate((paris, 0.0), (new_york, 0.0), hour * 8, 1000.0);
+        assert_eq!(travel.verdict, TravelVerdict::Possible);
+
+        // same place, no time: fine
+        let travel = Travel::evaluate((paris, 0.0), (paris, 0.0), Duration::ZERO, 1000.0);
+        assert_eq!(travel.verdict, TravelVerdict::Possible);
+        assert_eq!(travel.speed_kmh, Some(0.0));
+
+        // far apart, no time: impossible
+        let travel = Travel::evaluate((paris, 0.0), (new_york, 0.0), Duration::ZERO, 1000.0);
+        assert_eq!(travel.verdict, TravelVerdict::Impossible);
+
+        // accuracy radii that overlap mean we can't tell them apart
+        let travel = Travel::evaluate((paris, 3000.0), (new_york, 3000.0), hour, 1000.0);
+        assert_eq!(travel.verdict, TravelVerdict::Possible);
+        assert_eq!(travel.distance_km, Some(0.0));
+    }
+}
@@ -0,0 +1,267 @@
+//! Least-privilege views of a [`Locat`], for handing to parts of an
+//! application that shouldn't be able to do everything: request handlers
+//! get a [`LookupHandle`], the admin endpoint an [`AdminHandle`].
+
+#[cfg(feature = "analytics")]
+use std::time::Duration;
+use std::{collections::BTreeSet, net::IpAddr};
+
+#[cfg(feature = "analytics")]
+use crate::{AnalyticsQuery, AnalyticsTransaction, AuditEntry, LookupStats, Measures, SubnetCount};
+use crate::{Asn, Bogons, Error, Locat, Location, LookupError, Outcome, Resolution};
+
+/// Geolocation only, see [`Locat::lookup_handle`]. Lookups are still
+/// counted, but analytics can be neither read nor changed.
+#[derive(Clone)]
+pub struct LookupHandle {
+    locat: Locat,
+}
+
+/// Reading and changing analytics, and swapping databases, see
+/// [`Locat::admin_handle`]
+#[derive(Clone)]
+pub struct AdminHandle {
+    locat: Locat,
+}
+
+impl Locat {
+    pub fn lookup_handle(&self) -> LookupHandle {
+        LookupHandle {
+            locat: self.clone(),
+        }
+    }
+
+    pub fn admin_handle(&self) -> AdminHandle {
+        AdminHandle {
+            locat: self.clone(),
+        }
+    }
+}
+
+impl LookupHandle {
+    /// See [`Locat::ip_to_iso_code`]
+    pub async fn ip_to_iso_code(&self, addr: IpAddr) -> Option<&str> {
+        self.locat.ip_to_iso_code(addr).await
+    }
+
+    /// See [`Locat::ip_to_iso_code_untracked`]
+    pub fn ip_to_iso_code_untracked(&self, addr: IpAddr) -> Option<&str> {
+        self.locat.ip_to_iso_code_untracked(addr)
+    }
+
+    /// See [`Locat::ip_to_iso_codes`]
+    pub async fn ip_to_iso_codes(&self, addrs: &[IpAddr]) -> Vec<Option<&str>> {
+        self.locat.ip_to_iso_codes(addrs).await
+    }
+
+    /// See [`Locat::try_ip_to_iso_code`]
+    pub async fn try_ip_to_iso_code(&self, addr: IpAddr) -> Result<&str, LookupError> {
+        self.locat.try_ip_to_iso_code(addr).await
+    }
+
+    /// See [`Locat::resolve`]
+    pub async fn resolve(&self, addr: IpAddr) -> Option<Resolution> {
+        self.locat.resolve(addr).await
+    }
+
+    /// See [`Locat::lookup`]
+    pub async fn lookup(&self, addr: IpAddr) -> Outcome {
+        self.locat.lookup(addr).await
+    }
+
+    /// See [`Locat::lookup_untracked`]
+    pub fn lookup_untracked(&self, addr: IpAddr) -> Outcome {
+        self.locat.lookup_untracked(addr)
+    }
+
+    /// See [`Locat::ip_to_city`]
+    pub fn ip_to_city(&self, addr: IpAddr) -> Option<String> {
+        self.locat.ip_to_city(addr)
+    }
+
+    /// See [`Locat::ip_to_location`]
+    pub fn ip_to_location(&self, addr: IpAddr) -> Option<Location> {
+        self.locat.ip_to_location(addr)
+    }
+
+    /// See [`Locat::ip_to_asn`]
+    pub fn ip_to_asn(&self, addr: IpAddr) -> Option<Asn> {
+        self.locat.ip_to_asn(addr)
+    }
+
+    /// See [`Locat::route_region`]
+    pub fn route_region(&self, addr: IpAddr) -> Option<&str> {
+        self.locat.route_region(addr)
+    }
+
+    /// See [`Locat::nearest_region`]
+    pub fn nearest_region(&self, addr: IpAddr) -> Option<&str> {
+        self.locat.nearest_region(addr)
+    }
+
+    /// See [`Locat::available_countries`]
+    pub fn available_countries(&self) -> BTreeSet<String> {
+        self.locat.available_countries()
+    }
+}
+
+impl AdminHandle {
+    #[cfg(feature = "analytics")]
+    /// See [`Locat::get_analytics`]
+    pub async fn get_analytics(&self) -> Result<Vec<(String, u64)>, Error> {
+        self.locat.get_analytics().await
+    }
+
+    #[cfg(feature = "analytics")]
+    /// See [`Locat::get_analytics_filtered`]
+    pub async fn get_analytics_filtered(
+        &self,
+        query: &AnalyticsQuery,
+    ) -> Result<Vec<(String, u64)>, Error> {
+        self.locat.get_analytics_filtered(query).await
+    }
+
+    #[cfg(feature = "analytics")]
+    /// See [`Locat::get_weighted_analytics`]
+    pub async fn get_weighted_analytics(&self) -> Result<Vec<(String, String, Measures)>, Error> {
+        self.locat.get_weighted_analytics().await
+    }
+
+    #[cfg(feature = "analytics")]
+    /// See [`Locat::get_lookup_stats`]
+    pub async fn get_lookup_stats(&self) -> Result<LookupStats, Error> {
+        self.locat.get_lookup_stats().await
+    }
+
+    #[cfg(feature = "analytics")]
+    /// See [`Locat::get_subnet_analytics`]
+    pub async fn get_subnet_analytics(&self) -> Result<Vec<SubnetCount>, Error> {
+        self.locat.get_subnet_analytics().await
+    }
+
+    #[cfg(feature = "analytics")]
+    /// See [`Locat::get_bogon_count`]
+    pub async fn get_bogon_count(&self) -> Result<u64, Error> {
+        self.locat.get_bogon_count().await
+    }
+
+    #[cfg(feature = "analytics")]
+    /// See [`Locat::clear_analytics`]
+    pub async fn clear_analytics(&self) -> Result<(), Error> {
+        self.locat.clear_analytics().await
+    }
+
+    #[cfg(feature = "analytics")]
+    /// See [`Locat::reset_analytics`]
+    pub async fn reset_analytics(&self) -> Result<(), Error> {
+        self.locat.reset_analytics().await
+    }
+
+    #[cfg(feature = "analytics")]
+    /// See [`Locat::prune_analytics_older_than`]
+    pub async fn prune_analytics_older_than(&self, age: Duration) -> Result<usize, Error> {
+        self.locat.prune_analytics_older_than(age).await
+    }
+
+    #[cfg(feature = "analytics")]
+    /// See [`Locat::vacuum`]
+    pub async fn vacuum(&self) -> Result<(), Error> {
+        self.locat.vacuum().await
+    }
+
+    #[cfg(feature = "analytics")]
+    /// See [`Locat::adjust`]
+    pub async fn adjust(&self, iso_code: &str, delta: i64) -> Result<u64, Error> {
+        self.locat.adjust(iso_code, delta).await
+    }
+
+    #[cfg(feature = "analytics")]
+    /// See [`Locat::transaction`]
+    pub async fn transaction<T>(
+        &self,
+        f: impl FnOnce(&mut AnalyticsTransaction) -> T,
+    ) -> Result<T, Error> {
+        self.locat.transaction(f).await
+    }
+
+    #[cfg(feature = "analytics")]
+    /// See [`Locat::export_portable`]
+    pub async fn export_portable(&self) -> Result<String, Error> {
+        self.locat.export_portable().await
+    }
+
+    #[cfg(feature = "analytics")]
+    /// See [`Locat::import_portable`]
+    pub async fn import_portable(&self, data: &str) -> Result<(), Error> {
+        self.locat.import_portable(data).await
+    }
+
+    #[cfg(feature = "analytics")]
+    /// See [`Locat::analytics_to_prometheus`]
+    pub async fn analytics_to_prometheus(&self) -> Result<String, Error> {
+        self.locat.analytics_to_prometheus().await
+    }
+
+    #[cfg(feature = "analytics")]
+    /// See [`Locat::flush`]
+    pub async fn flush(&self) -> Result<(), Error> {
+        self.locat.flush().await
+    }
+
+    /// See [`Locat::reload_geoip`]
+    pub async fn reload_geoip(&self) -> Result<(), Error> {
+        self.locat.reload_geoip().await
+    }
+
+    /// See [`Locat::set_bogons`]
+    pub fn set_bogons(&self, bogons: Bogons) {
+        self.locat.set_bogons(bogons)
+    }
+
+    #[cfg(feature = "analytics")]
+    /// See [`Locat::audit`]
+    pub async fn audit(&self, action: &str, detail: Option<String>) -> Result<(), Error> {
+        self.locat.audit(action, detail).await
+    }
+
+    #[cfg(feature = "analytics")]
+    /// See [`Locat::audit_log`]
+    pub async fn audit_log(&self) -> Result<Vec<AuditEntry>, Error> {
+        self.locat.audit_log().await
+    }
+}
+
+#[cfg(all(test, feature = "analytics"))]
+mod tests {
+    use crate::{
+        testing::{RemoveOnDrop, TestDb},
+        Locat,
+    };
+
+    #[tokio::test]
+    async fn test_handles() {
+        let geoip_path = "/tmp/loca-test-handles.mmdb";
+        let analytics_path = "/tmp/loca-test-handles.db";
+        TestDb::new()
+            .country("1.2.3.0/24", "US", "NA")
+            .write(geoip_path);
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
+
+        let locat = Locat::new(geoip_path, analytics_path).await.unwrap();
+        let lookups = locat.lookup_handle();
+        let admin = locat.admin_handle();
+        drop(locat);
+
+        let addr = "1.2.3.4".parse().unwrap();
+        assert_eq!(lookups.ip_to_iso_code(addr).await, Some("US"));
+        assert_eq!(
+            admin.get_analytics().await.unwrap(),
+            vec![("US".to_string(), 1)]
+        );
+        admin.clear_analytics().await.unwrap();
+        assert!(admin.get_analytics().await.unwrap().is_empty());
+    }
+}
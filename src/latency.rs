@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+use crate::{Error, Locat};
+
+impl Locat {
+    /// Records how long answering a client located in a country took, right
+    /// away: `GeoLayer::with_latencies` in `locat::middleware` batches them
+    /// instead. Only the distribution is kept, to the microsecond. SQLite
+    /// only.
+    pub async fn record_latency(&self, iso_code: &str, latency: Duration) -> Result<(), Error> {
+        let micros = u64::try_from(latency.as_micros()).unwrap_or(u64::MAX);
+        Ok(self
+            .sqlite("record_latency")?
+            .record_latency(iso_code, micros)
+            .await?)
+    }
+
+    /// The `q`-quantile (between 0 and 1, e.g. `0.99` for p99) of the
+    /// latencies recorded for a country with [`Locat::record_latency`],
+    /// within 1% of the actual value. `None` if none were recorded.
+    pub async fn latency_quantile(
+        &self,
+        iso_code: &str,
+        q: f64,
+    ) -> Result<Option<Duration>, Error> {
+        let sketch = self.sqlite("latency_quantile")?.latencies(iso_code).await?;
+        Ok(sketch
+            .and_then(|sketch| sketch.quantile(q))
+            .map(|micros| Duration::from_micros(micros.round() as u64)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        testing::{RemoveOnDrop, TestDb},
+        Locat,
+    };
+
+    #[tokio::test]
+    async fn test_latency_quantile() {
+        let analytics_path = "/tmp/loca-test-latency.db";
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
+        let locat = Locat::builder()
+            .with_geoip_bytes(TestDb::new().build())
+            .with_analytics_path(analytics_path)
+            .build()
+            .await
+            .unwrap();
+
+        assert_eq!(locat.latency_quantile("AU", 0.5).await.unwrap(), None);
+        for millis in 1..=100 {
+            locat
+                .record_latency("AU", Duration::from_millis(millis))
+                .await
+                .unwrap();
+        }
+        locat
+            .record_latency("US", Duration::from_millis(5))
+            .await
+            .unwrap();
+
+        let p99 = locat.latency_quantile("AU", 0.99).await.unwrap().unwrap();
+        assert!(p99.abs_diff(Duration::from_millis(99)) <= Duration::from_millis(1));
+        let p50 = locat.latency_quantile("US", 0.5).await.unwrap().unwrap();
+        assert!(p50.abs_diff(Duration::from_millis(5)) <= Duration::from_micros(50));
+        // not counted as lookups
+        assert!(locat.get_analytics().await.unwrap().is_empty());
+
+        locat.clear_analytics().await.unwrap();
+        assert_eq!(locat.latency_quantile("AU", 0.5).await.unwrap(), None);
+    }
+}
@@ -1,12 +1,267 @@
-use std::net::IpAddr;
+use std::{
+    collections::{BTreeSet, HashMap},
+    fmt,
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::{Arc, RwLock},
+    time::Instant,
+};
+#[cfg(feature = "analytics")]
+use std::{
+    sync::Mutex,
+    time::{Duration, SystemTime},
+};
 
-// We're using tokio-rusqlite's own Connection type now
-use tokio_rusqlite::Connection;
+use arc_swap::ArcSwap;
+use trace::Instrument;
+
+mod accuracy;
+#[cfg(feature = "analytics")]
+mod admission;
+mod anycast;
+#[cfg(feature = "archive")]
+mod archive;
+#[cfg(feature = "analytics")]
+mod audit;
+#[cfg(feature = "analytics")]
+mod batch;
+mod bogon;
+mod builder;
+#[cfg(feature = "signed-overrides")]
+mod bundle;
+mod cidr;
+#[cfg(feature = "analytics")]
+mod config;
+#[cfg(feature = "corpus")]
+mod corpus;
+mod country;
+mod crosscheck;
+#[cfg(feature = "polars")]
+mod dataframe;
+#[cfg(feature = "analytics")]
+mod db;
+#[cfg(feature = "analytics")]
+mod dedup;
+mod diff;
+#[cfg(feature = "dns")]
+mod dns;
+#[cfg(feature = "embedded")]
+mod embedded;
+#[cfg(feature = "analytics")]
+mod epoch;
+#[cfg(feature = "analytics")]
+mod exemplars;
+mod geo;
+mod handles;
+#[cfg(feature = "analytics")]
+mod latency;
+mod locator;
+#[cfg(feature = "analytics")]
+mod manifest;
+mod metadata;
+#[cfg(feature = "analytics")]
+mod metrics;
+#[cfg(feature = "middleware")]
+pub mod middleware;
+#[cfg(feature = "mqtt")]
+mod mqtt;
+#[cfg(feature = "otlp")]
+mod otlp;
+#[cfg(feature = "analytics")]
+mod periods;
+#[cfg(feature = "analytics")]
+mod portable;
+#[cfg(feature = "analytics")]
+mod query;
+mod reload;
+#[cfg(feature = "analytics")]
+mod report;
+mod resolver;
+#[cfg(feature = "analytics")]
+mod retention;
+#[cfg(feature = "analytics")]
+mod rollover;
+#[cfg(feature = "analytics")]
+mod rollups;
+mod routing;
+mod scrub;
+#[cfg(feature = "analytics")]
+mod sketch;
+#[cfg(feature = "analytics")]
+pub mod snapshot;
+#[cfg(feature = "analytics")]
+mod stats;
+#[cfg(feature = "analytics")]
+mod status;
+#[cfg(feature = "analytics")]
+mod store;
+#[cfg(feature = "analytics")]
+mod subnets;
+mod summary;
+mod tasks;
+#[cfg(test)]
+mod testing;
+mod trace;
+#[cfg(feature = "analytics")]
+mod transaction;
+mod tunnel;
+#[cfg(feature = "auto-update")]
+mod update;
+mod verify;
+#[cfg(feature = "webhook")]
+mod webhook;
+
+pub use accuracy::{AccuracyReport, CountryScore};
+#[cfg(feature = "analytics")]
+pub use admission::{Admission, Overload};
+pub use anycast::anycast_operator;
+#[cfg(feature = "analytics")]
+pub use audit::AuditEntry;
+#[cfg(feature = "analytics")]
+pub use batch::DryRun;
+pub use bogon::Bogons;
+pub use builder::LocatBuilder;
+pub use cidr::{aggregate_networks, CidrFormat};
+#[cfg(feature = "analytics")]
+pub use config::{LocatConfig, Profile};
+#[cfg(feature = "corpus")]
+pub use corpus::{CorpusReport, Mismatch, CORPUS};
+pub use country::CountryCode;
+pub use crosscheck::{Agreement, Crosscheck, CrosscheckReport};
+#[cfg(feature = "analytics")]
+use db::Db;
+#[cfg(feature = "analytics")]
+pub use db::{AnalyticsInfo, RawRows};
+#[cfg(feature = "analytics")]
+pub use diff::Replay;
+pub use diff::{diff_databases, DatabaseDiff};
+#[cfg(feature = "dns")]
+pub use dns::{Consensus, HostCache, TtlCache};
+#[cfg(feature = "signed-overrides")]
+pub use ed25519_dalek::{SigningKey, VerifyingKey};
+#[cfg(feature = "analytics")]
+pub use exemplars::{Exemplars, MAX_EXEMPLARS_PER_DAY};
+pub use geo::{Asn, Coordinates, GeoDelta, Location, Travel, TravelVerdict};
+pub use handles::{AdminHandle, LookupHandle};
+pub use locator::{IpLocator, StaticLocat};
+#[cfg(feature = "analytics")]
+pub use manifest::Manifest;
+pub use metadata::GeoIpMetadata;
+#[cfg(feature = "mqtt")]
+pub use mqtt::{MqttClient, MqttPublish};
+#[cfg(feature = "otlp")]
+pub use otlp::OtlpLogs;
+#[cfg(feature = "analytics")]
+pub use periods::{PeriodDelta, Seasonality};
+#[cfg(feature = "analytics")]
+pub use query::{AnalyticsQuery, OrderBy};
+pub use reload::LiveGeoIp;
+#[cfg(feature = "analytics")]
+pub use report::{AnalyticsReport, CountryRow};
+pub use resolver::{
+    Cache, CacheStats, Cached, GeoIp, LookupError, Overrides, Provenance, Reliability, Resolution,
+    Resolver, Source, Then,
+};
+pub use routing::RegionRouter;
+pub use scrub::{Field, ScrubPolicy, Scrubber};
+#[cfg(feature = "analytics")]
+pub use snapshot::AnalyticsSnapshot;
+#[cfg(feature = "analytics")]
+pub use stats::LookupStats;
+#[cfg(feature = "analytics")]
+pub use store::{AnalyticsStore, MemoryStore};
+#[cfg(feature = "analytics")]
+pub use subnets::{SubnetAnalytics, SubnetCount};
+pub use summary::Summary;
+pub use tasks::{TaskExit, TaskInfo};
+#[cfg(feature = "analytics")]
+pub use transaction::AnalyticsTransaction;
+pub use tunnel::{unwrap_tunneled, Tunnel, Unwrapped};
+#[cfg(feature = "auto-update")]
+pub use update::AutoUpdate;
+pub use verify::{Check, Verification};
+#[cfg(feature = "webhook")]
+pub use webhook::{Schedule, WebhookReport};
 
 /// Allows geo-locating IPs and keeps analytics
+///
+/// Cloning is cheap: clones share the same databases, analytics and caches,
+/// so there's no need to wrap it in an `Arc`.
+#[derive(Clone)]
 pub struct Locat {
-    reader: maxminddb::Reader<Vec<u8>>,
-    analytics: Db,
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    geoip_path: String,
+    // swapped out by `Locat::reload_geoip`
+    geoip: Arc<ArcSwap<GeoIp>>,
+    city: Option<GeoIp>,
+    asn: Option<GeoIp>,
+    resolver: Option<Box<dyn Resolver>>,
+    // in front of `geoip`, see `Locat::with_lookup_cache`
+    cache: Option<Cache>,
+    // see `Locat::intern`
+    other_codes: reload::OtherCodes,
+    #[cfg(feature = "analytics")]
+    analytics: Arc<dyn AnalyticsStore>,
+    // the same store as `analytics` when it's the default one, for the
+    // features that need SQLite
+    #[cfg(feature = "analytics")]
+    sqlite: Option<Arc<Db>>,
+    #[cfg(feature = "analytics")]
+    dedup: Option<dedup::Dedup>,
+    // the analytics epoch `dedup` was filled in, see `Locat::check_epoch`
+    #[cfg(feature = "analytics")]
+    epoch: Mutex<Option<u64>>,
+    #[cfg(feature = "analytics")]
+    bucket_size: Option<Duration>,
+    #[cfg(feature = "analytics")]
+    write_behind: Option<batch::WriteBehind>,
+    #[cfg(feature = "analytics")]
+    subnets: Option<subnets::Subnets>,
+    #[cfg(feature = "analytics")]
+    exemplars: Option<exemplars::ExemplarState>,
+    #[cfg(feature = "analytics")]
+    retention: Option<retention::Retention>,
+    // where pruned rows go, see `Locat::with_archive`
+    #[cfg(feature = "archive")]
+    archive: Option<std::path::PathBuf>,
+    #[cfg(feature = "analytics")]
+    admission: Option<admission::AdmissionControl>,
+    // writes that failed for good, see `Locat::analytics_error_count`
+    #[cfg(feature = "analytics")]
+    analytics_errors: std::sync::atomic::AtomicU64,
+    #[cfg(feature = "otlp")]
+    otlp: Option<otlp::OtlpExporter>,
+    #[cfg(feature = "webhook")]
+    reports: Option<webhook::Reports>,
+    router: RegionRouter,
+    bogons: RwLock<Bogons>,
+    databases: HashMap<String, GeoIp>,
+    // who administrative actions are attributed to in the audit log
+    #[cfg(feature = "analytics")]
+    actor: Option<String>,
+    // whether lookups are counted, see `LocatBuilder::with_recording`
+    #[cfg(feature = "analytics")]
+    recording: bool,
+    // whether addresses that aren't in the database are counted too, see
+    // `LocatBuilder::with_miss_recording`
+    #[cfg(feature = "analytics")]
+    record_misses: bool,
+    #[cfg(feature = "analytics")]
+    metrics: metrics::LookupMetrics,
+    #[cfg(feature = "dns")]
+    host_cache: Option<Box<dyn dns::HostCache>>,
+    scrubber: Option<Arc<dyn Scrubber>>,
+    // whether databases are mapped rather than read, see
+    // `LocatBuilder::with_mmap`
+    #[cfg(feature = "mmap")]
+    mmap: bool,
+    // see `LocatBuilder::with_filesystem`
+    filesystem: bool,
+    // see `Locat::tasks`
+    tasks: tasks::Tasks,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -18,141 +273,1547 @@ pub enum Error {
     #[error("io error: {0}")]
     Io(#[from] std::io::Error),
 
+    #[cfg(feature = "analytics")]
     #[error("rusqlite error: {0}")]
-    Rusqlite(#[from] rusqlite::Error),
+    Rusqlite(#[source] rusqlite::Error),
+
+    #[error("invalid network: {0}")]
+    InvalidNetwork(#[from] ipnetwork::IpNetworkError),
+
+    #[error("no database named {0:?}")]
+    UnknownDatabase(String),
+
+    #[error("unknown country code {0:?}")]
+    UnknownCountry(String),
+
+    /// See [`Locat::record_weighted`]
+    #[cfg(feature = "analytics")]
+    #[error("invalid measure name {0:?}")]
+    InvalidMeasure(String),
+
+    #[cfg(feature = "analytics")]
+    #[error("invalid export: {0}")]
+    InvalidExport(String),
+
+    #[error("invalid config: {0}")]
+    InvalidConfig(String),
+
+    /// See [`Locat::close`]
+    #[error("other clones of this Locat are still in use")]
+    StillShared,
+
+    /// Analytics written or read after [`Locat::close`], e.g. by a final
+    /// write-behind flush
+    #[cfg(feature = "analytics")]
+    #[error("the analytics store was closed")]
+    Closed,
+
+    /// See [`LocatBuilder::with_filesystem`]
+    #[error("{0} needs the file system")]
+    NoFilesystem(&'static str),
+
+    #[cfg(feature = "analytics")]
+    #[error("the analytics path doesn't roll over every hour")]
+    NotHourly,
+
+    #[cfg(feature = "analytics")]
+    #[error("only SELECT statements are allowed here")]
+    NotReadOnly,
+
+    #[cfg(feature = "analytics")]
+    #[error("{0} needs the SQLite analytics store")]
+    Unsupported(&'static str),
+
+    /// Reported by an [`AnalyticsStore`] implementation
+    #[cfg(feature = "analytics")]
+    #[error("analytics store error: {0}")]
+    Store(Box<dyn std::error::Error + Send + Sync>),
+
+    #[cfg(any(feature = "auto-update", feature = "otlp", feature = "webhook"))]
+    #[error("HTTP error: {0}")]
+    Http(#[from] reqwest::Error),
+
+    #[cfg(feature = "auto-update")]
+    #[error("invalid download: {0}")]
+    InvalidDownload(String),
+
+    /// From the broker, see [`MqttClient`]
+    #[cfg(feature = "mqtt")]
+    #[error("MQTT error: {0}")]
+    Mqtt(String),
+
+    #[cfg(feature = "embedded")]
+    #[error("no GeoIP database was embedded, build with LOCAT_EMBED_GEOIP set")]
+    NotEmbedded,
+
+    #[cfg(feature = "signed-overrides")]
+    #[error("invalid override bundle: {0}")]
+    InvalidBundle(String),
+
+    /// The override bundle wasn't signed with the expected key, or was
+    /// changed since
+    #[cfg(feature = "signed-overrides")]
+    #[error("override bundle signature doesn't match")]
+    BadSignature,
+
+    #[cfg(feature = "dns")]
+    #[error("dns error: {0}")]
+    Dns(#[from] hickory_resolver::net::NetError),
+
+    #[cfg(feature = "polars")]
+    #[error("polars error: {0}")]
+    Polars(#[from] polars::error::PolarsError),
+}
+
+#[cfg(feature = "analytics")]
+impl From<rusqlite::Error> for Error {
+    fn from(e: rusqlite::Error) -> Self {
+        match db::is_closed(&e) {
+            true => Error::Closed,
+            false => Error::Rusqlite(e),
+        }
+    }
+}
+
+// only what helps tell instances apart in logs: no addresses, no counts
+// that would need a trip to the database
+impl fmt::Debug for Locat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = &self.inner;
+        let mut databases: Vec<_> = inner.databases.keys().collect();
+        databases.sort();
+        let mut debug = f.debug_struct("Locat");
+        debug
+            .field("geoip_path", &inner.geoip_path)
+            .field("geoip", &**inner.geoip.load());
+        #[cfg(feature = "analytics")]
+        debug.field("analytics", &inner.analytics.describe());
+        debug
+            .field("databases", &databases)
+            .field("custom_resolver", &inner.resolver.is_some());
+        #[cfg(feature = "analytics")]
+        debug.field(
+            "dedup_window",
+            &inner.dedup.as_ref().map(|dedup| dedup.window()),
+        );
+        debug
+            .field("bogon_networks", &inner.bogons.read().unwrap().len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// A one-line status, e.g. `GeoLite2-Country (built 12d ago) from
+/// /var/lib/country.mmdb, analytics in analytics.db`
+impl fmt::Display for Locat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let inner = &self.inner;
+        let metadata = inner.geoip.load().metadata();
+        write!(
+            f,
+            "{} (built {} ago) from {}",
+            metadata.database_type,
+            summary::HumanDuration(metadata.age()),
+            inner.geoip_path,
+        )?;
+        #[cfg(feature = "analytics")]
+        write!(f, ", analytics in {}", inner.analytics.describe())?;
+        if !inner.databases.is_empty() {
+            write!(f, ", {} more databases", inner.databases.len())?;
+        }
+        Ok(())
+    }
+}
+
+/// What a lookup found out about an address
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
+pub enum Outcome {
+    Located(Resolution),
+    /// Reserved or unallocated address space, see [`Bogons`]
+    Bogon,
+    /// Nothing knows where this address is
+    NotFound,
+    /// Refused while analytics can't keep up, see [`Overload::Shed`]
+    #[cfg(feature = "analytics")]
+    Overloaded,
 }
 
 impl Locat {
+    #[cfg(feature = "analytics")]
+    /// The analytics path may contain date placeholders (`%Y`, `%m`, `%d`,
+    /// `%H`, in UTC), e.g. `analytics-%Y-%m.db`: analytics then roll over to
+    /// a new file every period, leaving the old ones around for archival.
     pub async fn new(geoip_country_db_path: &str, analytics_db_path: &str) -> Result<Self, Error> {
+        let db = Arc::new(Db::open(analytics_db_path).await?);
+        Self::with_store(geoip_country_db_path, db.clone(), Some(db)).await
+    }
+
+    #[cfg(feature = "analytics")]
+    /// Keeps analytics in `store` instead of a SQLite database
+    pub async fn from_store(
+        geoip_country_db_path: &str,
+        store: impl AnalyticsStore + 'static,
+    ) -> Result<Self, Error> {
+        Self::with_store(geoip_country_db_path, Arc::new(store), None).await
+    }
+
+    #[cfg(feature = "analytics")]
+    async fn with_store(
+        geoip_country_db_path: &str,
+        analytics: Arc<dyn AnalyticsStore>,
+        sqlite: Option<Arc<Db>>,
+    ) -> Result<Self, Error> {
         // read geoip db into memory asynchronously
         let geoip_data = tokio::fs::read(geoip_country_db_path).await?;
+        let geoip = GeoIp::load(geoip_data)?;
+        let locat = Self::from_parts(geoip_country_db_path, geoip, analytics, sqlite);
+        locat.check_epoch().await?;
+        Ok(locat)
+    }
+
+    fn from_parts(
+        geoip_path: &str,
+        geoip: GeoIp,
+        #[cfg(feature = "analytics")] analytics: Arc<dyn AnalyticsStore>,
+        #[cfg(feature = "analytics")] sqlite: Option<Arc<Db>>,
+    ) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                geoip_path: geoip_path.to_owned(),
+                geoip: Arc::new(ArcSwap::from_pointee(geoip)),
+                city: None,
+                asn: None,
+                resolver: None,
+                cache: None,
+                other_codes: Default::default(),
+                #[cfg(feature = "analytics")]
+                analytics,
+                #[cfg(feature = "analytics")]
+                sqlite,
+                #[cfg(feature = "analytics")]
+                dedup: None,
+                #[cfg(feature = "analytics")]
+                epoch: Mutex::new(None),
+                #[cfg(feature = "analytics")]
+                bucket_size: None,
+                #[cfg(feature = "analytics")]
+                write_behind: None,
+                #[cfg(feature = "analytics")]
+                subnets: None,
+                #[cfg(feature = "analytics")]
+                exemplars: None,
+                #[cfg(feature = "analytics")]
+                retention: None,
+                #[cfg(feature = "archive")]
+                archive: None,
+                #[cfg(feature = "analytics")]
+                admission: None,
+                #[cfg(feature = "analytics")]
+                analytics_errors: Default::default(),
+                #[cfg(feature = "otlp")]
+                otlp: None,
+                #[cfg(feature = "webhook")]
+                reports: None,
+                router: RegionRouter::default(),
+                bogons: RwLock::new(Bogons::default()),
+                databases: HashMap::new(),
+                #[cfg(feature = "analytics")]
+                actor: None,
+                #[cfg(feature = "analytics")]
+                recording: true,
+                #[cfg(feature = "analytics")]
+                record_misses: true,
+                #[cfg(feature = "analytics")]
+                metrics: Default::default(),
+                #[cfg(feature = "dns")]
+                host_cache: None,
+                scrubber: None,
+                #[cfg(feature = "mmap")]
+                mmap: false,
+                filesystem: true,
+                tasks: Default::default(),
+            }),
+        }
+    }
+
+    #[cfg(feature = "analytics")]
+    /// The SQLite store, for features other stores don't have
+    fn sqlite(&self, feature: &'static str) -> Result<&Db, Error> {
+        self.inner
+            .sqlite
+            .as_deref()
+            .ok_or(Error::Unsupported(feature))
+    }
+
+    /// Refuses `feature` when the file system is off limits, see
+    /// [`LocatBuilder::with_filesystem`]
+    fn filesystem(&self, feature: &'static str) -> Result<(), Error> {
+        match self.inner.filesystem {
+            true => Ok(()),
+            false => Err(Error::NoFilesystem(feature)),
+        }
+    }
+
+    // configuration happens before the handle is shared
+    fn inner_mut(&mut self) -> &mut Inner {
+        Arc::get_mut(&mut self.inner).expect("`with_*` methods must be called before cloning")
+    }
+
+    /// Reads a database, or maps it with [`LocatBuilder::with_mmap`]
+    async fn open_geoip(&self, path: impl AsRef<Path>) -> Result<GeoIp, Error> {
+        self.filesystem("opening GeoIP databases")?;
+        #[cfg(feature = "mmap")]
+        if self.inner.mmap {
+            return GeoIp::open_mmap(path);
+        }
+        GeoIp::load(tokio::fs::read(path).await?)
+    }
+
+    /// Loads additional GeoIP databases by name, e.g. one per customer who
+    /// brings their own license, for use with [`Locat::ip_to_iso_code_in`].
+    pub async fn with_databases(
+        mut self,
+        databases: HashMap<String, PathBuf>,
+    ) -> Result<Self, Error> {
+        for (name, path) in databases {
+            let geoip = self.open_geoip(path).await?;
+            self.inner_mut().databases.insert(name, geoip);
+        }
+        Ok(self)
+    }
+
+    /// Loads a City database (GeoLite2-City, GeoIP2-City) for
+    /// [`Locat::ip_to_location`] and everything that needs coordinates.
+    /// Without one, those use the main database, which works if it's a City
+    /// database itself.
+    pub async fn with_city_database(mut self, path: impl AsRef<Path>) -> Result<Self, Error> {
+        self.inner_mut().city = Some(self.open_geoip(path).await?);
+        Ok(self)
+    }
+
+    /// Loads an ASN database (GeoLite2-ASN) for [`Locat::ip_to_asn`] and
+    /// [`Locat::compare`]. Without one, those use the main database, which
+    /// works with Enterprise databases.
+    pub async fn with_asn_database(mut self, path: impl AsRef<Path>) -> Result<Self, Error> {
+        self.inner_mut().asn = Some(self.open_geoip(path).await?);
+        Ok(self)
+    }
+
+    /// Resolves addresses through `resolver` in [`Locat::resolve`], instead
+    /// of only asking the GeoIP database.
+    pub fn with_resolver(mut self, resolver: impl Resolver + 'static) -> Self {
+        self.inner_mut().resolver = Some(Box::new(resolver));
+        self
+    }
+
+    /// Remembers which country addresses were found in, so that addresses
+    /// seen again skip the GeoIP database. Lookups are still counted. The
+    /// cache is emptied by [`Locat::reload_geoip`], and doesn't apply to
+    /// [`Locat::with_resolver`] chains, which can have their own.
+    pub fn with_lookup_cache(mut self, cache: Cache) -> Self {
+        self.inner_mut().cache = Some(cache);
+        self
+    }
+
+    /// How the cache set with [`Locat::with_lookup_cache`] is doing, `None`
+    /// without one
+    pub fn cache_stats(&self) -> Option<CacheStats> {
+        self.inner.cache.as_ref().map(Cache::stats)
+    }
+
+    #[cfg(feature = "analytics")]
+    /// Also counts lookups per `size`-long slice of time, e.g. an hour, for
+    /// [`Locat::get_analytics_between`] and [`Locat::get_analytics_by_day`].
+    /// Only the SQLite store keeps time buckets.
+    ///
+    /// # Panics
+    ///
+    /// If `size` is shorter than a second.
+    pub fn with_time_buckets(mut self, size: Duration) -> Self {
+        assert!(
+            size.as_secs() > 0,
+            "time buckets must last at least a second"
+        );
+        self.inner_mut().bucket_size = Some(size);
+        self
+    }
 
-        Ok(Self {
-            reader: maxminddb::Reader::from_source(geoip_data)?,
-            analytics: Db::open(analytics_db_path).await?,
-        })
+    #[cfg(feature = "analytics")]
+    /// Counts each (IP, country) pair at most once per `window`, so analytics
+    /// approximate visitors rather than raw request volume.
+    pub fn with_dedup_window(mut self, window: Duration) -> Self {
+        self.inner_mut().dedup = Some(dedup::Dedup::new(window));
+        self
     }
 
     /// Converts an address to an ISO 3166-1 alpha-2 country code
+    ///
+    /// 6to4 and Teredo addresses are looked up through the IPv4 address
+    /// they embed.
     pub async fn ip_to_iso_code(&self, addr: IpAddr) -> Option<&str> {
-        let iso_code = self
-            .reader
-            .lookup::<maxminddb::geoip2::Country>(addr)
-            .ok()?
-            .country?
-            .iso_code?;
+        let geoip = self.inner.geoip.load_full();
+        // the database may be reloaded while the code is still in use
+        self.ip_to_iso_code_with(&geoip, addr)
+            .await
+            .map(|code| self.intern(code))
+    }
+
+    /// Like [`Locat::ip_to_iso_code`], using one of the databases loaded
+    /// with [`Locat::with_databases`]
+    pub async fn ip_to_iso_code_in(
+        &self,
+        database: &str,
+        addr: IpAddr,
+    ) -> Result<Option<&str>, Error> {
+        let geoip = self
+            .inner
+            .databases
+            .get(database)
+            .ok_or_else(|| Error::UnknownDatabase(database.to_owned()))?;
+        Ok(self.ip_to_iso_code_with(geoip, addr).await)
+    }
+
+    /// Like [`Locat::ip_to_iso_code`], telling apart why there's no
+    /// country code, so that a corrupt database doesn't go unnoticed among
+    /// private addresses
+    pub async fn try_ip_to_iso_code(&self, addr: IpAddr) -> Result<&str, LookupError> {
+        let geoip = self.inner.geoip.load_full();
+        self.try_ip_to_iso_code_with(&geoip, addr)
+            .await
+            .map(|code| self.intern(code))
+    }
+
+    /// Like [`Locat::ip_to_iso_code`], without counting the lookup, e.g.
+    /// for health checks and internal probes
+    pub fn ip_to_iso_code_untracked(&self, addr: IpAddr) -> Option<&str> {
+        let addr = unwrap_tunneled(addr).map_or(addr, |(v4, _)| v4.into());
+        self.lookup_cached(&self.inner.geoip.load(), addr)
+            .ok()
+            .map(|code| self.intern(code))
+    }
 
-        if let Err(e) = self.analytics.increment(iso_code).await {
-            eprintln!("Could not increment analytics: {e}");
+    /// Like [`Locat::ip_to_iso_code`] for many addresses at once, e.g. a
+    /// whole access log. Counts are added up in memory and written in a
+    /// single transaction, rather than one write per address.
+    pub async fn ip_to_iso_codes(&self, addrs: &[IpAddr]) -> Vec<Option<&str>> {
+        async {
+            let geoip = self.inner.geoip.load_full();
+            let mut iso_codes = Vec::with_capacity(addrs.len());
+            #[cfg(feature = "analytics")]
+            let mut pending = batch::Pending::default();
+            for &addr in addrs {
+                #[cfg(feature = "analytics")]
+                if self.shed() {
+                    iso_codes.push(None);
+                    continue;
+                }
+                let started = Instant::now();
+                let addr = unwrap_tunneled(addr).map_or(addr, |(v4, _)| v4.into());
+                let iso_code = self
+                    .lookup_cached(&geoip, addr)
+                    .ok()
+                    .map(|code| self.intern(code));
+                let bogon = iso_code.is_none() && self.is_bogon(addr);
+                #[cfg(feature = "analytics")]
+                self.tally(&mut pending, addr, iso_code, bogon);
+                self.observe(started, iso_code.is_none() && !bogon);
+                iso_codes.push(iso_code);
+            }
+            #[cfg(feature = "analytics")]
+            self.record_pending(pending).await;
+            iso_codes
         }
+        .instrument(trace::span!(TRACE, "locat.lookup", addrs = addrs.len()))
+        .await
+    }
 
-        Some(iso_code)
+    async fn ip_to_iso_code_with<'a>(&self, geoip: &'a GeoIp, addr: IpAddr) -> Option<&'a str> {
+        self.try_ip_to_iso_code_with(geoip, addr).await.ok()
+    }
+
+    async fn try_ip_to_iso_code_with<'a>(
+        &self,
+        geoip: &'a GeoIp,
+        addr: IpAddr,
+    ) -> Result<&'a str, LookupError> {
+        async {
+            #[cfg(feature = "analytics")]
+            if self.shed() {
+                return Err(LookupError::Overloaded);
+            }
+            let started = Instant::now();
+            let addr = unwrap_tunneled(addr).map_or(addr, |(v4, _)| v4.into());
+            let iso_code = match self.lookup_cached(geoip, addr) {
+                Ok(iso_code) => iso_code,
+                Err(e @ LookupError::Database(_)) => {
+                    self.observe(started, false);
+                    return Err(e);
+                }
+                Err(e) => {
+                    let outcome = self.record_miss(addr).await;
+                    self.observe(started, outcome == Outcome::NotFound);
+                    return Err(match outcome {
+                        Outcome::Bogon => LookupError::Bogon(addr),
+                        _ => e,
+                    });
+                }
+            };
+            self.record_lookup(addr, iso_code).await;
+            self.observe(started, false);
+            Ok(iso_code)
+        }
+        .instrument(trace::span!(TRACE, "locat.lookup"))
+        .await
+    }
+
+    /// Looks `addr` up in `geoip`, through the lookup cache when `geoip` is
+    /// the one currently loaded
+    fn lookup_cached<'a>(&self, geoip: &'a GeoIp, addr: IpAddr) -> Result<&'a str, LookupError> {
+        let cache = match &self.inner.cache {
+            // a reload may have happened since `geoip` was loaded
+            Some(cache) if Arc::ptr_eq(&geoip.reader, &self.inner.geoip.load().reader) => cache,
+            _ => return geoip.try_lookup(addr),
+        };
+        if let Some(Some(resolution)) = cache.get(addr) {
+            // borrowed from the ISO 3166 table rather than the cache. codes
+            // that aren't in it are looked up again
+            if let Ok(code) = resolution.iso_code.parse::<CountryCode>() {
+                return Ok(code.as_str());
+            }
+        }
+        let iso_code = geoip.try_lookup(addr)?;
+        cache.insert(addr, Some(Resolution::new(iso_code, geoip.provenance)));
+        Ok(iso_code)
+    }
+
+    /// Like [`Locat::ip_to_iso_code`], but goes through the resolver chain
+    /// set with [`Locat::with_resolver`], if any, and reports which source
+    /// the answer came from and whether it can be relied upon.
+    pub async fn resolve(&self, addr: IpAddr) -> Option<Resolution> {
+        match self.lookup(addr).await {
+            Outcome::Located(resolution) => Some(resolution),
+            _ => None,
+        }
+    }
+
+    /// Like [`Locat::resolve`], but tells apart addresses that are merely
+    /// unknown from bogons
+    pub async fn lookup(&self, addr: IpAddr) -> Outcome {
+        async {
+            #[cfg(feature = "analytics")]
+            if self.shed() {
+                return Outcome::Overloaded;
+            }
+            let started = Instant::now();
+            let (addr, outcome) = self.classify(addr);
+            match &outcome {
+                Outcome::Located(resolution) => {
+                    self.record_lookup(addr, &resolution.iso_code).await;
+                }
+                Outcome::Bogon => self.record_bogon().await,
+                Outcome::NotFound => self.record_not_found().await,
+                #[cfg(feature = "analytics")]
+                Outcome::Overloaded => {}
+            }
+            self.observe(started, outcome == Outcome::NotFound);
+            outcome
+        }
+        .instrument(trace::span!(TRACE, "locat.lookup"))
+        .await
+    }
+
+    /// Like [`Locat::lookup`], without counting the lookup
+    pub fn lookup_untracked(&self, addr: IpAddr) -> Outcome {
+        self.classify(addr).1
+    }
+
+    /// Everything [`Locat::lookup`] does short of counting, along with the
+    /// address that was actually looked up
+    fn classify(&self, addr: IpAddr) -> (IpAddr, Outcome) {
+        let (addr, unwrapped) = match unwrap_tunneled(addr) {
+            Some((v4, unwrapped)) => (v4.into(), Some(unwrapped)),
+            None => (addr, None),
+        };
+
+        let resolution = match &self.inner.resolver {
+            Some(resolver) => resolver.resolve(addr),
+            None => {
+                let geoip = self.inner.geoip.load();
+                self.lookup_cached(&geoip, addr)
+                    .ok()
+                    .map(|iso_code| Resolution::new(iso_code, geoip.provenance))
+            }
+        };
+        let Some(mut resolution) = resolution else {
+            let outcome = if self.is_bogon(addr) {
+                Outcome::Bogon
+            } else {
+                Outcome::NotFound
+            };
+            return (addr, outcome);
+        };
+
+        if let Some(operator) = anycast_operator(addr) {
+            resolution.reliability = Reliability::GeoUnreliable { operator };
+        }
+        resolution.unwrapped = unwrapped;
+        (addr, Outcome::Located(resolution))
+    }
+
+    /// Replaces the bogon list, e.g. with a freshly downloaded list of
+    /// unallocated space. Lookups in flight keep using the old one.
+    pub fn set_bogons(&self, bogons: Bogons) {
+        *self.inner.bogons.write().unwrap() = bogons;
+    }
+
+    #[cfg(feature = "analytics")]
+    /// Returns how many lookups came from bogon addresses
+    pub async fn get_bogon_count(&self) -> Result<u64, Error> {
+        self.inner.analytics.counter("bogon").await
+    }
+
+    /// Replaces the default continent-based region mapping used by
+    /// [`Locat::route_region`]
+    pub fn with_region_router(mut self, router: RegionRouter) -> Self {
+        self.inner_mut().router = router;
+        self
+    }
+
+    /// English name of the city `addr` is in, see
+    /// [`Locat::with_city_database`]. This doesn't count towards analytics.
+    pub fn ip_to_city(&self, addr: IpAddr) -> Option<String> {
+        self.ip_to_location(addr)?.city
+    }
+
+    /// Where `addr` is, as precisely as the City database knows, see
+    /// [`Locat::with_city_database`]. This doesn't count towards analytics.
+    pub fn ip_to_location(&self, addr: IpAddr) -> Option<Location> {
+        let mut location = self.city_db().lookup_full_location(addr)?;
+        if let Some(coordinates) = &mut location.coordinates {
+            self.scrub(Field::Coordinates(coordinates));
+        }
+        Some(location)
+    }
+
+    /// The network `addr` belongs to, see [`Locat::with_asn_database`].
+    /// This doesn't count towards analytics.
+    pub fn ip_to_asn(&self, addr: IpAddr) -> Option<Asn> {
+        self.asn_db().lookup_asn(addr)
+    }
+
+    fn city_db(&self) -> GeoIp {
+        self.inner.city.clone().unwrap_or_else(|| self.geoip())
+    }
+
+    fn asn_db(&self) -> GeoIp {
+        self.inner.asn.clone().unwrap_or_else(|| self.geoip())
+    }
+
+    /// Picks the deployment region that should serve `addr`, e.g.
+    /// `"eu-west"`. This doesn't count towards analytics.
+    pub fn route_region(&self, addr: IpAddr) -> Option<&str> {
+        let geoip = self.inner.geoip.load();
+        let country = geoip.lookup_country(addr);
+        let iso_code = country.as_ref().and_then(|c| c.country.as_ref()?.iso_code);
+        let continent = country.as_ref().and_then(|c| c.continent.as_ref()?.code);
+        self.inner.router.route(addr, iso_code, continent)
+    }
+
+    /// Picks the region closest to `addr` among the sites configured on the
+    /// [`RegionRouter`], using coordinates from a City database. With a
+    /// Country database (or no sites), this is the same as
+    /// [`Locat::route_region`].
+    pub fn nearest_region(&self, addr: IpAddr) -> Option<&str> {
+        self.city_db()
+            .lookup_coordinates(addr)
+            .and_then(|at| self.inner.router.nearest(at))
+            .or_else(|| self.route_region(addr))
+    }
+
+    /// Reports how far apart two addresses are: country, network and
+    /// distance. Handy to tell whether a session suddenly moved. This
+    /// doesn't count towards analytics.
+    pub fn compare(&self, a: IpAddr, b: IpAddr) -> GeoDelta {
+        let (geoip, city, asn) = (self.geoip(), self.city_db(), self.asn_db());
+        let coordinates_a = city.lookup_coordinates(a);
+        let coordinates_b = city.lookup_coordinates(b);
+
+        GeoDelta {
+            country_a: geoip.lookup(a).map(str::to_owned),
+            country_b: geoip.lookup(b).map(str::to_owned),
+            asn_a: asn.lookup_asn(a).map(|asn| asn.number),
+            asn_b: asn.lookup_asn(b).map(|asn| asn.number),
+            distance_km: coordinates_a
+                .zip(coordinates_b)
+                .map(|(a, b)| a.distance_km(&b)),
+        }
+    }
+
+    /// Tells whether someone could have moved from `prev` to `next` in the
+    /// time between them without going faster than `max_speed_kmh` (a
+    /// commercial flight is around 900 km/h). Needs a City database, the
+    /// verdict is [`TravelVerdict::Unknown`] otherwise. This doesn't count
+    /// towards analytics.
+    pub fn impossible_travel(
+        &self,
+        prev: (IpAddr, Instant),
+        next: (IpAddr, Instant),
+        max_speed_kmh: f64,
+    ) -> Travel {
+        let city = self.city_db();
+        let (Some(from), Some(to)) = (city.lookup_location(prev.0), city.lookup_location(next.0))
+        else {
+            return Travel::unknown();
+        };
+
+        // events can arrive out of order
+        let elapsed = if next.1 >= prev.1 {
+            next.1 - prev.1
+        } else {
+            prev.1 - next.1
+        };
+        Travel::evaluate(from, to, elapsed, max_speed_kmh)
+    }
+
+    /// The GeoIP database as currently loaded. It doesn't follow
+    /// [`Locat::reload_geoip`], use [`Locat::live_geoip`] in resolver chains.
+    pub fn geoip(&self) -> GeoIp {
+        (**self.inner.geoip.load()).clone()
+    }
+
+    /// Every country code the GeoIP database can return, e.g. for filters
+    /// in a UI, see [`GeoIp::available_countries`]
+    pub fn available_countries(&self) -> BTreeSet<String> {
+        self.inner.geoip.load().available_countries().clone()
+    }
+
+    /// One of the databases loaded with [`Locat::with_databases`]
+    pub fn database(&self, name: &str) -> Option<GeoIp> {
+        self.inner.databases.get(name).cloned()
+    }
+
+    async fn record_miss(&self, addr: IpAddr) -> Outcome {
+        if !self.is_bogon(addr) {
+            self.record_not_found().await;
+            return Outcome::NotFound;
+        }
+        self.record_bogon().await;
+        Outcome::Bogon
+    }
+
+    // only checked on a miss: an override might deliberately place private
+    // ranges somewhere
+    fn is_bogon(&self, addr: IpAddr) -> bool {
+        self.inner.bogons.read().unwrap().contains(addr)
+    }
+}
+
+// lookup-only builds don't count anything
+#[cfg(not(feature = "analytics"))]
+impl Locat {
+    async fn record_lookup(&self, _addr: IpAddr, _iso_code: &str) {}
+
+    async fn record_bogon(&self) {}
+
+    async fn record_not_found(&self) {}
+
+    fn observe(&self, _started: Instant, _not_found: bool) {}
+}
+
+#[cfg(feature = "analytics")]
+impl Locat {
+    async fn record_lookup(&self, addr: IpAddr, iso_code: &str) {
+        if !self.should_count(addr, iso_code) || !self.admit() {
+            return;
+        }
+        #[cfg(feature = "otlp")]
+        self.emit("located", Some(iso_code));
+        #[cfg(feature = "webhook")]
+        self.start_reports();
+        self.prune_when_due().await;
+
+        let bucket_start = self.bucket_start();
+        let subnet = self.subnet_key(addr);
+        let exemplar = self.exemplar(addr, iso_code);
+        if let Some(write_behind) = &self.inner.write_behind {
+            write_behind.add_lookup(iso_code, bucket_start, subnet, exemplar);
+            self.start_flusher(write_behind);
+            return;
+        }
+        if subnet.is_some() || exemplar.is_some() {
+            // in a single transaction along with the rest
+            let mut pending = batch::Pending::default();
+            pending.add_lookup(iso_code, bucket_start, subnet, exemplar);
+            self.record_pending(pending).await;
+            self.prune_subnets_every_minute().await;
+            return;
+        }
+
+        let result = match (bucket_start, &self.inner.sqlite) {
+            (Some(bucket_start), Some(db)) => db
+                .increment_bucketed(iso_code, bucket_start)
+                .instrument(trace::span!(DEBUG, "locat.analytics.write"))
+                .await
+                .map_err(Error::from),
+            _ => {
+                self.inner
+                    .analytics
+                    .increment(iso_code)
+                    .instrument(trace::span!(DEBUG, "locat.analytics.write"))
+                    .await
+            }
+        };
+        self.wrote(result.is_ok());
+        if let Err(e) = result {
+            self.write_failed(Some(iso_code), &e);
+        }
+    }
+
+    /// Whether a lookup counts, see [`Locat::with_dedup_window`]
+    fn should_count(&self, addr: IpAddr, iso_code: &str) -> bool {
+        if !self.inner.recording {
+            return false;
+        }
+        match &self.inner.dedup {
+            Some(dedup) => dedup.should_count(addr, iso_code),
+            None => true,
+        }
+    }
+
+    /// The time bucket lookups made now count towards, if any
+    fn bucket_start(&self) -> Option<u64> {
+        match (self.inner.bucket_size, &self.inner.sqlite) {
+            (Some(size), Some(_)) => Some(periods::bucket_start(SystemTime::now(), size)),
+            _ => None,
+        }
+    }
+
+    async fn record_bogon(&self) {
+        if self.inner.recording {
+            self.record_counter("bogon").await;
+        }
+    }
+
+    async fn record_not_found(&self) {
+        if self.inner.recording && self.inner.record_misses {
+            self.record_counter("not_found").await;
+        }
+    }
+
+    async fn record_counter(&self, name: &'static str) {
+        if !self.admit() {
+            return;
+        }
+        #[cfg(feature = "otlp")]
+        self.emit(name, None);
+        if let Some(write_behind) = &self.inner.write_behind {
+            write_behind.add_counter(name);
+            self.start_flusher(write_behind);
+            return;
+        }
+        let result = self
+            .inner
+            .analytics
+            .increment_counter(name)
+            .instrument(trace::span!(DEBUG, "locat.analytics.write"))
+            .await;
+        self.wrote(result.is_ok());
+        if let Err(e) = result {
+            self.write_failed(None, &e);
+        }
+    }
+
+    fn observe(&self, started: Instant, not_found: bool) {
+        self.inner.metrics.observe(started.elapsed(), not_found);
+    }
+
+    /// Which versions of this crate created and last opened the analytics
+    /// database, and its schema version
+    pub async fn analytics_info(&self) -> Result<AnalyticsInfo, Error> {
+        Ok(self.sqlite("analytics_info")?.info().await?)
     }
 
     /// Returns a map of country codes to number of requests
     pub async fn get_analytics(&self) -> Result<Vec<(String, u64)>, Error> {
-        Ok(self.analytics.list().await?)
+        let analytics = self.inner.analytics.measures().await?;
+        Ok(analytics
+            .into_iter()
+            .map(|(iso_code, measures)| (iso_code, measures.count))
+            .collect())
+    }
+
+    /// Records one event for a country carrying a weight for `measure`
+    /// (`"bytes"`, `"revenue"`, ...), which is counted and summed up apart
+    /// from lookups and from other measures, with its distribution. SQLite
+    /// only.
+    ///
+    /// Measure names can't be empty or contain tabs or line breaks.
+    pub async fn record_weighted(
+        &self,
+        iso_code: &str,
+        measure: &str,
+        weight: u64,
+    ) -> Result<(), Error> {
+        let db = self.sqlite("record_weighted")?;
+        if measure.is_empty() || measure.contains(['\t', '\n', '\r']) {
+            return Err(Error::InvalidMeasure(measure.to_owned()));
+        }
+        Ok(db.record_weighted(iso_code, measure, weight).await?)
+    }
+
+    /// The `q`-quantile (between 0 and 1, e.g. `0.99` for p99) of the
+    /// weights recorded for a country and measure with
+    /// [`Locat::record_weighted`], within 1% of the actual value. `None` if
+    /// none were recorded.
+    pub async fn weighted_quantile(
+        &self,
+        iso_code: &str,
+        measure: &str,
+        q: f64,
+    ) -> Result<Option<f64>, Error> {
+        let sketch = self
+            .sqlite("weighted_quantile")?
+            .sketch(iso_code, measure)
+            .await?;
+        Ok(sketch.and_then(|sketch| sketch.quantile(q)))
+    }
+
+    /// Everything recorded with [`Locat::record_weighted`], as `(iso_code,
+    /// measure, measures)`, by country then measure. SQLite only.
+    pub async fn get_weighted_analytics(&self) -> Result<Vec<(String, String, Measures)>, Error> {
+        let weights = self
+            .sqlite("get_weighted_analytics")?
+            .list_weights()
+            .await?;
+        Ok(weights
+            .into_iter()
+            .map(|weight| (weight.iso_code, weight.measure, weight.measures))
+            .collect())
+    }
+
+    /// Runs a single read-only SQL statement (`SELECT`, `WITH`, `VALUES` or
+    /// `EXPLAIN`) against the analytics database, for the one aggregation
+    /// the other methods don't cover. Writes are refused by SQLite itself
+    /// (`PRAGMA query_only`), whatever the statement looks like.
+    ///
+    /// The schema isn't part of the API: queries may break between
+    /// versions.
+    pub async fn query_raw_readonly(
+        &self,
+        sql: &str,
+        params: Vec<rusqlite::types::Value>,
+    ) -> Result<RawRows, Error> {
+        let keyword = sql
+            .trim_start()
+            .split(|c: char| !c.is_ascii_alphabetic())
+            .next()
+            .unwrap_or_default()
+            .to_ascii_uppercase();
+        if !matches!(keyword.as_str(), "SELECT" | "WITH" | "VALUES" | "EXPLAIN") {
+            return Err(Error::NotReadOnly);
+        }
+
+        Ok(self
+            .sqlite("query_raw_readonly")?
+            .query_readonly(sql.to_owned(), params)
+            .await?)
+    }
+
+    /// Dumps all analytics in a versioned text format that this version of
+    /// the crate and later ones can import with [`Locat::import_portable`],
+    /// whatever their database schema looks like: lookup counts, counters,
+    /// weighted measures, time buckets, subnets, latencies and status
+    /// classes. Daily totals per continent are left out, see
+    /// [`Locat::get_totals_by_day`], and so are exemplars, see
+    /// [`Locat::with_exemplars`]. Only the current file of a rolling
+    /// analytics path is exported.
+    pub async fn export_portable(&self) -> Result<String, Error> {
+        Ok(self.export().await?.render())
+    }
+
+    /// Like [`Locat::export_portable`], along with a [`Manifest`] to be
+    /// written next to the export, so its consumers can check it's complete
+    /// before loading it. [`Manifest::from`] is missing unless the analytics
+    /// database recorded when it was created: files from before this was
+    /// recorded, and stores other than SQLite, don't say.
+    pub async fn export_with_manifest(&self) -> Result<(String, Manifest), Error> {
+        let export = self.export().await?;
+        let from = match &self.inner.sqlite {
+            Some(db) => db.info().await?.created_at,
+            None => None,
+        };
+        let epoch = self.epoch().await?;
+        let rendered = export.render();
+        let manifest = Manifest::new(&export, &rendered, from, epoch);
+        Ok((rendered, manifest))
+    }
+
+    /// Adds the analytics from an [`Locat::export_portable`] dump to this
+    /// database. Either everything is imported or nothing is.
+    pub async fn import_portable(&self, data: &str) -> Result<(), Error> {
+        let export = portable::Export::parse(data)?;
+        let detail = format!("{} countries", export.analytics.len());
+        match &self.inner.sqlite {
+            Some(db) => {
+                db.import_as_new_epoch(export).await?;
+                self.check_epoch().await?;
+            }
+            // other stores don't keep weighted measures
+            None => {
+                self.inner
+                    .analytics
+                    .add(&export.analytics, &export.counters)
+                    .await?
+            }
+        }
+        self.audit("import_portable", Some(detail)).await
+    }
+
+    async fn export(&self) -> Result<portable::Export, Error> {
+        let mut export = match &self.inner.sqlite {
+            Some(db) => db.export().await?,
+            None => portable::Export {
+                analytics: self.inner.analytics.measures().await?,
+                counters: self.inner.analytics.counters().await?,
+                ..Default::default()
+            },
+        };
+        if self.inner.scrubber.is_some() {
+            for (iso_code, measures) in &mut export.analytics {
+                self.scrub(Field::ExportedCountry { iso_code, measures });
+            }
+            export.analytics.retain(|(_, measures)| measures.count > 0);
+            let kept: BTreeSet<_> = export
+                .analytics
+                .iter()
+                .map(|(iso_code, _)| iso_code.clone())
+                .collect();
+            export
+                .weights
+                .retain(|weight| kept.contains(&weight.iso_code));
+            export
+                .buckets
+                .retain(|(iso_code, _, _)| kept.contains(iso_code));
+            export
+                .subnets
+                .retain(|subnet| kept.contains(&subnet.iso_code));
+            for subnet in &mut export.subnets {
+                self.scrub(Field::Subnet(&mut subnet.subnet));
+            }
+            export
+                .latencies
+                .retain(|(iso_code, _)| kept.contains(iso_code));
+            export
+                .statuses
+                .retain(|(iso_code, _, _)| kept.contains(iso_code));
+        }
+        Ok(export)
     }
 }
 
-struct Db {
-    conn: Connection
+/// Everything recorded for a single country
+#[cfg(feature = "analytics")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Measures {
+    /// Number of events
+    pub count: u64,
+    /// Sum of their weights, always zero for lookups, see
+    /// [`Locat::get_weighted_analytics`]
+    pub sum: u64,
 }
 
-impl Db {
-    async fn open(path: &str) -> Result<Self, rusqlite::Error> {
-        // open and migrate a db in a non-blocking way
-        let conn = Connection::open(path).await?;
-
-        // this is how operations are run on a thread pool: we pass a
-        // closure. not that it must be `'static`, so we can't borrow
-        // anything from the outside: owned types only.
-        conn.call(|conn| {
-            // create analytics table
-            conn.execute(
-                "CREATE TABLE IF NOT EXISTS analytics (
-                iso_code TEXT PRIMARY KEY,
-                count INTEGER NOT NULL
-            )",
-                [],
-            )?;
-
-            Ok::<_, rusqlite::Error>(())
-        })
-        .await?;
-
-        Ok(Self { conn })
-    }
-
-    async fn list(&self) -> Result<Vec<(String, u64)>, rusqlite::Error> {
-        self.conn
-            .call(|conn| {
-                let mut stmt = conn.prepare("SELECT iso_code, count FROM analytics")?;
-                let mut rows = stmt.query([])?;
-                let mut analytics = Vec::new();
-                while let Some(row) = rows.next()? {
-                    let iso_code: String = row.get(0)?;
-                    let count: u64 = row.get(1)?;
-                    analytics.push((iso_code, count));
-                }
-                Ok(analytics)
+#[cfg(all(test, feature = "analytics"))]
+mod tests {
+    use std::{
+        collections::HashMap,
+        time::{Duration, Instant},
+    };
+
+    use crate::{
+        testing::{RemoveOnDrop, TestDb},
+        Asn, Bogons, Coordinates, Error, Locat, LookupError, MemoryStore, Outcome, Overrides,
+        Provenance, RegionRouter, Reliability, Resolution, Resolver, TravelVerdict, Tunnel,
+        Unwrapped,
+    };
+
+    #[tokio::test]
+    async fn test_resolve_provenance() {
+        let geoip_path = "/tmp/loca-test-resolve.mmdb";
+        let analytics_path = "/tmp/loca-test-resolve.db";
+        TestDb::new()
+            .country("1.2.3.0/24", "US", "NA")
+            .write(geoip_path);
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
+
+        let locat = Locat::new(geoip_path, analytics_path).await.unwrap();
+        let addr = "1.2.3.4".parse().unwrap();
+        assert_eq!(locat.ip_to_iso_code(addr).await, Some("US"));
+        assert_eq!(
+            locat.resolve(addr).await,
+            Some(Resolution::new("US", Provenance::GeoIp))
+        );
+
+        let chain = Overrides::new()
+            .rule("1.2.3.4/32".parse().unwrap(), "FR")
+            .then(locat.geoip());
+        let locat = locat.with_resolver(chain);
+        assert_eq!(
+            locat.resolve(addr).await,
+            Some(Resolution::new("FR", Provenance::Override))
+        );
+        assert_eq!(
+            locat.resolve("1.2.3.5".parse().unwrap()).await,
+            Some(Resolution::new("US", Provenance::GeoIp))
+        );
+
+        let chain = Overrides::new().rule("1.1.1.0/24".parse().unwrap(), "AU");
+        let locat = locat.with_resolver(chain);
+        let resolution = locat.resolve("1.1.1.1".parse().unwrap()).await.unwrap();
+        assert_eq!(
+            resolution.reliability,
+            Reliability::GeoUnreliable {
+                operator: "Cloudflare DNS"
+            }
+        );
+
+        let analytics = locat.get_analytics().await.unwrap();
+        assert!(analytics.contains(&("US".to_string(), 3)));
+        assert!(analytics.contains(&("FR".to_string(), 1)));
+        assert!(analytics.contains(&("AU".to_string(), 1)));
+    }
+
+    #[tokio::test]
+    async fn test_route_region() {
+        let geoip_path = "/tmp/loca-test-route.mmdb";
+        let analytics_path = "/tmp/loca-test-route.db";
+        TestDb::new()
+            .country("1.2.3.0/24", "US", "NA")
+            .country("5.6.7.0/24", "DE", "EU")
+            .write(geoip_path);
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
+
+        let locat = Locat::new(geoip_path, analytics_path)
+            .await
+            .unwrap()
+            .with_region_router(RegionRouter::default().country("DE", "eu-central"));
+        assert_eq!(
+            locat.route_region("1.2.3.4".parse().unwrap()),
+            Some("us-east")
+        );
+        assert_eq!(
+            locat.route_region("5.6.7.8".parse().unwrap()),
+            Some("eu-central")
+        );
+        assert!(locat.get_analytics().await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_nearest_region() {
+        let geoip_path = "/tmp/loca-test-nearest.mmdb";
+        let analytics_path = "/tmp/loca-test-nearest.db";
+        TestDb::new()
+            .city("1.2.3.0/24", "US", "NA", Coordinates::new(47.6, -122.3))
+            .country("5.6.7.0/24", "DE", "EU")
+            .write(geoip_path);
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
+
+        let router = RegionRouter::default()
+            .site("us-east", Coordinates::new(38.9, -77.0))
+            .site("us-west", Coordinates::new(45.5, -122.7));
+        let locat = Locat::new(geoip_path, analytics_path)
+            .await
+            .unwrap()
+            .with_region_router(router);
+
+        // Seattle is closer to us-west, even though NA maps to us-east
+        assert_eq!(
+            locat.nearest_region("1.2.3.4".parse().unwrap()),
+            Some("us-west")
+        );
+        // no coordinates: continent mapping
+        assert_eq!(
+            locat.nearest_region("5.6.7.8".parse().unwrap()),
+            Some("eu-west")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bogons() {
+        let geoip_path = "/tmp/loca-test-bogons.mmdb";
+        let analytics_path = "/tmp/loca-test-bogons.db";
+        TestDb::new()
+            .country("1.2.3.0/24", "US", "NA")
+            .write(geoip_path);
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
+
+        let locat = Locat::new(geoip_path, analytics_path).await.unwrap();
+        assert_eq!(
+            locat.lookup("10.0.0.1".parse().unwrap()).await,
+            Outcome::Bogon
+        );
+        assert_eq!(
+            locat.lookup("5.6.7.8".parse().unwrap()).await,
+            Outcome::NotFound
+        );
+        assert_eq!(
+            locat.ip_to_iso_code("192.168.1.1".parse().unwrap()).await,
+            None
+        );
+        assert_eq!(locat.get_bogon_count().await.unwrap(), 2);
+
+        locat.set_bogons(Bogons::parse("5.6.0.0/16").unwrap());
+        assert_eq!(
+            locat.lookup("5.6.7.8".parse().unwrap()).await,
+            Outcome::Bogon
+        );
+        assert_eq!(
+            locat.lookup("10.0.0.1".parse().unwrap()).await,
+            Outcome::NotFound
+        );
+        assert_eq!(locat.get_bogon_count().await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_try_ip_to_iso_code() {
+        let geoip_path = "/tmp/loca-test-try.mmdb";
+        let analytics_path = "/tmp/loca-test-try.db";
+        TestDb::new()
+            .country("1.2.3.0/24", "US", "NA")
+            .asn("5.6.7.0/24", 64512, "Example")
+            .write(geoip_path);
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
+
+        let locat = Locat::new(geoip_path, analytics_path).await.unwrap();
+        let addr = "1.2.3.4".parse().unwrap();
+        assert_eq!(locat.try_ip_to_iso_code(addr).await.unwrap(), "US");
+        assert!(matches!(
+            locat.try_ip_to_iso_code("8.8.8.8".parse().unwrap()).await,
+            Err(LookupError::NotFound(_))
+        ));
+        assert!(matches!(
+            locat.try_ip_to_iso_code("10.0.0.1".parse().unwrap()).await,
+            Err(LookupError::Bogon(_))
+        ));
+        assert!(matches!(
+            locat.try_ip_to_iso_code("5.6.7.8".parse().unwrap()).await,
+            Err(LookupError::NoIsoCode(_))
+        ));
+        assert_eq!(
+            locat.get_analytics().await.unwrap(),
+            vec![("US".to_string(), 1)]
+        );
+        assert_eq!(locat.get_bogon_count().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_untracked() {
+        let geoip_path = "/tmp/loca-test-untracked.mmdb";
+        let analytics_path = "/tmp/loca-test-untracked.db";
+        TestDb::new()
+            .country("1.2.3.0/24", "US", "NA")
+            .write(geoip_path);
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
+
+        let locat = Locat::new(geoip_path, analytics_path).await.unwrap();
+        let addr = "1.2.3.4".parse().unwrap();
+        assert_eq!(locat.ip_to_iso_code_untracked(addr), Some("US"));
+        assert!(matches!(locat.lookup_untracked(addr), Outcome::Located(_)));
+        assert_eq!(
+            locat.lookup_untracked("10.0.0.1".parse().unwrap()),
+            Outcome::Bogon
+        );
+        assert!(locat.get_analytics().await.unwrap().is_empty());
+        assert_eq!(locat.get_bogon_count().await.unwrap(), 0);
+
+        locat.ip_to_iso_code(addr).await;
+        assert_eq!(
+            locat.get_analytics().await.unwrap(),
+            vec![("US".to_string(), 1)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unwrap_tunneled() {
+        let geoip_path = "/tmp/loca-test-tunnel.mmdb";
+        let analytics_path = "/tmp/loca-test-tunnel.db";
+        TestDb::new()
+            .country("81.2.69.0/24", "GB", "EU")
+            .write(geoip_path);
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
+
+        let locat = Locat::new(geoip_path, analytics_path).await.unwrap();
+        let six_to_four = "2002:5102:45a0::1".parse().unwrap();
+        assert_eq!(locat.ip_to_iso_code(six_to_four).await, Some("GB"));
+
+        let Outcome::Located(resolution) = locat.lookup(six_to_four).await else {
+            panic!("6to4 address should resolve");
+        };
+        assert_eq!(resolution.iso_code, "GB");
+        assert_eq!(
+            resolution.unwrapped,
+            Some(Unwrapped {
+                original: "2002:5102:45a0::1".parse().unwrap(),
+                tunnel: Tunnel::SixToFour,
             })
+        );
+    }
+
+    #[tokio::test]
+    async fn test_compare() {
+        let geoip_path = "/tmp/loca-test-compare.mmdb";
+        let analytics_path = "/tmp/loca-test-compare.db";
+        TestDb::new()
+            .city("1.2.3.0/24", "FR", "EU", Coordinates::new(48.8566, 2.3522))
+            .city(
+                "5.6.7.0/24",
+                "US",
+                "NA",
+                Coordinates::new(40.7128, -74.0060),
+            )
+            .country("9.9.9.0/24", "US", "NA")
+            .write(geoip_path);
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
+
+        let locat = Locat::new(geoip_path, analytics_path).await.unwrap();
+        let delta = locat.compare("1.2.3.4".parse().unwrap(), "5.6.7.8".parse().unwrap());
+        assert!(delta.country_changed());
+        assert!(delta.distance_km.unwrap() > 5000.0);
+        assert_eq!(delta.asn_a, None);
+
+        let delta = locat.compare("5.6.7.8".parse().unwrap(), "9.9.9.9".parse().unwrap());
+        assert!(!delta.changed());
+        assert_eq!(delta.distance_km, None);
+
+        let now = Instant::now();
+        let later = now + Duration::from_secs(3600);
+        let travel = locat.impossible_travel(
+            ("1.2.3.4".parse().unwrap(), later),
+            ("5.6.7.8".parse().unwrap(), now),
+            900.0,
+        );
+        assert_eq!(travel.verdict, TravelVerdict::Impossible);
+        let travel = locat.impossible_travel(
+            ("1.2.3.4".parse().unwrap(), now),
+            ("9.9.9.9".parse().unwrap(), later),
+            900.0,
+        );
+        assert_eq!(travel.verdict, TravelVerdict::Unknown);
+    }
+
+    #[tokio::test]
+    async fn test_city_and_asn() {
+        let geoip_path = "/tmp/loca-test-city-and-asn.mmdb";
+        let city_path = "/tmp/loca-test-city-and-asn-city.mmdb";
+        let asn_path = "/tmp/loca-test-city-and-asn-asn.mmdb";
+        let analytics_path = "/tmp/loca-test-city-and-asn.db";
+        TestDb::new()
+            .country("1.2.3.0/24", "US", "NA")
+            .write(geoip_path);
+        TestDb::new()
+            .full_city(
+                "1.2.3.0/24",
+                "US",
+                "CA",
+                "San Francisco",
+                "America/Los_Angeles",
+                Coordinates::new(37.7749, -122.4194),
+            )
+            .write(city_path);
+        TestDb::new()
+            .asn("1.2.3.0/24", 13335, "Cloudflare, Inc.")
+            .write(asn_path);
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+        let _remove_city = RemoveOnDrop { path: city_path };
+        let _remove_asn = RemoveOnDrop { path: asn_path };
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
+
+        let addr = "1.2.3.4".parse().unwrap();
+        let locat = Locat::new(geoip_path, analytics_path).await.unwrap();
+        assert_eq!(locat.ip_to_city(addr), None);
+        assert_eq!(locat.ip_to_asn(addr), None);
+
+        let locat = Locat::new(geoip_path, analytics_path)
             .await
+            .unwrap()
+            .with_city_database(city_path)
+            .await
+            .unwrap()
+            .with_asn_database(asn_path)
+            .await
+            .unwrap();
+        assert_eq!(locat.ip_to_city(addr).as_deref(), Some("San Francisco"));
+        let location = locat.ip_to_location(addr).unwrap();
+        assert_eq!(location.iso_code.as_deref(), Some("US"));
+        assert_eq!(location.subdivision.as_deref(), Some("CA"));
+        assert_eq!(location.time_zone.as_deref(), Some("America/Los_Angeles"));
+        assert!(location.coordinates.is_some());
+        assert_eq!(
+            locat.ip_to_asn(addr),
+            Some(Asn {
+                number: 13335,
+                organization: Some("Cloudflare, Inc.".to_owned()),
+            })
+        );
+        assert_eq!(locat.compare(addr, addr).asn_a, Some(13335));
+        assert_eq!(locat.ip_to_location("9.9.9.9".parse().unwrap()), None);
     }
 
-    async fn increment(&self, iso_code: &str) -> Result<(), rusqlite::Error> {
-        // we have to use `iso_code` from within the closure and the closure
-        // must be 'static, so:
-        let iso_code = iso_code.to_owned();
+    #[tokio::test]
+    async fn test_databases() {
+        let geoip_path = "/tmp/loca-test-databases.mmdb";
+        let acme_path = "/tmp/loca-test-databases-acme.mmdb";
+        let analytics_path = "/tmp/loca-test-databases.db";
+        TestDb::new()
+            .country("1.2.3.0/24", "US", "NA")
+            .write(geoip_path);
+        TestDb::new()
+            .country("1.2.3.0/24", "CA", "NA")
+            .write(acme_path);
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+        let _remove_acme = RemoveOnDrop { path: acme_path };
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
 
-        self.conn.call(|conn| {
-            let mut stmt = conn
-                .prepare("INSERT INTO analytics (iso_code, count) VALUES (?, 1) ON CONFLICT (iso_code) DO UPDATE SET count = count + 1")
-                ?;
-            stmt.execute([iso_code])?;
-            Ok(())
-        }).await
+        let locat = Locat::new(geoip_path, analytics_path)
+            .await
+            .unwrap()
+            .with_databases(HashMap::from([("acme".to_string(), acme_path.into())]))
+            .await
+            .unwrap();
+        let addr = "1.2.3.4".parse().unwrap();
+        assert_eq!(locat.ip_to_iso_code(addr).await, Some("US"));
+        assert_eq!(
+            locat.ip_to_iso_code_in("acme", addr).await.unwrap(),
+            Some("CA")
+        );
+        assert!(matches!(
+            locat.ip_to_iso_code_in("globex", addr).await,
+            Err(Error::UnknownDatabase(_))
+        ));
+        assert!(locat.database("acme").is_some());
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::Db;
+    #[tokio::test]
+    async fn test_available_countries() {
+        let geoip_path = "/tmp/loca-test-countries.mmdb";
+        let analytics_path = "/tmp/loca-test-countries.db";
+        TestDb::new()
+            .country("1.2.3.0/24", "US", "NA")
+            .country("1.2.4.0/24", "US", "NA")
+            .country("5.6.7.0/24", "DE", "EU")
+            .write(geoip_path);
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
 
-    struct RemoveOnDrop {
-        path: &'static str,
+        let locat = Locat::new(geoip_path, analytics_path).await.unwrap();
+        let countries: Vec<_> = locat.available_countries().into_iter().collect();
+        assert_eq!(countries, ["DE", "US"]);
     }
 
-    impl Drop for RemoveOnDrop {
-        fn drop(&mut self) {
-            _ = std::fs::remove_file(self.path);
-        }
+    #[tokio::test]
+    async fn test_clone_shares_state() {
+        let geoip_path = "/tmp/loca-test-clone.mmdb";
+        let analytics_path = "/tmp/loca-test-clone.db";
+        TestDb::new()
+            .country("1.2.3.0/24", "US", "NA")
+            .write(geoip_path);
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
+
+        let locat = Locat::new(geoip_path, analytics_path).await.unwrap();
+        let clone = locat.clone();
+        let addr = "1.2.3.4".parse().unwrap();
+        tokio::spawn(async move { clone.ip_to_iso_code(addr).await.map(str::to_owned) })
+            .await
+            .unwrap();
+        locat.ip_to_iso_code(addr).await;
+        assert_eq!(
+            locat.get_analytics().await.unwrap(),
+            vec![("US".to_string(), 2)]
+        );
+
+        let debug = format!("{locat:?}");
+        assert!(debug.contains("analytics: \"sqlite:/tmp/loca-test-clone.db\""));
+        assert!(debug.contains("GeoIP2-Country"));
+        assert!(!debug.contains("1.2.3"));
+        assert!(locat.to_string().starts_with("GeoIP2-Country (built "));
     }
 
-    // this test needs an async runtime now, hence, `tokio::test`
     #[tokio::test]
-    async fn test_db() {
-        let path = "/tmp/loca-test.db";
-        let db = Db::open(path).await.unwrap();
-
-        let _remove_on_drop = RemoveOnDrop { path };
-
-        let analytics = db.list().await.unwrap();
-        assert_eq!(analytics.len(), 0);
-
-        db.increment("US").await.unwrap();
-        let analytics = db.list().await.unwrap();
-        assert_eq!(analytics.len(), 1);
-
-        db.increment("US").await.unwrap();
-        db.increment("FR").await.unwrap();
-        let analytics = db.list().await.unwrap();
-        assert_eq!(analytics.len(), 2);
-        // contains US at count 2
-        assert!(analytics.contains(&("US".to_string(), 2)));
-        // contains FR at count 1
-        assert!(analytics.contains(&("FR".to_string(), 1)));
-        // doesn't contain DE
-        assert!(!analytics.contains(&("DE".to_string(), 0)));
+    async fn test_memory_store() {
+        let geoip_path = "/tmp/loca-test-memory-store.mmdb";
+        TestDb::new()
+            .country("1.2.3.0/24", "US", "NA")
+            .write(geoip_path);
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+
+        let locat = Locat::from_store(geoip_path, MemoryStore::new())
+            .await
+            .unwrap();
+        let addr = "1.2.3.4".parse().unwrap();
+        locat.ip_to_iso_code(addr).await;
+        locat.ip_to_iso_code("10.0.0.1".parse().unwrap()).await;
+        assert!(matches!(
+            locat.record_weighted("US", "bytes", 5).await,
+            Err(Error::Unsupported("record_weighted"))
+        ));
+
+        assert_eq!(
+            locat.get_analytics().await.unwrap(),
+            vec![("US".to_string(), 1)]
+        );
+        assert_eq!(locat.get_bogon_count().await.unwrap(), 1);
+        assert!(locat.to_string().ends_with("analytics in memory"));
+
+        // exports still work, SQLite-only features say so
+        let export = locat.export_portable().await.unwrap();
+        locat.import_portable(&export).await.unwrap();
+        assert_eq!(
+            locat.get_analytics().await.unwrap(),
+            vec![("US".to_string(), 2)]
+        );
+        assert!(matches!(
+            locat.query_raw_readonly("SELECT 1", vec![]).await,
+            Err(Error::Unsupported("query_raw_readonly"))
+        ));
+        assert!(locat
+            .verify()
+            .await
+            .checks
+            .iter()
+            .any(|check| check.name == "analytics readable"));
     }
 }
@@ -0,0 +1,165 @@
+#[cfg(feature = "analytics")]
+use std::{collections::BTreeMap, sync::Mutex};
+use std::{collections::HashMap, net::IpAddr};
+
+use async_trait::async_trait;
+
+#[cfg(feature = "analytics")]
+use crate::Error;
+use crate::{Bogons, Locat, Outcome, Provenance, Resolution};
+
+/// What services need from [`Locat`], so that they can take a
+/// [`StaticLocat`] in tests instead of a real database
+#[async_trait]
+pub trait IpLocator: Send + Sync {
+    /// See [`Locat::ip_to_iso_code`]
+    async fn ip_to_iso_code(&self, addr: IpAddr) -> Option<&str>;
+
+    /// See [`Locat::ip_to_iso_code_untracked`]
+    fn ip_to_iso_code_untracked(&self, addr: IpAddr) -> Option<&str>;
+
+    /// See [`Locat::lookup`]
+    async fn lookup(&self, addr: IpAddr) -> Outcome;
+
+    #[cfg(feature = "analytics")]
+    /// See [`Locat::get_analytics`]
+    async fn get_analytics(&self) -> Result<Vec<(String, u64)>, Error>;
+}
+
+#[async_trait]
+impl IpLocator for Locat {
+    async fn ip_to_iso_code(&self, addr: IpAddr) -> Option<&str> {
+        Locat::ip_to_iso_code(self, addr).await
+    }
+
+    fn ip_to_iso_code_untracked(&self, addr: IpAddr) -> Option<&str> {
+        Locat::ip_to_iso_code_untracked(self, addr)
+    }
+
+    async fn lookup(&self, addr: IpAddr) -> Outcome {
+        Locat::lookup(self, addr).await
+    }
+
+    #[cfg(feature = "analytics")]
+    async fn get_analytics(&self) -> Result<Vec<(String, u64)>, Error> {
+        Locat::get_analytics(self).await
+    }
+}
+
+/// An [`IpLocator`] with canned answers, for tests. Addresses it wasn't
+/// told about are bogons or not found, like with [`Locat`], and lookups are
+/// counted in memory.
+///
+/// ```
+/// # #[tokio::main(flavor = "current_thread")]
+/// # async fn main() {
+/// use locat::{IpLocator, StaticLocat};
+///
+/// let locator = StaticLocat::new([("1.2.3.4".parse().unwrap(), "US")]);
+/// assert_eq!(locator.ip_to_iso_code("1.2.3.4".parse().unwrap()).await, Some("US"));
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct StaticLocat {
+    answers: HashMap<IpAddr, &'static str>,
+    bogons: Bogons,
+    #[cfg(feature = "analytics")]
+    analytics: Mutex<BTreeMap<String, u64>>,
+}
+
+impl StaticLocat {
+    pub fn new(answers: impl IntoIterator<Item = (IpAddr, &'static str)>) -> Self {
+        Self {
+            answers: answers.into_iter().collect(),
+            ..Default::default()
+        }
+    }
+}
+
+impl From<HashMap<IpAddr, &'static str>> for StaticLocat {
+    fn from(answers: HashMap<IpAddr, &'static str>) -> Self {
+        Self::new(answers)
+    }
+}
+
+#[async_trait]
+impl IpLocator for StaticLocat {
+    async fn ip_to_iso_code(&self, addr: IpAddr) -> Option<&str> {
+        let iso_code = self.answers.get(&addr).copied();
+        #[cfg(feature = "analytics")]
+        if let Some(iso_code) = iso_code {
+            *self
+                .analytics
+                .lock()
+                .unwrap()
+                .entry(iso_code.to_owned())
+                .or_default() += 1;
+        }
+        iso_code
+    }
+
+    fn ip_to_iso_code_untracked(&self, addr: IpAddr) -> Option<&str> {
+        self.answers.get(&addr).copied()
+    }
+
+    async fn lookup(&self, addr: IpAddr) -> Outcome {
+        match IpLocator::ip_to_iso_code(self, addr).await {
+            Some(iso_code) => {
+                Outcome::Located(Resolution::new(iso_code, Provenance::Other("static")))
+            }
+            None if self.bogons.contains(addr) => Outcome::Bogon,
+            None => Outcome::NotFound,
+        }
+    }
+
+    #[cfg(feature = "analytics")]
+    async fn get_analytics(&self) -> Result<Vec<(String, u64)>, Error> {
+        let analytics = self.analytics.lock().unwrap();
+        Ok(analytics
+            .iter()
+            .map(|(iso_code, count)| (iso_code.clone(), *count))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::IpAddr;
+
+    use crate::{testing::TestDb, IpLocator, Locat, Outcome, StaticLocat};
+
+    // what a service generic over its locator would do
+    async fn greet(locator: &dyn IpLocator, addr: IpAddr) -> String {
+        match locator.ip_to_iso_code(addr).await {
+            Some(iso_code) => format!("hello from {iso_code}"),
+            None => "hello".to_owned(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_ip_locator() {
+        let addr = "1.2.3.4".parse().unwrap();
+        let fake = StaticLocat::new([(addr, "US")]);
+        let real = Locat::builder()
+            .with_geoip_bytes(TestDb::new().country("1.2.3.0/24", "US", "NA").build())
+            .build()
+            .await
+            .unwrap();
+        for locator in [&fake as &dyn IpLocator, &real] {
+            assert_eq!(greet(locator, addr).await, "hello from US");
+            assert_eq!(
+                locator.lookup("10.0.0.1".parse().unwrap()).await,
+                Outcome::Bogon
+            );
+            assert_eq!(
+                locator.lookup("8.8.8.8".parse().unwrap()).await,
+                Outcome::NotFound
+            );
+            #[cfg(feature = "analytics")]
+            assert_eq!(
+                locator.get_analytics().await.unwrap(),
+                vec![("US".to_string(), 1)]
+            );
+        }
+    }
+}
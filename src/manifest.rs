@@ -0,0 +1,231 @@
+//! A small companion file for [`crate::Locat::export_portable`] dumps, so
+//! whatever loads them downstream can check it got all of it:
+//!
+//! ```text
+//! locat-manifest  1
+//! generator   locat 1.0.0
+//! from        1700000000
+//! to          1700086400
+//! sha256      9f86d081884c7d65...
+//! rows        analytics   12
+//! rows        counters    1
+//! rows        weights     12
+//! rows        buckets     40
+//! rows        subnets     30
+//! rows        latencies   12
+//! rows        statuses    20
+//! epoch       8206254317845271
+//! ```
+//!
+//! Fields are separated by tabs, times are unix seconds. `from` is missing
+//! when the analytics database predates creation times being recorded,
+//! `epoch` when the manifest predates epochs.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use sha2::{Digest, Sha256};
+
+use crate::{portable::Export, Error};
+
+const MAGIC: &str = "locat-manifest";
+const FORMAT_VERSION: u32 = 1;
+
+/// Describes an export, see [`crate::Locat::export_with_manifest`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Manifest {
+    /// Crate name and version that wrote the export
+    pub generator: String,
+    /// When counting started, if known: missing for analytics databases
+    /// created before that was recorded, and for other stores
+    pub from: Option<SystemTime>,
+    /// When the export was taken
+    pub to: SystemTime,
+    /// Hex-encoded SHA-256 of the export
+    pub sha256: String,
+    /// Number of rows in each table of the export
+    pub rows: Vec<(String, usize)>,
+    /// See [`crate::Locat::epoch`]: exports from different epochs can't be
+    /// diffed against each other
+    pub epoch: Option<u64>,
+}
+
+impl Manifest {
+    pub(crate) fn new(
+        export: &Export,
+        rendered: &str,
+        from: Option<SystemTime>,
+        epoch: u64,
+    ) -> Self {
+        Self {
+            generator: concat!(env!("CARGO_PKG_NAME"), " ", env!("CARGO_PKG_VERSION")).to_owned(),
+            from,
+            to: SystemTime::now(),
+            sha256: sha256(rendered),
+            rows: vec![
+                ("analytics".to_owned(), export.analytics.len()),
+                ("counters".to_owned(), export.counters.len()),
+                ("weights".to_owned(), export.weights.len()),
+                ("buckets".to_owned(), export.buckets.len()),
+                ("subnets".to_owned(), export.subnets.len()),
+                ("latencies".to_owned(), export.latencies.len()),
+                ("statuses".to_owned(), export.statuses.len()),
+            ],
+            epoch: Some(epoch),
+        }
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = format!("{MAGIC}\t{FORMAT_VERSION}\n");
+        out.push_str(&format!("generator\t{}\n", self.generator));
+        if let Some(from) = self.from {
+            out.push_str(&format!("from\t{}\n", unix_secs(from)));
+        }
+        out.push_str(&format!("to\t{}\n", unix_secs(self.to)));
+        out.push_str(&format!("sha256\t{}\n", self.sha256));
+        for (table, rows) in &self.rows {
+            out.push_str(&format!("rows\t{table}\t{rows}\n"));
+        }
+        if let Some(epoch) = self.epoch {
+            out.push_str(&format!("epoch\t{epoch}\n"));
+        }
+        out
+    }
+
+    pub fn parse(data: &str) -> Result<Self, Error> {
+        let mut lines = data.lines();
+        match lines.next().and_then(|header| header.split_once('\t')) {
+            Some((MAGIC, version)) if version.parse::<u32>().is_ok() => {}
+            _ => return Err(invalid("missing manifest header")),
+        }
+
+        let time = |value: &str| {
+            value
+                .parse()
+                .map(|secs| UNIX_EPOCH + Duration::from_secs(secs))
+                .map_err(|_| invalid(&format!("bad time {value:?}")))
+        };
+
+        let (mut generator, mut from, mut to, mut sha256, mut epoch) =
+            (None, None, None, None, None);
+        let mut rows = Vec::new();
+        for line in lines.filter(|line| !line.is_empty()) {
+            let fields: Vec<&str> = line.split('\t').collect();
+            match fields[..] {
+                ["generator", value] => generator = Some(value.to_owned()),
+                ["from", value] => from = Some(time(value)?),
+                ["to", value] => to = Some(time(value)?),
+                ["sha256", value] => sha256 = Some(value.to_owned()),
+                ["rows", table, count] => rows.push((
+                    table.to_owned(),
+                    count
+                        .parse()
+                        .map_err(|_| invalid(&format!("bad row count {count:?}")))?,
+                )),
+                ["epoch", value] => {
+                    epoch = Some(
+                        value
+                            .parse()
+                            .map_err(|_| invalid(&format!("bad epoch {value:?}")))?,
+                    )
+                }
+                // written by a newer version
+                _ => {}
+            }
+        }
+
+        Ok(Self {
+            generator: generator.ok_or_else(|| invalid("manifest without generator"))?,
+            from,
+            to: to.ok_or_else(|| invalid("manifest without time range"))?,
+            sha256: sha256.ok_or_else(|| invalid("manifest without checksum"))?,
+            rows,
+            epoch,
+        })
+    }
+
+    /// Checks that `export` is the complete, unmodified export this
+    /// manifest describes
+    pub fn verify(&self, export: &str) -> Result<(), Error> {
+        if sha256(export) != self.sha256 {
+            return Err(invalid("checksum mismatch"));
+        }
+
+        // the checksum already covers this, but a clear message helps
+        let parsed = Export::parse(export)?;
+        for (table, expected) in &self.rows {
+            let actual = match table.as_str() {
+                "analytics" => parsed.analytics.len(),
+                "counters" => parsed.counters.len(),
+                "weights" => parsed.weights.len(),
+                "buckets" => parsed.buckets.len(),
+                "subnets" => parsed.subnets.len(),
+                "latencies" => parsed.latencies.len(),
+                "statuses" => parsed.statuses.len(),
+                _ => continue,
+            };
+            if actual != *expected {
+                return Err(invalid(&format!(
+                    "{table} has {actual} rows, expected {expected}"
+                )));
+            }
+        }
+        Ok(())
+    }
+}
+
+fn sha256(data: &str) -> String {
+    Sha256::digest(data.as_bytes())
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::InvalidExport(reason.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::Manifest;
+    use crate::{portable::Export, Measures};
+
+    #[test]
+    fn test_manifest() {
+        let export = Export {
+            analytics: vec![("US".to_string(), Measures { count: 2, sum: 0 })],
+            counters: vec![("bogon".to_string(), 3)],
+            ..Default::default()
+        };
+        let rendered = export.render();
+
+        let mut manifest = Manifest::new(&export, &rendered, None, 1);
+        manifest.to = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let parsed = Manifest::parse(&manifest.render()).unwrap();
+        assert_eq!(parsed, manifest);
+        assert_eq!(
+            parsed.rows,
+            vec![
+                ("analytics".to_string(), 1),
+                ("counters".to_string(), 1),
+                ("weights".to_string(), 0),
+                ("buckets".to_string(), 0),
+                ("subnets".to_string(), 0),
+                ("latencies".to_string(), 0),
+                ("statuses".to_string(), 0)
+            ]
+        );
+        parsed.verify(&rendered).unwrap();
+
+        // a truncated export is caught
+        let truncated = rendered.lines().take(3).collect::<Vec<_>>().join("\n");
+        assert!(parsed.verify(&truncated).is_err());
+    }
+}
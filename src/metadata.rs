@@ -0,0 +1,103 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::{GeoIp, Locat};
+
+/// What a GeoIP database says about itself, see [`Locat::geoip_metadata`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GeoIpMetadata {
+    /// e.g. `GeoLite2-Country`
+    pub database_type: String,
+    /// When the vendor built the database, to the second
+    pub built_at: SystemTime,
+    pub node_count: u32,
+    /// Languages names are available in, e.g. `["de", "en", "fr"]`
+    pub languages: Vec<String>,
+    /// 4 for IPv4-only databases, 6 otherwise
+    pub ip_version: u16,
+}
+
+impl GeoIpMetadata {
+    /// Time since the database was built, zero if it claims to be from the
+    /// future
+    pub fn age(&self) -> Duration {
+        SystemTime::now()
+            .duration_since(self.built_at)
+            .unwrap_or_default()
+    }
+
+    /// Whether the database was built more than `max_age` ago. MaxMind
+    /// publishes twice a week, so a month means updates stopped.
+    pub fn is_stale(&self, max_age: Duration) -> bool {
+        self.age() > max_age
+    }
+}
+
+impl GeoIp {
+    pub fn metadata(&self) -> GeoIpMetadata {
+        let metadata = &self.reader.metadata;
+        GeoIpMetadata {
+            database_type: metadata.database_type.clone(),
+            built_at: UNIX_EPOCH + Duration::from_secs(metadata.build_epoch),
+            node_count: metadata.node_count,
+            languages: metadata.languages.clone(),
+            ip_version: metadata.ip_version,
+        }
+    }
+}
+
+impl Locat {
+    /// Which vintage of data lookups are answered with, see
+    /// [`Locat::reload_geoip`] to get a newer one in
+    pub fn geoip_metadata(&self) -> GeoIpMetadata {
+        self.geoip().metadata()
+    }
+
+    /// Whether the GeoIP database in use was built more than `max_age` ago,
+    /// for alerting on updates that stopped happening
+    pub fn is_stale(&self, max_age: Duration) -> bool {
+        self.geoip_metadata().is_stale(max_age)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use crate::{testing::TestDb, Locat};
+
+    #[tokio::test]
+    async fn test_geoip_metadata() {
+        const DAY: Duration = Duration::from_secs(86400);
+        let built_at = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let locat = Locat::builder()
+            .with_geoip_bytes(
+                TestDb::new()
+                    .country("1.2.3.0/24", "US", "NA")
+                    .built_at(built_at)
+                    .build(),
+            )
+            .build()
+            .await
+            .unwrap();
+
+        let metadata = locat.geoip_metadata();
+        assert_eq!(metadata.database_type, "GeoIP2-Country");
+        assert_eq!(metadata.built_at, built_at);
+        assert_eq!(metadata.languages, ["en"]);
+        assert_eq!(metadata.ip_version, 4);
+        assert!(metadata.node_count > 0);
+        assert!(locat.is_stale(30 * DAY));
+
+        let fresh = Locat::builder()
+            .with_geoip_bytes(
+                TestDb::new()
+                    .country("1.2.3.0/24", "US", "NA")
+                    .built_at(SystemTime::now() - DAY)
+                    .build(),
+            )
+            .build()
+            .await
+            .unwrap();
+        assert!(!fresh.is_stale(30 * DAY));
+    }
+}
@@ -0,0 +1,160 @@
+use std::{
+    fmt::Write,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use crate::{Error, Locat};
+
+// upper bounds of the latency histogram, in seconds
+const LATENCY_BUCKETS: [f64; 10] = [
+    0.000_001, 0.000_005, 0.000_01, 0.000_05, 0.000_1, 0.000_5, 0.001, 0.005, 0.01, 0.1,
+];
+
+/// What happened to lookups since the process started, see
+/// [`Locat::analytics_to_prometheus`]
+#[derive(Default)]
+pub(crate) struct LookupMetrics {
+    not_found: AtomicU64,
+    // one count per bucket, each only counting the lookups that didn't fit
+    // in the previous one
+    latency: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    latency_sum_nanos: AtomicU64,
+}
+
+impl LookupMetrics {
+    /// `not_found` for lookups that weren't bogons either
+    pub(crate) fn observe(&self, elapsed: Duration, not_found: bool) {
+        if not_found {
+            self.not_found.fetch_add(1, Ordering::Relaxed);
+        }
+        let secs = elapsed.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|bound| secs <= *bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        self.latency[bucket].fetch_add(1, Ordering::Relaxed);
+        self.latency_sum_nanos
+            .fetch_add(elapsed.as_nanos() as u64, Ordering::Relaxed);
+    }
+}
+
+impl Locat {
+    /// Renders analytics in the OpenMetrics text format, for a `/metrics`
+    /// endpoint:
+    ///
+    /// - `locat_lookups_total`, counted lookups per country
+    /// - `locat_lookup_failures_total`, lookups that found nothing, by
+    ///   reason: `bogon` or `not_found`
+    /// - `locat_lookup_duration_seconds`, how long lookups took, counting
+    ///   included
+    ///
+    /// Latencies and `not_found` cover this process only, the rest comes
+    /// from the analytics store.
+    pub async fn analytics_to_prometheus(&self) -> Result<String, Error> {
+        let analytics = self.get_analytics().await?;
+        let bogons = self.get_bogon_count().await?;
+        let metrics = &self.inner.metrics;
+
+        let mut out = String::new();
+        out.push_str("# TYPE locat_lookups counter\n");
+        out.push_str("# HELP locat_lookups Lookups counted per country.\n");
+        for (iso_code, count) in analytics {
+            _ = writeln!(
+                out,
+                "locat_lookups_total{{country=\"{}\"}} {count}",
+                escape(&iso_code)
+            );
+        }
+
+        out.push_str("# TYPE locat_lookup_failures counter\n");
+        out.push_str("# HELP locat_lookup_failures Lookups that found no country.\n");
+        _ = writeln!(
+            out,
+            "locat_lookup_failures_total{{reason=\"bogon\"}} {bogons}"
+        );
+        _ = writeln!(
+            out,
+            "locat_lookup_failures_total{{reason=\"not_found\"}} {}",
+            metrics.not_found.load(Ordering::Relaxed)
+        );
+
+        out.push_str("# TYPE locat_lookup_duration_seconds histogram\n");
+        out.push_str("# UNIT locat_lookup_duration_seconds seconds\n");
+        out.push_str("# HELP locat_lookup_duration_seconds How long lookups took.\n");
+        let mut cumulative = 0;
+        for (i, count) in metrics.latency.iter().enumerate() {
+            cumulative += count.load(Ordering::Relaxed);
+            let bound = match LATENCY_BUCKETS.get(i) {
+                Some(bound) => bound.to_string(),
+                None => "+Inf".to_owned(),
+            };
+            _ = writeln!(
+                out,
+                "locat_lookup_duration_seconds_bucket{{le=\"{bound}\"}} {cumulative}"
+            );
+        }
+        let sum = Duration::from_nanos(metrics.latency_sum_nanos.load(Ordering::Relaxed));
+        _ = writeln!(
+            out,
+            "locat_lookup_duration_seconds_sum {}",
+            sum.as_secs_f64()
+        );
+        _ = writeln!(out, "locat_lookup_duration_seconds_count {cumulative}");
+        out.push_str("# TYPE locat_analytics_write_errors counter\n");
+        out.push_str(
+            "# HELP locat_analytics_write_errors Analytics writes that failed, losing counts.\n",
+        );
+        _ = writeln!(
+            out,
+            "locat_analytics_write_errors_total {}",
+            self.analytics_error_count()
+        );
+        self.admission_metrics(&mut out);
+        out.push_str("# EOF\n");
+        Ok(out)
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        testing::{RemoveOnDrop, TestDb},
+        Locat,
+    };
+
+    #[tokio::test]
+    async fn test_prometheus() {
+        let geoip_path = "/tmp/loca-test-prometheus.mmdb";
+        let analytics_path = "/tmp/loca-test-prometheus.db";
+        TestDb::new()
+            .country("1.2.3.0/24", "US", "NA")
+            .write(geoip_path);
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
+
+        let locat = Locat::new(geoip_path, analytics_path).await.unwrap();
+        locat.ip_to_iso_code("1.2.3.4".parse().unwrap()).await;
+        locat.lookup("1.2.3.5".parse().unwrap()).await;
+        locat.lookup("10.0.0.1".parse().unwrap()).await;
+        locat.ip_to_iso_code("8.8.8.8".parse().unwrap()).await;
+
+        let text = locat.analytics_to_prometheus().await.unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert!(lines.contains(&"locat_lookups_total{country=\"US\"} 2"));
+        assert!(lines.contains(&"locat_lookup_failures_total{reason=\"bogon\"} 1"));
+        assert!(lines.contains(&"locat_lookup_failures_total{reason=\"not_found\"} 1"));
+        assert!(lines.contains(&"locat_lookup_duration_seconds_bucket{le=\"+Inf\"} 4"));
+        assert!(lines.contains(&"locat_lookup_duration_seconds_count 4"));
+        assert_eq!(lines.last(), Some(&"# EOF"));
+    }
+}
@@ -0,0 +1,436 @@
+//! Geolocates every request of a tower (or axum, hyper, ...) service:
+//!
+//! ```no_run
+//! # fn wrap<S>(locat: locat::Locat, service: S) {
+//! use locat::middleware::GeoLayer;
+//! use tower_layer::Layer;
+//!
+//! let layer = GeoLayer::new(locat).with_trusted_proxies(["10.0.0.0/8".parse().unwrap()]);
+//! let service = layer.layer(service);
+//! # }
+//! ```
+//!
+//! Handlers then find the [`CountryCode`] of the client among the request
+//! extensions, when it could be located. With analytics, the layer can also
+//! keep response times and status classes per country, see
+//! [`GeoLayer::with_latencies`] and [`GeoLayer::with_status_classes`].
+
+use std::{
+    fmt,
+    future::Future,
+    net::{IpAddr, SocketAddr},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use http::{Extensions, HeaderMap, Request, Response, StatusCode};
+use ipnetwork::IpNetwork;
+use tower_layer::Layer;
+use tower_service::Service;
+
+use crate::{CountryCode, Locat};
+
+type PeerAddr = dyn Fn(&Extensions) -> Option<IpAddr> + Send + Sync;
+
+/// Looks up the client of every request, counting it, and adds its
+/// [`CountryCode`] to the request extensions
+#[derive(Clone)]
+pub struct GeoLayer {
+    locat: Locat,
+    trusted_proxies: Arc<[IpNetwork]>,
+    peer_addr: Arc<PeerAddr>,
+    latencies: bool,
+    status_classes: bool,
+}
+
+impl fmt::Debug for GeoLayer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GeoLayer")
+            .field("trusted_proxies", &self.trusted_proxies)
+            .field("latencies", &self.latencies)
+            .field("status_classes", &self.status_classes)
+            .finish_non_exhaustive()
+    }
+}
+
+impl GeoLayer {
+    /// Trusts no proxy: the client is whoever connected. The address of the
+    /// connection is read from a [`SocketAddr`] request extension, see
+    /// [`GeoLayer::with_peer_addr`] for servers that store it differently.
+    pub fn new(locat: Locat) -> Self {
+        Self {
+            locat,
+            trusted_proxies: Arc::new([]),
+            peer_addr: Arc::new(|extensions| extensions.get::<SocketAddr>().map(SocketAddr::ip)),
+            latencies: false,
+            status_classes: false,
+        }
+    }
+
+    /// Proxies whose `Forwarded` and `X-Forwarded-For` headers are believed.
+    /// The client is the last address they forwarded for that isn't one of
+    /// them; headers sent by anyone else are ignored, since clients can
+    /// claim to be anywhere.
+    pub fn with_trusted_proxies(mut self, proxies: impl IntoIterator<Item = IpNetwork>) -> Self {
+        self.trusted_proxies = proxies.into_iter().collect();
+        self
+    }
+
+    /// Where the address of the connection is found in the request
+    /// extensions, e.g. for axum:
+    ///
+    /// ```ignore
+    /// layer.with_peer_addr(|extensions| {
+    ///     let ConnectInfo(addr) = extensions.get::<ConnectInfo<SocketAddr>>()?;
+    ///     Some(addr.ip())
+    /// })
+    /// ```
+    pub fn with_peer_addr(
+        mut self,
+        peer_addr: impl Fn(&Extensions) -> Option<IpAddr> + Send + Sync + 'static,
+    ) -> Self {
+        self.peer_addr = Arc::new(peer_addr);
+        self
+    }
+
+    /// Also records how long the service took to respond to clients that
+    /// could be located, per country, see [`Locat::latency_quantile`].
+    /// Kept like lookups are counted: not with recording off, held back
+    /// under load, and batched with [`Locat::with_write_behind`], without
+    /// ever holding the response back. SQLite only.
+    #[cfg(feature = "analytics")]
+    pub fn with_latencies(mut self) -> Self {
+        self.latencies = true;
+        self
+    }
+
+    /// Also counts responses to clients that could be located by country
+    /// and status class, see [`Locat::get_status_classes`]. Errors of the
+    /// service aren't responses, and aren't counted. Kept like
+    /// [`GeoLayer::with_latencies`]. SQLite only.
+    #[cfg(feature = "analytics")]
+    pub fn with_status_classes(mut self) -> Self {
+        self.status_classes = true;
+        self
+    }
+
+    /// The client of a request with these headers and extensions, if known
+    pub fn client_addr(&self, headers: &HeaderMap, extensions: &Extensions) -> Option<IpAddr> {
+        let peer = (self.peer_addr)(extensions)?;
+        if !self.is_trusted(peer) {
+            return Some(peer);
+        }
+        // the nearest hop comes last, walk back until someone we don't know
+        let mut client = peer;
+        for hop in forwarded_for(headers).into_iter().rev() {
+            // an obfuscated hop could be anyone, including the client
+            let hop = hop?;
+            client = hop;
+            if !self.is_trusted(hop) {
+                break;
+            }
+        }
+        Some(client)
+    }
+
+    fn is_trusted(&self, addr: IpAddr) -> bool {
+        self.trusted_proxies
+            .iter()
+            .any(|network| network.contains(addr))
+    }
+}
+
+impl<S> Layer<S> for GeoLayer {
+    type Service = GeoService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GeoService {
+            inner,
+            layer: self.clone(),
+        }
+    }
+}
+
+/// See [`GeoLayer`]. The wrapped service has to answer with an
+/// [`http::Response`], whose status [`GeoLayer::with_status_classes`] reads:
+/// services with other responses can't be wrapped anymore.
+#[derive(Debug, Clone)]
+pub struct GeoService<S> {
+    inner: S,
+    layer: GeoLayer,
+}
+
+impl<S, B, ResB> Service<Request<B>> for GeoService<S>
+where
+    S: Service<Request<B>, Response = Response<ResB>> + Clone + Send + 'static,
+    S::Future: Send,
+    B: Send + 'static,
+{
+    type Response = Response<ResB>;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Response<ResB>, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: Request<B>) -> Self::Future {
+        // the clone may not be ready, the one that was polled is
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let layer = self.layer.clone();
+        Box::pin(async move {
+            let mut located = None;
+            if let Some(addr) = layer.client_addr(request.headers(), request.extensions()) {
+                let iso_code = layer.locat.ip_to_iso_code(addr).await;
+                if let Some(code) = iso_code.and_then(|iso_code| iso_code.parse().ok()) {
+                    request.extensions_mut().insert::<CountryCode>(code);
+                    located = Some(code);
+                }
+            }
+            let started = Instant::now();
+            let response = inner.call(request).await;
+            if let Some(code) = located {
+                let status = response.as_ref().ok().map(Response::status);
+                layer.record_response(code, started, status);
+            }
+            response
+        })
+    }
+}
+
+impl GeoLayer {
+    /// Records what [`GeoLayer::with_latencies`] and
+    /// [`GeoLayer::with_status_classes`] ask for
+    #[cfg(feature = "analytics")]
+    fn record_response(&self, code: CountryCode, started: Instant, status: Option<StatusCode>) {
+        let latency = self.latencies.then(|| started.elapsed());
+        let status = status
+            .filter(|_| self.status_classes)
+            .map(|status| status.as_u16());
+        self.locat.record_response(code.as_str(), latency, status);
+    }
+
+    // only set with analytics
+    #[cfg(not(feature = "analytics"))]
+    fn record_response(&self, _code: CountryCode, _started: Instant, _status: Option<StatusCode>) {}
+}
+
+/// The addresses in `Forwarded`, or else `X-Forwarded-For`, farthest first.
+/// `None` stands for hops that hid their address.
+fn forwarded_for(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let joined = |name| {
+        let values: Vec<_> = headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect();
+        (!values.is_empty()).then(|| values.join(","))
+    };
+
+    if let Some(forwarded) = joined(http::header::FORWARDED) {
+        // `for=192.0.2.60;proto=http, for="[2001:db8::1]:4711"`
+        return forwarded
+            .split(',')
+            .filter_map(|element| {
+                element.split(';').find_map(|pair| {
+                    let (key, value) = pair.split_once('=')?;
+                    key.trim()
+                        .eq_ignore_ascii_case("for")
+                        .then(|| parse_node(value.trim().trim_matches('"')))
+                })
+            })
+            .collect();
+    }
+    let Some(forwarded_for) = joined(http::header::HeaderName::from_static("x-forwarded-for"))
+    else {
+        return Vec::new();
+    };
+    forwarded_for
+        .split(',')
+        .map(|hop| parse_node(hop.trim()))
+        .collect()
+}
+
+/// An address with an optional port, IPv6 ones in brackets if so
+fn parse_node(node: &str) -> Option<IpAddr> {
+    node.parse().ok().or_else(|| {
+        node.parse::<SocketAddr>()
+            .ok()
+            .map(|addr| addr.ip())
+            .or_else(|| {
+                node.strip_prefix('[')
+                    .and_then(|node| node.strip_suffix(']'))
+                    .and_then(|node| node.parse().ok())
+            })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        convert::Infallible,
+        future::{poll_fn, Ready},
+        net::{IpAddr, SocketAddr},
+        task::{Context, Poll},
+    };
+
+    use http::{Extensions, HeaderMap, Request, Response, StatusCode};
+    use tower_layer::Layer;
+    use tower_service::Service;
+
+    use super::{parse_node, GeoLayer};
+    use crate::{testing::TestDb, CountryCode, Locat};
+
+    /// Answers with the country it was given, `404 Not Found` without
+    #[derive(Clone)]
+    struct Echo;
+
+    impl Service<Request<()>> for Echo {
+        type Response = Response<Option<CountryCode>>;
+        type Error = Infallible;
+        type Future = Ready<Result<Self::Response, Infallible>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, request: Request<()>) -> Self::Future {
+            let code = request.extensions().get::<CountryCode>().copied();
+            let mut response = Response::new(code);
+            if code.is_none() {
+                *response.status_mut() = StatusCode::NOT_FOUND;
+            }
+            std::future::ready(Ok(response))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_geo_layer() {
+        let locat = Locat::builder()
+            .with_geoip_bytes(
+                TestDb::new()
+                    .country("1.2.3.0/24", "US", "NA")
+                    .country("5.6.7.0/24", "FR", "EU")
+                    .build(),
+            )
+            .build()
+            .await
+            .unwrap();
+        let layer =
+            GeoLayer::new(locat.clone()).with_trusted_proxies(["10.0.0.0/8".parse().unwrap()]);
+        let client = |peer: &str, headers: &[(&'static str, &str)]| {
+            let mut extensions = Extensions::new();
+            extensions.insert(SocketAddr::new(peer.parse().unwrap(), 443));
+            let mut map = HeaderMap::new();
+            for (name, value) in headers {
+                map.append(*name, value.parse().unwrap());
+            }
+            layer.client_addr(&map, &extensions)
+        };
+        let addr = |addr: &str| Some(addr.parse::<IpAddr>().unwrap());
+
+        // only trusted proxies are believed
+        assert_eq!(
+            client("1.2.3.4", &[("x-forwarded-for", "5.6.7.8")]),
+            addr("1.2.3.4")
+        );
+        assert_eq!(
+            client(
+                "10.0.0.1",
+                &[("x-forwarded-for", "9.9.9.9, 5.6.7.8, 10.1.1.1")]
+            ),
+            addr("5.6.7.8")
+        );
+        assert_eq!(
+            client(
+                "10.0.0.1",
+                &[(
+                    "forwarded",
+                    r#"for=5.6.7.8;proto=https, for="[2001:db8::1]:4711""#
+                )]
+            ),
+            addr("2001:db8::1")
+        );
+        assert_eq!(client("10.0.0.1", &[("forwarded", "for=_hidden")]), None);
+        assert_eq!(client("10.0.0.1", &[]), addr("10.0.0.1"));
+        assert_eq!(parse_node("1.2.3.4:80"), addr("1.2.3.4"));
+
+        let mut service = layer.layer(Echo);
+        let mut request = Request::new(());
+        request
+            .extensions_mut()
+            .insert(SocketAddr::new("10.0.0.1".parse().unwrap(), 443));
+        request
+            .headers_mut()
+            .insert("x-forwarded-for", "5.6.7.8".parse().unwrap());
+        poll_fn(|cx| service.poll_ready(cx)).await.unwrap();
+        let code = service.call(request).await.unwrap().into_body();
+        assert_eq!(code.map(|code| code.to_string()), Some("FR".to_string()));
+        #[cfg(feature = "analytics")]
+        {
+            assert_eq!(
+                locat.get_analytics().await.unwrap(),
+                vec![("FR".to_string(), 1)]
+            );
+            assert_eq!(locat.latency_quantile("FR", 0.5).await.unwrap(), None);
+        }
+    }
+
+    /// Sends a request from each of `peers` through `layer`
+    #[cfg(feature = "analytics")]
+    async fn call_from(layer: &GeoLayer, peers: &[&str]) {
+        let mut service = layer.layer(Echo);
+        for peer in peers {
+            let mut request = Request::new(());
+            request
+                .extensions_mut()
+                .insert(SocketAddr::new(peer.parse().unwrap(), 443));
+            poll_fn(|cx| service.poll_ready(cx)).await.unwrap();
+            service.call(request).await.unwrap();
+        }
+    }
+
+    #[cfg(feature = "analytics")]
+    #[tokio::test]
+    async fn test_geo_layer_responses() {
+        use std::time::Duration;
+
+        let geoip = TestDb::new().country("5.6.7.0/24", "FR", "EU").build();
+        let locat = Locat::builder()
+            .with_geoip_bytes(geoip.clone())
+            .build()
+            .await
+            .unwrap()
+            .with_write_behind(Duration::from_secs(3600), 1000);
+        let layer = GeoLayer::new(locat.clone())
+            .with_latencies()
+            .with_status_classes();
+        // the last one can't be located, and isn't counted
+        call_from(&layer, &["5.6.7.8", "5.6.7.9", "9.9.9.9"]).await;
+        // waiting along with lookups
+        assert_eq!(locat.latency_quantile("FR", 0.5).await.unwrap(), None);
+        locat.flush().await.unwrap();
+        assert!(locat.latency_quantile("FR", 0.5).await.unwrap().is_some());
+        assert_eq!(locat.latency_quantile("US", 0.5).await.unwrap(), None);
+        assert_eq!(
+            locat.get_status_classes().await.unwrap(),
+            vec![("FR".to_string(), "2xx".to_string(), 2)]
+        );
+
+        let locat = Locat::builder()
+            .with_geoip_bytes(geoip)
+            .with_recording(false)
+            .build()
+            .await
+            .unwrap();
+        let layer = GeoLayer::new(locat.clone())
+            .with_latencies()
+            .with_status_classes();
+        call_from(&layer, &["5.6.7.8"]).await;
+        assert_eq!(locat.latency_quantile("FR", 0.5).await.unwrap(), None);
+        assert!(locat.get_status_classes().await.unwrap().is_empty());
+    }
+}
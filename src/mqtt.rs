@@ -0,0 +1,270 @@
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    sync::Mutex,
+    task::AbortHandle,
+};
+
+use crate::{Error, Locat};
+
+/// Publishes to an MQTT broker, see [`Locat::publish_analytics_every`].
+/// Implement it for the client a device already has, or use
+/// [`MqttClient`].
+#[async_trait]
+pub trait MqttPublish: Send + Sync {
+    async fn publish(&self, topic: &str, payload: Vec<u8>) -> Result<(), Error>;
+}
+
+/// A bare-bones MQTT 3.1.1 client: QoS 0 publishes over plain TCP,
+/// connecting on first use and again after a failed publish
+#[derive(Debug)]
+pub struct MqttClient {
+    addr: String,
+    client_id: String,
+    credentials: Option<(String, String)>,
+    stream: Mutex<Option<TcpStream>>,
+}
+
+impl MqttClient {
+    /// A client for the broker at `addr`, e.g. `localhost:1883`
+    pub fn new(addr: impl Into<String>, client_id: impl Into<String>) -> Self {
+        Self {
+            addr: addr.into(),
+            client_id: client_id.into(),
+            credentials: None,
+            stream: Mutex::new(None),
+        }
+    }
+
+    pub fn with_credentials(
+        mut self,
+        username: impl Into<String>,
+        password: impl Into<String>,
+    ) -> Self {
+        self.credentials = Some((username.into(), password.into()));
+        self
+    }
+
+    async fn connect(&self) -> Result<TcpStream, Error> {
+        let mut stream = TcpStream::connect(&self.addr).await?;
+        // clean session, no keep alive: publishes may be far apart
+        let mut flags = 0x02;
+        let mut payload = Vec::new();
+        put_string(&mut payload, &self.client_id)?;
+        if let Some((username, password)) = &self.credentials {
+            flags |= 0xc0;
+            put_string(&mut payload, username)?;
+            put_string(&mut payload, password)?;
+        }
+        let mut body = Vec::new();
+        put_string(&mut body, "MQTT")?;
+        body.extend_from_slice(&[4, flags, 0, 0]);
+        body.extend_from_slice(&payload);
+        stream.write_all(&packet(0x10, &body)?).await?;
+
+        let mut connack = [0; 4];
+        stream.read_exact(&mut connack).await?;
+        match connack {
+            [0x20, 2, _, 0] => Ok(stream),
+            [0x20, 2, _, code] => Err(Error::Mqtt(format!(
+                "connection refused with return code {code}"
+            ))),
+            _ => Err(Error::Mqtt("expected CONNACK".to_owned())),
+        }
+    }
+}
+
+#[async_trait]
+impl MqttPublish for MqttClient {
+    async fn publish(&self, topic: &str, payload: Vec<u8>) -> Result<(), Error> {
+        let mut body = Vec::with_capacity(topic.len() + payload.len() + 2);
+        put_string(&mut body, topic)?;
+        body.extend_from_slice(&payload);
+        let packet = packet(0x30, &body)?;
+
+        let mut stream = self.stream.lock().await;
+        if stream.is_none() {
+            *stream = Some(self.connect().await?);
+        }
+        let result = stream.as_mut().unwrap().write_all(&packet).await;
+        if result.is_err() {
+            // the next publish connects again
+            *stream = None;
+        }
+        Ok(result?)
+    }
+}
+
+/// The most the remaining length of a packet can encode
+const MAX_REMAINING_LENGTH: usize = 268_435_455;
+
+/// A control packet: type and flags, remaining length, then `body`
+fn packet(header: u8, body: &[u8]) -> Result<Vec<u8>, Error> {
+    if body.len() > MAX_REMAINING_LENGTH {
+        return Err(Error::Mqtt(format!(
+            "packet of {} bytes is over the limit of {MAX_REMAINING_LENGTH}",
+            body.len()
+        )));
+    }
+    let mut out = vec![header];
+    let mut len = body.len();
+    loop {
+        let mut byte = (len % 128) as u8;
+        len /= 128;
+        if len > 0 {
+            byte |= 0x80;
+        }
+        out.push(byte);
+        if len == 0 {
+            break;
+        }
+    }
+    out.extend_from_slice(body);
+    Ok(out)
+}
+
+/// Appends `s` prefixed with its length, which has to fit in 16 bits
+fn put_string(out: &mut Vec<u8>, s: &str) -> Result<(), Error> {
+    let len = u16::try_from(s.len()).map_err(|_| {
+        Error::Mqtt(format!(
+            "string of {} bytes is over the limit of {}",
+            s.len(),
+            u16::MAX
+        ))
+    })?;
+    out.extend_from_slice(&len.to_be_bytes());
+    out.extend_from_slice(s.as_bytes());
+    Ok(())
+}
+
+impl Locat {
+    /// Publishes [`Locat::get_analytics_json`] to `topic`, once
+    pub async fn publish_analytics(
+        &self,
+        publisher: &dyn MqttPublish,
+        topic: &str,
+    ) -> Result<(), Error> {
+        let report = self.get_analytics_json().await?;
+        publisher.publish(topic, report.into_bytes()).await
+    }
+
+    /// [`Locat::publish_analytics`] every `interval`, starting now, for
+    /// devices whose only way out is MQTT. Failures are logged and retried
+    /// on the next interval. The task ends once every clone of this `Locat`
+    /// is dropped, or on [`Locat::shutdown`].
+    pub fn publish_analytics_every(
+        &self,
+        publisher: impl MqttPublish + 'static,
+        topic: impl Into<String>,
+        interval: Duration,
+    ) -> AbortHandle {
+        let inner = Arc::downgrade(&self.inner);
+        let topic = topic.into();
+        self.spawn("publish_mqtt", |mut stop| async move {
+            let mut ticks = tokio::time::interval(interval);
+            loop {
+                tokio::select! {
+                    _ = ticks.tick() => {}
+                    _ = stop.requested() => return,
+                }
+                let Some(inner) = inner.upgrade() else {
+                    return;
+                };
+                let locat = Locat { inner };
+                if let Err(e) = locat.publish_analytics(&publisher, &topic).await {
+                    locat.log(format!("Could not publish analytics over MQTT: {e}"));
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        sync::mpsc,
+    };
+
+    use super::{MqttClient, MqttPublish};
+    use crate::{testing::TestDb, Error, Locat};
+
+    /// Takes one connection like a broker would, handing over the topic and
+    /// payload of every PUBLISH
+    async fn broker() -> (String, mpsc::UnboundedReceiver<(String, Vec<u8>)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (published, received) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut connected = false;
+            loop {
+                let header = stream.read_u8().await.unwrap();
+                let mut len = 0;
+                let mut shift = 0;
+                loop {
+                    let byte = stream.read_u8().await.unwrap();
+                    len |= usize::from(byte & 0x7f) << shift;
+                    shift += 7;
+                    if byte & 0x80 == 0 {
+                        break;
+                    }
+                }
+                let mut body = vec![0; len];
+                stream.read_exact(&mut body).await.unwrap();
+                match header >> 4 {
+                    1 => {
+                        assert_eq!(&body[..7], b"\0\x04MQTT\x04");
+                        stream.write_all(&[0x20, 2, 0, 0]).await.unwrap();
+                        connected = true;
+                    }
+                    3 => {
+                        assert!(connected);
+                        let topic_len = usize::from(u16::from_be_bytes([body[0], body[1]]));
+                        let topic = String::from_utf8(body[2..2 + topic_len].to_vec()).unwrap();
+                        _ = published.send((topic, body[2 + topic_len..].to_vec()));
+                    }
+                    other => panic!("unexpected packet type {other}"),
+                }
+            }
+        });
+        (addr.to_string(), received)
+    }
+
+    #[tokio::test]
+    async fn test_publish_analytics() {
+        let (addr, mut received) = broker().await;
+        let locat = Locat::builder()
+            .with_geoip_bytes(TestDb::new().country("1.2.3.0/24", "US", "NA").build())
+            .build()
+            .await
+            .unwrap();
+        locat.ip_to_iso_code("1.2.3.4".parse().unwrap()).await;
+
+        // refused before connecting, rather than sent corrupt
+        let client = MqttClient::new(addr.clone(), "edge-1");
+        assert!(matches!(
+            client.publish(&"a".repeat(65_536), Vec::new()).await,
+            Err(Error::Mqtt(_))
+        ));
+
+        locat.publish_analytics_every(
+            MqttClient::new(addr, "edge-1"),
+            "fleet/edge-1/analytics",
+            Duration::from_millis(10),
+        );
+        for _ in 0..2 {
+            let (topic, payload) = received.recv().await.unwrap();
+            assert_eq!(topic, "fleet/edge-1/analytics");
+            let report: crate::AnalyticsReport = serde_json::from_slice(&payload).unwrap();
+            assert_eq!(report.countries[0].iso_code, "US");
+        }
+        locat.shutdown().await;
+    }
+}
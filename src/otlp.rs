@@ -0,0 +1,333 @@
+use std::{
+    mem,
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::{json, Value};
+use tokio::sync::Notify;
+
+use crate::{Error, Locat};
+
+/// Where and how [`Locat::with_otlp_logs`] sends lookup events: OTLP over
+/// HTTP, JSON encoded
+#[derive(Debug, Clone)]
+pub struct OtlpLogs {
+    endpoint: String,
+    headers: Vec<(String, String)>,
+    service_name: String,
+    interval: Duration,
+    max_batch: usize,
+    max_queue: usize,
+}
+
+impl OtlpLogs {
+    /// Sends to `endpoint`, e.g. `http://localhost:4318/v1/logs`, every 5
+    /// seconds or 512 events, whichever comes first. Up to 10,000 events
+    /// wait to be sent, later ones are dropped.
+    pub fn new(endpoint: impl Into<String>) -> Self {
+        Self {
+            endpoint: endpoint.into(),
+            headers: Vec::new(),
+            service_name: "locat".to_owned(),
+            interval: Duration::from_secs(5),
+            max_batch: 512,
+            max_queue: 10_000,
+        }
+    }
+
+    /// Sent with every request, e.g. for authentication
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// The `service.name` resource attribute, `locat` by default
+    pub fn with_service_name(mut self, service_name: impl Into<String>) -> Self {
+        self.service_name = service_name.into();
+        self
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// How many events are sent at once, and how many may wait
+    pub fn with_batch(mut self, max_batch: usize, max_queue: usize) -> Self {
+        self.max_batch = max_batch.max(1);
+        self.max_queue = max_queue.max(self.max_batch);
+        self
+    }
+}
+
+/// An [`OtlpLogs`] and the events waiting to be sent
+pub(crate) struct OtlpExporter {
+    config: OtlpLogs,
+    client: reqwest::Client,
+    queue: Mutex<Vec<LookupEvent>>,
+    // wakes the sender up early when a batch is ready
+    full: Arc<Notify>,
+    sender: OnceLock<()>,
+}
+
+struct LookupEvent {
+    at: SystemTime,
+    outcome: &'static str,
+    iso_code: Option<String>,
+}
+
+impl LookupEvent {
+    fn to_otlp(&self) -> Value {
+        let nanos = self
+            .at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos()
+            .to_string();
+        let mut attributes = vec![json!({
+            "key": "locat.outcome",
+            "value": { "stringValue": self.outcome },
+        })];
+        if let Some(iso_code) = &self.iso_code {
+            attributes.push(json!({
+                "key": "locat.iso_code",
+                "value": { "stringValue": iso_code },
+            }));
+        }
+        json!({
+            "timeUnixNano": nanos,
+            "observedTimeUnixNano": nanos,
+            "severityNumber": 9,
+            "severityText": "INFO",
+            "body": { "stringValue": "lookup" },
+            "attributes": attributes,
+        })
+    }
+}
+
+impl Locat {
+    /// Sends an OpenTelemetry log record for every counted lookup, with
+    /// its outcome (`located`, `bogon` or `not_found`) and country code as
+    /// attributes, in batches. Addresses aren't sent. Events that can't be
+    /// sent are dropped, and counting carries on regardless.
+    pub fn with_otlp_logs(mut self, logs: OtlpLogs) -> Self {
+        self.inner_mut().otlp = Some(OtlpExporter {
+            config: logs,
+            client: reqwest::Client::new(),
+            queue: Mutex::new(Vec::new()),
+            full: Default::default(),
+            sender: OnceLock::new(),
+        });
+        self
+    }
+
+    /// Queues a lookup event, if there's an exporter
+    pub(crate) fn emit(&self, outcome: &'static str, iso_code: Option<&str>) {
+        let Some(otlp) = &self.inner.otlp else {
+            return;
+        };
+        {
+            let mut queue = otlp.queue.lock().unwrap();
+            if queue.len() >= otlp.config.max_queue {
+                return;
+            }
+            queue.push(LookupEvent {
+                at: SystemTime::now(),
+                outcome,
+                iso_code: iso_code.map(str::to_owned),
+            });
+            if queue.len() >= otlp.config.max_batch {
+                otlp.full.notify_one();
+            }
+        }
+        self.start_sender(otlp);
+    }
+
+    /// Sends the lookup events waiting for [`Locat::with_otlp_logs`], if
+    /// any. They're dropped if the collector can't take them.
+    pub async fn flush_otlp_logs(&self) -> Result<(), Error> {
+        let Some(otlp) = &self.inner.otlp else {
+            return Ok(());
+        };
+        loop {
+            let batch = {
+                let mut queue = otlp.queue.lock().unwrap();
+                let len = queue.len().min(otlp.config.max_batch);
+                let rest = queue.split_off(len);
+                mem::replace(&mut *queue, rest)
+            };
+            if batch.is_empty() {
+                return Ok(());
+            }
+            let body = json!({
+                "resourceLogs": [{
+                    "resource": {
+                        "attributes": [{
+                            "key": "service.name",
+                            "value": { "stringValue": otlp.config.service_name },
+                        }],
+                    },
+                    "scopeLogs": [{
+                        "scope": { "name": "locat", "version": env!("CARGO_PKG_VERSION") },
+                        "logRecords": batch.iter().map(LookupEvent::to_otlp).collect::<Vec<_>>(),
+                    }],
+                }],
+            });
+            let mut request = otlp
+                .client
+                .post(&otlp.config.endpoint)
+                .header("content-type", "application/json")
+                .body(body.to_string());
+            for (name, value) in &otlp.config.headers {
+                request = request.header(name, value);
+            }
+            request.send().await?.error_for_status()?;
+        }
+    }
+
+    /// Starts the task sending events in the background, on first use so
+    /// that it runs within the caller's runtime
+    fn start_sender(&self, otlp: &OtlpExporter) {
+        otlp.sender.get_or_init(|| {
+            let inner = Arc::downgrade(&self.inner);
+            let (interval, full) = (otlp.config.interval, otlp.full.clone());
+            self.spawn("otlp_logs", |mut stop| async move {
+                let mut ticks = tokio::time::interval(interval);
+                loop {
+                    let stopping = tokio::select! {
+                        _ = ticks.tick() => false,
+                        _ = full.notified() => false,
+                        _ = stop.requested() => true,
+                    };
+                    let Some(inner) = inner.upgrade() else {
+                        return;
+                    };
+                    let locat = Locat { inner };
+                    if let Err(e) = locat.flush_otlp_logs().await {
+                        locat.log(format!("Could not send lookup events: {e}"));
+                    }
+                    if stopping {
+                        return;
+                    }
+                }
+            });
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        sync::mpsc,
+    };
+
+    use super::OtlpLogs;
+    use crate::{testing::TestDb, Locat};
+
+    /// Accepts OTLP requests like a collector would, handing over their
+    /// bodies
+    async fn collector() -> (String, mpsc::UnboundedReceiver<serde_json::Value>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (bodies, received) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = vec![0; 4096];
+                // up to the end of a body as long as content-length says
+                let body = loop {
+                    let len = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..len]);
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    let Some((head, body)) = text.split_once("\r\n\r\n") else {
+                        continue;
+                    };
+                    let length: usize = head
+                        .lines()
+                        .find_map(|line| {
+                            line.to_lowercase()
+                                .strip_prefix("content-length: ")?
+                                .parse()
+                                .ok()
+                        })
+                        .unwrap();
+                    if body.len() >= length {
+                        break body.to_owned();
+                    }
+                };
+                _ = bodies.send(serde_json::from_str(&body).unwrap());
+                stream
+                    .write_all(
+                        b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\nconnection: close\r\n\r\n{}",
+                    )
+                    .await
+                    .unwrap();
+            }
+        });
+        (format!("http://{addr}/v1/logs"), received)
+    }
+
+    #[tokio::test]
+    async fn test_otlp_logs() {
+        let addr = "1.2.3.4".parse().unwrap();
+        let (endpoint, mut received) = collector().await;
+        let locat = Locat::builder()
+            .with_geoip_bytes(TestDb::new().country("1.2.3.0/24", "US", "NA").build())
+            .build()
+            .await
+            .unwrap()
+            .with_dedup_window(Duration::from_secs(3600))
+            .with_otlp_logs(
+                OtlpLogs::new(endpoint)
+                    .with_service_name("edge")
+                    .with_batch(2, 10),
+            );
+        locat.ip_to_iso_code(addr).await;
+        locat.lookup("10.0.0.1".parse().unwrap()).await;
+        // not counted, not sent: untracked, then deduplicated twice
+        locat.ip_to_iso_code_untracked(addr);
+        locat.ip_to_iso_code(addr).await;
+        locat
+            .ip_to_iso_codes(&["8.8.8.8".parse().unwrap(), addr])
+            .await;
+        locat.flush_otlp_logs().await.unwrap();
+        // waits for the sender too, should it have taken a batch
+        locat.shutdown().await;
+
+        let mut records = Vec::new();
+        while let Ok(body) = received.try_recv() {
+            let resource = &body["resourceLogs"][0];
+            assert_eq!(
+                resource["resource"]["attributes"][0]["value"]["stringValue"],
+                "edge"
+            );
+            let batch = resource["scopeLogs"][0]["logRecords"].as_array().unwrap();
+            assert!(batch.len() <= 2);
+            records.extend(batch.iter().cloned());
+        }
+        let attributes: Vec<Vec<&str>> = records
+            .iter()
+            .map(|record| {
+                record["attributes"]
+                    .as_array()
+                    .unwrap()
+                    .iter()
+                    .map(|attribute| attribute["value"]["stringValue"].as_str().unwrap())
+                    .collect()
+            })
+            .collect();
+        // batches may arrive in any order
+        let mut attributes = attributes;
+        attributes.sort();
+        assert_eq!(
+            attributes,
+            vec![vec!["bogon"], vec!["located", "US"], vec!["not_found"]]
+        );
+    }
+}
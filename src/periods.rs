@@ -0,0 +1,383 @@
+use std::{
+    collections::BTreeMap,
+    ops::Range,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{Error, Locat};
+
+/// When a country's traffic happens, see [`Locat::seasonality`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Seasonality {
+    pub iso_code: String,
+    /// Counts by hour of the day, UTC
+    pub by_hour: [u64; 24],
+    /// Counts by day of the week, Monday first
+    pub by_weekday: [u64; 7],
+}
+
+/// How a country's count changed from one period to the next, see
+/// [`Locat::compare_periods`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct PeriodDelta {
+    pub iso_code: String,
+    pub before: u64,
+    pub after: u64,
+    pub delta: i64,
+    /// `None` when the country wasn't seen at all before
+    pub percent: Option<f64>,
+}
+
+impl Locat {
+    /// Per-country changes between periods `a` and `b`, largest changes
+    /// first.
+    ///
+    /// Periods are made of the files a dated analytics path (see
+    /// [`Locat::new`]) rolled over to: a file counts in full as soon as its
+    /// period overlaps the range. Without placeholders in the path, both
+    /// periods see the same single file.
+    pub async fn compare_periods(
+        &self,
+        a: Range<SystemTime>,
+        b: Range<SystemTime>,
+    ) -> Result<Vec<PeriodDelta>, Error> {
+        let before = self.sqlite("compare_periods")?.list_period(a).await?;
+        let after = self.sqlite("compare_periods")?.list_period(b).await?;
+        Ok(deltas(before, after))
+    }
+
+    /// Countries whose count changed the most, relatively, over the last
+    /// `window` compared to the `window` before it. Countries that weren't
+    /// seen before come first, then the rest by the size of their
+    /// percentage change, up or down.
+    pub async fn top_movers(&self, window: Duration) -> Result<Vec<PeriodDelta>, Error> {
+        let now = SystemTime::now();
+        let start = now - window;
+        let mut deltas = self
+            .compare_periods(start - window..start, start..now)
+            .await?;
+        sort_by_relative_change(&mut deltas);
+        Ok(deltas)
+    }
+
+    /// Counts for one country over consecutive `bucket`-long slices of
+    /// `window`, oldest first, e.g. for a sparkline.
+    ///
+    /// Like [`Locat::compare_periods`], this reads rolled-over files, so
+    /// buckets shorter than the rollover period repeat the counts of the
+    /// file they fall in.
+    ///
+    /// # Panics
+    ///
+    /// If `bucket` is zero.
+    pub async fn trend(
+        &self,
+        iso_code: &str,
+        bucket: Duration,
+        window: Range<SystemTime>,
+    ) -> Result<Vec<(SystemTime, u64)>, Error> {
+        let mut series = Vec::new();
+        for range in buckets(bucket, window) {
+            let start = range.start;
+            let count = self
+                .sqlite("trend")?
+                .list_period(range)
+                .await?
+                .into_iter()
+                .find(|(code, _)| code == iso_code)
+                .map_or(0, |(_, count)| count);
+            series.push((start, count));
+        }
+        Ok(series)
+    }
+
+    /// Per-country counts of the time buckets (see
+    /// [`Locat::with_time_buckets`]) that start within `range`
+    pub async fn get_analytics_between(
+        &self,
+        range: Range<SystemTime>,
+    ) -> Result<Vec<(String, u64)>, Error> {
+        let buckets = self
+            .sqlite("get_analytics_between")?
+            .list_buckets(range)
+            .await?;
+        let mut totals: BTreeMap<String, u64> = BTreeMap::new();
+        for (_, iso_code, count) in buckets {
+            *totals.entry(iso_code).or_default() += count;
+        }
+        Ok(totals.into_iter().collect())
+    }
+
+    /// Per-country counts of each day (UTC) within `range`, oldest first,
+    /// from the time buckets (see [`Locat::with_time_buckets`]). Buckets
+    /// longer than a day count towards the day they start in.
+    pub async fn get_analytics_by_day(
+        &self,
+        range: Range<SystemTime>,
+    ) -> Result<Vec<(SystemTime, Vec<(String, u64)>)>, Error> {
+        let buckets = self
+            .sqlite("get_analytics_by_day")?
+            .list_buckets(range)
+            .await?;
+        Ok(by_day(buckets))
+    }
+
+    /// Per-country counts by hour of the day and day of the week over
+    /// `window`, for finding when each region peaks.
+    ///
+    /// This needs an analytics path that rolls over every hour (`%H`, see
+    /// [`Locat::new`]), otherwise there is no way to tell hours apart.
+    pub async fn seasonality(&self, window: Range<SystemTime>) -> Result<Vec<Seasonality>, Error> {
+        let hours = self
+            .sqlite("seasonality")?
+            .list_hourly(window)
+            .await?
+            .ok_or(Error::NotHourly)?;
+        Ok(seasonality(hours))
+    }
+}
+
+/// Start of the `size`-long bucket `at` falls in, in seconds since the
+/// epoch
+pub(crate) fn bucket_start(at: SystemTime, size: Duration) -> u64 {
+    let secs = at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    secs - secs % size.as_secs()
+}
+
+fn by_day(buckets: Vec<(SystemTime, String, u64)>) -> Vec<(SystemTime, Vec<(String, u64)>)> {
+    let day = Duration::from_secs(86400);
+    let mut days: BTreeMap<SystemTime, BTreeMap<String, u64>> = BTreeMap::new();
+    for (start, iso_code, count) in buckets {
+        let start = UNIX_EPOCH + Duration::from_secs(bucket_start(start, day));
+        *days.entry(start).or_default().entry(iso_code).or_default() += count;
+    }
+    days.into_iter()
+        .map(|(start, counts)| (start, counts.into_iter().collect()))
+        .collect()
+}
+
+fn seasonality(hours: Vec<(SystemTime, Vec<(String, u64)>)>) -> Vec<Seasonality> {
+    let mut countries: BTreeMap<String, Seasonality> = BTreeMap::new();
+    for (at, counts) in hours {
+        let secs = at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let hour = (secs % 86400 / 3600) as usize;
+        // 1970-01-01 was a Thursday
+        let weekday = ((secs / 86400 + 3) % 7) as usize;
+        for (iso_code, count) in counts {
+            let entry = countries
+                .entry(iso_code.clone())
+                .or_insert_with(|| Seasonality {
+                    iso_code,
+                    by_hour: [0; 24],
+                    by_weekday: [0; 7],
+                });
+            entry.by_hour[hour] += count;
+            entry.by_weekday[weekday] += count;
+        }
+    }
+    countries.into_values().collect()
+}
+
+fn sort_by_relative_change(deltas: &mut [PeriodDelta]) {
+    deltas.sort_by(|a, b| match (a.percent, b.percent) {
+        (None, None) => b.after.cmp(&a.after),
+        (None, Some(_)) => std::cmp::Ordering::Less,
+        (Some(_), None) => std::cmp::Ordering::Greater,
+        (Some(a), Some(b)) => b.abs().total_cmp(&a.abs()),
+    });
+}
+
+fn buckets(bucket: Duration, window: Range<SystemTime>) -> Vec<Range<SystemTime>> {
+    assert!(!bucket.is_zero(), "trend buckets can't be empty");
+    let mut buckets = Vec::new();
+    let mut start = window.start;
+    while start < window.end {
+        let end = (start + bucket).min(window.end);
+        buckets.push(start..end);
+        start = end;
+    }
+    buckets
+}
+
+pub(crate) fn deltas(before: Vec<(String, u64)>, after: Vec<(String, u64)>) -> Vec<PeriodDelta> {
+    let mut counts: BTreeMap<String, (u64, u64)> = BTreeMap::new();
+    for (iso_code, count) in before {
+        counts.entry(iso_code).or_default().0 += count;
+    }
+    for (iso_code, count) in after {
+        counts.entry(iso_code).or_default().1 += count;
+    }
+
+    let mut deltas: Vec<_> = counts
+        .into_iter()
+        .map(|(iso_code, (before, after))| {
+            let delta = after as i64 - before as i64;
+            PeriodDelta {
+                iso_code,
+                before,
+                after,
+                delta,
+                percent: (before > 0).then(|| delta as f64 * 100.0 / before as f64),
+            }
+        })
+        .collect();
+    deltas.sort_by_key(|delta| std::cmp::Reverse(delta.delta.unsigned_abs()));
+    deltas
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{bucket_start, buckets, by_day, deltas, seasonality, sort_by_relative_change};
+    use crate::{
+        testing::{RemoveOnDrop, TestDb},
+        Locat,
+    };
+
+    #[test]
+    fn test_deltas() {
+        let deltas = deltas(
+            vec![("US".to_string(), 10), ("FR".to_string(), 4)],
+            vec![
+                ("US".to_string(), 5),
+                ("FR".to_string(), 5),
+                ("DE".to_string(), 2),
+            ],
+        );
+        let summary: Vec<_> = deltas
+            .iter()
+            .map(|d| (d.iso_code.as_str(), d.delta, d.percent))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("US", -5, Some(-50.0)),
+                ("DE", 2, None),
+                ("FR", 1, Some(25.0))
+            ]
+        );
+    }
+
+    #[test]
+    fn test_buckets() {
+        let start = UNIX_EPOCH;
+        let day = Duration::from_secs(86400);
+        assert_eq!(
+            buckets(day, start..start + day * 2 + day / 2),
+            vec![
+                start..start + day,
+                start + day..start + day * 2,
+                start + day * 2..start + day * 2 + day / 2
+            ]
+        );
+        assert!(buckets(day, start..start).is_empty());
+    }
+
+    #[test]
+    fn test_sort_by_relative_change() {
+        let mut deltas = deltas(
+            vec![("US".to_string(), 1000), ("FR".to_string(), 10)],
+            vec![
+                ("US".to_string(), 1500),
+                ("FR".to_string(), 2),
+                ("DE".to_string(), 1),
+            ],
+        );
+        sort_by_relative_change(&mut deltas);
+        let order: Vec<_> = deltas.iter().map(|d| d.iso_code.as_str()).collect();
+        assert_eq!(order, ["DE", "FR", "US"]);
+    }
+
+    #[test]
+    fn test_seasonality() {
+        // 2023-06-15 was a Thursday
+        let thursday = UNIX_EPOCH + Duration::from_secs(1686787200);
+        let hour = Duration::from_secs(3600);
+        let seasonality = seasonality(vec![
+            (thursday + hour * 13, vec![("US".to_string(), 3)]),
+            (
+                thursday + hour * 24 * 4 + hour * 13,
+                vec![("US".to_string(), 2)],
+            ),
+            (thursday + hour * 2, vec![("FR".to_string(), 1)]),
+        ]);
+
+        assert_eq!(seasonality.len(), 2);
+        let us = &seasonality[1];
+        assert_eq!(us.iso_code, "US");
+        assert_eq!(us.by_hour[13], 5);
+        assert_eq!(us.by_hour.iter().sum::<u64>(), 5);
+        // thursday and monday
+        assert_eq!(us.by_weekday, [2, 0, 0, 3, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_by_day() {
+        // 2023-06-15 00:00:00 UTC
+        let day = UNIX_EPOCH + Duration::from_secs(1686787200);
+        let hour = Duration::from_secs(3600);
+        assert_eq!(
+            bucket_start(day + hour * 5 + hour / 2, hour),
+            1686787200 + 5 * 3600
+        );
+
+        let days = by_day(vec![
+            (day + hour, "US".to_string(), 2),
+            (day + hour * 23, "US".to_string(), 1),
+            (day + hour * 23, "FR".to_string(), 4),
+            (day + hour * 25, "US".to_string(), 7),
+        ]);
+        assert_eq!(
+            days,
+            vec![
+                (day, vec![("FR".to_string(), 4), ("US".to_string(), 3)]),
+                (day + hour * 24, vec![("US".to_string(), 7)]),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_time_buckets() {
+        let geoip_path = "/tmp/loca-test-buckets.mmdb";
+        let analytics_path = "/tmp/loca-test-buckets.db";
+        TestDb::new()
+            .country("1.2.3.0/24", "US", "NA")
+            .write(geoip_path);
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
+
+        let locat = Locat::new(geoip_path, analytics_path)
+            .await
+            .unwrap()
+            .with_time_buckets(Duration::from_secs(3600));
+        let addr = "1.2.3.4".parse().unwrap();
+        locat.ip_to_iso_code(addr).await;
+        locat.ip_to_iso_code(addr).await;
+
+        let now = std::time::SystemTime::now();
+        let hour = Duration::from_secs(3600);
+        assert_eq!(
+            locat.get_analytics_between(now - hour..now).await.unwrap(),
+            vec![("US".to_string(), 2)]
+        );
+        assert!(locat
+            .get_analytics_between(now + hour..now + hour * 2)
+            .await
+            .unwrap()
+            .is_empty());
+        let days = locat
+            .get_analytics_by_day(now - hour * 24..now)
+            .await
+            .unwrap();
+        assert_eq!(days.len(), 1);
+        assert_eq!(days[0].1, vec![("US".to_string(), 2)]);
+        // lifetime counts keep going
+        assert_eq!(
+            locat.get_analytics().await.unwrap(),
+            vec![("US".to_string(), 2)]
+        );
+    }
+}
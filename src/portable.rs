@@ -0,0 +1,371 @@
+//! A plain-text dump of the analytics database that doesn't depend on the
+//! SQLite schema, so it can be carried across crate versions.
+//!
+//! ```text
+//! locat-export    3
+//! table   analytics   iso_code    count   sum
+//! US      2           0
+//! table   counters    name        count
+//! bogon   3
+//! table   weights     iso_code    measure count   sum     sketch
+//! US      bytes       1           1500    0000000000000000...
+//! table   buckets     iso_code    bucket_start    count
+//! US      1700000000  2
+//! table   subnets     subnet      iso_code        count   last_seen
+//! 1.2.3.0/24  US      2           1700003599
+//! table   latencies   iso_code    sketch
+//! US      0000000000000000...
+//! table   statuses    iso_code    class   count
+//! US      2xx         2
+//! ```
+//!
+//! Fields are separated by tabs. Every table names its columns. Readers
+//! look columns up by name, skip tables and columns they don't know about,
+//! and default missing numeric columns to zero, which is what lets older
+//! and newer versions read each other's exports. The format version only
+//! changes when that isn't enough, and readers refuse versions newer than
+//! their own. Sketches are hex-encoded and merged into existing ones on
+//! import.
+//!
+//! Version 1 had a single weighted measure, summed up in `analytics` with
+//! its distribution in a `sketches` table. It's read as the measure named
+//! [`LEGACY_MEASURE`]. Version 2 left out everything but analytics,
+//! counters and weights.
+//!
+//! Daily totals per continent are never exported: they only count lookups
+//! made where they are kept, see [`crate::Locat::get_totals_by_day`].
+
+use std::{
+    collections::HashMap,
+    mem,
+    time::{Duration, UNIX_EPOCH},
+};
+
+use crate::{sketch::Sketch, Error, Measures, SubnetCount};
+
+const MAGIC: &str = "locat-export";
+const FORMAT_VERSION: u32 = 3;
+
+/// The name given to weights recorded before measures had names
+pub(crate) const LEGACY_MEASURE: &str = "weight";
+
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct Export {
+    pub(crate) analytics: Vec<(String, Measures)>,
+    pub(crate) counters: Vec<(String, u64)>,
+    pub(crate) weights: Vec<Weight>,
+    /// `(iso_code, bucket_start, count)`, see [`crate::Locat::with_time_buckets`]
+    pub(crate) buckets: Vec<(String, u64, u64)>,
+    pub(crate) subnets: Vec<SubnetCount>,
+    /// Response times in microseconds, see [`crate::Locat::record_latency`]
+    pub(crate) latencies: Vec<(String, Sketch)>,
+    /// `(iso_code, class, count)`, see [`crate::Locat::record_status`]
+    pub(crate) statuses: Vec<(String, String, u64)>,
+}
+
+/// One weighted measure of one country, see [`crate::Locat::record_weighted`]
+#[derive(Debug, PartialEq)]
+pub(crate) struct Weight {
+    pub(crate) iso_code: String,
+    pub(crate) measure: String,
+    pub(crate) measures: Measures,
+    pub(crate) sketch: Sketch,
+}
+
+impl Export {
+    pub(crate) fn render(&self) -> String {
+        let mut out = format!("{MAGIC}\t{FORMAT_VERSION}\n");
+
+        out.push_str("table\tanalytics\tiso_code\tcount\tsum\n");
+        for (iso_code, measures) in &self.analytics {
+            out.push_str(&format!(
+                "{iso_code}\t{}\t{}\n",
+                measures.count, measures.sum
+            ));
+        }
+
+        out.push_str("table\tcounters\tname\tcount\n");
+        for (name, count) in &self.counters {
+            out.push_str(&format!("{name}\t{count}\n"));
+        }
+
+        out.push_str("table\tweights\tiso_code\tmeasure\tcount\tsum\tsketch\n");
+        for weight in &self.weights {
+            out.push_str(&format!(
+                "{}\t{}\t{}\t{}\t{}\n",
+                weight.iso_code,
+                weight.measure,
+                weight.measures.count,
+                weight.measures.sum,
+                encode_hex(&weight.sketch.to_bytes())
+            ));
+        }
+
+        out.push_str("table\tbuckets\tiso_code\tbucket_start\tcount\n");
+        for (iso_code, bucket_start, count) in &self.buckets {
+            out.push_str(&format!("{iso_code}\t{bucket_start}\t{count}\n"));
+        }
+
+        out.push_str("table\tsubnets\tsubnet\tiso_code\tcount\tlast_seen\n");
+        for subnet in &self.subnets {
+            let last_seen = subnet
+                .last_seen
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            out.push_str(&format!(
+                "{}\t{}\t{}\t{last_seen}\n",
+                subnet.subnet, subnet.iso_code, subnet.count
+            ));
+        }
+
+        out.push_str("table\tlatencies\tiso_code\tsketch\n");
+        for (iso_code, sketch) in &self.latencies {
+            out.push_str(&format!("{iso_code}\t{}\n", encode_hex(&sketch.to_bytes())));
+        }
+
+        out.push_str("table\tstatuses\tiso_code\tclass\tcount\n");
+        for (iso_code, class, count) in &self.statuses {
+            out.push_str(&format!("{iso_code}\t{class}\t{count}\n"));
+        }
+
+        out
+    }
+
+    pub(crate) fn parse(data: &str) -> Result<Self, Error> {
+        let mut lines = data.lines();
+        let header = lines.next().unwrap_or_default();
+        let version = match header.split_once('\t') {
+            Some((MAGIC, version)) => match version.parse::<u32>() {
+                Ok(version) if version <= FORMAT_VERSION => version,
+                Ok(_) => return Err(invalid("export format is newer than this version")),
+                Err(_) => return Err(invalid("missing export header")),
+            },
+            _ => return Err(invalid("missing export header")),
+        };
+
+        let mut export = Export::default();
+        let mut legacy_sketches = HashMap::new();
+        let mut table: Option<(String, HashMap<String, usize>)> = None;
+        for line in lines.filter(|line| !line.is_empty()) {
+            let fields: Vec<&str> = line.split('\t').collect();
+            if fields[0] == "table" {
+                let name = fields.get(1).ok_or_else(|| invalid("unnamed table"))?;
+                let columns = fields[2..]
+                    .iter()
+                    .enumerate()
+                    .map(|(i, column)| (column.to_string(), i))
+                    .collect();
+                table = Some((name.to_string(), columns));
+                continue;
+            }
+
+            let Some((name, columns)) = &table else {
+                return Err(invalid("row outside of a table"));
+            };
+            let text = |column: &str| -> Result<String, Error> {
+                columns
+                    .get(column)
+                    .and_then(|i| fields.get(*i))
+                    .map(|value| value.to_string())
+                    .ok_or_else(|| invalid(&format!("{name} row without {column}")))
+            };
+            let number = |column: &str| -> Result<u64, Error> {
+                match columns.get(column).and_then(|i| fields.get(*i)) {
+                    Some(value) => value
+                        .parse()
+                        .map_err(|_| invalid(&format!("bad {column} in {name}: {value:?}"))),
+                    None => Ok(0),
+                }
+            };
+
+            match name.as_str() {
+                "analytics" => export.analytics.push((
+                    text("iso_code")?,
+                    Measures {
+                        count: number("count")?,
+                        sum: number("sum")?,
+                    },
+                )),
+                "counters" => export.counters.push((text("name")?, number("count")?)),
+                "weights" => export.weights.push(Weight {
+                    iso_code: text("iso_code")?,
+                    measure: text("measure")?,
+                    measures: Measures {
+                        count: number("count")?,
+                        sum: number("sum")?,
+                    },
+                    sketch: sketch(&text("sketch")?)?,
+                }),
+                "buckets" => export.buckets.push((
+                    text("iso_code")?,
+                    number("bucket_start")?,
+                    number("count")?,
+                )),
+                "subnets" => export.subnets.push(SubnetCount {
+                    subnet: text("subnet")?,
+                    iso_code: text("iso_code")?,
+                    count: number("count")?,
+                    last_seen: UNIX_EPOCH + Duration::from_secs(number("last_seen")?),
+                }),
+                "latencies" => export
+                    .latencies
+                    .push((text("iso_code")?, sketch(&text("sketch")?)?)),
+                "statuses" => {
+                    export
+                        .statuses
+                        .push((text("iso_code")?, text("class")?, number("count")?))
+                }
+                "sketches" if version < 2 => {
+                    legacy_sketches.insert(text("iso_code")?, sketch(&text("sketch")?)?);
+                }
+                // written by a newer version, nothing we can do with it
+                _ => {}
+            }
+        }
+
+        if version < 2 {
+            // the legacy measure's events stay counted as lookups, like in
+            // the analytics database itself
+            for (iso_code, measures) in &mut export.analytics {
+                let sketch = legacy_sketches.remove(iso_code.as_str());
+                if measures.sum == 0 && sketch.is_none() {
+                    continue;
+                }
+                let sketch = sketch.unwrap_or_default();
+                export.weights.push(Weight {
+                    iso_code: iso_code.clone(),
+                    measure: LEGACY_MEASURE.to_owned(),
+                    measures: Measures {
+                        count: sketch.count(),
+                        sum: mem::take(&mut measures.sum),
+                    },
+                    sketch,
+                });
+            }
+        }
+
+        Ok(export)
+    }
+}
+
+fn sketch(hex: &str) -> Result<Sketch, Error> {
+    decode_hex(hex)
+        .and_then(|bytes| Sketch::from_bytes(&bytes))
+        .ok_or_else(|| invalid(&format!("bad sketch: {hex:?}")))
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn invalid(reason: &str) -> Error {
+    Error::InvalidExport(reason.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{encode_hex, Export, Weight, LEGACY_MEASURE};
+    use crate::{sketch::Sketch, Measures, SubnetCount};
+
+    #[test]
+    fn test_roundtrip() {
+        let mut sketch = Sketch::default();
+        sketch.insert(1500);
+        let export = Export {
+            analytics: vec![("US".to_string(), Measures { count: 2, sum: 0 })],
+            counters: vec![("bogon".to_string(), 3)],
+            weights: vec![Weight {
+                iso_code: "US".to_string(),
+                measure: "bytes".to_string(),
+                measures: Measures {
+                    count: 1,
+                    sum: 1500,
+                },
+                sketch: sketch.clone(),
+            }],
+            buckets: vec![("US".to_string(), 1_700_000_000, 2)],
+            subnets: vec![SubnetCount {
+                subnet: "1.2.3.0/24".to_string(),
+                iso_code: "US".to_string(),
+                count: 2,
+                last_seen: UNIX_EPOCH + Duration::from_secs(1_700_003_599),
+            }],
+            latencies: vec![("US".to_string(), sketch)],
+            statuses: vec![("US".to_string(), "2xx".to_string(), 2)],
+        };
+        assert_eq!(Export::parse(&export.render()).unwrap(), export);
+    }
+
+    #[test]
+    fn test_legacy_weights() {
+        let mut sketch = Sketch::default();
+        sketch.insert(1500);
+        let hex = encode_hex(&sketch.to_bytes());
+        let data = format!(
+            "locat-export\t1\n\
+            table\tanalytics\tiso_code\tcount\tsum\n\
+            US\t2\t1500\n\
+            FR\t1\t0\n\
+            table\tsketches\tiso_code\tsketch\n\
+            US\t{hex}\n"
+        );
+        let export = Export::parse(&data).unwrap();
+        assert_eq!(
+            export.analytics,
+            vec![
+                ("US".to_string(), Measures { count: 2, sum: 0 }),
+                ("FR".to_string(), Measures { count: 1, sum: 0 }),
+            ]
+        );
+        assert_eq!(
+            export.weights,
+            vec![Weight {
+                iso_code: "US".to_string(),
+                measure: LEGACY_MEASURE.to_string(),
+                measures: Measures {
+                    count: 1,
+                    sum: 1500,
+                },
+                sketch,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_schema_evolution() {
+        // an older export without sums, and a newer one with columns and
+        // tables we don't know about
+        let data = "locat-export\t2\n\
+            table\tanalytics\tcount\tiso_code\n\
+            4\tFR\n\
+            table\tanalytics\tiso_code\tcount\tsum\tflavor\n\
+            DE\t1\t7\tvanilla\n\
+            table\tfuture\tstuff\n\
+            whatever\n";
+        let export = Export::parse(data).unwrap();
+        assert_eq!(
+            export.analytics,
+            vec![
+                ("FR".to_string(), Measures { count: 4, sum: 0 }),
+                ("DE".to_string(), Measures { count: 1, sum: 7 }),
+            ]
+        );
+
+        assert!(Export::parse("not an export").is_err());
+        assert!(Export::parse("locat-export\t4\n").is_err());
+        assert!(Export::parse("locat-export\t1\nUS\t1\n").is_err());
+    }
+}
@@ -0,0 +1,178 @@
+use crate::{CountryCode, Error, Locat};
+
+/// Which countries [`Locat::get_analytics_filtered`] returns, and in what
+/// order. The default returns everything, most looked-up first.
+///
+/// ```
+/// # use locat::AnalyticsQuery;
+/// // a "top 10 countries" widget
+/// let query = AnalyticsQuery {
+///     top_n: Some(10),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AnalyticsQuery {
+    /// At most this many countries
+    pub top_n: Option<usize>,
+    /// Leaves out countries with fewer lookups
+    pub min_count: u64,
+    pub order_by: OrderBy,
+    /// Skips this many countries first, for pagination
+    pub offset: usize,
+    /// Only these countries, all of them when empty. User input can be
+    /// turned into codes with [`CountryCode::parse_lenient`].
+    pub countries: Vec<CountryCode>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[non_exhaustive]
+pub enum OrderBy {
+    /// Most looked-up first, ties by country code
+    #[default]
+    CountDesc,
+    /// Least looked-up first, ties by country code
+    CountAsc,
+    /// Alphabetically by country code
+    IsoCode,
+}
+
+impl OrderBy {
+    pub(crate) fn sql(self) -> &'static str {
+        match self {
+            OrderBy::CountDesc => "count DESC, iso_code",
+            OrderBy::CountAsc => "count ASC, iso_code",
+            OrderBy::IsoCode => "iso_code",
+        }
+    }
+}
+
+impl AnalyticsQuery {
+    fn includes(&self, iso_code: &str) -> bool {
+        self.countries.is_empty() || self.countries.iter().any(|code| code.as_str() == iso_code)
+    }
+}
+
+impl Locat {
+    /// Like [`Locat::get_analytics`], with filtering, sorting and
+    /// pagination done by SQLite rather than in memory. Other stores return
+    /// the same rows, computed in memory.
+    pub async fn get_analytics_filtered(
+        &self,
+        query: &AnalyticsQuery,
+    ) -> Result<Vec<(String, u64)>, Error> {
+        if let Some(db) = &self.inner.sqlite {
+            return Ok(db.query_analytics(query.clone()).await?);
+        }
+
+        let mut analytics: Vec<_> = self
+            .get_analytics()
+            .await?
+            .into_iter()
+            .filter(|(iso_code, count)| *count >= query.min_count && query.includes(iso_code))
+            .collect();
+        match query.order_by {
+            OrderBy::CountDesc => {
+                analytics.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)))
+            }
+            OrderBy::CountAsc => {
+                analytics.sort_by(|a, b| a.1.cmp(&b.1).then_with(|| a.0.cmp(&b.0)))
+            }
+            OrderBy::IsoCode => analytics.sort_by(|a, b| a.0.cmp(&b.0)),
+        }
+        Ok(analytics
+            .into_iter()
+            .skip(query.offset)
+            .take(query.top_n.unwrap_or(usize::MAX))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AnalyticsQuery, OrderBy};
+    use crate::{
+        testing::{RemoveOnDrop, TestDb},
+        CountryCode, Locat, Measures, MemoryStore,
+    };
+
+    #[tokio::test]
+    async fn test_get_analytics_filtered() {
+        let geoip_path = "/tmp/loca-test-query.mmdb";
+        let analytics_path = "/tmp/loca-test-query.db";
+        TestDb::new()
+            .country("1.2.3.0/24", "US", "NA")
+            .write(geoip_path);
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
+
+        let sqlite = Locat::new(geoip_path, analytics_path).await.unwrap();
+        let memory = Locat::from_store(geoip_path, MemoryStore::new())
+            .await
+            .unwrap();
+        for (iso_code, count) in [("US", 5), ("DE", 3), ("FR", 3), ("JP", 1)] {
+            let measures = Measures { count, sum: 0 };
+            for locat in [&sqlite, &memory] {
+                locat
+                    .inner
+                    .analytics
+                    .add(&[(iso_code.to_owned(), measures)], &[])
+                    .await
+                    .unwrap();
+            }
+        }
+
+        let cases = [
+            (
+                AnalyticsQuery {
+                    top_n: Some(2),
+                    ..Default::default()
+                },
+                vec![("US", 5), ("DE", 3)],
+            ),
+            (
+                AnalyticsQuery {
+                    top_n: Some(2),
+                    offset: 1,
+                    order_by: OrderBy::CountAsc,
+                    ..Default::default()
+                },
+                vec![("DE", 3), ("FR", 3)],
+            ),
+            (
+                AnalyticsQuery {
+                    min_count: 3,
+                    order_by: OrderBy::IsoCode,
+                    ..Default::default()
+                },
+                vec![("DE", 3), ("FR", 3), ("US", 5)],
+            ),
+            (
+                AnalyticsQuery {
+                    countries: ["deu", "us", "japan"]
+                        .into_iter()
+                        .filter_map(|input| CountryCode::parse_lenient(input).ok())
+                        .collect(),
+                    ..Default::default()
+                },
+                vec![("US", 5), ("DE", 3)],
+            ),
+        ];
+        for (query, expected) in cases {
+            let expected: Vec<_> = expected
+                .into_iter()
+                .map(|(iso_code, count)| (iso_code.to_string(), count))
+                .collect();
+            for locat in [&sqlite, &memory] {
+                assert_eq!(
+                    locat.get_analytics_filtered(&query).await.unwrap(),
+                    expected
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,215 @@
+use std::{
+    collections::HashSet,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime},
+};
+
+use arc_swap::ArcSwap;
+use tokio::task::AbortHandle;
+
+use crate::{CountryCode, Error, GeoIp, Locat, Resolution, Resolver};
+
+/// A resolver that always uses the GeoIP database [`Locat`] currently has
+/// loaded, see [`Locat::live_geoip`]
+#[derive(Clone)]
+pub struct LiveGeoIp {
+    geoip: Arc<ArcSwap<GeoIp>>,
+}
+
+impl Resolver for LiveGeoIp {
+    fn resolve(&self, addr: IpAddr) -> Option<Resolution> {
+        self.geoip.load().resolve(addr)
+    }
+}
+
+impl Locat {
+    /// Reads the GeoIP database again from the path it was loaded from, and
+    /// swaps it in. Lookups in flight finish with the old one. If the file
+    /// can't be read, the old one stays in use. Failing to note the reload
+    /// in the audit log is only logged, since the new one is in use by then.
+    pub async fn reload_geoip(&self) -> Result<(), Error> {
+        let geoip = self.open_geoip(&self.inner.geoip_path).await?;
+        self.inner.geoip.store(Arc::new(geoip));
+        if let Some(cache) = &self.inner.cache {
+            cache.clear();
+        }
+        #[cfg(feature = "analytics")]
+        if let Err(e) = self
+            .audit("reload_geoip", Some(self.inner.geoip_path.clone()))
+            .await
+        {
+            self.log(format!("Could not audit GeoIP reload: {e}"));
+        }
+        Ok(())
+    }
+
+    /// Checks every `interval` whether the GeoIP file was modified, and
+    /// reloads it if so, see [`Locat::reload_geoip`]. The task ends once
+    /// every clone of this `Locat` is dropped, or on [`Locat::shutdown`].
+    /// Without a file system (see [`crate::LocatBuilder::with_filesystem`])
+    /// there is nothing to watch, and the task ends right away.
+    pub fn watch_geoip(&self, interval: Duration) -> AbortHandle {
+        let inner = Arc::downgrade(&self.inner);
+        let path = self.inner.geoip_path.clone();
+        let filesystem = self.inner.filesystem;
+        self.spawn("watch_geoip", |mut stop| async move {
+            if !filesystem {
+                return;
+            }
+            let mut loaded = modified(&path).await;
+            let mut ticks = tokio::time::interval(interval);
+            loop {
+                tokio::select! {
+                    _ = ticks.tick() => {}
+                    _ = stop.requested() => return,
+                }
+                let Some(inner) = inner.upgrade() else {
+                    return;
+                };
+                let current = modified(&path).await;
+                if current == loaded {
+                    continue;
+                }
+                // a file that's still being written doesn't parse: keep the
+                // old database and try again on the next tick
+                if (Locat { inner }).reload_geoip().await.is_ok() {
+                    loaded = current;
+                }
+            }
+        })
+    }
+
+    /// Country codes that outlive the database they were read from, which
+    /// may be reloaded while they're in use: borrowed from the ISO 3166
+    /// table, or for the few databases with codes outside of it, from a set
+    /// that this `Locat` keeps
+    pub(crate) fn intern(&self, code: &str) -> &str {
+        if let Ok(code) = code.parse::<CountryCode>() {
+            return code.as_str();
+        }
+        let mut others = self.inner.other_codes.0.lock().unwrap();
+        if !others.contains(code) {
+            others.insert(code.into());
+        }
+        let code: *const str = &**others.get(code).unwrap();
+        // SAFETY: codes are boxed, so they don't move along with the set,
+        // and never removed, so they live as long as `self`
+        unsafe { &*code }
+    }
+
+    /// A resolver for chains (see [`Locat::with_resolver`]) that follows
+    /// [`Locat::reload_geoip`], unlike [`Locat::geoip`]
+    pub fn live_geoip(&self) -> LiveGeoIp {
+        LiveGeoIp {
+            geoip: self.inner.geoip.clone(),
+        }
+    }
+}
+
+/// See [`Locat::intern`]
+#[derive(Default)]
+pub(crate) struct OtherCodes(Mutex<HashSet<Box<str>>>);
+
+async fn modified(path: &str) -> Option<SystemTime> {
+    tokio::fs::metadata(path).await.ok()?.modified().ok()
+}
+
+#[cfg(all(test, feature = "analytics"))]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        testing::{RemoveOnDrop, TestDb},
+        CountryCode, Locat, Resolver,
+    };
+
+    #[tokio::test]
+    async fn test_reload_geoip() {
+        let geoip_path = "/tmp/loca-test-reload.mmdb";
+        let analytics_path = "/tmp/loca-test-reload.db";
+        TestDb::new()
+            .country("1.2.3.0/24", "US", "NA")
+            .write(geoip_path);
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
+
+        let locat = Locat::new(geoip_path, analytics_path).await.unwrap();
+        let live = locat.live_geoip();
+        let snapshot = locat.geoip();
+        let addr = "1.2.3.4".parse().unwrap();
+        assert_eq!(locat.ip_to_iso_code(addr).await, Some("US"));
+
+        TestDb::new()
+            .country("1.2.3.0/24", "DE", "EU")
+            .write(geoip_path);
+        locat.reload_geoip().await.unwrap();
+        assert_eq!(locat.ip_to_iso_code(addr).await, Some("DE"));
+        assert_eq!(live.resolve(addr).unwrap().iso_code, "DE");
+        assert_eq!(snapshot.resolve(addr).unwrap().iso_code, "US");
+
+        // codes outlive reloads whether they're in the ISO 3166 table or not
+        let known = locat.ip_to_iso_code(addr).await.unwrap();
+        assert!(std::ptr::eq(
+            known,
+            "DE".parse::<CountryCode>().unwrap().as_str()
+        ));
+        TestDb::new()
+            .country("1.2.3.0/24", "EU", "EU")
+            .write(geoip_path);
+        locat.reload_geoip().await.unwrap();
+        let other = locat.ip_to_iso_code(addr).await.unwrap();
+        TestDb::new()
+            .country("1.2.3.0/24", "DE", "EU")
+            .write(geoip_path);
+        locat.reload_geoip().await.unwrap();
+        assert_eq!((known, other), ("DE", "EU"));
+        assert!(std::ptr::eq(other, locat.intern(&String::from("EU"))));
+
+        // a broken file leaves the current database in place
+        std::fs::write(geoip_path, b"not a database").unwrap();
+        assert!(locat.reload_geoip().await.is_err());
+        assert_eq!(locat.ip_to_iso_code(addr).await, Some("DE"));
+    }
+
+    #[tokio::test]
+    async fn test_watch_geoip() {
+        let geoip_path = "/tmp/loca-test-watch.mmdb";
+        let analytics_path = "/tmp/loca-test-watch.db";
+        TestDb::new()
+            .country("1.2.3.0/24", "US", "NA")
+            .write(geoip_path);
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
+
+        let locat = Locat::new(geoip_path, analytics_path).await.unwrap();
+        let watcher = locat.watch_geoip(Duration::from_millis(10));
+        let addr = "1.2.3.4".parse().unwrap();
+
+        // make sure the modification time moves even on coarse filesystems
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        TestDb::new()
+            .country("1.2.3.0/24", "DE", "EU")
+            .write(geoip_path);
+        for _ in 0..200 {
+            if locat.ip_to_iso_code(addr).await == Some("DE") {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(locat.ip_to_iso_code(addr).await, Some("DE"));
+
+        drop(locat);
+        for _ in 0..100 {
+            if watcher.is_finished() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(watcher.is_finished());
+    }
+}
@@ -0,0 +1,124 @@
+use std::{fmt::Write, time::SystemTime};
+
+use crate::{Error, Locat, LookupStats};
+
+/// Everything counted, in one piece for dashboards and APIs, see
+/// [`Locat::analytics_report`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct AnalyticsReport {
+    pub generated_at: SystemTime,
+    /// Most looked-up first, ties by country code
+    pub countries: Vec<CountryRow>,
+    pub stats: LookupStats,
+}
+
+/// One country of an [`AnalyticsReport`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CountryRow {
+    pub iso_code: String,
+    pub count: u64,
+}
+
+impl AnalyticsReport {
+    /// The countries as CSV, with an `iso_code,count` header
+    pub fn to_csv(&self) -> String {
+        let mut out = String::from("iso_code,count\n");
+        for row in &self.countries {
+            // country codes never need quoting
+            _ = writeln!(out, "{},{}", row.iso_code, row.count);
+        }
+        out
+    }
+}
+
+impl Locat {
+    /// Countries and lookup stats at once, as exported: a
+    /// [`crate::Scrubber`] applies
+    pub async fn analytics_report(&self) -> Result<AnalyticsReport, Error> {
+        let export = self.export().await?;
+        let mut countries: Vec<_> = export
+            .analytics
+            .into_iter()
+            .map(|(iso_code, measures)| CountryRow {
+                iso_code,
+                count: measures.count,
+            })
+            .collect();
+        countries.sort_by(|a, b| {
+            b.count
+                .cmp(&a.count)
+                .then_with(|| a.iso_code.cmp(&b.iso_code))
+        });
+        let mut stats = LookupStats {
+            located: countries.iter().map(|row| row.count).sum(),
+            ..Default::default()
+        };
+        for (name, count) in export.counters {
+            match name.as_str() {
+                "bogon" => stats.bogons = count,
+                "not_found" => stats.not_found = count,
+                _ => {}
+            }
+        }
+        Ok(AnalyticsReport {
+            generated_at: SystemTime::now(),
+            countries,
+            stats,
+        })
+    }
+
+    /// [`Locat::analytics_report`] as CSV, see [`AnalyticsReport::to_csv`]
+    pub async fn get_analytics_csv(&self) -> Result<String, Error> {
+        Ok(self.analytics_report().await?.to_csv())
+    }
+
+    #[cfg(feature = "serde")]
+    /// [`Locat::analytics_report`] as JSON
+    pub async fn get_analytics_json(&self) -> Result<String, Error> {
+        let report = self.analytics_report().await?;
+        Ok(serde_json::to_string(&report).expect("reports serialize"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{testing::TestDb, Locat};
+
+    #[tokio::test]
+    async fn test_analytics_report() {
+        let locat = Locat::builder()
+            .with_geoip_bytes(
+                TestDb::new()
+                    .country("1.2.3.0/24", "US", "NA")
+                    .country("5.6.7.0/24", "FR", "EU")
+                    .build(),
+            )
+            .build()
+            .await
+            .unwrap();
+        for addr in ["5.6.7.8", "1.2.3.4", "1.2.3.5", "10.0.0.1"] {
+            locat.ip_to_iso_code(addr.parse().unwrap()).await;
+        }
+        // not a lookup
+        locat.record_weighted("FR", "bytes", 100).await.unwrap();
+
+        let report = locat.analytics_report().await.unwrap();
+        assert_eq!((report.stats.located, report.stats.bogons), (3, 1));
+        assert_eq!(
+            locat.get_analytics_csv().await.unwrap(),
+            "iso_code,count\nUS,2\nFR,1\n"
+        );
+
+        #[cfg(feature = "serde")]
+        {
+            let json = locat.get_analytics_json().await.unwrap();
+            let back: crate::AnalyticsReport = serde_json::from_str(&json).unwrap();
+            assert_eq!(back.countries, report.countries);
+            let code: crate::CountryCode = serde_json::from_str("\"FR\"").unwrap();
+            assert_eq!(serde_json::to_string(&code).unwrap(), "\"FR\"");
+            assert!(serde_json::from_str::<crate::CountryCode>("\"fr\"").is_err());
+        }
+    }
+}
@@ -0,0 +1,639 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fmt,
+    net::IpAddr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex, OnceLock,
+    },
+    time::{Duration, Instant},
+};
+
+use ipnetwork::IpNetwork;
+
+use crate::{Asn, Coordinates, Error, Location, Unwrapped};
+
+/// Why [`crate::Locat::try_ip_to_iso_code`] has no country code
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum LookupError {
+    /// Reserved or unallocated address space (private, loopback, ...), see
+    /// [`crate::Bogons`]
+    #[error("{0} is a bogon")]
+    Bogon(IpAddr),
+
+    #[error("{0} is not in the database")]
+    NotFound(IpAddr),
+
+    /// The address is in the database but not tied to a country, e.g. some
+    /// satellite providers
+    #[error("no country code for {0}")]
+    NoIsoCode(IpAddr),
+
+    /// The database couldn't be read where `addr` should be, it's likely
+    /// corrupt
+    #[error("database error: {0}")]
+    Database(maxminddb::MaxMindDBError),
+
+    /// Refused while analytics can't keep up, see
+    /// [`crate::Overload::Shed`]
+    #[cfg(feature = "analytics")]
+    #[error("analytics are saturated")]
+    Overloaded,
+}
+
+/// Which source resolved an address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
+pub enum Provenance {
+    /// The primary GeoIP database
+    GeoIp,
+    /// A secondary GeoIP database, see [`GeoIp::as_fallback`]
+    Fallback,
+    /// A rule from an [`Overrides`] table
+    Override,
+    /// A [`Cache`], the answer was originally resolved by `source`
+    Cache { source: Source },
+    /// A resolver from outside this crate (an HTTP API, ...), named by
+    /// that resolver
+    Other(&'static str),
+}
+
+/// The non-cache variants of [`Provenance`], what a cached answer came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+#[non_exhaustive]
+pub enum Source {
+    GeoIp,
+    Fallback,
+    Override,
+    Other(&'static str),
+}
+
+/// Whether a location can be acted upon
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub enum Reliability {
+    Reliable,
+    /// The address is anycast, announced by the named operator from many
+    /// places at once, so its country says little about where the client
+    /// actually is
+    GeoUnreliable {
+        operator: &'static str,
+    },
+}
+
+/// A country code along with where it came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Resolution {
+    /// ISO 3166-1 alpha-2 country code
+    pub iso_code: String,
+    pub provenance: Provenance,
+    pub reliability: Reliability,
+    /// Set when the address was a 6to4 or Teredo address and its embedded
+    /// IPv4 address was resolved instead
+    pub unwrapped: Option<Unwrapped>,
+}
+
+impl Resolution {
+    pub fn new(iso_code: impl Into<String>, provenance: Provenance) -> Self {
+        Self {
+            iso_code: iso_code.into(),
+            provenance,
+            reliability: Reliability::Reliable,
+            unwrapped: None,
+        }
+    }
+
+    pub(crate) fn into_cached(mut self) -> Self {
+        let source = match self.provenance {
+            Provenance::GeoIp => Source::GeoIp,
+            Provenance::Fallback => Source::Fallback,
+            Provenance::Override => Source::Override,
+            Provenance::Other(name) => Source::Other(name),
+            // nested caches: keep the original source
+            Provenance::Cache { source } => source,
+        };
+        self.provenance = Provenance::Cache { source };
+        self
+    }
+}
+
+/// Anything that can turn an address into an ISO 3166-1 alpha-2 country code.
+///
+/// Resolvers compose: `overrides.then(geoip).cached(Cache::new(10_000))`
+/// asks the override table first, falls back to the GeoIP database, and
+/// remembers the answers.
+pub trait Resolver: Send + Sync {
+    fn resolve(&self, addr: IpAddr) -> Option<Resolution>;
+
+    /// Asks `other` whenever this resolver has no answer
+    fn then<R: Resolver>(self, other: R) -> Then<Self, R>
+    where
+        Self: Sized,
+    {
+        Then {
+            first: self,
+            second: other,
+        }
+    }
+
+    /// Remembers answers (including misses) in `cache`
+    fn cached(self, cache: Cache) -> Cached<Self>
+    where
+        Self: Sized,
+    {
+        Cached { inner: self, cache }
+    }
+}
+
+impl<R: Resolver + ?Sized> Resolver for Box<R> {
+    fn resolve(&self, addr: IpAddr) -> Option<Resolution> {
+        (**self).resolve(addr)
+    }
+}
+
+/// The bytes of a database
+pub(crate) enum DatabaseBytes {
+    Memory(Vec<u8>),
+    #[cfg(any(feature = "embedded", feature = "mmap"))]
+    Mapped(memmap2::Mmap),
+}
+
+impl AsRef<[u8]> for DatabaseBytes {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            DatabaseBytes::Memory(data) => data,
+            #[cfg(any(feature = "embedded", feature = "mmap"))]
+            DatabaseBytes::Mapped(map) => map,
+        }
+    }
+}
+
+impl From<Vec<u8>> for DatabaseBytes {
+    fn from(data: Vec<u8>) -> Self {
+        DatabaseBytes::Memory(data)
+    }
+}
+
+/// The GeoIP country database loaded by [`crate::Locat`].
+///
+/// Cloning is cheap, the database is shared.
+#[derive(Clone)]
+pub struct GeoIp {
+    pub(crate) reader: Arc<maxminddb::Reader<DatabaseBytes>>,
+    pub(crate) provenance: Provenance,
+    countries: Arc<OnceLock<BTreeSet<String>>>,
+}
+
+impl GeoIp {
+    pub(crate) fn new(reader: maxminddb::Reader<DatabaseBytes>) -> Self {
+        Self {
+            reader: Arc::new(reader),
+            provenance: Provenance::GeoIp,
+            countries: Default::default(),
+        }
+    }
+
+    pub(crate) fn load(data: impl Into<DatabaseBytes>) -> Result<Self, Error> {
+        Ok(Self::new(maxminddb::Reader::from_source(data.into())?))
+    }
+
+    #[cfg(feature = "mmap")]
+    /// Maps the database at `path` into memory rather than reading it:
+    /// pages are loaded as lookups touch them, and shared with other
+    /// processes mapping the same file.
+    ///
+    /// The file must be replaced (e.g. renamed over), never modified in
+    /// place, while it's mapped.
+    pub fn open_mmap(path: impl AsRef<std::path::Path>) -> Result<Self, Error> {
+        let file = std::fs::File::open(path)?;
+        // SAFETY: changing the file in place is documented as not allowed,
+        // maxminddb's own `Reader::open_mmap` relies on the same
+        let map = unsafe { memmap2::Mmap::map(&file)? };
+        Self::load(DatabaseBytes::Mapped(map))
+    }
+
+    /// Reports answers from this database as [`Provenance::Fallback`], for
+    /// when it backs up another one in a chain
+    pub fn as_fallback(mut self) -> Self {
+        self.provenance = Provenance::Fallback;
+        self
+    }
+
+    /// Every country code this database can return. The first call walks
+    /// the whole database, which takes a moment with a full-size one; the
+    /// result is kept for later calls.
+    pub fn available_countries(&self) -> &BTreeSet<String> {
+        self.countries.get_or_init(|| {
+            self.networks()
+                .filter_map(|(_, country)| Some(country.country?.iso_code?.to_owned()))
+                .collect()
+        })
+    }
+
+    /// Every network in the database along with its country record
+    pub(crate) fn networks(
+        &self,
+    ) -> impl Iterator<Item = (IpNetwork, maxminddb::geoip2::Country<'_>)> {
+        let all = if self.reader.metadata.ip_version == 6 {
+            "::/0"
+        } else {
+            "0.0.0.0/0"
+        };
+        self.reader
+            .within(all.parse().unwrap())
+            .into_iter()
+            .flatten()
+            // records that don't look like countries (ASN databases, ...)
+            .filter_map(Result::ok)
+            .map(|item| (item.ip_net, item.info))
+    }
+
+    pub(crate) fn lookup(&self, addr: IpAddr) -> Option<&str> {
+        self.try_lookup(addr).ok()
+    }
+
+    /// Like `lookup`, telling apart why there's no country code. Bogons
+    /// are left to the caller.
+    pub(crate) fn try_lookup(&self, addr: IpAddr) -> Result<&str, LookupError> {
+        match self.reader.lookup::<maxminddb::geoip2::Country>(addr) {
+            Ok(country) => country
+                .country
+                .and_then(|country| country.iso_code)
+                .ok_or(LookupError::NoIsoCode(addr)),
+            Err(maxminddb::MaxMindDBError::AddressNotFoundError(_)) => {
+                Err(LookupError::NotFound(addr))
+            }
+            Err(e) => Err(LookupError::Database(e)),
+        }
+    }
+
+    /// Only City databases have coordinates, this is `None` with a Country
+    /// database
+    pub(crate) fn lookup_coordinates(&self, addr: IpAddr) -> Option<Coordinates> {
+        self.lookup_location(addr).map(|(at, _)| at)
+    }
+
+    /// Coordinates along with their accuracy radius in kilometers
+    pub(crate) fn lookup_location(&self, addr: IpAddr) -> Option<(Coordinates, f64)> {
+        let location = self
+            .reader
+            .lookup::<maxminddb::geoip2::City>(addr)
+            .ok()?
+            .location?;
+        let at = Coordinates::new(location.latitude?, location.longitude?);
+        Some((at, location.accuracy_radius.map_or(0.0, f64::from)))
+    }
+
+    /// Everything a City database has on `addr`. Country databases only
+    /// fill in the country.
+    pub(crate) fn lookup_full_location(&self, addr: IpAddr) -> Option<Location> {
+        let city = self.reader.lookup::<maxminddb::geoip2::City>(addr).ok()?;
+        let location = city.location.as_ref();
+        Some(Location {
+            iso_code: city
+                .country
+                .and_then(|country| country.iso_code)
+                .map(str::to_owned),
+            subdivision: city
+                .subdivisions
+                .and_then(|subdivisions| subdivisions.first()?.iso_code)
+                .map(str::to_owned),
+            city: city
+                .city
+                .and_then(|city| city.names?.get("en").copied())
+                .map(str::to_owned),
+            coordinates: location.and_then(|location| {
+                Some(Coordinates::new(location.latitude?, location.longitude?))
+            }),
+            accuracy_km: location
+                .and_then(|location| location.accuracy_radius)
+                .map(f64::from),
+            time_zone: location
+                .and_then(|location| location.time_zone)
+                .map(str::to_owned),
+        })
+    }
+
+    /// ASN databases have the number at the top level, Enterprise
+    /// databases have it in the traits
+    pub(crate) fn lookup_asn(&self, addr: IpAddr) -> Option<Asn> {
+        if let Ok(maxminddb::geoip2::Asn {
+            autonomous_system_number: Some(number),
+            autonomous_system_organization,
+        }) = self.reader.lookup(addr)
+        {
+            return Some(Asn {
+                number,
+                organization: autonomous_system_organization.map(str::to_owned),
+            });
+        }
+        let traits = self
+            .reader
+            .lookup::<maxminddb::geoip2::Enterprise>(addr)
+            .ok()?
+            .traits?;
+        Some(Asn {
+            number: traits.autonomous_system_number?,
+            organization: traits.autonomous_system_organization.map(str::to_owned),
+        })
+    }
+
+    pub(crate) fn lookup_country(&self, addr: IpAddr) -> Option<maxminddb::geoip2::Country<'_>> {
+        self.reader.lookup(addr).ok()
+    }
+}
+
+impl fmt::Debug for GeoIp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let metadata = &self.reader.metadata;
+        f.debug_struct("GeoIp")
+            .field("database_type", &metadata.database_type)
+            .field("build_epoch", &metadata.build_epoch)
+            .field("node_count", &metadata.node_count)
+            .field("provenance", &self.provenance)
+            .finish()
+    }
+}
+
+impl Resolver for GeoIp {
+    fn resolve(&self, addr: IpAddr) -> Option<Resolution> {
+        let iso_code = self.lookup(addr)?;
+        Some(Resolution::new(iso_code, self.provenance))
+    }
+}
+
+/// A static table of networks pinned to a country, for correcting the
+/// database where we know better. The most specific network wins.
+#[derive(Default, Clone)]
+pub struct Overrides {
+    pub(crate) rules: Vec<(IpNetwork, String)>,
+}
+
+impl Overrides {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a rule resolving every address in `network` to `iso_code`
+    pub fn rule(mut self, network: IpNetwork, iso_code: impl Into<String>) -> Self {
+        self.rules.push((network, iso_code.into()));
+        self
+    }
+}
+
+impl Resolver for Overrides {
+    fn resolve(&self, addr: IpAddr) -> Option<Resolution> {
+        self.rules
+            .iter()
+            .filter(|(network, _)| network.contains(addr))
+            .max_by_key(|(network, _)| network.prefix())
+            .map(|(_, iso_code)| Resolution::new(iso_code.as_str(), Provenance::Override))
+    }
+}
+
+/// See [`Resolver::then`]
+pub struct Then<A, B> {
+    first: A,
+    second: B,
+}
+
+impl<A: Resolver, B: Resolver> Resolver for Then<A, B> {
+    fn resolve(&self, addr: IpAddr) -> Option<Resolution> {
+        self.first
+            .resolve(addr)
+            .or_else(|| self.second.resolve(addr))
+    }
+}
+
+/// A bounded cache of resolved addresses. Once full, the least recently
+/// used entry is evicted first.
+pub struct Cache {
+    capacity: usize,
+    ttl: Option<Duration>,
+    entries: Mutex<CacheEntries>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+#[derive(Default)]
+struct CacheEntries {
+    map: HashMap<IpAddr, CacheEntry>,
+    // entries by when they were last used, least recent first
+    order: BTreeMap<u64, IpAddr>,
+    tick: u64,
+}
+
+struct CacheEntry {
+    resolution: Option<Resolution>,
+    inserted: Instant,
+    used: u64,
+}
+
+/// How well a [`Cache`] does, see [`crate::Locat::cache_stats`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Addresses cached right now
+    pub len: usize,
+}
+
+impl Cache {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            ttl: None,
+            entries: Default::default(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Forgets entries `ttl` after they were cached, e.g. to pick up
+    /// changes to overrides
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            len: self.entries.lock().unwrap().map.len(),
+        }
+    }
+
+    pub(crate) fn get(&self, addr: IpAddr) -> Option<Option<Resolution>> {
+        let mut guard = self.entries.lock().unwrap();
+        let entries = &mut *guard;
+        let expired = match entries.map.get(&addr) {
+            Some(entry) => self.ttl.is_some_and(|ttl| entry.inserted.elapsed() >= ttl),
+            None => {
+                self.misses.fetch_add(1, Ordering::Relaxed);
+                return None;
+            }
+        };
+        if expired {
+            if let Some(entry) = entries.map.remove(&addr) {
+                entries.order.remove(&entry.used);
+            }
+            self.misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+
+        entries.tick += 1;
+        let entry = entries.map.get_mut(&addr)?;
+        entries.order.remove(&entry.used);
+        entry.used = entries.tick;
+        entries.order.insert(entry.used, addr);
+        self.hits.fetch_add(1, Ordering::Relaxed);
+        Some(entry.resolution.clone())
+    }
+
+    pub(crate) fn insert(&self, addr: IpAddr, resolution: Option<Resolution>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut guard = self.entries.lock().unwrap();
+        let entries = &mut *guard;
+        entries.tick += 1;
+        let entry = CacheEntry {
+            resolution,
+            inserted: Instant::now(),
+            used: entries.tick,
+        };
+        if let Some(old) = entries.map.insert(addr, entry) {
+            entries.order.remove(&old.used);
+        }
+        entries.order.insert(entries.tick, addr);
+        while entries.map.len() > self.capacity {
+            let Some((_, oldest)) = entries.order.pop_first() else {
+                break;
+            };
+            entries.map.remove(&oldest);
+        }
+    }
+
+    /// Forgets every entry, keeping the stats
+    pub(crate) fn clear(&self) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.map.clear();
+        entries.order.clear();
+    }
+}
+
+/// See [`Resolver::cached`]
+pub struct Cached<R> {
+    inner: R,
+    cache: Cache,
+}
+
+impl<R: Resolver> Resolver for Cached<R> {
+    fn resolve(&self, addr: IpAddr) -> Option<Resolution> {
+        if let Some(hit) = self.cache.get(addr) {
+            return hit.map(Resolution::into_cached);
+        }
+
+        let resolution = self.inner.resolve(addr);
+        self.cache.insert(addr, resolution.clone());
+        resolution
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::IpAddr,
+        sync::atomic::{AtomicUsize, Ordering},
+        time::Duration,
+    };
+
+    use super::{Cache, CacheStats, Overrides, Provenance, Resolution, Resolver, Source};
+
+    struct Counting<'a>(&'a AtomicUsize);
+
+    impl Resolver for Counting<'_> {
+        fn resolve(&self, _addr: IpAddr) -> Option<Resolution> {
+            self.0.fetch_add(1, Ordering::SeqCst);
+            Some(Resolution::new("DE", Provenance::Other("counting")))
+        }
+    }
+
+    #[test]
+    fn test_chain() {
+        let calls = AtomicUsize::new(0);
+        let chain = Overrides::new()
+            .rule("10.0.0.0/8".parse().unwrap(), "US")
+            .rule("10.1.0.0/16".parse().unwrap(), "FR")
+            .then(Counting(&calls))
+            .cached(Cache::new(1));
+
+        // most specific override wins
+        assert_eq!(
+            chain.resolve("10.1.2.3".parse().unwrap()),
+            Some(Resolution::new("FR", Provenance::Override))
+        );
+        assert_eq!(
+            chain.resolve("10.2.3.4".parse().unwrap()),
+            Some(Resolution::new("US", Provenance::Override))
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 0);
+
+        // falls through to the second resolver, then hits the cache
+        let addr = "1.2.3.4".parse().unwrap();
+        assert_eq!(
+            chain.resolve(addr),
+            Some(Resolution::new("DE", Provenance::Other("counting")))
+        );
+        assert_eq!(
+            chain.resolve(addr),
+            Some(Resolution::new(
+                "DE",
+                Provenance::Cache {
+                    source: Source::Other("counting")
+                }
+            ))
+        );
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        // capacity 1: resolving another address evicts it
+        chain.resolve("5.6.7.8".parse().unwrap());
+        chain.resolve(addr);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_cache() {
+        let [a, b, c]: [IpAddr; 3] = ["1.1.1.1", "2.2.2.2", "3.3.3.3"].map(|a| a.parse().unwrap());
+        let resolution = Some(Resolution::new("DE", Provenance::GeoIp));
+
+        // least recently used goes first
+        let cache = Cache::new(2);
+        cache.insert(a, resolution.clone());
+        cache.insert(b, resolution.clone());
+        assert!(cache.get(a).is_some());
+        cache.insert(c, resolution.clone());
+        assert!(cache.get(b).is_none());
+        assert!(cache.get(a).is_some());
+        assert!(cache.get(c).is_some());
+        assert_eq!(
+            cache.stats(),
+            CacheStats {
+                hits: 3,
+                misses: 1,
+                len: 2
+            }
+        );
+
+        let cache = Cache::new(2).with_ttl(Duration::ZERO);
+        cache.insert(a, resolution);
+        assert!(cache.get(a).is_none());
+        assert_eq!(cache.stats().len, 0);
+    }
+}
@@ -0,0 +1,215 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use tokio::task::AbortHandle;
+
+use crate::{Error, Locat};
+
+pub(crate) struct Retention {
+    age: Duration,
+    last_prune: Mutex<Option<Instant>>,
+}
+
+impl Locat {
+    /// Deletes time buckets and subnets once they're older than `age`,
+    /// checked at most once an hour while lookups are counted, see
+    /// [`Locat::prune_analytics_older_than`]. Services that sit idle for
+    /// long can add [`Locat::prune_every`].
+    pub fn with_retention(mut self, age: Duration) -> Self {
+        self.inner_mut().retention = Some(Retention {
+            age,
+            last_prune: Mutex::new(None),
+        });
+        self
+    }
+
+    /// Removes every count and counter, with any store that supports it
+    /// (see [`crate::AnalyticsStore::reset`]). Counts still waiting to be
+    /// written are dropped too, and visitors already seen are counted again.
+    pub async fn reset_analytics(&self) -> Result<(), Error> {
+        self.reset_as("reset_analytics").await
+    }
+
+    pub(crate) async fn reset_as(&self, action: &str) -> Result<(), Error> {
+        if let Some(write_behind) = &self.inner.write_behind {
+            write_behind.discard();
+        }
+        self.inner.analytics.reset().await?;
+        // stores without epochs wouldn't get it cleared otherwise
+        if let Some(dedup) = &self.inner.dedup {
+            dedup.clear();
+        }
+        self.check_epoch().await?;
+        self.audit(action, None).await
+    }
+
+    /// Deletes the time buckets (see [`Locat::with_time_buckets`]) and
+    /// subnets (see [`Locat::with_subnet_analytics`]) older than `age`,
+    /// returning how many. Totals aren't dated, so they stay; neither are
+    /// rolled-over files removed. SQLite only.
+    ///
+    /// The file doesn't shrink until [`Locat::vacuum`]. With the `archive`
+    /// feature, rows can be kept elsewhere first, see `Locat::with_archive`.
+    pub async fn prune_analytics_older_than(&self, age: Duration) -> Result<usize, Error> {
+        let db = self.sqlite("prune_analytics_older_than")?;
+        let cutoff = SystemTime::now()
+            .checked_sub(age)
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        #[cfg(feature = "archive")]
+        let pruned = match &self.inner.archive {
+            Some(dir) => {
+                self.filesystem("with_archive")?;
+                db.archive_before(cutoff, dir.clone()).await?
+            }
+            None => db.prune_before(cutoff).await?,
+        };
+        #[cfg(not(feature = "archive"))]
+        let pruned = db.prune_before(cutoff).await?;
+        if pruned > 0 {
+            let detail = format!(
+                "{pruned} rows older than {}",
+                humantime::format_duration(age)
+            );
+            self.audit("prune_analytics", Some(detail)).await?;
+        }
+        Ok(pruned)
+    }
+
+    pub(crate) async fn prune_when_due(&self) {
+        let Some(retention) = &self.inner.retention else {
+            return;
+        };
+        {
+            let mut last_prune = retention.last_prune.lock().unwrap();
+            if last_prune.is_some_and(|at| at.elapsed() < Duration::from_secs(3600)) {
+                return;
+            }
+            // concurrent lookups shouldn't all prune at once
+            *last_prune = Some(Instant::now());
+        }
+        if let Err(e) = self.prune_analytics_older_than(retention.age).await {
+            self.log(format!("Could not prune analytics: {e}"));
+        }
+    }
+
+    /// Prunes what's older than `age` every `interval`, starting now, see
+    /// [`Locat::prune_analytics_older_than`]. The task ends once every
+    /// clone of this `Locat` is dropped, or on [`Locat::shutdown`].
+    pub fn prune_every(&self, age: Duration, interval: Duration) -> AbortHandle {
+        let inner = Arc::downgrade(&self.inner);
+        self.spawn("prune_every", |mut stop| async move {
+            let mut ticks = tokio::time::interval(interval);
+            loop {
+                tokio::select! {
+                    _ = ticks.tick() => {}
+                    _ = stop.requested() => return,
+                }
+                let Some(inner) = inner.upgrade() else {
+                    return;
+                };
+                let locat = Locat { inner };
+                if let Err(e) = locat.prune_analytics_older_than(age).await {
+                    locat.log(format!("Could not prune analytics: {e}"));
+                }
+            }
+        })
+    }
+
+    /// Gives the space of deleted counts back to the file system by
+    /// rewriting the analytics file, which blocks writers until done.
+    /// SQLite only.
+    pub async fn vacuum(&self) -> Result<(), Error> {
+        Ok(self.sqlite("vacuum")?.vacuum().await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use crate::{
+        testing::{RemoveOnDrop, TestDb},
+        Error, Locat, MemoryStore,
+    };
+
+    #[tokio::test]
+    async fn test_retention() {
+        let geoip_path = "/tmp/loca-test-retention.mmdb";
+        let analytics_path = "/tmp/loca-test-retention.db";
+        TestDb::new()
+            .country("1.2.3.0/24", "US", "NA")
+            .write(geoip_path);
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
+
+        let locat = Locat::new(geoip_path, analytics_path)
+            .await
+            .unwrap()
+            .with_time_buckets(Duration::from_secs(1))
+            .with_retention(Duration::from_secs(3600));
+        let addr = "1.2.3.4".parse().unwrap();
+        locat.ip_to_iso_code(addr).await;
+        let all = SystemTime::UNIX_EPOCH..SystemTime::now() + Duration::from_secs(60);
+        assert_eq!(
+            locat.get_analytics_between(all.clone()).await.unwrap(),
+            vec![("US".to_string(), 1)]
+        );
+
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        assert_eq!(
+            locat
+                .prune_analytics_older_than(Duration::from_secs(3600))
+                .await
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            locat
+                .prune_analytics_older_than(Duration::ZERO)
+                .await
+                .unwrap(),
+            1
+        );
+        assert!(locat
+            .get_analytics_between(all.clone())
+            .await
+            .unwrap()
+            .is_empty());
+        // totals stay
+        assert_eq!(
+            locat.get_analytics().await.unwrap(),
+            vec![("US".to_string(), 1)]
+        );
+        locat.vacuum().await.unwrap();
+
+        // in the background
+        locat.ip_to_iso_code(addr).await;
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        let task = locat.prune_every(Duration::ZERO, Duration::from_millis(10));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(locat.get_analytics_between(all).await.unwrap().is_empty());
+        drop(locat);
+        for _ in 0..100 {
+            if task.is_finished() {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(task.is_finished());
+
+        let locat = Locat::from_store(geoip_path, MemoryStore::new())
+            .await
+            .unwrap();
+        locat.ip_to_iso_code(addr).await;
+        locat.reset_analytics().await.unwrap();
+        assert!(locat.get_analytics().await.unwrap().is_empty());
+        assert!(matches!(
+            locat.vacuum().await,
+            Err(Error::Unsupported("vacuum"))
+        ));
+    }
+}
@@ -0,0 +1,164 @@
+use std::{
+    borrow::Cow,
+    ops::Range,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// A file path that may contain date placeholders, rendered in UTC:
+/// `%Y` (year), `%m` (month), `%d` (day), `%H` (hour) and `%%` for a
+/// literal `%`.
+#[derive(Debug, Clone)]
+pub(crate) struct PathTemplate {
+    template: String,
+}
+
+impl PathTemplate {
+    pub(crate) fn new(template: &str) -> Self {
+        Self {
+            template: template.to_owned(),
+        }
+    }
+
+    pub(crate) fn render(&self, at: SystemTime) -> Cow<'_, str> {
+        if !self.template.contains('%') {
+            return Cow::Borrowed(&self.template);
+        }
+
+        let secs = at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let (year, month, day) = civil_from_days((secs / 86400) as i64);
+        let hour = secs % 86400 / 3600;
+
+        let mut out = String::with_capacity(self.template.len() + 8);
+        let mut chars = self.template.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                out.push(c);
+                continue;
+            }
+            match chars.next() {
+                Some('Y') => out.push_str(&format!("{year:04}")),
+                Some('m') => out.push_str(&format!("{month:02}")),
+                Some('d') => out.push_str(&format!("{day:02}")),
+                Some('H') => out.push_str(&format!("{hour:02}")),
+                Some('%') => out.push('%'),
+                // not a placeholder we know, leave it alone
+                Some(other) => {
+                    out.push('%');
+                    out.push(other);
+                }
+                None => out.push('%'),
+            }
+        }
+        Cow::Owned(out)
+    }
+}
+
+impl PathTemplate {
+    /// Every path the template renders to within `range`, in order. A file
+    /// is included as soon as any hour of its period falls in the range.
+    pub(crate) fn paths(&self, range: Range<SystemTime>) -> Vec<String> {
+        let mut paths: Vec<String> = Vec::new();
+        for (_, path) in self.hours(range) {
+            if paths.last() != Some(&path) {
+                paths.push(path);
+            }
+        }
+        paths
+    }
+
+    /// The start of every hour overlapping `range`, with the path it
+    /// renders to
+    pub(crate) fn hours(&self, range: Range<SystemTime>) -> Vec<(SystemTime, String)> {
+        let secs = range
+            .start
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        // no template has a finer granularity than the hour
+        let mut at = UNIX_EPOCH + Duration::from_secs(secs - secs % 3600);
+
+        let mut hours = Vec::new();
+        while at < range.end {
+            hours.push((at, self.render(at).into_owned()));
+            at += Duration::from_secs(3600);
+        }
+        hours
+    }
+
+    /// Whether every hour gets its own file
+    pub(crate) fn is_hourly(&self) -> bool {
+        let hour = Duration::from_secs(3600);
+        self.render(UNIX_EPOCH) != self.render(UNIX_EPOCH + hour)
+    }
+}
+
+/// Turns days since 1970-01-01 into a (year, month, day) date, see
+/// <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{civil_from_days, PathTemplate};
+
+    #[test]
+    fn test_civil_from_days() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(11016), (2000, 2, 29));
+        assert_eq!(civil_from_days(19722), (2023, 12, 31));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+    }
+
+    #[test]
+    fn test_render() {
+        // 2023-06-15 13:20:00 UTC
+        let at = UNIX_EPOCH + Duration::from_secs(1686835200);
+        assert_eq!(
+            PathTemplate::new("analytics-%Y-%m.db").render(at),
+            "analytics-2023-06.db"
+        );
+        assert_eq!(
+            PathTemplate::new("/var/%Y/%m/%d/%H%%.db").render(at),
+            "/var/2023/06/15/13%.db"
+        );
+        assert_eq!(PathTemplate::new("plain.db").render(at), "plain.db");
+    }
+
+    #[test]
+    fn test_paths() {
+        let day = Duration::from_secs(86400);
+        // 2023-06-15 00:00:00 UTC
+        let start = UNIX_EPOCH + Duration::from_secs(1686787200);
+
+        let template = PathTemplate::new("analytics-%Y-%m-%d.db");
+        assert_eq!(
+            template.paths(start..start + day * 2),
+            ["analytics-2023-06-15.db", "analytics-2023-06-16.db"]
+        );
+        assert_eq!(
+            template.paths(start + day / 2..start + day / 2 + day),
+            ["analytics-2023-06-15.db", "analytics-2023-06-16.db"]
+        );
+        assert!(template.paths(start..start).is_empty());
+        assert_eq!(
+            PathTemplate::new("plain.db").paths(start..start + day),
+            ["plain.db"]
+        );
+
+        assert!(!template.is_hourly());
+        assert!(PathTemplate::new("%Y/%m/%d/%H.db").is_hourly());
+        assert!(!PathTemplate::new("%%H.db").is_hourly());
+    }
+}
@@ -0,0 +1,167 @@
+use std::{
+    collections::BTreeMap,
+    ops::Range,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::{Error, Locat};
+
+/// Stands for countries no continent is known for
+pub(crate) const UNKNOWN_CONTINENT: &str = "--";
+
+/// The continent of every country, with the codes GeoIP databases use.
+/// Countries that span two are listed under one.
+pub(crate) const CONTINENTS: &[(&str, &[&str])] = &[
+    (
+        "AF",
+        &[
+            "AO", "BF", "BI", "BJ", "BW", "CD", "CF", "CG", "CI", "CM", "CV", "DJ", "DZ", "EG",
+            "EH", "ER", "ET", "GA", "GH", "GM", "GN", "GQ", "GW", "KE", "KM", "LR", "LS", "LY",
+            "MA", "MG", "ML", "MR", "MU", "MW", "MZ", "NA", "NE", "NG", "RE", "RW", "SC", "SD",
+            "SH", "SL", "SN", "SO", "SS", "ST", "SZ", "TD", "TG", "TN", "TZ", "UG", "YT", "ZA",
+            "ZM", "ZW",
+        ],
+    ),
+    ("AN", &["AQ", "BV", "GS", "HM", "TF"]),
+    (
+        "AS",
+        &[
+            "AE", "AF", "AM", "AZ", "BD", "BH", "BN", "BT", "CC", "CN", "CX", "CY", "GE", "HK",
+            "ID", "IL", "IN", "IO", "IQ", "IR", "JO", "JP", "KG", "KH", "KP", "KR", "KW", "KZ",
+            "LA", "LB", "LK", "MM", "MN", "MO", "MV", "MY", "NP", "OM", "PH", "PK", "PS", "QA",
+            "SA", "SG", "SY", "TH", "TJ", "TL", "TM", "TR", "TW", "UZ", "VN", "YE",
+        ],
+    ),
+    (
+        "EU",
+        &[
+            "AD", "AL", "AT", "AX", "BA", "BE", "BG", "BY", "CH", "CZ", "DE", "DK", "EE", "ES",
+            "FI", "FO", "FR", "GB", "GG", "GI", "GR", "HR", "HU", "IE", "IM", "IS", "IT", "JE",
+            "LI", "LT", "LU", "LV", "MC", "MD", "ME", "MK", "MT", "NL", "NO", "PL", "PT", "RO",
+            "RS", "RU", "SE", "SI", "SJ", "SK", "SM", "UA", "VA", "XK",
+        ],
+    ),
+    (
+        "NA",
+        &[
+            "AG", "AI", "AW", "BB", "BL", "BM", "BQ", "BS", "BZ", "CA", "CR", "CU", "CW", "DM",
+            "DO", "GD", "GL", "GP", "GT", "HN", "HT", "JM", "KN", "KY", "LC", "MF", "MQ", "MS",
+            "MX", "NI", "PA", "PM", "PR", "SV", "SX", "TC", "TT", "UM", "US", "VC", "VG", "VI",
+        ],
+    ),
+    (
+        "OC",
+        &[
+            "AS", "AU", "CK", "FJ", "FM", "GU", "KI", "MH", "MP", "NC", "NF", "NR", "NU", "NZ",
+            "PF", "PG", "PN", "PW", "SB", "TK", "TO", "TV", "VU", "WF", "WS",
+        ],
+    ),
+    (
+        "SA",
+        &[
+            "AR", "BO", "BR", "CL", "CO", "EC", "FK", "GF", "GY", "PE", "PY", "SR", "UY", "VE",
+        ],
+    ),
+];
+
+impl Locat {
+    /// Lookups per continent (`"EU"`, `"NA"`, ...), from a summary kept up
+    /// to date as they are written, so it stays cheap however many rows
+    /// there are. Countries of no known continent count under `"--"`.
+    /// Imports and corrections (see [`Locat::adjust`]) aren't part of it.
+    /// SQLite only.
+    pub async fn get_analytics_by_continent(&self) -> Result<Vec<(String, u64)>, Error> {
+        Ok(self
+            .sqlite("get_analytics_by_continent")?
+            .list_continent_totals()
+            .await?)
+    }
+
+    /// Lookups per continent of each day (UTC) `range` touches, oldest
+    /// first, from the same summary as [`Locat::get_analytics_by_continent`].
+    /// Lookups count towards the day they were made on, even when
+    /// [`Locat::with_write_behind`] writes them the day after. SQLite only.
+    pub async fn get_totals_by_day(
+        &self,
+        range: Range<SystemTime>,
+    ) -> Result<Vec<(SystemTime, Vec<(String, u64)>)>, Error> {
+        let secs = |at: SystemTime| at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        // every day the range touches
+        let days = secs(range.start) / 86400..secs(range.end).div_ceil(86400);
+        let rows = self
+            .sqlite("get_totals_by_day")?
+            .list_daily_totals(days)
+            .await?;
+        let mut days: BTreeMap<u64, Vec<(String, u64)>> = BTreeMap::new();
+        for (day, continent, count) in rows {
+            days.entry(day).or_default().push((continent, count));
+        }
+        Ok(days
+            .into_iter()
+            .map(|(day, counts)| (UNIX_EPOCH + Duration::from_secs(day * 86400), counts))
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use crate::{
+        testing::{RemoveOnDrop, TestDb},
+        Locat,
+    };
+
+    #[tokio::test]
+    async fn test_rollups() {
+        let geoip_path = "/tmp/loca-test-rollups.mmdb";
+        let analytics_path = "/tmp/loca-test-rollups.db";
+        TestDb::new()
+            .country("1.2.3.0/24", "US", "NA")
+            .country("5.6.7.0/24", "FR", "EU")
+            .write(geoip_path);
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
+
+        let locat = Locat::new(geoip_path, analytics_path)
+            .await
+            .unwrap()
+            .with_write_behind(Duration::from_secs(3600), 1000);
+        for addr in ["1.2.3.4", "1.2.3.5", "5.6.7.8"] {
+            locat.ip_to_iso_code(addr.parse().unwrap()).await;
+        }
+        locat.flush().await.unwrap();
+        locat
+            .transaction(|tx| {
+                tx.increment("ZZ");
+            })
+            .await
+            .unwrap();
+        // corrections and imports only change lifetime counts
+        locat.adjust("US", -1).await.unwrap();
+        locat
+            .import_portable(&locat.export_portable().await.unwrap())
+            .await
+            .unwrap();
+
+        let continents = vec![
+            ("--".to_string(), 1),
+            ("EU".to_string(), 1),
+            ("NA".to_string(), 2),
+        ];
+        assert_eq!(
+            locat.get_analytics_by_continent().await.unwrap(),
+            continents
+        );
+        let now = SystemTime::now();
+        let days = locat
+            .get_totals_by_day(now - Duration::from_secs(86400)..now + Duration::from_secs(86400))
+            .await
+            .unwrap();
+        assert_eq!(days.len(), 1);
+        assert_eq!(days[0].1, continents);
+        locat.shutdown().await;
+    }
+}
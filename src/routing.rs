@@ -0,0 +1,170 @@
+use std::{collections::BTreeMap, net::IpAddr};
+
+use crate::{Coordinates, CountryCode};
+
+/// Maps countries and continents to deployment region labels like
+/// `"eu-west"`.
+///
+/// Country rules win over continent rules. Addresses that can't be located
+/// are spread over all known regions with rendezvous hashing, so a given
+/// address always lands in the same region as long as the set of regions
+/// doesn't change.
+///
+/// Regions can also be placed on the map with [`RegionRouter::site`], for
+/// [`crate::Locat::nearest_region`].
+#[derive(Debug, Clone)]
+pub struct RegionRouter {
+    countries: BTreeMap<String, String>,
+    continents: BTreeMap<String, String>,
+    sites: Vec<(String, Coordinates)>,
+}
+
+impl RegionRouter {
+    /// A router with no rules at all
+    pub fn empty() -> Self {
+        Self {
+            countries: BTreeMap::new(),
+            continents: BTreeMap::new(),
+            sites: Vec::new(),
+        }
+    }
+
+    /// Routes a country to `region`. Any code [`CountryCode::parse_lenient`]
+    /// understands works, e.g. `"UK"` for `GB`.
+    pub fn country(mut self, iso_code: &str, region: impl Into<String>) -> Self {
+        let iso_code = CountryCode::parse_lenient(iso_code)
+            .map_or_else(|_| iso_code.to_ascii_uppercase(), |code| code.to_string());
+        self.countries.insert(iso_code, region.into());
+        self
+    }
+
+    /// Routes a continent (`"EU"`, `"NA"`, ...) to `region`
+    pub fn continent(mut self, code: &str, region: impl Into<String>) -> Self {
+        self.continents
+            .insert(code.to_ascii_uppercase(), region.into());
+        self
+    }
+
+    /// Places `region` at the given coordinates, e.g. where its datacenter is
+    pub fn site(mut self, region: impl Into<String>, at: Coordinates) -> Self {
+        self.sites.push((region.into(), at));
+        self
+    }
+
+    /// Picks the region whose site is closest to `at`, if any sites were
+    /// configured
+    pub fn nearest(&self, at: Coordinates) -> Option<&str> {
+        self.sites
+            .iter()
+            .map(|(region, site)| (region, site.distance_km(&at)))
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(region, _)| region.as_str())
+    }
+
+    /// Picks a region for an address given what the database knows about it
+    pub fn route(
+        &self,
+        addr: IpAddr,
+        iso_code: Option<&str>,
+        continent: Option<&str>,
+    ) -> Option<&str> {
+        iso_code
+            .and_then(|iso_code| self.countries.get(iso_code))
+            .or_else(|| continent.and_then(|code| self.continents.get(code)))
+            .map(String::as_str)
+            .or_else(|| self.hash_route(addr))
+    }
+
+    fn regions(&self) -> impl Iterator<Item = &str> {
+        let mut regions: Vec<&str> = self
+            .countries
+            .values()
+            .chain(self.continents.values())
+            .chain(self.sites.iter().map(|(region, _)| region))
+            .map(String::as_str)
+            .collect();
+        regions.sort_unstable();
+        regions.dedup();
+        regions.into_iter()
+    }
+
+    fn hash_route(&self, addr: IpAddr) -> Option<&str> {
+        let addr = match addr {
+            IpAddr::V4(addr) => addr.octets().to_vec(),
+            IpAddr::V6(addr) => addr.octets().to_vec(),
+        };
+        self.regions()
+            .max_by_key(|region| fnv1a(region.as_bytes().iter().chain(&addr)))
+    }
+}
+
+impl Default for RegionRouter {
+    /// One region per continent, named the way most cloud providers do
+    fn default() -> Self {
+        Self::empty()
+            .continent("NA", "us-east")
+            .continent("SA", "sa-east")
+            .continent("EU", "eu-west")
+            .continent("AF", "eu-west")
+            .continent("AS", "ap-southeast")
+            .continent("OC", "ap-southeast")
+            .continent("AN", "ap-southeast")
+    }
+}
+
+// stable across processes and platforms, unlike std's `RandomState`, which
+// matters since every node has to agree on where an address goes
+fn fnv1a<'a>(bytes: impl Iterator<Item = &'a u8>) -> u64 {
+    bytes.fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RegionRouter;
+    use crate::Coordinates;
+
+    #[test]
+    fn test_route() {
+        let router = RegionRouter::default()
+            // normalized to GB
+            .country("uk", "eu-north")
+            .continent("AS", "ap-northeast");
+        let addr = "1.2.3.4".parse().unwrap();
+
+        assert_eq!(router.route(addr, Some("FR"), Some("EU")), Some("eu-west"));
+        assert_eq!(router.route(addr, Some("GB"), Some("EU")), Some("eu-north"));
+        assert_eq!(
+            router.route(addr, Some("JP"), Some("AS")),
+            Some("ap-northeast")
+        );
+
+        // unknown addresses still get a region, and always the same one
+        let unknown = router.route(addr, None, None);
+        assert!(unknown.is_some());
+        assert_eq!(router.route(addr, None, None), unknown);
+
+        assert_eq!(RegionRouter::empty().route(addr, None, None), None);
+    }
+
+    #[test]
+    fn test_nearest() {
+        let router = RegionRouter::empty()
+            .site("us-east", Coordinates::new(38.9, -77.0))
+            .site("eu-west", Coordinates::new(53.3, -6.3));
+
+        assert_eq!(
+            router.nearest(Coordinates::new(48.8566, 2.3522)),
+            Some("eu-west")
+        );
+        assert_eq!(
+            router.nearest(Coordinates::new(40.7128, -74.0060)),
+            Some("us-east")
+        );
+        assert_eq!(
+            RegionRouter::empty().nearest(Coordinates::new(0.0, 0.0)),
+            None
+        );
+    }
+}
@@ -0,0 +1,300 @@
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+};
+
+#[cfg(feature = "analytics")]
+use crate::Measures;
+use crate::{Coordinates, Locat};
+
+/// A piece of output that may say too much about someone, handed to the
+/// [`Scrubber`] before it leaves [`Locat`]. More kinds are added as
+/// features that output them are.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Field<'a> {
+    /// From [`Locat::ip_to_location`]
+    Coordinates(&'a mut Coordinates),
+    /// From [`Locat::get_subnet_analytics`]
+    #[cfg(feature = "analytics")]
+    Subnet(&'a mut String),
+    /// Who took an administrative action, before it's written to the audit
+    /// log, see [`Locat::with_audit_actor`]
+    #[cfg(feature = "analytics")]
+    Actor(&'a mut Option<String>),
+    /// A country's row in [`Locat::export_portable`]. Everything about a
+    /// country whose count ends up zero is left out, from weights to
+    /// subnets.
+    #[cfg(feature = "analytics")]
+    ExportedCountry {
+        iso_code: &'a str,
+        measures: &'a mut Measures,
+    },
+    /// A salted hash of a client's address, before it's kept, see
+    /// [`Locat::with_exemplars`]. Set it to `None` not to keep it.
+    #[cfg(feature = "analytics")]
+    Exemplar {
+        iso_code: &'a str,
+        exemplar: &'a mut Option<String>,
+    },
+    /// A message about to be written to stderr
+    Log(&'a mut String),
+}
+
+/// Redacts or transforms [`Field`]s according to a privacy policy, see
+/// [`Locat::with_scrubber`]. [`ScrubPolicy`] covers the usual needs;
+/// closures taking a [`Field`] work too.
+pub trait Scrubber: Send + Sync {
+    fn scrub(&self, field: Field<'_>);
+}
+
+impl<F: Fn(Field<'_>) + Send + Sync> Scrubber for F {
+    fn scrub(&self, field: Field<'_>) {
+        self(field)
+    }
+}
+
+/// A [`Scrubber`] for common policies, which leaves everything alone until
+/// told otherwise, but keeps no [`Field::Exemplar`] unless told to
+#[derive(Clone, Default)]
+pub struct ScrubPolicy {
+    coordinate_decimals: Option<u8>,
+    #[cfg(feature = "analytics")]
+    subnet_salt: Option<Vec<u8>>,
+    #[cfg(feature = "analytics")]
+    hide_actors: bool,
+    #[cfg(feature = "analytics")]
+    min_exported_count: u64,
+    #[cfg(feature = "analytics")]
+    keep_exemplars: bool,
+    redact_logged_addresses: bool,
+}
+
+// the salt stays out of logs
+impl fmt::Debug for ScrubPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ScrubPolicy")
+            .field("coordinate_decimals", &self.coordinate_decimals)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ScrubPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Rounds coordinates to this many decimals: 1 is about 10 km, 0 about
+    /// 100 km
+    pub fn with_coordinate_decimals(mut self, decimals: u8) -> Self {
+        self.coordinate_decimals = Some(decimals);
+        self
+    }
+
+    #[cfg(feature = "analytics")]
+    /// Replaces subnets with a salted hash, like
+    /// [`crate::SubnetAnalytics::with_salt`] does for new ones
+    pub fn with_hashed_subnets(mut self, salt: impl Into<Vec<u8>>) -> Self {
+        self.subnet_salt = Some(salt.into());
+        self
+    }
+
+    #[cfg(feature = "analytics")]
+    /// Leaves actors out of the audit log
+    pub fn without_actors(mut self) -> Self {
+        self.hide_actors = true;
+        self
+    }
+
+    #[cfg(feature = "analytics")]
+    /// Leaves countries with fewer than `count` lookups out of exports, so
+    /// that the few visitors of a small country can't be singled out
+    pub fn with_min_exported_count(mut self, count: u64) -> Self {
+        self.min_exported_count = count;
+        self
+    }
+
+    #[cfg(feature = "analytics")]
+    /// Lets [`Locat::with_exemplars`] keep hashed addresses
+    pub fn with_exemplars(mut self) -> Self {
+        self.keep_exemplars = true;
+        self
+    }
+
+    /// Replaces IP addresses in logged messages with `<redacted>`
+    pub fn with_redacted_log_addresses(mut self) -> Self {
+        self.redact_logged_addresses = true;
+        self
+    }
+}
+
+impl Scrubber for ScrubPolicy {
+    fn scrub(&self, field: Field<'_>) {
+        match field {
+            Field::Coordinates(at) => {
+                if let Some(decimals) = self.coordinate_decimals {
+                    let scale = 10f64.powi(decimals.into());
+                    at.latitude = (at.latitude * scale).round() / scale;
+                    at.longitude = (at.longitude * scale).round() / scale;
+                }
+            }
+            #[cfg(feature = "analytics")]
+            Field::Subnet(subnet) => {
+                if let Some(salt) = &self.subnet_salt {
+                    *subnet = crate::subnets::salted_hash(salt, subnet);
+                }
+            }
+            #[cfg(feature = "analytics")]
+            Field::Actor(actor) => {
+                if self.hide_actors {
+                    *actor = None;
+                }
+            }
+            #[cfg(feature = "analytics")]
+            Field::ExportedCountry { measures, .. } => {
+                if measures.count < self.min_exported_count {
+                    measures.count = 0;
+                }
+            }
+            #[cfg(feature = "analytics")]
+            Field::Exemplar { exemplar, .. } => {
+                if !self.keep_exemplars {
+                    *exemplar = None;
+                }
+            }
+            Field::Log(message) => {
+                if self.redact_logged_addresses {
+                    *message = redact_addresses(message);
+                }
+            }
+        }
+    }
+}
+
+impl Locat {
+    /// Passes everything listed in [`Field`] through `scrubber` before it's
+    /// returned, written or logged, to enforce a privacy policy in one
+    /// place
+    pub fn with_scrubber(mut self, scrubber: impl Scrubber + 'static) -> Self {
+        let scrubber: Arc<dyn Scrubber> = Arc::new(scrubber);
+        let inner = self.inner_mut();
+        #[cfg(feature = "analytics")]
+        if let Some(write_behind) = &mut inner.write_behind {
+            write_behind.scrubber = Some(scrubber.clone());
+        }
+        inner.scrubber = Some(scrubber);
+        self
+    }
+
+    pub(crate) fn scrub(&self, field: Field<'_>) {
+        if let Some(scrubber) = &self.inner.scrubber {
+            scrubber.scrub(field);
+        }
+    }
+
+    /// Writes `message` to stderr, scrubbed, or as a `warn!` event with
+    /// the `tracing` feature
+    #[cfg(feature = "analytics")]
+    pub(crate) fn log(&self, message: String) {
+        log(self.inner.scrubber.as_deref(), message);
+    }
+}
+
+#[cfg(feature = "analytics")]
+pub(crate) fn log(scrubber: Option<&dyn Scrubber>, mut message: String) {
+    if let Some(scrubber) = scrubber {
+        scrubber.scrub(Field::Log(&mut message));
+    }
+    #[cfg(feature = "tracing")]
+    tracing::warn!("{message}");
+    #[cfg(not(feature = "tracing"))]
+    eprintln!("{message}");
+}
+
+/// Replaces whatever parses as an IP address, with or without a port
+fn redact_addresses(message: &str) -> String {
+    let mut out = String::with_capacity(message.len());
+    let mut rest = message;
+    while !rest.is_empty() {
+        let is_part = |c: char| c.is_ascii_hexdigit() || matches!(c, '.' | ':' | '[' | ']');
+        let start = rest.find(is_part).unwrap_or(rest.len());
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest.find(|c| !is_part(c)).unwrap_or(rest.len());
+        let token = &rest[..end];
+        // sentences end in `.` and labels in `:`
+        let trimmed = token.trim_end_matches(['.', ':']);
+        if trimmed.parse::<IpAddr>().is_ok() || trimmed.parse::<SocketAddr>().is_ok() {
+            out.push_str("<redacted>");
+            out.push_str(&token[trimmed.len()..]);
+        } else {
+            out.push_str(token);
+        }
+        rest = &rest[end..];
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{redact_addresses, Field, ScrubPolicy, Scrubber};
+    use crate::Coordinates;
+
+    #[tokio::test]
+    async fn test_scrub_policy() {
+        let policy = ScrubPolicy::new()
+            .with_coordinate_decimals(1)
+            .with_redacted_log_addresses();
+        let mut at = Coordinates::new(48.8566, 2.3522);
+        policy.scrub(Field::Coordinates(&mut at));
+        assert_eq!(at, Coordinates::new(48.9, 2.4));
+
+        let mut message = "Could not look up 1.2.3.4: timed out, [2001:db8::1]:443 too.".to_owned();
+        policy.scrub(Field::Log(&mut message));
+        assert_eq!(
+            message,
+            "Could not look up <redacted>: timed out, <redacted> too."
+        );
+        assert_eq!(redact_addresses("deadbeef 10:30"), "deadbeef 10:30");
+
+        #[cfg(feature = "analytics")]
+        {
+            let policy = ScrubPolicy::new()
+                .with_hashed_subnets("salt")
+                .without_actors()
+                .with_min_exported_count(2);
+            let mut subnet = "1.2.3.0/24".to_owned();
+            policy.scrub(Field::Subnet(&mut subnet));
+            assert_eq!(subnet.len(), 32);
+            let mut actor = Some("alice".to_owned());
+            policy.scrub(Field::Actor(&mut actor));
+            assert_eq!(actor, None);
+            let mut measures = crate::Measures { count: 1, sum: 10 };
+            policy.scrub(Field::ExportedCountry {
+                iso_code: "LI",
+                measures: &mut measures,
+            });
+            assert_eq!(measures.count, 0);
+
+            // on everything `Locat` puts out
+            let locat = crate::Locat::builder()
+                .with_geoip_bytes(
+                    crate::testing::TestDb::new()
+                        .country("1.2.3.0/24", "US", "NA")
+                        .country("5.6.7.0/24", "FR", "EU")
+                        .build(),
+                )
+                .build()
+                .await
+                .unwrap()
+                .with_scrubber(policy);
+            for addr in ["1.2.3.4", "1.2.3.5", "5.6.7.8"] {
+                locat.ip_to_iso_code(addr.parse().unwrap()).await;
+            }
+            let export = locat.export_portable().await.unwrap();
+            assert!(export.contains("US\t2"));
+            assert!(!export.contains("FR"));
+        }
+    }
+}
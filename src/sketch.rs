@@ -0,0 +1,124 @@
+//! A DDSketch: values are counted in logarithmic buckets, so any quantile
+//! can be answered within a fixed relative error, and two sketches merge by
+//! adding up their buckets. That's what lets us keep percentiles of weights
+//! per country without keeping every weight.
+
+use std::collections::BTreeMap;
+
+// quantiles come out within 1% of the actual value
+const RELATIVE_ACCURACY: f64 = 0.01;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Sketch {
+    // zero doesn't fit in a logarithmic bucket
+    zeros: u64,
+    buckets: BTreeMap<i32, u64>,
+}
+
+impl Sketch {
+    fn gamma() -> f64 {
+        (1.0 + RELATIVE_ACCURACY) / (1.0 - RELATIVE_ACCURACY)
+    }
+
+    pub(crate) fn insert(&mut self, value: u64) {
+        if value == 0 {
+            self.zeros += 1;
+            return;
+        }
+        let index = (value as f64).ln() / Self::gamma().ln();
+        *self.buckets.entry(index.ceil() as i32).or_default() += 1;
+    }
+
+    pub(crate) fn merge(&mut self, other: &Sketch) {
+        self.zeros += other.zeros;
+        for (index, count) in &other.buckets {
+            *self.buckets.entry(*index).or_default() += count;
+        }
+    }
+
+    pub(crate) fn count(&self) -> u64 {
+        self.zeros + self.buckets.values().sum::<u64>()
+    }
+
+    /// `q` between 0 and 1, `None` for an empty sketch
+    pub(crate) fn quantile(&self, q: f64) -> Option<f64> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = (q.clamp(0.0, 1.0) * (count - 1) as f64).round() as u64;
+
+        if rank < self.zeros {
+            return Some(0.0);
+        }
+        let mut seen = self.zeros;
+        let gamma = Self::gamma();
+        for (index, bucket) in &self.buckets {
+            seen += bucket;
+            if seen > rank {
+                // the middle of the bucket, relatively speaking
+                return Some(2.0 * gamma.powi(*index) / (gamma + 1.0));
+            }
+        }
+        None
+    }
+
+    /// Stored as a blob: the zero count, then (index, count) pairs, all
+    /// little endian
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(8 + self.buckets.len() * 12);
+        bytes.extend_from_slice(&self.zeros.to_le_bytes());
+        for (index, count) in &self.buckets {
+            bytes.extend_from_slice(&index.to_le_bytes());
+            bytes.extend_from_slice(&count.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        let (zeros, mut rest) = bytes.split_first_chunk::<8>()?;
+        let mut sketch = Sketch {
+            zeros: u64::from_le_bytes(*zeros),
+            buckets: BTreeMap::new(),
+        };
+        while !rest.is_empty() {
+            let (index, after) = rest.split_first_chunk::<4>()?;
+            let (count, after) = after.split_first_chunk::<8>()?;
+            sketch
+                .buckets
+                .insert(i32::from_le_bytes(*index), u64::from_le_bytes(*count));
+            rest = after;
+        }
+        Some(sketch)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Sketch;
+
+    #[test]
+    fn test_sketch() {
+        let mut low = Sketch::default();
+        let mut high = Sketch::default();
+        for value in 1..=500 {
+            low.insert(value);
+        }
+        for value in 501..=1000 {
+            high.insert(value);
+        }
+        low.insert(0);
+        low.merge(&high);
+        assert_eq!(low.count(), 1001);
+
+        let within = |actual: f64, expected: f64| (actual - expected).abs() <= expected * 0.01;
+        assert_eq!(low.quantile(0.0), Some(0.0));
+        assert!(within(low.quantile(0.5).unwrap(), 500.0));
+        assert!(within(low.quantile(0.99).unwrap(), 990.0));
+        assert!(within(low.quantile(1.0).unwrap(), 1000.0));
+
+        assert_eq!(Sketch::from_bytes(&low.to_bytes()), Some(low));
+        assert_eq!(Sketch::default().quantile(0.5), None);
+        assert_eq!(Sketch::from_bytes(&[1, 2, 3]), None);
+    }
+}
@@ -0,0 +1,215 @@
+//! A small file other processes on the host can read analytics from,
+//! without SQLite. Integers are little-endian:
+//!
+//! ```text
+//! offset  size  field
+//! 0       8     magic, "LOCATSNP"
+//! 8       4     version, 1
+//! 12      4     number of countries
+//! 16      8     generated at, seconds since the Unix epoch
+//! 24      8     bogon lookups
+//! 32      8     not found lookups
+//! 40      16 × number of countries:
+//!               8  country code, ASCII, padded with zeros
+//!               8  lookups
+//! ```
+//!
+//! The file is replaced rather than modified, so it can be mapped or read
+//! without locking: readers keep whichever version they opened.
+
+use std::{
+    path::Path,
+    time::{Duration, SystemTime},
+};
+
+use tokio::task::AbortHandle;
+
+use crate::{Error, Locat};
+
+const MAGIC: &[u8; 8] = b"LOCATSNP";
+const VERSION: u32 = 1;
+const HEADER_LEN: usize = 40;
+const ENTRY_LEN: usize = 16;
+
+/// Analytics as published by [`Locat::write_snapshot`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnalyticsSnapshot {
+    /// To the second
+    pub generated_at: SystemTime,
+    /// Most looked-up first
+    pub countries: Vec<(String, u64)>,
+    pub bogons: u64,
+    pub not_found: u64,
+}
+
+impl AnalyticsSnapshot {
+    /// Reads a snapshot written by another process
+    pub fn read(path: impl AsRef<Path>) -> Result<Self, Error> {
+        Self::from_bytes(&std::fs::read(path)?)
+    }
+
+    fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        let invalid = |reason: &str| Error::InvalidExport(format!("snapshot: {reason}"));
+        if bytes.len() < HEADER_LEN || &bytes[..8] != MAGIC {
+            return Err(invalid("not a snapshot"));
+        }
+        let u32_at = |at: usize| u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+        let u64_at = |at: usize| u64::from_le_bytes(bytes[at..at + 8].try_into().unwrap());
+        if u32_at(8) != VERSION {
+            return Err(invalid("unsupported version"));
+        }
+        let len = u32_at(12) as usize;
+        if bytes.len() != HEADER_LEN + len * ENTRY_LEN {
+            return Err(invalid("truncated"));
+        }
+        let countries = (0..len)
+            .map(|i| {
+                let at = HEADER_LEN + i * ENTRY_LEN;
+                let code = &bytes[at..at + 8];
+                let end = code.iter().position(|&b| b == 0).unwrap_or(8);
+                let code = std::str::from_utf8(&code[..end])
+                    .map_err(|_| invalid("country code is not ASCII"))?;
+                Ok((code.to_owned(), u64_at(at + 8)))
+            })
+            .collect::<Result<_, Error>>()?;
+        Ok(Self {
+            generated_at: SystemTime::UNIX_EPOCH + Duration::from_secs(u64_at(16)),
+            countries,
+            bogons: u64_at(24),
+            not_found: u64_at(32),
+        })
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HEADER_LEN + self.countries.len() * ENTRY_LEN);
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        out.extend_from_slice(&(self.countries.len() as u32).to_le_bytes());
+        let secs = self
+            .generated_at
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |age| age.as_secs());
+        out.extend_from_slice(&secs.to_le_bytes());
+        out.extend_from_slice(&self.bogons.to_le_bytes());
+        out.extend_from_slice(&self.not_found.to_le_bytes());
+        for (iso_code, count) in &self.countries {
+            let mut code = [0; 8];
+            code[..iso_code.len()].copy_from_slice(iso_code.as_bytes());
+            out.extend_from_slice(&code);
+            out.extend_from_slice(&count.to_le_bytes());
+        }
+        out
+    }
+}
+
+impl Locat {
+    /// Publishes analytics to `path` for other processes to read with
+    /// [`AnalyticsSnapshot::read`], see the [layout](crate::snapshot). A
+    /// path under `/dev/shm` keeps it in shared memory. What a
+    /// [`crate::Scrubber`] leaves out of exports is left out here too.
+    pub async fn write_snapshot(&self, path: impl AsRef<Path>) -> Result<(), Error> {
+        self.filesystem("write_snapshot")?;
+        let report = self.analytics_report().await?;
+        let snapshot = AnalyticsSnapshot {
+            generated_at: report.generated_at,
+            countries: report
+                .countries
+                .into_iter()
+                // codes that don't fit the layout can't be real ones
+                .filter(|row| row.iso_code.len() <= 8 && row.iso_code.is_ascii())
+                .map(|row| (row.iso_code, row.count))
+                .collect(),
+            bogons: report.stats.bogons,
+            not_found: report.stats.not_found,
+        };
+        let path = path.as_ref();
+        let mut temp = path.as_os_str().to_owned();
+        temp.push(".tmp");
+        tokio::fs::write(&temp, snapshot.to_bytes()).await?;
+        tokio::fs::rename(&temp, path).await?;
+        Ok(())
+    }
+
+    /// [`Locat::write_snapshot`]s every `interval`, starting now. The task
+    /// ends once every clone of this `Locat` is dropped, or on
+    /// [`Locat::shutdown`].
+    pub fn publish_snapshot_every(
+        &self,
+        path: impl AsRef<Path>,
+        interval: Duration,
+    ) -> AbortHandle {
+        let inner = std::sync::Arc::downgrade(&self.inner);
+        let path = path.as_ref().to_owned();
+        self.spawn("publish_snapshot", |mut stop| async move {
+            let mut ticks = tokio::time::interval(interval);
+            loop {
+                tokio::select! {
+                    _ = ticks.tick() => {}
+                    _ = stop.requested() => return,
+                }
+                let Some(inner) = inner.upgrade() else {
+                    return;
+                };
+                let locat = Locat { inner };
+                if let Err(e) = locat.write_snapshot(&path).await {
+                    locat.log(format!("Could not publish analytics snapshot: {e}"));
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::{
+        testing::{RemoveOnDrop, TestDb},
+        AnalyticsSnapshot, Locat,
+    };
+
+    #[tokio::test]
+    async fn test_snapshot() {
+        let path = "/tmp/loca-test-snapshot.bin";
+        let _remove = RemoveOnDrop { path };
+        let locat = Locat::builder()
+            .with_geoip_bytes(
+                TestDb::new()
+                    .country("1.2.3.0/24", "US", "NA")
+                    .country("5.6.7.0/24", "FR", "EU")
+                    .build(),
+            )
+            .build()
+            .await
+            .unwrap();
+        for addr in ["1.2.3.4", "1.2.3.5", "5.6.7.8", "10.0.0.1"] {
+            locat.ip_to_iso_code(addr.parse().unwrap()).await;
+        }
+
+        locat.write_snapshot(path).await.unwrap();
+        let snapshot = AnalyticsSnapshot::read(path).unwrap();
+        assert_eq!(
+            snapshot.countries,
+            vec![("US".to_string(), 2), ("FR".to_string(), 1)]
+        );
+        assert_eq!((snapshot.bogons, snapshot.not_found), (1, 0));
+        assert_eq!(
+            AnalyticsSnapshot::from_bytes(&snapshot.to_bytes()).unwrap(),
+            snapshot
+        );
+        assert!(AnalyticsSnapshot::from_bytes(&snapshot.to_bytes()[..50]).is_err());
+
+        // kept up to date
+        locat.publish_snapshot_every(path, Duration::from_millis(10));
+        locat.ip_to_iso_code("5.6.7.9".parse().unwrap()).await;
+        let updated = vec![("FR".to_string(), 2), ("US".to_string(), 2)];
+        for _ in 0..100 {
+            if AnalyticsSnapshot::read(path).unwrap().countries == updated {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert_eq!(AnalyticsSnapshot::read(path).unwrap().countries, updated);
+        locat.shutdown().await;
+    }
+}
@@ -0,0 +1,95 @@
+use crate::{Error, Locat};
+
+/// How counted lookups turned out, see [`Locat::get_lookup_stats`]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct LookupStats {
+    /// Lookups that found a country, the total of [`Locat::get_analytics`]
+    pub located: u64,
+    /// Lookups of reserved or unallocated addresses, see [`crate::Bogons`]
+    pub bogons: u64,
+    /// Lookups of addresses the GeoIP database doesn't know
+    pub not_found: u64,
+}
+
+impl LookupStats {
+    pub fn total(&self) -> u64 {
+        self.located + self.bogons + self.not_found
+    }
+
+    /// The fraction of lookups that found no country, bogons included, or
+    /// 0 if nothing was counted
+    pub fn miss_rate(&self) -> f64 {
+        match self.total() {
+            0 => 0.0,
+            total => (self.bogons + self.not_found) as f64 / total as f64,
+        }
+    }
+}
+
+impl Locat {
+    /// Counted lookups by outcome, to tell what fraction of traffic can't
+    /// be geolocated. Misses aren't counted with
+    /// [`crate::LocatBuilder::with_miss_recording`] turned off.
+    pub async fn get_lookup_stats(&self) -> Result<LookupStats, Error> {
+        let located = self
+            .get_analytics()
+            .await?
+            .into_iter()
+            .map(|(_, count)| count)
+            .sum();
+        let mut stats = LookupStats {
+            located,
+            ..Default::default()
+        };
+        for (name, count) in self.inner.analytics.counters().await? {
+            match name.as_str() {
+                "bogon" => stats.bogons = count,
+                "not_found" => stats.not_found = count,
+                _ => {}
+            }
+        }
+        Ok(stats)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{testing::TestDb, Locat, LookupStats};
+
+    #[tokio::test]
+    async fn test_lookup_stats() {
+        let data = TestDb::new().country("1.2.3.0/24", "US", "NA").build();
+        let addrs = ["1.2.3.4", "1.2.3.5", "10.0.0.1", "8.8.8.8"].map(|a| a.parse().unwrap());
+
+        let locat = Locat::builder()
+            .with_geoip_bytes(data.clone())
+            .build()
+            .await
+            .unwrap();
+        locat.ip_to_iso_code(addrs[0]).await;
+        locat.lookup(addrs[1]).await;
+        locat.ip_to_iso_codes(&addrs[2..]).await;
+        let stats = locat.get_lookup_stats().await.unwrap();
+        assert_eq!(
+            stats,
+            LookupStats {
+                located: 2,
+                bogons: 1,
+                not_found: 1
+            }
+        );
+        assert_eq!(stats.miss_rate(), 0.5);
+
+        let locat = Locat::builder()
+            .with_geoip_bytes(data)
+            .with_miss_recording(false)
+            .build()
+            .await
+            .unwrap();
+        for addr in addrs {
+            locat.ip_to_iso_code(addr).await;
+        }
+        assert_eq!(locat.get_lookup_stats().await.unwrap().not_found, 0);
+    }
+}
@@ -0,0 +1,73 @@
+use crate::{Error, Locat};
+
+/// `"1xx"` to `"5xx"`, `None` for codes outside of those
+pub(crate) fn class(status: u16) -> Option<&'static str> {
+    match status / 100 {
+        1 => Some("1xx"),
+        2 => Some("2xx"),
+        3 => Some("3xx"),
+        4 => Some("4xx"),
+        5 => Some("5xx"),
+        _ => None,
+    }
+}
+
+impl Locat {
+    /// Counts a response with an HTTP `status` to a client located in a
+    /// country, by class (`"2xx"`, `"4xx"`, ...), right away:
+    /// `GeoLayer::with_status_classes` in `locat::middleware` batches them
+    /// instead. Codes outside of 100 to 599 aren't counted. SQLite only.
+    pub async fn record_status(&self, iso_code: &str, status: u16) -> Result<(), Error> {
+        let db = self.sqlite("record_status")?;
+        let Some(class) = class(status) else {
+            return Ok(());
+        };
+        Ok(db.increment_status(iso_code, class).await?)
+    }
+
+    /// Responses counted with [`Locat::record_status`], as `(iso_code,
+    /// class, count)`, by country then class: enough for error rates per
+    /// region. SQLite only.
+    pub async fn get_status_classes(&self) -> Result<Vec<(String, String, u64)>, Error> {
+        Ok(self
+            .sqlite("get_status_classes")?
+            .list_status_classes()
+            .await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{testing::TestDb, Locat};
+
+    #[tokio::test]
+    async fn test_status_classes() {
+        let locat = Locat::builder()
+            .with_geoip_bytes(TestDb::new().build())
+            .build()
+            .await
+            .unwrap();
+        for (iso_code, status) in [
+            ("US", 200),
+            ("US", 204),
+            ("US", 503),
+            ("FR", 404),
+            ("FR", 999),
+        ] {
+            locat.record_status(iso_code, status).await.unwrap();
+        }
+        let row =
+            |iso_code: &str, class: &str, count| (iso_code.to_string(), class.to_string(), count);
+        assert_eq!(
+            locat.get_status_classes().await.unwrap(),
+            vec![
+                row("FR", "4xx", 1),
+                row("US", "2xx", 2),
+                row("US", "5xx", 1)
+            ]
+        );
+
+        locat.clear_analytics().await.unwrap();
+        assert!(locat.get_status_classes().await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,157 @@
+use std::{collections::BTreeMap, sync::Mutex};
+
+use async_trait::async_trait;
+
+use crate::{Error, Measures};
+
+/// Where analytics are kept. [`crate::Locat::new`] uses SQLite; other
+/// backends (Postgres, ...) plug in with [`crate::Locat::from_store`].
+///
+/// Only [`AnalyticsStore::add`], [`AnalyticsStore::measures`] and
+/// [`AnalyticsStore::counters`] have to be implemented. Features that rely
+/// on SQLite specifics (raw queries, periods, weighted measures, ...)
+/// return [`Error::Unsupported`] with other stores.
+///
+/// Errors from the backend itself can be reported as [`Error::Store`].
+#[async_trait]
+pub trait AnalyticsStore: Send + Sync {
+    /// Adds `measures` to each country's and `count` to each counter, all or
+    /// nothing if the backend can manage it
+    async fn add(
+        &self,
+        analytics: &[(String, Measures)],
+        counters: &[(String, u64)],
+    ) -> Result<(), Error>;
+
+    async fn measures(&self) -> Result<Vec<(String, Measures)>, Error>;
+
+    async fn counters(&self) -> Result<Vec<(String, u64)>, Error>;
+
+    /// Counts one event for a country, carrying `weight`
+    async fn record(&self, iso_code: &str, weight: u64) -> Result<(), Error> {
+        let measures = Measures {
+            count: 1,
+            sum: weight,
+        };
+        self.add(&[(iso_code.to_owned(), measures)], &[]).await
+    }
+
+    async fn increment(&self, iso_code: &str) -> Result<(), Error> {
+        self.record(iso_code, 0).await
+    }
+
+    async fn increment_counter(&self, name: &str) -> Result<(), Error> {
+        self.add(&[], &[(name.to_owned(), 1)]).await
+    }
+
+    /// Zero if the counter was never incremented
+    async fn counter(&self, name: &str) -> Result<u64, Error> {
+        let counters = self.counters().await?;
+        Ok(counters
+            .into_iter()
+            .find(|(counter, _)| counter == name)
+            .map_or(0, |(_, count)| count))
+    }
+
+    /// A token that changes whenever analytics are reset or replaced
+    /// wholesale, so that anything derived from them knows to start over,
+    /// see [`crate::Locat::epoch`]. Stores that are never reset can keep
+    /// the default, which never changes.
+    async fn epoch(&self) -> Result<u64, Error> {
+        Ok(0)
+    }
+
+    /// Removes every count and counter, and starts a new epoch if the store
+    /// keeps track of them, see [`crate::Locat::reset_analytics`]
+    async fn reset(&self) -> Result<(), Error> {
+        Err(Error::Unsupported("reset_analytics"))
+    }
+
+    /// Where analytics go, for logs
+    fn describe(&self) -> String {
+        "custom store".to_owned()
+    }
+}
+
+/// Keeps analytics in memory, e.g. for tests. Everything is lost on drop.
+#[derive(Debug, Default)]
+pub struct MemoryStore {
+    analytics: Mutex<BTreeMap<String, Measures>>,
+    counters: Mutex<BTreeMap<String, u64>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl AnalyticsStore for MemoryStore {
+    async fn add(
+        &self,
+        analytics: &[(String, Measures)],
+        counters: &[(String, u64)],
+    ) -> Result<(), Error> {
+        let mut all = self.analytics.lock().unwrap();
+        for (iso_code, measures) in analytics {
+            let entry = all
+                .entry(iso_code.clone())
+                .or_insert(Measures { count: 0, sum: 0 });
+            entry.count += measures.count;
+            entry.sum += measures.sum;
+        }
+        let mut all = self.counters.lock().unwrap();
+        for (name, count) in counters {
+            *all.entry(name.clone()).or_default() += count;
+        }
+        Ok(())
+    }
+
+    async fn measures(&self) -> Result<Vec<(String, Measures)>, Error> {
+        let analytics = self.analytics.lock().unwrap();
+        Ok(analytics
+            .iter()
+            .map(|(iso_code, measures)| (iso_code.clone(), *measures))
+            .collect())
+    }
+
+    async fn counters(&self) -> Result<Vec<(String, u64)>, Error> {
+        let counters = self.counters.lock().unwrap();
+        Ok(counters
+            .iter()
+            .map(|(name, count)| (name.clone(), *count))
+            .collect())
+    }
+
+    async fn reset(&self) -> Result<(), Error> {
+        self.analytics.lock().unwrap().clear();
+        self.counters.lock().unwrap().clear();
+        Ok(())
+    }
+
+    fn describe(&self) -> String {
+        "memory".to_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{AnalyticsStore, MemoryStore};
+    use crate::Measures;
+
+    #[tokio::test]
+    async fn test_memory_store() {
+        let store = MemoryStore::new();
+        store.increment("US").await.unwrap();
+        store.record("US", 10).await.unwrap();
+        store.increment_counter("bogon").await.unwrap();
+
+        assert_eq!(
+            store.measures().await.unwrap(),
+            vec![("US".to_string(), Measures { count: 2, sum: 10 })]
+        );
+        assert_eq!(store.counter("bogon").await.unwrap(), 1);
+        assert_eq!(store.counter("other").await.unwrap(), 0);
+    }
+}
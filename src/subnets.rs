@@ -0,0 +1,254 @@
+use std::{
+    fmt,
+    net::IpAddr,
+    sync::Mutex,
+    time::{Duration, Instant, SystemTime},
+};
+
+use ipnetwork::IpNetwork;
+use sha2::{Digest, Sha256};
+
+use crate::{Error, Field, Locat};
+
+/// How lookups are counted per subnet, see [`Locat::with_subnet_analytics`].
+///
+/// By default addresses are truncated to their /24 (IPv4) or /48 (IPv6),
+/// and subnets not seen for 30 days are deleted.
+#[derive(Clone)]
+pub struct SubnetAnalytics {
+    v4_prefix: u8,
+    v6_prefix: u8,
+    salt: Option<Vec<u8>>,
+    retention: Duration,
+}
+
+impl Default for SubnetAnalytics {
+    fn default() -> Self {
+        Self {
+            v4_prefix: 24,
+            v6_prefix: 48,
+            salt: None,
+            retention: Duration::from_secs(30 * 24 * 3600),
+        }
+    }
+}
+
+// the salt stays out of logs
+impl fmt::Debug for SubnetAnalytics {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SubnetAnalytics")
+            .field("v4_prefix", &self.v4_prefix)
+            .field("v6_prefix", &self.v6_prefix)
+            .field("salted", &self.salt.is_some())
+            .field("retention", &self.retention)
+            .finish()
+    }
+}
+
+impl SubnetAnalytics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How much of addresses is kept, `32` and `128` to count single
+    /// addresses
+    ///
+    /// # Panics
+    ///
+    /// If a prefix is longer than the address.
+    pub fn with_prefixes(mut self, v4: u8, v6: u8) -> Self {
+        assert!(v4 <= 32 && v6 <= 128, "prefix longer than the address");
+        self.v4_prefix = v4;
+        self.v6_prefix = v6;
+        self
+    }
+
+    /// Keeps a salted SHA-256 of each subnet rather than the subnet itself:
+    /// repeat offenders still stand out, but can't be told from the
+    /// analytics alone. Short prefixes can be brute-forced by whoever has
+    /// the salt, so keep it secret.
+    pub fn with_salt(mut self, salt: impl Into<Vec<u8>>) -> Self {
+        self.salt = Some(salt.into());
+        self
+    }
+
+    /// Deletes subnets once they haven't been seen for `retention`
+    pub fn with_retention(mut self, retention: Duration) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    fn key(&self, addr: IpAddr) -> String {
+        let prefix = match addr {
+            IpAddr::V4(_) => self.v4_prefix,
+            IpAddr::V6(_) => self.v6_prefix,
+        };
+        let truncated = IpNetwork::new(addr, prefix)
+            .and_then(|network| IpNetwork::new(network.network(), prefix))
+            .expect("prefixes are checked by with_prefixes");
+        let subnet = truncated.to_string();
+        match &self.salt {
+            Some(salt) => salted_hash(salt, &subnet),
+            None => subnet,
+        }
+    }
+}
+
+pub(crate) fn salted_hash(salt: &[u8], subnet: &str) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(salt);
+    hasher.update(subnet.as_bytes());
+    // half of it is plenty to tell subnets apart
+    hasher.finalize()[..16]
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
+
+/// Lookups from one subnet located in one country, see
+/// [`Locat::get_subnet_analytics`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SubnetCount {
+    /// e.g. `1.2.3.0/24`, or a hash of it with [`SubnetAnalytics::with_salt`]
+    pub subnet: String,
+    pub iso_code: String,
+    pub count: u64,
+    /// To the second
+    pub last_seen: SystemTime,
+}
+
+pub(crate) struct Subnets {
+    config: SubnetAnalytics,
+    last_prune: Mutex<Option<Instant>>,
+}
+
+impl Locat {
+    /// Also counts lookups per subnet and country, for abuse analysis,
+    /// see [`Locat::get_subnet_analytics`]. SQLite only. Exports carry
+    /// subnets as they're stored: hashed with a salt, or as is.
+    pub fn with_subnet_analytics(mut self, config: SubnetAnalytics) -> Self {
+        self.inner_mut().subnets = Some(Subnets {
+            config,
+            last_prune: Mutex::new(None),
+        });
+        self
+    }
+
+    /// What `addr` is counted under, if subnets are counted
+    pub(crate) fn subnet_key(&self, addr: IpAddr) -> Option<String> {
+        self.inner.sqlite.as_ref()?;
+        Some(self.inner.subnets.as_ref()?.config.key(addr))
+    }
+
+    /// Counts per subnet and country, most counted first, see
+    /// [`Locat::with_subnet_analytics`]
+    pub async fn get_subnet_analytics(&self) -> Result<Vec<SubnetCount>, Error> {
+        let mut subnets = self.sqlite("get_subnet_analytics")?.list_subnets().await?;
+        for row in &mut subnets {
+            self.scrub(Field::Subnet(&mut row.subnet));
+        }
+        Ok(subnets)
+    }
+
+    /// Deletes the subnets that weren't seen within the retention period,
+    /// returning how many. This also happens on its own, at most once a
+    /// minute, while lookups are counted.
+    pub async fn prune_subnets(&self) -> Result<usize, Error> {
+        let Some(subnets) = &self.inner.subnets else {
+            return Ok(0);
+        };
+        let db = self.sqlite("prune_subnets")?;
+        *subnets.last_prune.lock().unwrap() = Some(Instant::now());
+        let cutoff = SystemTime::now()
+            .checked_sub(subnets.config.retention)
+            .unwrap_or(SystemTime::UNIX_EPOCH);
+        Ok(db.prune_subnets(cutoff).await?)
+    }
+
+    pub(crate) async fn prune_subnets_every_minute(&self) {
+        let Some(subnets) = &self.inner.subnets else {
+            return;
+        };
+        let due = subnets
+            .last_prune
+            .lock()
+            .unwrap()
+            .is_none_or(|at| at.elapsed() >= Duration::from_secs(60));
+        if !due || self.inner.sqlite.is_none() {
+            return;
+        }
+        if let Err(e) = self.prune_subnets().await {
+            self.log(format!("Could not prune subnets: {e}"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::SubnetAnalytics;
+    use crate::{
+        testing::{RemoveOnDrop, TestDb},
+        Locat,
+    };
+
+    #[tokio::test]
+    async fn test_subnet_analytics() {
+        let geoip_path = "/tmp/loca-test-subnets.mmdb";
+        let analytics_path = "/tmp/loca-test-subnets.db";
+        TestDb::new()
+            .country("1.2.3.0/24", "US", "NA")
+            .country("2001:db8::/32", "DE", "EU")
+            .write(geoip_path);
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
+
+        let locat = Locat::new(geoip_path, analytics_path)
+            .await
+            .unwrap()
+            .with_subnet_analytics(SubnetAnalytics::new());
+        locat.ip_to_iso_code("1.2.3.4".parse().unwrap()).await;
+        locat.ip_to_iso_code("1.2.3.5".parse().unwrap()).await;
+        locat
+            .ip_to_iso_codes(&["2001:db8:1:2::1".parse().unwrap()])
+            .await;
+        let subnets = locat.get_subnet_analytics().await.unwrap();
+        let counts: Vec<_> = subnets
+            .iter()
+            .map(|row| (row.subnet.as_str(), row.iso_code.as_str(), row.count))
+            .collect();
+        assert_eq!(
+            counts,
+            [("1.2.3.0/24", "US", 2), ("2001:db8:1::/48", "DE", 1)]
+        );
+        // country counts are unchanged
+        assert_eq!(locat.get_lookup_stats().await.unwrap().located, 3);
+
+        // hashed subnets can't be read back, but still add up
+        let salted = SubnetAnalytics::new().with_salt("pepper");
+        assert_eq!(salted.key("1.2.3.4".parse().unwrap()).len(), 32);
+        assert_eq!(
+            salted.key("1.2.3.4".parse().unwrap()),
+            salted.key("1.2.3.200".parse().unwrap())
+        );
+        assert_ne!(
+            salted.key("1.2.3.4".parse().unwrap()),
+            SubnetAnalytics::new()
+                .with_salt("salt")
+                .key("1.2.3.4".parse().unwrap())
+        );
+        let per_ip = SubnetAnalytics::new().with_prefixes(32, 128);
+        assert_eq!(per_ip.key("1.2.3.4".parse().unwrap()), "1.2.3.4/32");
+
+        // everything is older than no time at all
+        let locat =
+            locat.with_subnet_analytics(SubnetAnalytics::new().with_retention(Duration::ZERO));
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        assert_eq!(locat.prune_subnets().await.unwrap(), 2);
+        assert!(locat.get_subnet_analytics().await.unwrap().is_empty());
+    }
+}
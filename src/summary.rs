@@ -0,0 +1,170 @@
+use std::{fmt, time::Duration};
+
+#[cfg(feature = "analytics")]
+use crate::{Error, Locat};
+
+/// A quick look at a [`Locat`], see [`Locat::summary`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct Summary {
+    /// The most looked-up countries, most frequent first
+    pub top: Vec<(String, u64)>,
+    pub countries: usize,
+    pub lookups: u64,
+    pub bogons: u64,
+    /// `database_type` from the GeoIP metadata, e.g. `GeoLite2-Country`
+    pub geoip_type: String,
+    /// Time since the GeoIP database was built
+    pub geoip_age: Duration,
+    /// Time since the analytics database was created, if it's known
+    pub analytics_age: Option<Duration>,
+}
+
+#[cfg(feature = "analytics")]
+impl Locat {
+    /// Summarizes what's loaded and what's been counted, in a form that
+    /// prints nicely (`println!("{}", locat.summary().await?)`, or as a
+    /// table in evcxr notebooks)
+    pub async fn summary(&self) -> Result<Summary, Error> {
+        const TOP: usize = 5;
+
+        let mut analytics = self.get_analytics().await?;
+        analytics.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        let countries = analytics.len();
+        let lookups = analytics.iter().map(|(_, count)| count).sum();
+        analytics.truncate(TOP);
+
+        let metadata = self.geoip_metadata();
+
+        let analytics_age = match &self.inner.sqlite {
+            Some(db) => db
+                .info()
+                .await?
+                .created_at
+                .and_then(|created| created.elapsed().ok()),
+            None => None,
+        };
+
+        Ok(Summary {
+            top: analytics,
+            countries,
+            lookups,
+            bogons: self.inner.analytics.counter("bogon").await?,
+            geoip_age: metadata.age(),
+            geoip_type: metadata.database_type,
+            analytics_age,
+        })
+    }
+}
+
+impl Summary {
+    /// Renders the summary as HTML in evcxr notebooks, which look for a
+    /// method with this name
+    pub fn evcxr_display(&self) {
+        println!(
+            "EVCXR_BEGIN_CONTENT text/html\n{}\nEVCXR_END_CONTENT",
+            self.html()
+        );
+    }
+
+    fn html(&self) -> String {
+        let mut html = format!(
+            "<pre>{}</pre><table><tr><th>country</th><th>lookups</th></tr>",
+            escape(&self.header())
+        );
+        for (iso_code, count) in &self.top {
+            let iso_code = escape(iso_code);
+            html.push_str(&format!("<tr><td>{iso_code}</td><td>{count}</td></tr>"));
+        }
+        html.push_str("</table>");
+        html
+    }
+
+    fn header(&self) -> String {
+        let mut header = format!(
+            "{} built {} ago\n{} lookups across {} countries, {} from bogons",
+            self.geoip_type,
+            HumanDuration(self.geoip_age),
+            self.lookups,
+            self.countries,
+            self.bogons,
+        );
+        if let Some(age) = self.analytics_age {
+            header.push_str(&format!(", counting for {}", HumanDuration(age)));
+        }
+        header
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{}", self.header())?;
+        for (iso_code, count) in &self.top {
+            let share = if self.lookups == 0 {
+                0.0
+            } else {
+                *count as f64 * 100.0 / self.lookups as f64
+            };
+            writeln!(f, "  {iso_code:<4}{count:>10}{share:>7.1}%")?;
+        }
+        Ok(())
+    }
+}
+
+// codes and database types come from files that may not be trusted
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Only the largest unit matters for a quick look
+pub(crate) struct HumanDuration(pub(crate) Duration);
+
+impl fmt::Display for HumanDuration {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.0.as_secs();
+        match secs {
+            0..=59 => write!(f, "{secs}s"),
+            60..=3599 => write!(f, "{}m", secs / 60),
+            3600..=86399 => write!(f, "{}h", secs / 3600),
+            _ => write!(f, "{}d", secs / 86400),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::Summary;
+
+    #[test]
+    fn test_display() {
+        let summary = Summary {
+            top: vec![("US".to_string(), 3), ("FR".to_string(), 1)],
+            countries: 2,
+            lookups: 4,
+            bogons: 1,
+            geoip_type: "GeoLite2-Country".to_string(),
+            geoip_age: Duration::from_secs(86400 * 12),
+            analytics_age: Some(Duration::from_secs(7200)),
+        };
+        assert_eq!(
+            summary.to_string(),
+            "GeoLite2-Country built 12d ago\n\
+             4 lookups across 2 countries, 1 from bogons, counting for 2h\n  \
+             US           3   75.0%\n  \
+             FR           1   25.0%\n"
+        );
+
+        let summary = Summary {
+            top: vec![("<b>\"&".to_string(), 1)],
+            geoip_type: "<script>".to_string(),
+            ..summary
+        };
+        let html = summary.html();
+        assert!(html.starts_with("<pre>&lt;script&gt; built 12d ago"));
+        assert!(html.contains("<td>&lt;b&gt;&quot;&amp;</td>"));
+    }
+}
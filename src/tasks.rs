@@ -0,0 +1,217 @@
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use tokio::{
+    sync::watch,
+    task::{AbortHandle, JoinError, JoinHandle},
+};
+
+use crate::{Error, Locat};
+
+/// The background tasks of a [`Locat`] and what tells them to stop
+pub(crate) struct Tasks {
+    running: Mutex<Vec<(&'static str, JoinHandle<()>)>>,
+    stop: watch::Sender<bool>,
+}
+
+impl Default for Tasks {
+    fn default() -> Self {
+        Self {
+            running: Mutex::new(Vec::new()),
+            stop: watch::channel(false).0,
+        }
+    }
+}
+
+/// A background task, see [`Locat::tasks`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TaskInfo {
+    /// What started it: `"flusher"`, `"watch_geoip"`, `"prune_every"`,
+    /// `"keep_updated"`, `"publish_snapshot"`, `"otlp_logs"`,
+    /// `"publish_mqtt"` or `"send_reports"`
+    pub name: &'static str,
+    pub finished: bool,
+}
+
+/// How a background task ended, see [`Locat::shutdown`]
+#[derive(Debug)]
+pub struct TaskExit {
+    pub name: &'static str,
+    /// An error if the task panicked or was aborted
+    pub result: Result<(), JoinError>,
+}
+
+/// Handed to background tasks, which stop between two rounds of work once
+/// it resolves
+pub(crate) struct Stop(watch::Receiver<bool>);
+
+impl Stop {
+    /// Resolves once [`Locat::shutdown`] is called, or every clone of the
+    /// `Locat` is dropped
+    pub(crate) async fn requested(&mut self) {
+        _ = self.0.wait_for(|stop| *stop).await;
+    }
+}
+
+impl Locat {
+    /// Spawns a background task on the current runtime, kept track of for
+    /// [`Locat::tasks`] and [`Locat::shutdown`]
+    pub(crate) fn spawn<F>(&self, name: &'static str, task: impl FnOnce(Stop) -> F) -> AbortHandle
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let tasks = &self.inner.tasks;
+        let handle = tokio::spawn(task(Stop(tasks.stop.subscribe())));
+        let abort = handle.abort_handle();
+        tasks.running.lock().unwrap().push((name, handle));
+        abort
+    }
+
+    /// The background tasks started so far, finished ones included until
+    /// [`Locat::shutdown`]
+    pub fn tasks(&self) -> Vec<TaskInfo> {
+        let running = self.inner.tasks.running.lock().unwrap();
+        running
+            .iter()
+            .map(|(name, handle)| TaskInfo {
+                name,
+                finished: handle.is_finished(),
+            })
+            .collect()
+    }
+
+    /// Stops every background task once it's done with what it's doing,
+    /// and waits for them. Tasks started afterwards stop right away, and
+    /// counts waiting with [`Locat::with_write_behind`] are only written by
+    /// [`Locat::flush`] from then on.
+    pub async fn shutdown(&self) -> Vec<TaskExit> {
+        let tasks = &self.inner.tasks;
+        tasks.stop.send_replace(true);
+        let running = std::mem::take(&mut *tasks.running.lock().unwrap());
+        let mut exits = Vec::with_capacity(running.len());
+        for (name, handle) in running {
+            exits.push(TaskExit {
+                name,
+                result: handle.await,
+            });
+        }
+        exits
+    }
+
+    /// Shuts down for good: stops background tasks, writes pending counts,
+    /// and checkpoints and closes the SQLite file, returning the first
+    /// error. Every other clone of this `Locat` must be dropped first,
+    /// handles included, or nothing is done.
+    pub async fn close(self) -> Result<(), Error> {
+        if Arc::strong_count(&self.inner) > 1 {
+            return Err(Error::StillShared);
+        }
+        self.shutdown().await;
+        #[cfg(feature = "analytics")]
+        {
+            let flushed = self.flush().await;
+            let closed = match &self.inner.sqlite {
+                Some(db) => db.close().await.map_err(Error::from),
+                None => Ok(()),
+            };
+            flushed.and(closed)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    #[cfg(feature = "analytics")]
+    use crate::{testing::RemoveOnDrop, Error};
+    use crate::{testing::TestDb, Locat, TaskInfo};
+
+    #[tokio::test]
+    async fn test_shutdown() {
+        let locat = Locat::builder()
+            .with_geoip_bytes(TestDb::new().country("1.2.3.0/24", "US", "NA").build())
+            .build()
+            .await
+            .unwrap();
+        #[cfg(feature = "analytics")]
+        let locat = locat.with_write_behind(Duration::from_secs(3600), 100);
+        let watcher = locat.watch_geoip(Duration::from_millis(10));
+        #[cfg(feature = "analytics")]
+        locat.ip_to_iso_code("1.2.3.4".parse().unwrap()).await;
+        assert!(locat.tasks().contains(&TaskInfo {
+            name: "watch_geoip",
+            finished: false
+        }));
+        #[cfg(feature = "analytics")]
+        assert_eq!(locat.tasks().len(), 2);
+
+        let exits = locat.shutdown().await;
+        assert!(exits.iter().all(|exit| exit.result.is_ok()));
+        assert!(watcher.is_finished());
+        assert!(locat.tasks().is_empty());
+        // pending counts are still there to flush
+        #[cfg(feature = "analytics")]
+        {
+            locat.flush().await.unwrap();
+            assert_eq!(
+                locat.get_analytics().await.unwrap(),
+                vec![("US".to_string(), 1)]
+            );
+        }
+
+        // too late to start anything
+        locat.watch_geoip(Duration::from_millis(10));
+        let exits = locat.shutdown().await;
+        assert_eq!(exits[0].name, "watch_geoip");
+        assert!(exits[0].result.is_ok());
+    }
+
+    #[cfg(feature = "analytics")]
+    #[tokio::test]
+    async fn test_close() {
+        let geoip_path = "/tmp/loca-test-close.mmdb";
+        let analytics_path = "/tmp/loca-test-close.db";
+        TestDb::new()
+            .country("1.2.3.0/24", "US", "NA")
+            .write(geoip_path);
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
+        let _remove_wal = RemoveOnDrop {
+            path: "/tmp/loca-test-close.db-wal",
+        };
+        let _remove_shm = RemoveOnDrop {
+            path: "/tmp/loca-test-close.db-shm",
+        };
+
+        let open = || {
+            Locat::builder()
+                .with_geoip_path(geoip_path)
+                .with_analytics_path(analytics_path)
+                .with_wal(true)
+                .build()
+        };
+        let locat = open()
+            .await
+            .unwrap()
+            .with_write_behind(Duration::from_secs(3600), 100);
+        locat.ip_to_iso_code("1.2.3.4".parse().unwrap()).await;
+        let clone = locat.clone();
+        assert!(matches!(locat.close().await, Err(Error::StillShared)));
+
+        clone.close().await.unwrap();
+        // checkpointed into the file
+        let wal = std::fs::metadata("/tmp/loca-test-close.db-wal").map_or(0, |wal| wal.len());
+        assert_eq!(wal, 0);
+        let locat = open().await.unwrap();
+        assert_eq!(
+            locat.get_analytics().await.unwrap(),
+            vec![("US".to_string(), 1)]
+        );
+    }
+}
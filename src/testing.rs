@@ -0,0 +1,198 @@
+//! Builds small GeoIP databases for tests, so we don't have to check in
+//! (or download) real `.mmdb` files.
+
+// most tests that use these go through analytics too
+#![cfg_attr(not(feature = "analytics"), allow(dead_code))]
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use maxminddb_writer::{metadata::IpVersion, paths::IpAddrWithMask, Database};
+use serde::Serialize;
+
+use crate::Coordinates;
+
+#[derive(Serialize)]
+struct Record {
+    country: Country,
+    continent: Continent,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    location: Option<Location>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    city: Option<Names>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    subdivisions: Option<Vec<Subdivision>>,
+}
+
+#[derive(Serialize)]
+struct Names {
+    names: English,
+}
+
+#[derive(Serialize)]
+struct English {
+    en: &'static str,
+}
+
+#[derive(Serialize)]
+struct Subdivision {
+    iso_code: &'static str,
+}
+
+#[derive(Serialize)]
+struct Asn {
+    autonomous_system_number: u32,
+    autonomous_system_organization: &'static str,
+}
+
+#[derive(Serialize)]
+struct Country {
+    iso_code: &'static str,
+}
+
+#[derive(Serialize)]
+struct Continent {
+    code: &'static str,
+}
+
+#[derive(Serialize)]
+struct Location {
+    latitude: f64,
+    longitude: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    time_zone: Option<&'static str>,
+}
+
+pub(crate) struct TestDb {
+    db: Database,
+}
+
+impl TestDb {
+    pub(crate) fn new() -> Self {
+        let mut db = Database::default();
+        db.metadata.ip_version = IpVersion::V4;
+        db.metadata.database_type = "GeoIP2-Country".to_string();
+        db.metadata.languages = vec!["en".to_string()];
+        db.metadata.binary_format_major_version = 2;
+        Self { db }
+    }
+
+    /// Adds a network like `"1.2.3.0/24"` belonging to a country
+    pub(crate) fn country(
+        self,
+        network: &str,
+        iso_code: &'static str,
+        continent: &'static str,
+    ) -> Self {
+        self.insert(
+            network,
+            Record {
+                country: Country { iso_code },
+                continent: Continent { code: continent },
+                location: None,
+                city: None,
+                subdivisions: None,
+            },
+        )
+    }
+
+    /// Like [`TestDb::country`], with coordinates like a City database
+    pub(crate) fn city(
+        self,
+        network: &str,
+        iso_code: &'static str,
+        continent: &'static str,
+        at: Coordinates,
+    ) -> Self {
+        self.insert(
+            network,
+            Record {
+                country: Country { iso_code },
+                continent: Continent { code: continent },
+                location: Some(Location {
+                    latitude: at.latitude,
+                    longitude: at.longitude,
+                    time_zone: None,
+                }),
+                city: None,
+                subdivisions: None,
+            },
+        )
+    }
+
+    /// Like [`TestDb::city`], with everything a City database knows
+    pub(crate) fn full_city(
+        self,
+        network: &str,
+        iso_code: &'static str,
+        subdivision: &'static str,
+        city: &'static str,
+        time_zone: &'static str,
+        at: Coordinates,
+    ) -> Self {
+        self.insert(
+            network,
+            Record {
+                country: Country { iso_code },
+                continent: Continent { code: "NA" },
+                location: Some(Location {
+                    latitude: at.latitude,
+                    longitude: at.longitude,
+                    time_zone: Some(time_zone),
+                }),
+                city: Some(Names {
+                    names: English { en: city },
+                }),
+                subdivisions: Some(vec![Subdivision {
+                    iso_code: subdivision,
+                }]),
+            },
+        )
+    }
+
+    /// Adds a network belonging to an autonomous system, like an ASN
+    /// database
+    pub(crate) fn asn(self, network: &str, number: u32, organization: &'static str) -> Self {
+        self.insert(
+            network,
+            Asn {
+                autonomous_system_number: number,
+                autonomous_system_organization: organization,
+            },
+        )
+    }
+
+    /// Sets when the database claims to have been built
+    pub(crate) fn built_at(mut self, at: SystemTime) -> Self {
+        self.db.metadata.build_epoch = at.duration_since(UNIX_EPOCH).unwrap().as_secs();
+        self
+    }
+
+    fn insert(mut self, network: &str, record: impl Serialize) -> Self {
+        let data = self.db.insert_value(record).unwrap();
+        self.db
+            .insert_node(network.parse::<IpAddrWithMask>().unwrap(), data);
+        self
+    }
+
+    pub(crate) fn write(self, path: &str) {
+        let file = std::fs::File::create(path).unwrap();
+        self.db.write_to(file).unwrap();
+    }
+
+    pub(crate) fn build(self) -> Vec<u8> {
+        let mut data = Vec::new();
+        self.db.write_to(&mut data).unwrap();
+        data
+    }
+}
+
+/// Removes a file once the test is done with it, even if it panics
+pub(crate) struct RemoveOnDrop {
+    pub(crate) path: &'static str,
+}
+
+impl Drop for RemoveOnDrop {
+    fn drop(&mut self) {
+        _ = std::fs::remove_file(self.path);
+    }
+}
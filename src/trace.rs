@@ -0,0 +1,115 @@
+//! Spans with the `tracing` feature, nothing without it
+
+#[cfg(feature = "analytics")]
+use std::sync::atomic::Ordering;
+
+#[cfg(feature = "tracing")]
+pub(crate) use tracing::Instrument;
+
+#[cfg(feature = "analytics")]
+use crate::{scrub::Field, Error, Locat};
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) struct Span;
+
+#[cfg(not(feature = "tracing"))]
+pub(crate) trait Instrument: Sized {
+    fn instrument(self, _span: Span) -> Self {
+        self
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+impl<T> Instrument for T {}
+
+/// `span!(DEBUG, "name", field = value)`, as `tracing::span!` takes them
+macro_rules! span {
+    ($level:ident, $($args:tt)*) => {{
+        #[cfg(feature = "tracing")]
+        let span = tracing::span!(tracing::Level::$level, $($args)*);
+        #[cfg(not(feature = "tracing"))]
+        let span = $crate::trace::Span;
+        span
+    }};
+}
+
+pub(crate) use span;
+
+#[cfg(feature = "analytics")]
+impl Locat {
+    /// How many writes to the analytics store failed since this `Locat`
+    /// was created. What they counted is lost; counts waiting with
+    /// [`Locat::with_write_behind`] are kept and retried instead.
+    pub fn analytics_error_count(&self) -> u64 {
+        self.inner.analytics_errors.load(Ordering::Relaxed)
+    }
+
+    /// Notes a write that failed for good, along with the country it
+    /// counted, if it was a single one
+    pub(crate) fn write_failed(&self, iso_code: Option<&str>, e: &Error) {
+        self.inner.analytics_errors.fetch_add(1, Ordering::Relaxed);
+        let mut error = e.to_string();
+        if let Some(scrubber) = &self.inner.scrubber {
+            scrubber.scrub(Field::Log(&mut error));
+        }
+        #[cfg(feature = "tracing")]
+        tracing::warn!(iso_code, error, "Could not increment analytics");
+        #[cfg(not(feature = "tracing"))]
+        {
+            _ = iso_code;
+            eprintln!("Could not increment analytics: {error}");
+        }
+    }
+}
+
+#[cfg(all(test, feature = "analytics"))]
+mod tests {
+    use async_trait::async_trait;
+
+    use crate::{
+        testing::{RemoveOnDrop, TestDb},
+        AnalyticsStore, Error, Locat, Measures,
+    };
+
+    struct Down;
+
+    #[async_trait]
+    impl AnalyticsStore for Down {
+        async fn add(&self, _: &[(String, Measures)], _: &[(String, u64)]) -> Result<(), Error> {
+            Err(Error::Store("down".into()))
+        }
+
+        async fn measures(&self) -> Result<Vec<(String, Measures)>, Error> {
+            Ok(Vec::new())
+        }
+
+        async fn counters(&self) -> Result<Vec<(String, u64)>, Error> {
+            Ok(Vec::new())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_analytics_error_count() {
+        let geoip_path = "/tmp/loca-test-trace.mmdb";
+        TestDb::new()
+            .country("1.2.3.0/24", "US", "NA")
+            .write(geoip_path);
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+
+        let locat = Locat::from_store(geoip_path, Down).await.unwrap();
+        assert_eq!(locat.analytics_error_count(), 0);
+        // answered all the same
+        assert_eq!(
+            locat.ip_to_iso_code("1.2.3.4".parse().unwrap()).await,
+            Some("US")
+        );
+        locat.ip_to_iso_code("10.0.0.1".parse().unwrap()).await;
+        locat.ip_to_iso_codes(&["1.2.3.4".parse().unwrap()]).await;
+        assert_eq!(locat.analytics_error_count(), 3);
+
+        let text = locat.analytics_to_prometheus().await.unwrap();
+        assert!(text
+            .lines()
+            .any(|line| line == "locat_analytics_write_errors_total 3"));
+    }
+}
@@ -0,0 +1,157 @@
+use crate::{Error, Locat, Measures};
+
+/// Changes to analytics that are applied all at once or not at all, see
+/// [`Locat::transaction`]
+#[derive(Debug, Default)]
+pub struct AnalyticsTransaction {
+    changes: Vec<Change>,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) enum Change {
+    Increment(String),
+    Adjust(String, i64),
+    IncrementCounter(String),
+}
+
+impl AnalyticsTransaction {
+    /// Counts one lookup for a country, see [`Locat::ip_to_iso_code`]
+    pub fn increment(&mut self, iso_code: &str) -> &mut Self {
+        self.changes.push(Change::Increment(iso_code.to_owned()));
+        self
+    }
+
+    /// Corrects a country's count, see [`Locat::adjust`]
+    pub fn adjust(&mut self, iso_code: &str, delta: i64) -> &mut Self {
+        self.changes
+            .push(Change::Adjust(iso_code.to_owned(), delta));
+        self
+    }
+
+    pub fn increment_counter(&mut self, name: &str) -> &mut Self {
+        self.changes.push(Change::IncrementCounter(name.to_owned()));
+        self
+    }
+}
+
+impl Locat {
+    /// Applies several changes to analytics atomically, for ingestion,
+    /// merges and corrections that must not be left half done:
+    ///
+    /// ```no_run
+    /// # async fn run(locat: locat::Locat) -> Result<(), locat::Error> {
+    /// // move lookups misattributed to a country
+    /// locat
+    ///     .transaction(|tx| {
+    ///         tx.adjust("US", -120).adjust("CA", 120);
+    ///     })
+    ///     .await?;
+    /// # Ok(())
+    /// # }
+    /// ```
+    ///
+    /// Changes are applied in order, bypassing [`Locat::with_write_behind`].
+    /// With stores other than SQLite, negative adjustments are
+    /// [`Error::Unsupported`] and atomicity is up to
+    /// [`crate::AnalyticsStore::add`].
+    pub async fn transaction<T>(
+        &self,
+        f: impl FnOnce(&mut AnalyticsTransaction) -> T,
+    ) -> Result<T, Error> {
+        let mut tx = AnalyticsTransaction::default();
+        let value = f(&mut tx);
+        if tx.changes.is_empty() {
+            return Ok(value);
+        }
+
+        if let Some(db) = &self.inner.sqlite {
+            db.apply(tx.changes, self.bucket_start(), self.actor())
+                .await?;
+            return Ok(value);
+        }
+
+        let mut analytics = Vec::new();
+        let mut counters = Vec::new();
+        for change in tx.changes {
+            match change {
+                Change::Increment(iso_code) => {
+                    analytics.push((iso_code, Measures { count: 1, sum: 0 }))
+                }
+                Change::Adjust(iso_code, delta) => {
+                    let count = u64::try_from(delta).map_err(|_| Error::Unsupported("adjust"))?;
+                    analytics.push((iso_code, Measures { count, sum: 0 }));
+                }
+                Change::IncrementCounter(name) => counters.push((name, 1)),
+            }
+        }
+        self.inner.analytics.add(&analytics, &counters).await?;
+        Ok(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        testing::{RemoveOnDrop, TestDb},
+        Error, Locat, MemoryStore,
+    };
+
+    #[tokio::test]
+    async fn test_transaction() {
+        let geoip_path = "/tmp/loca-test-transaction.mmdb";
+        let analytics_path = "/tmp/loca-test-transaction.db";
+        TestDb::new()
+            .country("1.2.3.0/24", "US", "NA")
+            .write(geoip_path);
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
+
+        let locat = Locat::new(geoip_path, analytics_path).await.unwrap();
+        let count = locat
+            .transaction(|tx| {
+                tx.increment("US").increment("US").increment("CA");
+                tx.adjust("US", -1).adjust("CA", 1);
+                tx.increment_counter("imported");
+                3
+            })
+            .await
+            .unwrap();
+        assert_eq!(count, 3);
+        let mut analytics = locat.get_analytics().await.unwrap();
+        analytics.sort();
+        assert_eq!(
+            analytics,
+            vec![("CA".to_string(), 2), ("US".to_string(), 1)]
+        );
+        assert_eq!(locat.inner.analytics.counter("imported").await.unwrap(), 1);
+        assert_eq!(locat.audit_log().await.unwrap().len(), 2);
+
+        // nothing is applied when a change fails
+        let conn = rusqlite::Connection::open(analytics_path).unwrap();
+        conn.execute_batch("DROP TABLE counters").unwrap();
+        assert!(locat
+            .transaction(|tx| {
+                tx.increment("US").increment_counter("imported");
+            })
+            .await
+            .is_err());
+        let mut unchanged = locat.get_analytics().await.unwrap();
+        unchanged.sort();
+        assert_eq!(unchanged, analytics);
+
+        let memory = Locat::from_store(geoip_path, MemoryStore::new())
+            .await
+            .unwrap();
+        assert!(matches!(
+            memory
+                .transaction(|tx| {
+                    tx.increment("US").adjust("US", -1);
+                })
+                .await,
+            Err(Error::Unsupported(_))
+        ));
+        assert!(memory.get_analytics().await.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,71 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// IPv6 transition mechanisms that embed an IPv4 address
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum Tunnel {
+    /// `2002::/16`, the IPv4 address follows the prefix
+    SixToFour,
+    /// `2001::/32`, the client's IPv4 address is in the last 32 bits,
+    /// with every bit flipped
+    Teredo,
+}
+
+/// An address that was looked up through the IPv4 address embedded in it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Unwrapped {
+    pub original: Ipv6Addr,
+    pub tunnel: Tunnel,
+}
+
+/// Extracts the IPv4 address embedded in a 6to4 or Teredo address
+pub fn unwrap_tunneled(addr: IpAddr) -> Option<(Ipv4Addr, Unwrapped)> {
+    let IpAddr::V6(v6) = addr else {
+        return None;
+    };
+    let octets = v6.octets();
+    let segments = v6.segments();
+
+    let (v4, tunnel) = match segments {
+        [0x2002, ..] => (
+            Ipv4Addr::new(octets[2], octets[3], octets[4], octets[5]),
+            Tunnel::SixToFour,
+        ),
+        [0x2001, 0x0000, ..] => (
+            Ipv4Addr::new(!octets[12], !octets[13], !octets[14], !octets[15]),
+            Tunnel::Teredo,
+        ),
+        _ => return None,
+    };
+    Some((
+        v4,
+        Unwrapped {
+            original: v6,
+            tunnel,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::{unwrap_tunneled, Tunnel};
+
+    #[test]
+    fn test_unwrap_tunneled() {
+        let (v4, unwrapped) = unwrap_tunneled("2002:c000:0204::1".parse().unwrap()).unwrap();
+        assert_eq!(v4, Ipv4Addr::new(192, 0, 2, 4));
+        assert_eq!(unwrapped.tunnel, Tunnel::SixToFour);
+
+        // the example from RFC 4380
+        let (v4, unwrapped) =
+            unwrap_tunneled("2001:0:4136:e378:8000:63bf:3fff:fdd2".parse().unwrap()).unwrap();
+        assert_eq!(v4, Ipv4Addr::new(192, 0, 2, 45));
+        assert_eq!(unwrapped.tunnel, Tunnel::Teredo);
+
+        assert!(unwrap_tunneled("2001:db8::1".parse().unwrap()).is_none());
+        assert!(unwrap_tunneled("192.0.2.4".parse().unwrap()).is_none());
+    }
+}
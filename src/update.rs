@@ -0,0 +1,470 @@
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use reqwest::{
+    header::{self, HeaderMap},
+    RequestBuilder, StatusCode,
+};
+use sha2::{Digest, Sha256};
+use tokio::{io::AsyncWriteExt, task::AbortHandle};
+
+use crate::{Error, Locat};
+
+const ENDPOINT: &str = "https://download.maxmind.com/app/geoip_download";
+
+/// Where to download a GeoIP database from and keep it, see
+/// [`Locat::with_auto_update`]
+#[derive(Debug, Clone)]
+pub struct AutoUpdate {
+    license_key: String,
+    edition: String,
+    cache_dir: PathBuf,
+    interval: Duration,
+    endpoint: String,
+}
+
+impl AutoUpdate {
+    /// `edition` is MaxMind's edition ID, e.g. `"GeoLite2-Country"`. The
+    /// database is checked for updates once a day, MaxMind publishes them
+    /// twice a week.
+    pub fn new(
+        license_key: impl Into<String>,
+        edition: impl Into<String>,
+        cache_dir: impl Into<PathBuf>,
+    ) -> Self {
+        Self {
+            license_key: license_key.into(),
+            edition: edition.into(),
+            cache_dir: cache_dir.into(),
+            interval: Duration::from_secs(24 * 3600),
+            endpoint: ENDPOINT.to_owned(),
+        }
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Downloads from a mirror of MaxMind's download endpoint instead
+    pub fn with_endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.endpoint = endpoint.into();
+        self
+    }
+
+    /// Where the database is kept
+    pub fn path(&self) -> PathBuf {
+        self.cache_dir.join(format!("{}.mmdb", self.edition))
+    }
+
+    fn checksum_path(&self) -> PathBuf {
+        self.cache_dir
+            .join(format!("{}.tar.gz.sha256", self.edition))
+    }
+
+    /// Where an archive is downloaded to, kept around to resume from if
+    /// the download is interrupted
+    fn partial_path(&self) -> PathBuf {
+        self.cache_dir
+            .join(format!("{}.tar.gz.partial", self.edition))
+    }
+
+    /// Downloads the database unless the copy in the cache directory is
+    /// the latest one. Returns whether it was replaced.
+    ///
+    /// Checking is a conditional request for the checksum, and an
+    /// interrupted download picks up where it stopped on the next call, so
+    /// fleets of instances checking often cost little bandwidth.
+    pub async fn download(&self) -> Result<bool, Error> {
+        let client = reqwest::Client::new();
+        let checksum_validators = validators_path(&self.checksum_path());
+        let mut request = self.request(&client, "tar.gz.sha256");
+        if self.path().exists() {
+            request = Validators::read(&checksum_validators)
+                .await
+                .conditional(request);
+        }
+        let response = request.send().await?.error_for_status()?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(false);
+        }
+        let validators = Validators::from_headers(response.headers());
+        let checksum = response.bytes().await?;
+        let checksum = String::from_utf8_lossy(&checksum);
+        // `<sha256>  GeoLite2-Country_20230609.tar.gz`
+        let expected = checksum
+            .split_whitespace()
+            .next()
+            .ok_or_else(|| invalid("empty checksum".to_owned()))?
+            .to_ascii_lowercase();
+
+        let current = tokio::fs::read_to_string(self.checksum_path()).await.ok();
+        if current.as_deref() == Some(expected.as_str()) && self.path().exists() {
+            validators.write(&checksum_validators).await?;
+            return Ok(false);
+        }
+
+        let archive = self.fetch_archive(&client).await?;
+        // whatever happens next, there's nothing left to resume
+        _ = tokio::fs::remove_file(self.partial_path()).await;
+        _ = tokio::fs::remove_file(validators_path(&self.partial_path())).await;
+        let actual: String = Sha256::digest(&archive)
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+        if actual != expected {
+            return Err(invalid(format!(
+                "checksum mismatch: expected {expected}, got {actual}"
+            )));
+        }
+        let database = extract_database(&archive)?;
+        // don't replace a working database with one that doesn't load
+        maxminddb::Reader::from_source(&database[..])?;
+
+        tokio::fs::create_dir_all(&self.cache_dir).await?;
+        let path = self.path();
+        let partial = path.with_extension("mmdb.partial");
+        tokio::fs::write(&partial, &database).await?;
+        tokio::fs::rename(&partial, &path).await?;
+        tokio::fs::write(self.checksum_path(), &expected).await?;
+        validators.write(&checksum_validators).await?;
+        Ok(true)
+    }
+
+    fn request(&self, client: &reqwest::Client, suffix: &str) -> RequestBuilder {
+        client.get(&self.endpoint).query(&[
+            ("edition_id", self.edition.as_str()),
+            ("license_key", self.license_key.as_str()),
+            ("suffix", suffix),
+        ])
+    }
+
+    /// Downloads the archive into the partial file, resuming from what's
+    /// already there if the server still has the same archive
+    async fn fetch_archive(&self, client: &reqwest::Client) -> Result<Vec<u8>, Error> {
+        tokio::fs::create_dir_all(&self.cache_dir).await?;
+        let partial = self.partial_path();
+        let validators_path = validators_path(&self.partial_path());
+        let offset = tokio::fs::metadata(&partial)
+            .await
+            .map_or(0, |metadata| metadata.len());
+
+        let mut request = self.request(client, "tar.gz");
+        if offset > 0 {
+            if let Some(validator) = Validators::read(&validators_path).await.if_range() {
+                request = request
+                    .header(header::RANGE, format!("bytes={offset}-"))
+                    .header(header::IF_RANGE, validator);
+            }
+        }
+        let mut response = request.send().await?.error_for_status()?;
+
+        // anything but a partial response is the whole archive again
+        let resumed = response.status() == StatusCode::PARTIAL_CONTENT;
+        if !resumed {
+            Validators::from_headers(response.headers())
+                .write(&validators_path)
+                .await?;
+        }
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(resumed)
+            .truncate(!resumed)
+            .open(&partial)
+            .await?;
+        while let Some(chunk) = response.chunk().await? {
+            file.write_all(&chunk).await?;
+        }
+        file.flush().await?;
+        Ok(tokio::fs::read(&partial).await?)
+    }
+
+    /// Whether the cached copy was downloaded less than an interval ago
+    #[cfg(feature = "analytics")]
+    fn is_fresh(&self) -> bool {
+        std::fs::metadata(self.checksum_path())
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|modified| modified.elapsed().ok())
+            .is_some_and(|age| age < self.interval)
+            && self.path().exists()
+    }
+}
+
+impl Locat {
+    /// Like [`Locat::new`], with the GeoIP database downloaded from MaxMind
+    /// into the cache directory. A cached copy is used as is if it's recent
+    /// enough, or if MaxMind can't be reached.
+    ///
+    /// Call [`Locat::keep_updated`] once configured to keep checking for
+    /// new versions.
+    #[cfg(feature = "analytics")]
+    pub async fn with_auto_update(
+        update: &AutoUpdate,
+        analytics_db_path: &str,
+    ) -> Result<Self, Error> {
+        if !update.is_fresh() {
+            if let Err(e) = update.download().await {
+                if !update.path().exists() {
+                    return Err(e);
+                }
+            }
+        }
+        let path = update.path();
+        let path = path
+            .to_str()
+            .ok_or_else(|| invalid(format!("cache path is not valid UTF-8: {path:?}")))?;
+        Locat::new(path, analytics_db_path).await
+    }
+
+    /// Checks for a new database every interval, and swaps it in when
+    /// there is one, see [`Locat::reload_geoip`]. Failed checks are retried
+    /// on the next interval. The task ends once every clone of this `Locat`
+    /// is dropped, or on [`Locat::shutdown`].
+    pub fn keep_updated(&self, update: AutoUpdate) -> AbortHandle {
+        let inner = std::sync::Arc::downgrade(&self.inner);
+        self.spawn("keep_updated", |mut stop| async move {
+            let mut ticks = tokio::time::interval(update.interval);
+            // the first tick is immediate, and the database was just loaded
+            ticks.tick().await;
+            loop {
+                tokio::select! {
+                    _ = ticks.tick() => {}
+                    _ = stop.requested() => return,
+                }
+                if inner.strong_count() == 0 {
+                    return;
+                }
+                if let Ok(true) = update.download().await {
+                    let Some(inner) = inner.upgrade() else {
+                        return;
+                    };
+                    _ = (Locat { inner }).reload_geoip().await;
+                }
+            }
+        })
+    }
+}
+
+/// What a server said identifies the version of a file, for conditional
+/// and resumed requests
+#[derive(Debug, Default)]
+struct Validators {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl Validators {
+    fn from_headers(headers: &HeaderMap) -> Self {
+        let get = |name| Some(headers.get(name)?.to_str().ok()?.to_owned());
+        Self {
+            etag: get(header::ETAG),
+            last_modified: get(header::LAST_MODIFIED),
+        }
+    }
+
+    /// Reads validators written by [`Validators::write`], none if there
+    /// aren't any
+    async fn read(path: &Path) -> Self {
+        let data = tokio::fs::read_to_string(path).await.unwrap_or_default();
+        let mut validators = Self::default();
+        for line in data.lines() {
+            match line.split_once(": ") {
+                Some(("etag", etag)) => validators.etag = Some(etag.to_owned()),
+                Some(("last-modified", at)) => validators.last_modified = Some(at.to_owned()),
+                _ => {}
+            }
+        }
+        validators
+    }
+
+    async fn write(&self, path: &Path) -> Result<(), Error> {
+        let mut data = String::new();
+        if let Some(etag) = &self.etag {
+            data.push_str(&format!("etag: {etag}\n"));
+        }
+        if let Some(at) = &self.last_modified {
+            data.push_str(&format!("last-modified: {at}\n"));
+        }
+        Ok(tokio::fs::write(path, data).await?)
+    }
+
+    fn conditional(&self, mut request: RequestBuilder) -> RequestBuilder {
+        if let Some(etag) = &self.etag {
+            request = request.header(header::IF_NONE_MATCH, etag);
+        }
+        if let Some(at) = &self.last_modified {
+            request = request.header(header::IF_MODIFIED_SINCE, at);
+        }
+        request
+    }
+
+    fn if_range(&self) -> Option<&str> {
+        self.etag.as_deref().or(self.last_modified.as_deref())
+    }
+}
+
+fn validators_path(path: &Path) -> PathBuf {
+    let mut path = path.as_os_str().to_owned();
+    path.push(".validators");
+    path.into()
+}
+
+/// The `.mmdb` file out of MaxMind's `.tar.gz`, which has it in a dated
+/// directory
+fn extract_database(archive: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(archive));
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if entry.path()?.extension().and_then(|ext| ext.to_str()) == Some("mmdb") {
+            let mut database = Vec::new();
+            entry.read_to_end(&mut database)?;
+            return Ok(database);
+        }
+    }
+    Err(invalid("no database in the archive".to_owned()))
+}
+
+fn invalid(reason: String) -> Error {
+    Error::InvalidDownload(reason)
+}
+
+#[cfg(all(test, feature = "analytics"))]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use sha2::{Digest, Sha256};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+    };
+
+    use super::AutoUpdate;
+    use crate::{
+        testing::{RemoveOnDrop, TestDb},
+        Error, Locat,
+    };
+
+    fn archive(database: &[u8]) -> Vec<u8> {
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(
+            Vec::new(),
+            flate2::Compression::fast(),
+        ));
+        let mut header = tar::Header::new_gnu();
+        header.set_size(database.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder
+            .append_data(
+                &mut header,
+                "GeoLite2-Country_20230609/GeoLite2-Country.mmdb",
+                database,
+            )
+            .unwrap();
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    /// Serves `archive` and `checksum` like MaxMind's download endpoint,
+    /// with ETags and ranges, and keeps the requests it gets
+    async fn serve(archive: Vec<u8>, checksum: String) -> (String, Arc<Mutex<Vec<String>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let log = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = vec![0; 4096];
+                let len = stream.read(&mut request).await.unwrap();
+                let request = String::from_utf8_lossy(&request[..len]).to_lowercase();
+                log.lock().unwrap().push(request.clone());
+
+                let (body, etag) = if request.contains("suffix=tar.gz.sha256") {
+                    (checksum.clone().into_bytes(), "\"sum\"")
+                } else {
+                    (archive.clone(), "\"archive\"")
+                };
+                let offset = request
+                    .split_once("range: bytes=")
+                    .and_then(|(_, rest)| rest.split_once('-')?.0.parse::<usize>().ok())
+                    .filter(|_| request.contains(&format!("if-range: {etag}")));
+                let (status, body) = if request.contains(&format!("if-none-match: {etag}")) {
+                    ("304 Not Modified", Vec::new())
+                } else if let Some(offset) = offset {
+                    ("206 Partial Content", body[offset..].to_vec())
+                } else {
+                    ("200 OK", body)
+                };
+                let head = format!(
+                    "HTTP/1.1 {status}\r\netag: {etag}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n",
+                    body.len()
+                );
+                stream.write_all(head.as_bytes()).await.unwrap();
+                stream.write_all(&body).await.unwrap();
+            }
+        });
+        (format!("http://{addr}/app/geoip_download"), requests)
+    }
+
+    #[tokio::test]
+    async fn test_auto_update() {
+        let geoip_path = "/tmp/loca-test-update-source.mmdb";
+        let cache_dir = "/tmp/loca-test-update";
+        let analytics_path = "/tmp/loca-test-update.db";
+        TestDb::new()
+            .country("1.2.3.0/24", "US", "NA")
+            .write(geoip_path);
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
+        _ = std::fs::remove_dir_all(cache_dir);
+
+        let archive = archive(&std::fs::read(geoip_path).unwrap());
+        let checksum: String = Sha256::digest(&archive)
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect();
+
+        let (endpoint, _) = serve(archive.clone(), "0".repeat(64)).await;
+        let update = AutoUpdate::new("key", "GeoLite2-Country", cache_dir).with_endpoint(endpoint);
+        assert!(matches!(
+            Locat::with_auto_update(&update, analytics_path).await,
+            Err(Error::InvalidDownload(_))
+        ));
+
+        let (endpoint, requests) = serve(
+            archive.clone(),
+            format!("{checksum}  GeoLite2-Country.tar.gz\n"),
+        )
+        .await;
+        let update = update.with_endpoint(endpoint);
+        // an earlier download was interrupted half way
+        let half = archive.len() / 2;
+        std::fs::write(update.partial_path(), &archive[..half]).unwrap();
+        std::fs::write(
+            super::validators_path(&update.partial_path()),
+            "etag: \"archive\"\n",
+        )
+        .unwrap();
+        let locat = Locat::with_auto_update(&update, analytics_path)
+            .await
+            .unwrap();
+        assert_eq!(
+            locat.ip_to_iso_code("1.2.3.4".parse().unwrap()).await,
+            Some("US")
+        );
+        assert!(requests.lock().unwrap()[1].contains(&format!("range: bytes={half}-")));
+        assert!(!update.partial_path().exists());
+
+        // already the latest
+        assert!(!update.download().await.unwrap());
+        assert!(requests.lock().unwrap()[2].contains("if-none-match: \"sum\""));
+
+        std::fs::remove_dir_all(cache_dir).unwrap();
+    }
+}
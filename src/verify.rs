@@ -0,0 +1,140 @@
+use std::{fmt, net::IpAddr};
+
+use crate::{GeoIp, Locat};
+
+// addresses from MaxMind's test databases that real databases agree on, so
+// a smoke test works against both
+const KNOWN_ANSWERS: &[(&str, &str)] = &[
+    ("81.2.69.160", "GB"),
+    ("89.160.20.112", "SE"),
+    ("216.160.83.56", "US"),
+];
+
+/// One step of [`Locat::verify`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Check {
+    pub name: String,
+    /// Why the check failed, `None` if it passed
+    pub failure: Option<String>,
+}
+
+/// The outcome of [`Locat::verify`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Verification {
+    pub checks: Vec<Check>,
+}
+
+impl Verification {
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.failure.is_none())
+    }
+}
+
+impl fmt::Display for Verification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            match &check.failure {
+                None => writeln!(f, "ok    {}", check.name)?,
+                Some(failure) => writeln!(f, "FAIL  {}: {failure}", check.name)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Locat {
+    /// Smoke-tests a deployment: runs a few lookups with known answers
+    /// against every loaded database, and makes sure analytics can be
+    /// written (without actually writing anything).
+    ///
+    /// Lookups made here aren't counted.
+    pub async fn verify(&self) -> Verification {
+        let mut checks = known_answers("geoip", &self.geoip());
+        let mut names: Vec<_> = self.inner.databases.keys().collect();
+        names.sort();
+        for name in names {
+            checks.extend(known_answers(name, &self.inner.databases[name]));
+        }
+        #[cfg(feature = "analytics")]
+        checks.push(self.check_analytics().await);
+
+        Verification { checks }
+    }
+
+    #[cfg(feature = "analytics")]
+    async fn check_analytics(&self) -> Check {
+        // other stores can't make a write without keeping it, reading will
+        // have to do
+        let (name, result) = match &self.inner.sqlite {
+            Some(db) => (
+                "analytics writable",
+                db.check_writable().await.map_err(Into::into),
+            ),
+            None => (
+                "analytics readable",
+                self.inner.analytics.counters().await.map(|_| ()),
+            ),
+        };
+        Check {
+            name: name.to_owned(),
+            failure: result.err().map(|e| e.to_string()),
+        }
+    }
+}
+
+fn known_answers(database: &str, geoip: &GeoIp) -> Vec<Check> {
+    KNOWN_ANSWERS
+        .iter()
+        .map(|(addr, expected)| {
+            let addr: IpAddr = addr.parse().unwrap();
+            let failure = match geoip.lookup(addr) {
+                Some(iso_code) if iso_code == *expected => None,
+                Some(iso_code) => Some(format!("got {iso_code}, expected {expected}")),
+                None => Some(format!("not found, expected {expected}")),
+            };
+            Check {
+                name: format!("{database}: {addr}"),
+                failure,
+            }
+        })
+        .collect()
+}
+
+#[cfg(all(test, feature = "analytics"))]
+mod tests {
+    use crate::{
+        testing::{RemoveOnDrop, TestDb},
+        Locat,
+    };
+
+    #[tokio::test]
+    async fn test_verify() {
+        let geoip_path = "/tmp/loca-test-verify.mmdb";
+        let analytics_path = "/tmp/loca-test-verify.db";
+        let _remove_geoip = RemoveOnDrop { path: geoip_path };
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
+
+        TestDb::new()
+            .country("81.2.69.0/24", "GB", "EU")
+            .country("89.160.20.0/24", "SE", "EU")
+            .country("216.160.83.0/24", "US", "NA")
+            .write(geoip_path);
+        let locat = Locat::new(geoip_path, analytics_path).await.unwrap();
+        let verification = locat.verify().await;
+        assert!(verification.passed(), "{verification}");
+        assert_eq!(locat.get_analytics().await.unwrap(), vec![]);
+
+        // a database that disagrees
+        TestDb::new()
+            .country("81.2.69.0/24", "FR", "EU")
+            .write(geoip_path);
+        let locat = Locat::new(geoip_path, analytics_path).await.unwrap();
+        let verification = locat.verify().await;
+        assert!(!verification.passed());
+        assert!(verification
+            .to_string()
+            .contains("FAIL  geoip: 81.2.69.160: got FR, expected GB"));
+    }
+}
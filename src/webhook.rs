@@ -0,0 +1,480 @@
+use std::{
+    sync::{Arc, OnceLock},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde_json::{json, Value};
+use tokio::task::AbortHandle;
+
+use crate::{periods, rollover::civil_from_days, Error, Field, Locat, Measures};
+
+/// When [`Locat::send_reports`] sends, like a crontab line: minute, hour,
+/// day of the month, month and day of the week (0 or 7 for Sunday), in
+/// UTC. Fields take `*`, numbers, ranges like `1-5`, steps like `*/15`
+/// and lists of those, e.g. `0 9 * * 1-5` for 9:00 on weekdays.
+/// `@hourly`, `@daily` and `@weekly` are short for the obvious.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    // like cron, a day matches either field when both are restricted
+    any_day: bool,
+    any_weekday: bool,
+}
+
+impl Schedule {
+    pub fn parse(spec: &str) -> Result<Self, Error> {
+        let invalid = |reason: &str| Error::InvalidConfig(format!("schedule {spec:?}: {reason}"));
+        let spec = match spec.trim() {
+            "@hourly" => "0 * * * *",
+            "@daily" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            spec => spec,
+        };
+        let fields: Vec<_> = spec.split_whitespace().collect();
+        let [minutes, hours, days, months, weekdays] = fields[..] else {
+            return Err(invalid("expected 5 fields"));
+        };
+        let field = |name: &str, value: &str, min, max| {
+            parse_field(value, min, max).ok_or_else(|| invalid(&format!("bad {name} {value:?}")))
+        };
+        let mut weekday_mask = field("day of the week", weekdays, 0, 7)?;
+        if weekday_mask & 1 << 7 != 0 {
+            weekday_mask = weekday_mask & !(1 << 7) | 1;
+        }
+        let schedule = Self {
+            minutes: field("minute", minutes, 0, 59)?,
+            hours: field("hour", hours, 0, 23)?,
+            days: field("day of the month", days, 1, 31)?,
+            months: field("month", months, 1, 12)?,
+            weekdays: weekday_mask,
+            any_day: days.starts_with('*'),
+            any_weekday: weekdays.starts_with('*'),
+        };
+        // e.g. February 30th
+        if schedule.next_after(UNIX_EPOCH).is_none() {
+            return Err(invalid("never comes"));
+        }
+        Ok(schedule)
+    }
+
+    /// The first minute after `at` the schedule matches, `None` if there's
+    /// none within 5 years
+    pub fn next_after(&self, at: SystemTime) -> Option<SystemTime> {
+        let secs = at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let mut t = secs / 60 * 60 + 60;
+        let give_up = t + 5 * 366 * 86400;
+        while t < give_up {
+            let days = t / 86400;
+            if !self.matches_day(days) {
+                t = (days + 1) * 86400;
+                continue;
+            }
+            if self.hours & 1 << (t % 86400 / 3600) == 0 {
+                t = (t / 3600 + 1) * 3600;
+                continue;
+            }
+            if self.minutes & 1 << (t % 3600 / 60) == 0 {
+                t += 60;
+                continue;
+            }
+            return Some(UNIX_EPOCH + Duration::from_secs(t));
+        }
+        None
+    }
+
+    /// Whether the day `days` after 1970-01-01 matches
+    fn matches_day(&self, days: u64) -> bool {
+        let (_, month, day) = civil_from_days(days as i64);
+        // 1970-01-01 was a Thursday
+        let weekday = (days + 4) % 7;
+        if self.months & 1 << month == 0 {
+            return false;
+        }
+        let day = self.days & 1 << day != 0;
+        let weekday = self.weekdays & 1 << weekday != 0;
+        match (self.any_day, self.any_weekday) {
+            (false, false) => day || weekday,
+            _ => day && weekday,
+        }
+    }
+}
+
+/// One field of a [`Schedule`], as a mask of the values it takes
+fn parse_field(spec: &str, min: u32, max: u32) -> Option<u64> {
+    let mut mask = 0;
+    for item in spec.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (range, step.parse().ok().filter(|&step| step > 0)?),
+            None => (item, 1),
+        };
+        let (start, end) = match range.split_once('-') {
+            _ if range == "*" => (min, max),
+            Some((start, end)) => (start.parse().ok()?, end.parse().ok()?),
+            // `5/15` runs to the end, like cron
+            None if step > 1 => (range.parse().ok()?, max),
+            None => {
+                let value = range.parse().ok()?;
+                (value, value)
+            }
+        };
+        if start < min || end > max || start > end {
+            return None;
+        }
+        for value in (start..=end).step_by(step) {
+            mask |= 1 << value;
+        }
+    }
+    Some(mask)
+}
+
+/// Where and when [`Locat::send_reports`] posts analytics reports: the top
+/// countries over the last period, with how they changed since the period
+/// before, as Slack-compatible JSON
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WebhookReport {
+    url: String,
+    schedule: Schedule,
+    period: Duration,
+    top: usize,
+    headers: Vec<(String, String)>,
+}
+
+impl WebhookReport {
+    /// Posts to `url`, e.g. a Slack incoming webhook, on `schedule`. Reports
+    /// cover the last day and the top 10 countries by default.
+    pub fn new(url: impl Into<String>, schedule: Schedule) -> Self {
+        Self {
+            url: url.into(),
+            schedule,
+            period: Duration::from_secs(86400),
+            top: 10,
+            headers: Vec::new(),
+        }
+    }
+
+    /// How far back a report looks, and how long the period it's compared
+    /// with is
+    pub fn with_period(mut self, period: Duration) -> Self {
+        self.period = period;
+        self
+    }
+
+    /// How many countries are listed, the most looked-up first
+    pub fn with_top(mut self, top: usize) -> Self {
+        self.top = top;
+        self
+    }
+
+    /// Sent with every request, e.g. for authentication
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+}
+
+/// A [`WebhookReport`] and whether its task is running yet
+pub(crate) struct Reports {
+    report: WebhookReport,
+    started: OnceLock<()>,
+}
+
+impl Locat {
+    /// Sends reports on the schedule of `report`, from the first lookup
+    /// counted on, so that the task runs within the caller's runtime. See
+    /// [`Locat::send_reports`] to start it right away.
+    pub fn with_webhook_report(mut self, report: WebhookReport) -> Self {
+        self.inner_mut().reports = Some(Reports {
+            report,
+            started: OnceLock::new(),
+        });
+        self
+    }
+
+    /// Starts the task [`Locat::with_webhook_report`] asked for, if it
+    /// isn't running yet
+    pub(crate) fn start_reports(&self) {
+        let Some(reports) = &self.inner.reports else {
+            return;
+        };
+        reports.started.get_or_init(|| {
+            if let Err(e) = self.send_reports(reports.report.clone()) {
+                self.log(format!("Could not schedule analytics reports: {e}"));
+            }
+        });
+    }
+
+    /// Posts a [`WebhookReport`] for the period that ends now, once. Counts
+    /// come from the time buckets, see [`Locat::with_time_buckets`], as
+    /// exported: a [`crate::Scrubber`] applies.
+    pub async fn send_report(&self, report: &WebhookReport) -> Result<(), Error> {
+        let body = self.render_report(report, SystemTime::now()).await?;
+        let mut request = reqwest::Client::new()
+            .post(&report.url)
+            .header("content-type", "application/json")
+            .body(body.to_string());
+        for (name, value) in &report.headers {
+            request = request.header(name, value);
+        }
+        request.send().await?.error_for_status()?;
+        Ok(())
+    }
+
+    /// [`Locat::send_report`] whenever the schedule says so. Failures are
+    /// logged and the next report is sent as planned. The task ends once
+    /// every clone of this `Locat` is dropped, or on [`Locat::shutdown`].
+    pub fn send_reports(&self, report: WebhookReport) -> Result<AbortHandle, Error> {
+        self.check_reports()?;
+        let inner = Arc::downgrade(&self.inner);
+        Ok(self.spawn("send_reports", |mut stop| async move {
+            let mut last = SystemTime::now();
+            while let Some(next) = report.schedule.next_after(last) {
+                // the clock may have moved either way while sending
+                let wait = next.duration_since(SystemTime::now()).unwrap_or_default();
+                tokio::select! {
+                    _ = tokio::time::sleep(wait) => {}
+                    _ = stop.requested() => return,
+                }
+                last = next;
+                let Some(inner) = inner.upgrade() else {
+                    return;
+                };
+                let locat = Locat { inner };
+                if let Err(e) = locat.send_report(&report).await {
+                    locat.log(format!("Could not send analytics report: {e}"));
+                }
+            }
+        }))
+    }
+
+    /// Reports need time buckets to compare periods
+    pub(crate) fn check_reports(&self) -> Result<(), Error> {
+        self.sqlite("send_reports")?;
+        if self.inner.bucket_size.is_none() {
+            return Err(Error::InvalidConfig(
+                "analytics reports need time buckets".to_owned(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// The Slack message for the period of `report` that ends at `end`
+    async fn render_report(&self, report: &WebhookReport, end: SystemTime) -> Result<Value, Error> {
+        self.check_reports()?;
+        let start = end - report.period;
+        let before = self.exported_counts(start - report.period..start).await?;
+        let after = self.exported_counts(start..end).await?;
+        let mut deltas = periods::deltas(before, after);
+        // countries that went quiet still count towards the totals
+        let (before, after) = deltas.iter().fold((0, 0), |(before, after), delta| {
+            (before + delta.before, after + delta.after)
+        });
+        deltas.retain(|delta| delta.after > 0);
+        deltas.sort_by(|a, b| {
+            b.after
+                .cmp(&a.after)
+                .then_with(|| a.iso_code.cmp(&b.iso_code))
+        });
+        let mut text = format!(
+            "*Lookups from {} to {}*: {}",
+            humantime::format_rfc3339_seconds(start),
+            humantime::format_rfc3339_seconds(end),
+            change(before, after),
+        );
+        for (rank, delta) in deltas.iter().take(report.top).enumerate() {
+            text.push_str(&format!(
+                "\n{}. {}: {}",
+                rank + 1,
+                delta.iso_code,
+                change(delta.before, delta.after)
+            ));
+        }
+        if deltas.len() > report.top {
+            text.push_str(&format!("\n…and {} more", deltas.len() - report.top));
+        }
+        Ok(json!({ "text": text }))
+    }
+
+    /// [`Locat::get_analytics_between`], scrubbed like exports are
+    async fn exported_counts(
+        &self,
+        range: std::ops::Range<SystemTime>,
+    ) -> Result<Vec<(String, u64)>, Error> {
+        let mut counts = self.get_analytics_between(range).await?;
+        for (iso_code, count) in &mut counts {
+            let mut measures = Measures {
+                count: *count,
+                sum: 0,
+            };
+            self.scrub(Field::ExportedCountry {
+                iso_code,
+                measures: &mut measures,
+            });
+            *count = measures.count;
+        }
+        counts.retain(|(_, count)| *count > 0);
+        Ok(counts)
+    }
+}
+
+/// A count and how it changed, like `120 (+20, +20.0%)`
+fn change(before: u64, after: u64) -> String {
+    match (before, after) {
+        (0, 0) => return "0".to_owned(),
+        (0, _) => return format!("{after} (new)"),
+        _ => {}
+    }
+    let delta = after as i64 - before as i64;
+    let percent = delta as f64 * 100.0 / before as f64;
+    format!("{after} ({delta:+}, {percent:+.1}%)")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        sync::mpsc,
+    };
+
+    use super::{Schedule, WebhookReport};
+    use crate::{
+        testing::{RemoveOnDrop, TestDb},
+        Error, Locat,
+    };
+
+    fn at(secs: u64) -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(secs)
+    }
+
+    #[test]
+    fn test_schedule() {
+        // 2023-06-15 13:20:00 UTC, a Thursday
+        let now = at(1_686_835_200);
+        let next = |spec| Schedule::parse(spec).unwrap().next_after(now).unwrap();
+        assert_eq!(next("* * * * *"), now + Duration::from_secs(60));
+        assert_eq!(next("*/15 * * * *"), now + Duration::from_secs(10 * 60));
+        assert_eq!(next("@hourly"), now + Duration::from_secs(40 * 60));
+        // tomorrow at 9:00
+        assert_eq!(next("0 9 * * *"), at(1_686_906_000));
+        // Monday the 19th at 9:00
+        assert_eq!(next("0 9 * * 1"), at(1_687_165_200));
+        // the 1st of July, or a Sunday, whichever comes first
+        assert_eq!(next("0 0 1 * 7"), at(1_687_046_400));
+        // the 29th of February, in 2024
+        assert_eq!(next("0 0 29 2 *"), at(1_709_164_800));
+
+        for bad in [
+            "",
+            "* * * *",
+            "60 * * * *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "0 0 30 2 *",
+        ] {
+            assert!(
+                matches!(Schedule::parse(bad), Err(Error::InvalidConfig(_))),
+                "{bad}"
+            );
+        }
+    }
+
+    /// Accepts webhook requests, handing over their bodies
+    async fn webhook() -> (String, mpsc::UnboundedReceiver<serde_json::Value>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (bodies, received) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut request = Vec::new();
+                let mut buf = vec![0; 4096];
+                let body = loop {
+                    let len = stream.read(&mut buf).await.unwrap();
+                    request.extend_from_slice(&buf[..len]);
+                    let text = String::from_utf8_lossy(&request).to_string();
+                    if let Some((_, body)) = text.split_once("\r\n\r\n") {
+                        if let Ok(body) = serde_json::from_str(body) {
+                            break body;
+                        }
+                    }
+                };
+                _ = bodies.send(body);
+                stream
+                    .write_all(
+                        b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\nconnection: close\r\n\r\nok",
+                    )
+                    .await
+                    .unwrap();
+            }
+        });
+        (format!("http://{addr}/hook"), received)
+    }
+
+    #[tokio::test]
+    async fn test_send_report() {
+        let (url, mut received) = webhook().await;
+        // buckets are read back from the file
+        let analytics_path = "/tmp/loca-test-webhook.db";
+        let _remove_analytics = RemoveOnDrop {
+            path: analytics_path,
+        };
+        let locat = Locat::builder()
+            .with_geoip_bytes(
+                TestDb::new()
+                    .country("1.2.3.0/24", "US", "NA")
+                    .country("2.3.4.0/24", "FR", "EU")
+                    .country("3.4.5.0/24", "DE", "EU")
+                    .build(),
+            )
+            .with_analytics_path(analytics_path)
+            .build()
+            .await
+            .unwrap();
+        let report = WebhookReport::new(url, Schedule::parse("@daily").unwrap()).with_top(1);
+        assert!(matches!(
+            locat.send_report(&report).await,
+            Err(Error::InvalidConfig(_))
+        ));
+        assert!(matches!(
+            Locat::builder()
+                .with_geoip_bytes(TestDb::new().build())
+                .with_webhook_report(report.clone())
+                .build()
+                .await,
+            Err(Error::InvalidConfig(_))
+        ));
+
+        let locat = locat.with_time_buckets(Duration::from_secs(3600));
+        for addr in ["1.2.3.4", "1.2.3.4", "2.3.4.5"] {
+            locat.ip_to_iso_code(addr.parse().unwrap()).await;
+        }
+        locat.send_report(&report).await.unwrap();
+        let text = received.recv().await.unwrap()["text"]
+            .as_str()
+            .unwrap()
+            .to_owned();
+        let lines: Vec<_> = text.lines().collect();
+        assert!(lines[0].starts_with("*Lookups from "));
+        assert!(lines[0].ends_with("*: 3 (new)"));
+        assert_eq!(lines[1..], ["1. US: 2 (new)", "…and 1 more"]);
+
+        // on schedule, every minute
+        let report = report.with_period(Duration::from_secs(60));
+        let every_minute = WebhookReport {
+            schedule: Schedule::parse("* * * * *").unwrap(),
+            ..report
+        };
+        tokio::time::pause();
+        let locat = locat.with_webhook_report(every_minute);
+        assert!(received.try_recv().is_err());
+        locat.ip_to_iso_code("3.4.5.6".parse().unwrap()).await;
+        assert!(received.recv().await.unwrap()["text"].is_string());
+        locat.shutdown().await;
+    }
+}